@@ -57,6 +57,11 @@ impl Computer {
         &mut self.memory
     }
 
+    /// Address in ROM of the next instruction to execute
+    pub fn get_pc(&self) -> usize {
+        self.cpu.get_pc().into()
+    }
+
     /// Advances one cicle
     pub fn ticktock(&mut self) {
         self.tick();
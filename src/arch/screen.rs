@@ -95,6 +95,9 @@ impl Screen {
         self.address = address.into();
     }
 
+    /// Writes the screen as a black-and-white PNG, where pixels that are set
+    /// are white. PNG packs pixels from the MSB, so the bits of each word are
+    /// reversed to keep pixel `x` at column `x`
     pub fn dump(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
         let png_file = File::create(path)?;
         let buf_writer = BufWriter::new(png_file);
@@ -116,7 +119,13 @@ impl Screen {
 
         let mut writer = encoder.write_header()?;
 
-        writer.write_image_data(self.as_bytes())?;
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flatten()
+            .flat_map(|word| word.reverse_bits().to_be_bytes())
+            .collect();
+        writer.write_image_data(&data)?;
 
         Ok(())
     }
@@ -4,12 +4,20 @@
 
 use std::collections::HashMap;
 
-use crate::asm::instruction::AsmInstruction;
+use crate::asm::{
+    instruction::AsmInstruction,
+    source_map::{Origin, SourceMap},
+};
 
 /// The assembler translates programs written in asm language (text) to binary code
 pub struct Assembler {
     symbol_table: HashMap<String, u16>,
     next_variable_address: u16,
+
+    /// Origin of each instruction to resolve, labels included
+    origins: Vec<Option<Origin>>,
+    /// Origin of each resolved instruction, by ROM address
+    source_map: SourceMap,
 }
 
 impl Default for Assembler {
@@ -17,6 +25,8 @@ impl Default for Assembler {
         Self {
             symbol_table: Self::default_symbol_table(),
             next_variable_address: 16,
+            origins: vec![],
+            source_map: SourceMap::default(),
         }
     }
 }
@@ -55,6 +65,18 @@ impl Assembler {
         Self::default()
     }
 
+    /// Sets the origin of each instruction passed to `resolve`
+    pub fn with_origins(mut self, origins: Vec<Option<Origin>>) -> Self {
+        self.origins = origins;
+        self
+    }
+
+    /// Returns the origin of each instruction resolved by the last call to
+    /// `resolve`, which is empty without origins
+    pub fn get_source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
     /// Removes comments and trims lines
     fn preprocess(asm: &str) -> Vec<&str> {
         let mut lines = vec![];
@@ -82,9 +104,11 @@ impl Assembler {
             .collect()
     }
 
-    /// Resolves symbols in the assembly to physical memory addresses.
+    /// Resolves symbols in the assembly to physical memory addresses, mapping
+    /// each address to the origin of its instruction when origins are set.
     pub fn resolve(&mut self, mut asms: Vec<AsmInstruction>) -> Vec<AsmInstruction> {
         let mut no_label_instructions = vec![];
+        self.source_map = SourceMap::default();
 
         // First pass, collects labels and empty out those lines
         let mut skipped_lines = 0;
@@ -95,6 +119,9 @@ impl Assembler {
                 skipped_lines += 1;
             } else {
                 no_label_instructions.push(asm.clone());
+                if let Some(origin) = self.origins.get(n) {
+                    self.source_map.push(origin.clone());
+                }
             }
         }
 
@@ -4,4 +4,5 @@
 
 pub mod assembler;
 pub mod instruction;
+pub mod source_map;
 pub use assembler::*;
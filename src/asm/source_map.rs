@@ -0,0 +1,132 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::str::FromStr;
+
+use crate::{dump::ToJson, error::CalError, json::Json, tokenizer::Range};
+
+/// Function and range of source code an instruction is generated from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Origin {
+    pub function: String,
+    pub range: Range,
+}
+
+impl Origin {
+    pub fn new(function: impl Into<String>, range: Range) -> Self {
+        Self {
+            function: function.into(),
+            range,
+        }
+    }
+}
+
+/// Origin of each instruction of a program, by ROM address. Instructions
+/// which are not generated from source code, such as the boot code, have
+/// no origin
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    origins: Vec<Option<Origin>>,
+}
+
+impl SourceMap {
+    /// Appends the origin of the instruction at the next address
+    pub fn push(&mut self, origin: Option<Origin>) {
+        self.origins.push(origin);
+    }
+
+    pub fn len(&self) -> usize {
+        self.origins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.origins.is_empty()
+    }
+
+    /// Returns the origin of the instruction at an address
+    pub fn get(&self, address: usize) -> Option<&Origin> {
+        self.origins.get(address)?.as_ref()
+    }
+
+    /// Returns the addresses of the instructions generated from a function
+    pub fn addresses<'a>(&'a self, function: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.origins
+            .iter()
+            .enumerate()
+            .filter(move |(_, origin)| {
+                origin
+                    .as_ref()
+                    .is_some_and(|origin| origin.function == function)
+            })
+            .map(|(address, _)| address)
+    }
+
+    fn error<T>(message: &str) -> Result<T, CalError> {
+        Err(CalError::new(
+            format!("Invalid source map: {}", message),
+            Range::default(),
+        ))
+    }
+}
+
+/// A source map is written as the runs of addresses sharing an origin,
+/// where a run without origin has no function
+impl ToJson for SourceMap {
+    fn to_json(&self) -> Json {
+        let mut runs = vec![];
+        let mut start = 0;
+        for address in 1..=self.origins.len() {
+            if address < self.origins.len() && self.origins[address] == self.origins[start] {
+                continue;
+            }
+            let mut run = vec![
+                ("address".to_string(), start.into()),
+                ("count".to_string(), (address - start).into()),
+            ];
+            if let Some(origin) = &self.origins[start] {
+                run.push(("function".to_string(), origin.function.as_str().into()));
+                run.push(("range".to_string(), origin.range.to_json()));
+            }
+            runs.push(Json::Object(run));
+            start = address;
+        }
+        runs.into()
+    }
+}
+
+impl FromStr for SourceMap {
+    type Err = CalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json: Json = s.parse()?;
+        let Some(runs) = json.as_array() else {
+            return Self::error("expected an array of runs");
+        };
+        let mut ret = Self::default();
+        for run in runs {
+            let (Some(address), Some(count)) = (
+                run.get("address").and_then(Json::as_usize),
+                run.get("count").and_then(Json::as_usize),
+            ) else {
+                return Self::error("expected the address and the count of a run");
+            };
+            if address != ret.len() {
+                return Self::error("runs are not contiguous");
+            }
+            let origin = match run.get("function").and_then(Json::as_str) {
+                Some(function) => {
+                    let range = run.get("range");
+                    let offset = |key| range.and_then(|range| range.get(key)?.as_usize());
+                    let (Some(start), Some(end)) = (offset("start"), offset("end")) else {
+                        return Self::error("expected the range of a function");
+                    };
+                    Some(Origin::new(function, Range::new(start, end)))
+                }
+                None => None,
+            };
+            ret.origins.extend(std::iter::repeat_n(origin, count));
+        }
+        Ok(ret)
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::io::{self, BufRead, Write};
+
+use acs::{
+    debugger::{Breakpoint, Debugger, Frame, Step, Stop, Target},
+    error::CalError,
+    interpreter::Value,
+    structure::Variable,
+    tokenizer::Range,
+};
+
+const HELP: &str = "\
+break <line|function>  stop at the statements of a line, or entering a function
+delete <n>             remove a breakpoint
+continue               run until a breakpoint, or the end of the program
+step                   go to the next statement, entering calls
+next                   go to the next statement, stepping over calls
+finish                 go back to the caller of the current function
+backtrace              show the call stack
+frame <n>              select a frame of the call stack
+args                   show the arguments of the selected frame
+locals                 show the local variables of the selected frame
+print <expr>           evaluate an expression in the selected frame
+watch <expr>           evaluate an expression whenever the program stops
+unwatch <n>            remove a watch
+quit";
+
+/// Parses the number of a breakpoint, a watch or a frame
+fn number(arg: &str) -> Result<usize, CalError> {
+    arg.parse().map_err(|_| {
+        CalError::new(
+            format!("Expected a number, found `{}`", arg),
+            Range::default(),
+        )
+    })
+}
+
+/// Parses the number of a breakpoint or a watch, counted from 1 among
+/// `count` of them, returning its index
+fn index(arg: &str, count: usize, what: &str) -> Result<usize, CalError> {
+    match number(arg)? {
+        n if n == 0 || n > count => Err(CalError::new(
+            format!("No {} {}", what, n),
+            Range::default(),
+        )),
+        n => Ok(n - 1),
+    }
+}
+
+fn not_running() -> CalError {
+    CalError::new("The program is not running".into(), Range::default())
+}
+
+/// Debugs a program from the terminal, with commands in the style of gdb
+pub struct Session<'a, T: Target> {
+    debugger: Debugger<T>,
+    path: &'a str,
+    code: &'a str,
+    /// Frame of the call stack which variables and expressions refer to
+    frame: usize,
+}
+
+impl<'a, T: Target> Session<'a, T> {
+    pub fn new(debugger: Debugger<T>, path: &'a str, code: &'a str) -> Self {
+        Self {
+            debugger,
+            path,
+            code,
+            frame: 0,
+        }
+    }
+
+    /// Function, line and code of the statement a frame is executing
+    fn describe(&self, frame: &Frame) -> String {
+        let line = self.debugger.get_line(frame.origin.range);
+        let text = self.code.lines().nth(line - 1).unwrap_or_default();
+        format!(
+            "{} at {}:{}: {}",
+            frame.origin.function,
+            self.path,
+            line,
+            text.trim()
+        )
+    }
+
+    fn get_frame(&self) -> Result<Frame, CalError> {
+        self.debugger
+            .get_backtrace()
+            .into_iter()
+            .nth(self.frame)
+            .ok_or_else(not_running)
+    }
+
+    /// Runs the program and reports where it stopped, unless it finished
+    fn run_step(&mut self, step: Step) -> Result<(), CalError> {
+        if self.debugger.is_finished() {
+            return Err(not_running());
+        }
+        let stop = self.debugger.run(step);
+        self.report(stop);
+        Ok(())
+    }
+
+    fn print_variables(&self, variables: &[(Variable, Value)]) {
+        for (variable, value) in variables {
+            println!(
+                "{}: {} = {}",
+                variable.name,
+                variable.typ,
+                self.debugger.format_value(value)
+            );
+        }
+    }
+
+    /// Prints where the program stopped, along with the watches
+    fn report(&mut self, stop: Stop) {
+        self.frame = 0;
+        match stop {
+            Stop::Finished(value) => {
+                println!("main returned {}", self.debugger.format_value(&value));
+                return;
+            }
+            Stop::Breakpoint(index) => println!("Breakpoint {}", index + 1),
+            Stop::Step => (),
+        }
+        if let Some(frame) = self.debugger.get_backtrace().first() {
+            println!("{}", self.describe(frame));
+        }
+        let watches = self.debugger.get_watches().to_vec();
+        let values = self.debugger.get_watch_values();
+        for (i, (watch, value)) in watches.iter().zip(values).enumerate() {
+            match value {
+                Ok(value) => println!(
+                    "{}: {} = {}",
+                    i + 1,
+                    watch,
+                    self.debugger.format_value(&value)
+                ),
+                Err(err) => println!("{}: {} = <{}>", i + 1, watch, err.message),
+            }
+        }
+    }
+
+    /// Executes a command, returning whether to keep debugging
+    fn exec(&mut self, line: &str) -> Result<bool, CalError> {
+        let (command, arg) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let arg = arg.trim();
+        match command {
+            "break" | "b" => {
+                let breakpoint = match arg.parse() {
+                    Ok(line) => Breakpoint::Line(line),
+                    Err(_) => Breakpoint::Function(arg.to_string()),
+                };
+                let index = self.debugger.add_breakpoint(breakpoint)?;
+                println!("Breakpoint {} at {}", index + 1, arg);
+            }
+            "delete" | "d" => {
+                let count = self.debugger.get_breakpoints().len();
+                self.debugger
+                    .remove_breakpoint(index(arg, count, "breakpoint")?)?;
+            }
+            "continue" | "c" | "run" | "r" => self.run_step(Step::Continue)?,
+            "step" | "s" => self.run_step(Step::In)?,
+            "next" | "n" => self.run_step(Step::Over)?,
+            "finish" | "f" => self.run_step(Step::Out)?,
+            "backtrace" | "bt" => {
+                for (i, frame) in self.debugger.get_backtrace().iter().enumerate() {
+                    println!("#{} {}", i, self.describe(frame));
+                }
+            }
+            "frame" => {
+                let backtrace = self.debugger.get_backtrace();
+                let index = number(arg)?;
+                let Some(frame) = backtrace.get(index) else {
+                    return Err(CalError::new(
+                        format!("No frame {}", index),
+                        Range::default(),
+                    ));
+                };
+                self.frame = index;
+                println!("#{} {}", index, self.describe(frame));
+            }
+            "args" => {
+                let frame = self.get_frame()?;
+                let arguments = self.debugger.get_arguments(&frame)?;
+                self.print_variables(&arguments);
+            }
+            "locals" => {
+                let frame = self.get_frame()?;
+                let locals = self.debugger.get_locals(&frame)?;
+                self.print_variables(&locals);
+            }
+            "print" | "p" => {
+                let frame = self.get_frame()?;
+                let value = self.debugger.evaluate(arg, &frame)?;
+                println!("{}", self.debugger.format_value(&value));
+            }
+            "watch" | "w" => {
+                let index = self.debugger.add_watch(arg)?;
+                println!("Watch {}: {}", index + 1, arg);
+            }
+            "unwatch" => {
+                let count = self.debugger.get_watches().len();
+                self.debugger.remove_watch(index(arg, count, "watch")?)?;
+            }
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return Ok(false),
+            "" => (),
+            _ => {
+                return Err(CalError::new(
+                    format!("Unknown command `{}`, try `help`", command),
+                    Range::default(),
+                ))
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut lines = io::stdin().lock().lines();
+        loop {
+            print!("(cal) ");
+            io::stdout().flush()?;
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+            match self.exec(&line?) {
+                Ok(true) => (),
+                Ok(false) => return Ok(()),
+                Err(err) => println!("error: {}", err.message),
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+mod debug;
+
+use std::{
+    env,
+    fs::read_to_string,
+    io::{self, BufRead, Write},
+};
+
+use acs::{
+    debugger::Debugger,
+    error::CalError,
+    interpreter::{Interpreter, Value},
+    structure::{Module, Type},
+    tokenizer::{tokenize, Keyword, Symbol, TokenKind},
+    Computer, VmEmulator,
+};
+use debug::Session;
+
+/// Name of the function wrapping the statements typed into the REPL
+const REPL_FUNCTION: &str = "__repl";
+
+/// Keeps the definitions typed so far, so that every new input is parsed
+/// knowing about previous types and aliases
+#[derive(Default)]
+struct Repl {
+    interpreter: Interpreter,
+    definitions: String,
+}
+
+impl Repl {
+    /// Evaluates a definition or a series of statements, returning the value
+    /// of the last expression
+    fn eval(&mut self, input: &str) -> Result<Option<Value>, CalError> {
+        let mut tokens = tokenize(input)?;
+        let is_definition = matches!(
+            tokens.peek().map(|token| &token.value),
+            Some(
+                TokenKind::Keyword(
+                    Keyword::Function
+                        | Keyword::Const
+                        | Keyword::Struct
+                        | Keyword::Type
+                        | Keyword::Impl
+                ) | TokenKind::Symbol(Symbol::Hash)
+            )
+        );
+
+        if is_definition {
+            let code = format!("{}\n{}", self.definitions, input);
+            let module: Module = code.parse()?;
+            self.interpreter.load(&module)?;
+            self.definitions = code;
+            Ok(None)
+        } else {
+            let code = format!(
+                "{}\nfn {}() {{\n{}\n}}",
+                self.definitions, REPL_FUNCTION, input
+            );
+            let module: Module = code.parse()?;
+            let function = module
+                .functions
+                .iter()
+                .find(|function| function.name == REPL_FUNCTION)
+                .unwrap();
+            self.interpreter.exec(&function.body_statements)
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let mut input = String::new();
+        let mut lines = io::stdin().lock().lines();
+        loop {
+            print!("{}", if input.is_empty() { "> " } else { "| " });
+            io::stdout().flush()?;
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+            input.push_str(&line?);
+            input.push('\n');
+
+            // Keep reading until braces are balanced
+            if input.matches('{').count() > input.matches('}').count() {
+                continue;
+            }
+            if !input.trim().is_empty() {
+                match self.eval(&input) {
+                    Ok(Some(value)) if value.typ != Type::Void => {
+                        println!("{}", self.interpreter.format_value(&value))
+                    }
+                    Ok(_) => (),
+                    Err(err) => println!("error: {}", err.message),
+                }
+            }
+            input.clear();
+        }
+    }
+}
+
+fn main() -> Result<(), CalError> {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("repl") => {
+            Repl::default().run().expect("Failed to read from stdin");
+        }
+        Some("run") => {
+            let cal_path = args.get(2).expect("Expected one cli argument: cal_path");
+            let code = read_to_string(cal_path).expect("Failed to read string from cal");
+            let mut interpreter = Interpreter::default();
+            interpreter.load(&code.parse()?)?;
+            let value = interpreter.run()?;
+            if !value.words.is_empty() {
+                println!("{}", interpreter.format_value(&value));
+            }
+        }
+        Some("debug") => {
+            let cal_path = args.get(2).expect("Expected one cli argument: cal_path");
+            let code = read_to_string(cal_path).expect("Failed to read string from cal");
+            let libraries: Vec<String> = args
+                .windows(2)
+                .filter(|pair| pair[0] == "--link")
+                .map(|pair| pair[1].clone())
+                .collect();
+            let result = if args.iter().any(|arg| arg == "--vm") {
+                let debugger = Debugger::<VmEmulator>::new(&code, &libraries)?;
+                Session::new(debugger, cal_path, &code).run()
+            } else {
+                let debugger = Debugger::<Computer>::new(&code, &libraries)?;
+                Session::new(debugger, cal_path, &code).run()
+            };
+            result.expect("Failed to read from stdin");
+        }
+        _ => eprintln!(
+            "Usage: cal repl | cal run <cal_path> | cal debug <cal_path> [--vm] [--link <library>]"
+        ),
+    }
+    Ok(())
+}
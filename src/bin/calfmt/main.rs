@@ -0,0 +1,45 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    env,
+    fs::{read_to_string, write},
+    process::ExitCode,
+};
+
+use acs::formatter::format;
+
+/// Formats Cal files in place, or only checks whether they are formatted
+/// when `--check` is passed
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let check = args.iter().any(|arg| arg == "--check");
+    let cal_paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    if cal_paths.is_empty() {
+        eprintln!("Usage: calfmt [--check] <cal_path>...");
+        return ExitCode::FAILURE;
+    }
+
+    let mut ret = ExitCode::SUCCESS;
+    for cal_path in cal_paths {
+        let code = read_to_string(cal_path).expect("Failed to read string from cal");
+        let formatted = match format(&code) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprintln!("error: {}\n  --> {}", err.message, cal_path);
+                ret = ExitCode::FAILURE;
+                continue;
+            }
+        };
+        if check {
+            if formatted != code {
+                println!("{} is not formatted", cal_path);
+                ret = ExitCode::FAILURE;
+            }
+        } else if formatted != code {
+            write(cal_path, formatted).expect("Failed to write formatted cal");
+        }
+    }
+    ret
+}
@@ -4,22 +4,282 @@
 
 use std::{
     env,
-    fs::{read_to_string, File},
+    fs::{self, read_to_string, File},
     io::Write,
+    path::Path,
 };
 
-use acs::{compiler::compile, error::CalError, Assembler};
+use acs::{
+    asm::instruction::AsmInstruction,
+    compiler::{compile, link, Backend, CompileOptions},
+    doc::{document, index, DocFormat},
+    dump::{ToJson, ToSexp},
+    error::CalError,
+    generator::Generator,
+    ir::Program,
+    jack::compiler::Compile,
+    parser::parse,
+    tokenizer::{tokenize, Range, Token},
+    Assembler,
+};
 
 fn to_bytes(uint: &u16) -> &[u8] {
     unsafe { std::slice::from_raw_parts(uint as *const u16 as *const u8, 2) }
 }
 
+/// Returns line and column, starting from 1, of an offset in the code
+fn line_and_column(code: &str, offset: usize) -> (usize, usize) {
+    let before = &code[..offset.min(code.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// Compiles Jack classes, one per file, into asm instructions
+fn compile_jack(jack_paths: &[&String]) -> Result<Vec<AsmInstruction>, CalError> {
+    let sources: Vec<String> = jack_paths
+        .iter()
+        .map(|path| read_to_string(path).expect("Failed to read string from jack"))
+        .collect();
+    let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+    sources
+        .compile()
+        .map_err(|err| CalError::new(err.message, err.range))
+}
+
+/// Returns the value following an option such as `--emit vm`
+fn option_value<'a>(args: &'a [String], option: &str) -> Option<&'a String> {
+    let index = args.iter().position(|arg| arg == option)?;
+    args.get(index + 1)
+}
+
+/// Returns the values of an option which may be repeated, such as `--link`
+fn option_values(args: &[String], option: &str) -> Vec<String> {
+    args.windows(2)
+        .filter(|pair| pair[0] == option)
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+/// Returns the optimisation level of an option such as `-O1`, where `-O`
+/// stands for all optimisations, or `0` when there is none
+fn opt_level(args: &[String]) -> Result<u8, CalError> {
+    match args.iter().rev().find(|arg| arg.starts_with("-O")) {
+        None => Ok(0),
+        Some(arg) => match arg.as_str() {
+            "-O" | "-O2" => Ok(2),
+            "-O1" => Ok(1),
+            "-O0" => Ok(0),
+            _ => Err(CalError::new(
+                format!(
+                    "Unknown optimisation level `{}`, expected -O0, -O1 or -O2",
+                    arg
+                ),
+                Range::default(),
+            )),
+        },
+    }
+}
+
+/// Prints a stream as a JSON array, or as S-expressions one per line
+fn print_stream<T: ToJson + ToSexp>(items: &[T], format: &str) {
+    match format {
+        "json" => println!("{}", items.to_json()),
+        _ => items.iter().for_each(|item| println!("{}", item.to_sexp())),
+    }
+}
+
+/// Reports the functions left out of a program as unreachable
+fn report_pruned(pruned: &[String]) {
+    for name in pruned {
+        eprintln!("note: pruned unreachable function `{}`", name);
+    }
+}
+
+/// Prints an intermediate representation of the code
+fn emit(
+    code: &str,
+    jack_paths: &[&String],
+    what: &str,
+    format: &str,
+    options: &CompileOptions,
+    verbose: bool,
+) -> Result<(), CalError> {
+    match what {
+        "tokens" => {
+            let mut tokens = tokenize(code)?;
+            let mut all: Vec<Token> = tokens.by_ref().collect();
+            all.extend_from_slice(tokens.comments());
+            all.sort_by_key(|token| token.range.start);
+            print_stream(&all, format);
+        }
+        "ast" => {
+            let module = parse(tokenize(code)?)?;
+            match format {
+                "json" => println!("{}", module.to_json()),
+                _ => println!("{}", module.to_sexp().pretty(80)),
+            }
+        }
+        "vm" => {
+            let instructions = if jack_paths.is_empty() {
+                let module = parse(tokenize(code)?)?;
+                let mut generator = Generator::default().with_opt_level(options.opt_level);
+                let instructions = generator.gen(&link(module, &options.link)?)?;
+                if verbose {
+                    report_pruned(generator.get_pruned());
+                }
+                instructions
+            } else {
+                let sources: Vec<String> = jack_paths
+                    .iter()
+                    .map(|path| read_to_string(path).expect("Failed to read string from jack"))
+                    .collect();
+                let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+                acs::jack::compiler::compile(&sources)
+                    .map_err(|err| CalError::new(err.message, err.range))?
+            };
+            print_stream(&instructions, format);
+        }
+        "ir" => {
+            let module = parse(tokenize(code)?)?;
+            let mut generator = Generator::default();
+            let instructions = generator.gen(&link(module, &options.link)?)?;
+            let mut program = Program::new(&instructions);
+            program.inline = generator.get_inline();
+            program.optimize(options.opt_level);
+            print!("{}", program);
+        }
+        _ => {
+            return Err(CalError::new(
+                format!("Unknown `--emit {}`, expected tokens, ast, vm, or ir", what),
+                Range::default(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Writes a documentation page for each Cal module, along with an index of
+/// the modules, into the `out_dir` directory
+fn doc(cal_paths: &[&String], format: DocFormat, out_dir: &str) -> Result<(), CalError> {
+    fs::create_dir_all(out_dir).expect("Failed to create documentation directory");
+    let mut names = vec![];
+    for cal_path in cal_paths {
+        let code = read_to_string(cal_path).expect("Failed to read string from cal");
+        let module = parse(tokenize(&code)?)?;
+        let name = Path::new(cal_path)
+            .file_stem()
+            .map_or("main".into(), |stem| stem.to_string_lossy().to_string());
+        let page = document(&name, &module, format);
+        let page_path = Path::new(out_dir).join(format!("{}.{}", name, format.extension()));
+        fs::write(page_path, page).expect("Failed to write documentation page");
+        names.push(name);
+    }
+    let index_path = Path::new(out_dir).join(format!("index.{}", format.extension()));
+    fs::write(index_path, index(&names, format)).expect("Failed to write documentation index");
+    Ok(())
+}
+
 fn main() -> Result<(), CalError> {
     let args: Vec<String> = env::args().collect();
-    let cal_path = args.get(1).expect("Expected one cli argument: cal_path");
+    let options = CompileOptions {
+        deny_warnings: args.iter().any(|arg| arg == "--deny-warnings"),
+        link: option_values(&args, "--link"),
+        backend: option_value(&args, "--backend")
+            .map_or(Ok(Backend::Vm), |backend| backend.parse())?,
+        opt_level: opt_level(&args)?,
+    };
+    let verbose = args.iter().any(|arg| arg == "--verbose");
+    let debug_info = args.iter().any(|arg| arg == "-g");
+    let emit_what = option_value(&args, "--emit");
+    let format = option_value(&args, "--format").map_or("json", String::as_str);
+    let paths: Vec<&String> = args
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(i, arg)| {
+            !arg.starts_with('-')
+                && !matches!(
+                    args[i - 1].as_str(),
+                    "--emit" | "--format" | "--out" | "--link" | "--backend"
+                )
+        })
+        .map(|(_, arg)| arg)
+        .collect();
+    let cal_path = paths.first().expect("Expected one cli argument: cal_path");
 
-    let code = read_to_string(cal_path).expect("Failed to read string from asm");
-    let asm_instructions = compile(&code)?;
+    if cal_path.as_str() == "doc" {
+        let format = option_value(&args, "--format").map_or("markdown", String::as_str);
+        let out_dir = option_value(&args, "--out").map_or("doc", String::as_str);
+        return doc(&paths[1..], format.parse()?, out_dir);
+    }
+
+    if !matches!(format, "json" | "sexp") {
+        return Err(CalError::new(
+            format!("Unknown `--format {}`, expected json or sexp", format),
+            Range::default(),
+        ));
+    }
+    // Only the VM backend knows where its instructions come from, and
+    // emitting stops before either backend runs
+    let backend = option_value(&args, "--backend");
+    if debug_info && options.backend != Backend::Vm {
+        return Err(CalError::new(
+            "`-g` is only supported with `--backend vm`".into(),
+            Range::default(),
+        ));
+    }
+    if let (Some(what), Some(backend)) = (emit_what, backend) {
+        return Err(CalError::new(
+            format!(
+                "`--emit {}` stops before lowering to asm, so `--backend {}` has no effect",
+                what, backend
+            ),
+            Range::default(),
+        ));
+    }
+    if let Some(what) = emit_what {
+        if cal_path.ends_with(".jack") {
+            if what != "vm" {
+                return Err(CalError::new(
+                    format!("`--emit {}` is only supported for Cal", what),
+                    Range::default(),
+                ));
+            }
+            return emit("", &paths, what, format, &options, verbose);
+        }
+        let code = read_to_string(cal_path).expect("Failed to read string from cal");
+        return emit(&code, &[], what, format, &options, verbose);
+    }
+
+    let mut source_map = None;
+    let asm_instructions = if cal_path.ends_with(".jack") {
+        compile_jack(&paths)?
+    } else {
+        let code = read_to_string(cal_path).expect("Failed to read string from asm");
+        let compilation = compile(&code, &options)?;
+        for warning in &compilation.warnings {
+            let (line, column) = line_and_column(&code, warning.range.start);
+            eprintln!(
+                "warning: {}\n  --> {}:{}:{}",
+                warning.message, cal_path, line, column
+            );
+        }
+        if verbose {
+            report_pruned(&compilation.pruned);
+        }
+        // The optimiser does not keep track of the statements it rewrites
+        if debug_info {
+            for name in &compilation.rewritten {
+                eprintln!(
+                    "warning: `-O{}` rewrote `{}`, so its debug info only points at its declaration, compile with -O0 to map its statements",
+                    options.opt_level, name
+                );
+            }
+        }
+        source_map = Some(compilation.source_map);
+        compilation.instructions
+    };
 
     let mut assembler = Assembler::new();
     let asm_instructions = assembler.resolve(asm_instructions);
@@ -28,5 +288,12 @@ fn main() -> Result<(), CalError> {
     for asmi in asm_instructions {
         out.write_all(to_bytes(&u16::from(&asmi))).unwrap();
     }
+
+    // Debug info goes to a sidecar file next to the binary. It maps the
+    // statements of each function unless the optimiser rewrote it
+    if let (true, Some(source_map)) = (debug_info, source_map) {
+        fs::write("out.asm.map", source_map.to_json().to_string())
+            .expect("Failed to write source map");
+    }
     Ok(())
 }
@@ -0,0 +1,305 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    process::ExitCode,
+};
+
+use acs::{
+    analysis::{
+        offset_to_position, position_to_offset, Analysis, DocumentSymbol, Severity, SymbolKind,
+    },
+    json::Json,
+    tokenizer::Range,
+};
+
+/// A document opened by the editor, analysed every time it changes
+struct Document {
+    code: String,
+    analysis: Analysis,
+}
+
+impl Document {
+    fn new(code: String) -> Self {
+        let analysis = Analysis::new(&code);
+        Self { code, analysis }
+    }
+
+    fn range(&self, range: Range) -> Json {
+        let position = |offset| {
+            let (line, character) = offset_to_position(&self.code, offset);
+            Json::object([("line", line.into()), ("character", character.into())])
+        };
+        Json::object([
+            ("start", position(range.start)),
+            ("end", position(range.end)),
+        ])
+    }
+
+    /// Returns the offset of the `position` of a request
+    fn offset(&self, params: &Json) -> Option<usize> {
+        let position = params.get("position")?;
+        let line = position.get("line")?.as_usize()?;
+        let character = position.get("character")?.as_usize()?;
+        Some(position_to_offset(&self.code, line, character))
+    }
+
+    fn symbol(&self, symbol: &DocumentSymbol) -> Json {
+        let kind: usize = match symbol.kind {
+            SymbolKind::Method => 6,
+            SymbolKind::Field => 8,
+            SymbolKind::Function => 12,
+            SymbolKind::Static => 13,
+            SymbolKind::Constant => 14,
+            SymbolKind::Struct => 23,
+            SymbolKind::TypeAlias => 26,
+        };
+        Json::object([
+            ("name", symbol.name.as_str().into()),
+            ("detail", symbol.detail.as_str().into()),
+            ("kind", kind.into()),
+            ("range", self.range(symbol.range)),
+            ("selectionRange", self.range(symbol.range)),
+            (
+                "children",
+                symbol
+                    .children
+                    .iter()
+                    .map(|child| self.symbol(child))
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+        ])
+    }
+}
+
+/// A language server speaking JSON-RPC, handling one message at a time
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        ("result", result),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+fn error(id: &Json, code: i16, message: &str) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        (
+            "error",
+            Json::object([("code", code.into()), ("message", message.into())]),
+        ),
+    ])
+}
+
+impl Server {
+    fn publish_diagnostics(&self, uri: &str) -> Json {
+        let diagnostics = match self.documents.get(uri) {
+            Some(document) => document
+                .analysis
+                .get_diagnostics()
+                .iter()
+                .map(|diagnostic| {
+                    let severity: usize = match diagnostic.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    };
+                    Json::object([
+                        ("range", document.range(diagnostic.range)),
+                        ("severity", severity.into()),
+                        ("source", "cal".into()),
+                        ("message", diagnostic.message.as_str().into()),
+                    ])
+                })
+                .collect(),
+            None => vec![],
+        };
+        notification(
+            "textDocument/publishDiagnostics",
+            Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        )
+    }
+
+    /// Handles a notification about a document, returning the diagnostics
+    /// to publish
+    fn handle_notification(&mut self, method: &str, params: &Json) -> Option<Json> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        match method {
+            "textDocument/didOpen" => {
+                let code = params.get("textDocument")?.get("text")?.as_str()?;
+                self.documents
+                    .insert(uri.into(), Document::new(code.into()));
+            }
+            "textDocument/didChange" => {
+                // Documents are synchronized in full, the last change wins
+                let changes = params.get("contentChanges")?.as_array()?;
+                let code = changes.last()?.get("text")?.as_str()?;
+                self.documents
+                    .insert(uri.into(), Document::new(code.into()));
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+            }
+            _ => return None,
+        }
+        Some(self.publish_diagnostics(uri))
+    }
+
+    /// Handles a request about a document, returning its result
+    fn handle_document_request(&self, method: &str, params: &Json) -> Option<Json> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let document = self.documents.get(uri)?;
+        match method {
+            "textDocument/hover" => {
+                let (text, range) = document.analysis.hover(document.offset(params)?)?;
+                let contents = Json::object([
+                    ("kind", "markdown".into()),
+                    ("value", format!("```cal\n{}\n```", text).into()),
+                ]);
+                Some(Json::object([
+                    ("contents", contents),
+                    ("range", document.range(range)),
+                ]))
+            }
+            "textDocument/definition" => {
+                let range = document.analysis.definition(document.offset(params)?)?;
+                Some(Json::object([
+                    ("uri", uri.into()),
+                    ("range", document.range(range)),
+                ]))
+            }
+            "textDocument/documentSymbol" => Some(
+                document
+                    .analysis
+                    .symbols()
+                    .iter()
+                    .map(|symbol| document.symbol(symbol))
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Handles a message, returning the messages to send back
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let Some(id) = message.get("id") else {
+            return self
+                .handle_notification(method, params)
+                .into_iter()
+                .collect();
+        };
+
+        let result = match method {
+            "initialize" => Json::object([
+                (
+                    "capabilities",
+                    Json::object([
+                        ("textDocumentSync", 1usize.into()),
+                        ("hoverProvider", true.into()),
+                        ("definitionProvider", true.into()),
+                        ("documentSymbolProvider", true.into()),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Json::object([
+                        ("name", "cal-lsp".into()),
+                        ("version", env!("CARGO_PKG_VERSION").into()),
+                    ]),
+                ),
+            ]),
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/hover" | "textDocument/definition" | "textDocument/documentSymbol" => {
+                self.handle_document_request(method, params)
+                    .unwrap_or(Json::Null)
+            }
+            _ => return vec![error(id, -32601, &format!("Unknown method `{}`", method))],
+        };
+        vec![response(id, result)]
+    }
+}
+
+/// Reads a message framed by a `Content-Length` header, returning `None` at
+/// the end of the input
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            content_length = length.trim().parse().ok();
+        }
+    }
+
+    let mut content = vec![0; content_length.unwrap_or_default()];
+    reader.read_exact(&mut content)?;
+    Ok(Some(String::from_utf8_lossy(&content).into()))
+}
+
+fn write_message(writer: &mut impl Write, message: &Json) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
+
+/// Serves a single client over stdin and stdout, until it asks to exit
+fn main() -> io::Result<ExitCode> {
+    let mut server = Server::default();
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    while let Some(content) = read_message(&mut stdin)? {
+        let replies = match content.parse::<Json>() {
+            Ok(message) if message.get("method").and_then(Json::as_str) == Some("exit") => {
+                break;
+            }
+            Ok(message) => server.handle(&message),
+            Err(err) => vec![error(&Json::Null, -32700, &err.message)],
+        };
+        for reply in replies {
+            write_message(&mut stdout, &reply)?;
+        }
+    }
+    Ok(if server.shutdown {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,669 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{collections::HashMap, slice};
+
+use crate::{
+    error::CalError,
+    evaluator::Evaluator,
+    expression::Expression,
+    generator::Generator,
+    parser::Parser,
+    statement::Statement,
+    structure::{mangle, Constant, Function, Module, StructDec, Type, TypeAlias, Variable},
+    symboltable::SymbolTable,
+    tokenizer::{tokenize, Keyword, Range, Symbol, Token, TokenKind},
+    warning,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// An error or a warning found in the code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub range: Range,
+    pub severity: Severity,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Method,
+    Struct,
+    Field,
+    Constant,
+    Static,
+    TypeAlias,
+}
+
+/// An item declared by a module, as listed in the outline of a document
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub detail: String,
+    /// Range of the name of the item
+    pub range: Range,
+    pub children: Vec<DocumentSymbol>,
+}
+
+/// What an identifier refers to
+enum Definition<'a> {
+    /// A local variable, or a parameter when the flag is set
+    Variable(Variable, bool),
+    Function(&'a Function),
+    Constant(&'a Constant),
+    Struct(&'a StructDec),
+    Field(&'a StructDec, String),
+    TypeAlias(&'a TypeAlias),
+}
+
+/// Analyses a Cal document for editors, collecting all its errors and
+/// warnings and answering questions about the identifiers it contains
+#[derive(Default)]
+pub struct Analysis {
+    tokens: Vec<Token>,
+    /// Available only if the code could be parsed
+    module: Option<Module>,
+    /// Where structs, fields, constants and type aliases are declared, as
+    /// the parser does not keep their ranges. Fields are keyed as `Struct.field`.
+    declarations: HashMap<String, Range>,
+    /// Parameters and local variables, with the types resolved by the generator
+    variables: Vec<Variable>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Returns whether the offset is within the range, or right at its end
+fn contains(range: Range, offset: usize) -> bool {
+    range.start <= offset && offset <= range.end
+}
+
+/// Looks for the variable `name` visible at `offset`, walking the statements
+/// in order up to the one containing the offset. Returns `None` if the offset
+/// is not within these statements, otherwise whether the variable was found.
+fn find_in_statements(
+    statements: &[Statement],
+    name: &str,
+    offset: usize,
+    table: &mut SymbolTable,
+) -> Option<Option<Variable>> {
+    for statement in statements {
+        let found = match statement {
+            Statement::Expression(expr) | Statement::Return(Some(expr)) => {
+                find_in_expression(expr, name, offset, table)
+            }
+            Statement::Return(None) => None,
+            Statement::Let(variable, expr) => {
+                find_in_let(slice::from_ref(variable), expr, name, offset, table)
+            }
+            Statement::LetTuple(variables, expr) => {
+                find_in_let(variables, expr, name, offset, table)
+            }
+            Statement::If(if_stat) => find_in_expression(&if_stat.predicate, name, offset, table)
+                .or_else(|| find_in_statements(&if_stat.if_branch, name, offset, table))
+                .or_else(|| find_in_statements(&if_stat.else_branch, name, offset, table)),
+            Statement::While(while_stat) => {
+                find_in_expression(&while_stat.predicate, name, offset, table)
+                    .or_else(|| find_in_statements(&while_stat.body, name, offset, table))
+            }
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+fn find_in_expression(
+    expr: &Expression,
+    name: &str,
+    offset: usize,
+    table: &SymbolTable,
+) -> Option<Option<Variable>> {
+    contains(expr.range, offset).then(|| table.get(name).map(|entry| entry.variable.clone()))
+}
+
+/// Variables of a let are not visible within their own initializer
+fn find_in_let(
+    variables: &[Variable],
+    expr: &Expression,
+    name: &str,
+    offset: usize,
+    table: &mut SymbolTable,
+) -> Option<Option<Variable>> {
+    if let Some(found) = find_in_expression(expr, name, offset, table) {
+        return Some(found);
+    }
+    for variable in variables {
+        if contains(variable.range, offset) {
+            return Some((variable.name == name).then(|| variable.clone()));
+        }
+        table.insert_local(variable, 1);
+    }
+    None
+}
+
+/// Returns the variable `name` visible at `offset` within a function, and
+/// whether it is a parameter
+fn find_local(function: &Function, name: &str, offset: usize) -> Option<(Variable, bool)> {
+    let mut table = SymbolTable::default();
+    for parameter in &function.parameters {
+        if contains(parameter.range, offset) {
+            return (parameter.name == name).then(|| (parameter.clone(), true));
+        }
+        table.insert_argument(parameter, 1);
+    }
+    let variable = find_in_statements(&function.body_statements, name, offset, &mut table)??;
+    let is_parameter = function
+        .parameters
+        .iter()
+        .any(|parameter| parameter.range == variable.range);
+    Some((variable, is_parameter))
+}
+
+/// Finds where structs, their fields, constants, statics and type aliases are
+/// declared
+fn scan_declarations(tokens: &[Token]) -> HashMap<String, Range> {
+    let mut ret = HashMap::new();
+    for (i, pair) in tokens.windows(2).enumerate() {
+        let (TokenKind::Keyword(keyword), TokenKind::Identifier(name)) =
+            (&pair[0].value, &pair[1].value)
+        else {
+            continue;
+        };
+        match keyword {
+            Keyword::Const | Keyword::Static | Keyword::Type => {
+                ret.insert(name.clone(), pair[1].range);
+            }
+            Keyword::Struct => {
+                ret.insert(name.clone(), pair[1].range);
+                let mut depth = 0;
+                for (j, token) in tokens.iter().enumerate().skip(i + 2) {
+                    match &token.value {
+                        TokenKind::Symbol(
+                            Symbol::LeftBrace | Symbol::LeftParen | Symbol::LeftBracket,
+                        ) => depth += 1,
+                        TokenKind::Symbol(
+                            Symbol::RightBrace | Symbol::RightParen | Symbol::RightBracket,
+                        ) => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        TokenKind::Identifier(field)
+                            if depth == 1
+                                && matches!(
+                                    tokens.get(j + 1).map(|token| &token.value),
+                                    Some(TokenKind::Symbol(Symbol::Colon))
+                                ) =>
+                        {
+                            ret.insert(mangle(name, field), token.range);
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    ret
+}
+
+impl Analysis {
+    pub fn new(code: &str) -> Self {
+        let mut ret = Self::default();
+        let tokens = match tokenize(code) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                ret.error(err);
+                return ret;
+            }
+        };
+        ret.tokens = (*tokens).clone().collect();
+        ret.declarations = scan_declarations(&ret.tokens);
+
+        let module = match Parser::new(tokens).parse_module() {
+            Ok(module) => module,
+            Err(err) => {
+                ret.error(err);
+                return ret;
+            }
+        };
+        for warning in warning::check(&module) {
+            ret.diagnostics.push(Diagnostic {
+                message: warning.message,
+                range: warning.range,
+                severity: Severity::Warning,
+            });
+        }
+        let mut generator = Generator::default();
+        for err in generator.check_module(&module) {
+            ret.error(err);
+        }
+        ret.variables = generator.get_declarations().to_vec();
+        ret.module = Some(module);
+        ret
+    }
+
+    fn error(&mut self, err: CalError) {
+        self.diagnostics.push(Diagnostic {
+            message: err.message,
+            range: err.range,
+            severity: Severity::Error,
+        });
+    }
+
+    pub fn get_diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns the index of the token at the offset, preferring identifiers
+    /// when the offset is right between two tokens
+    fn token_at(&self, offset: usize) -> Option<usize> {
+        self.tokens
+            .iter()
+            .position(|token| token.range.start <= offset && offset < token.range.end)
+            .filter(|&i| matches!(self.tokens[i].value, TokenKind::Identifier(_)))
+            .or_else(|| {
+                self.tokens.iter().position(|token| {
+                    token.range.end == offset && matches!(token.value, TokenKind::Identifier(_))
+                })
+            })
+    }
+
+    fn token_value(&self, index: Option<usize>) -> Option<&TokenKind> {
+        index
+            .and_then(|i| self.tokens.get(i))
+            .map(|token| &token.value)
+    }
+
+    /// Returns the type of the closest `impl` block before a token
+    fn impl_type(&self, index: usize) -> Option<&str> {
+        let impl_index = self.tokens[..index]
+            .iter()
+            .rposition(|token| token.value == TokenKind::Keyword(Keyword::Impl))?;
+        match self.token_value(Some(impl_index + 1)) {
+            Some(TokenKind::Identifier(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the struct the identifier refers to, following
+    /// `Self` and type aliases
+    fn struct_name<'a>(&'a self, index: usize, name: &'a str) -> &'a str {
+        if name == "Self" {
+            return self.impl_type(index).unwrap_or(name);
+        }
+        let aliases = self.module.iter().flat_map(|module| &module.type_aliases);
+        for alias in aliases {
+            if let (true, Type::Struct(struct_name)) = (alias.name == name, &alias.typ) {
+                return struct_name;
+            }
+        }
+        name
+    }
+
+    /// Returns the struct of the block a token is in, if it is a struct
+    /// declaration or a struct literal
+    fn enclosing_struct(&self, index: usize) -> Option<&str> {
+        let mut depth = 0;
+        for i in (0..index).rev() {
+            match self.tokens[i].value {
+                TokenKind::Symbol(Symbol::RightBrace) => depth += 1,
+                TokenKind::Symbol(Symbol::LeftBrace) if depth > 0 => depth -= 1,
+                TokenKind::Symbol(Symbol::LeftBrace) => {
+                    let before = self.token_value(i.checked_sub(2));
+                    return match (self.token_value(i.checked_sub(1)), before) {
+                        (
+                            _,
+                            Some(
+                                TokenKind::Symbol(Symbol::RightArrow | Symbol::Ampersand)
+                                | TokenKind::Keyword(Keyword::Impl | Keyword::Mut),
+                            ),
+                        ) => None,
+                        (Some(TokenKind::Identifier(name)), _) => Some(self.struct_name(i, name)),
+                        _ => None,
+                    };
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Returns the function whose definition contains the offset
+    fn enclosing_function(&self, offset: usize) -> Option<&Function> {
+        self.module
+            .as_ref()?
+            .functions
+            .iter()
+            .filter(|function| function.range.start <= offset)
+            .max_by_key(|function| function.range.start)
+    }
+
+    /// Returns the variable visible at the offset, with its resolved type
+    fn find_variable(&self, name: &str, offset: usize) -> Option<(Variable, bool)> {
+        let function = self.enclosing_function(offset)?;
+        let (variable, is_parameter) = find_local(function, name, offset)?;
+        let resolved = self
+            .variables
+            .iter()
+            .find(|resolved| resolved.range == variable.range && resolved.name == variable.name)
+            .cloned()
+            .unwrap_or(variable);
+        Some((resolved, is_parameter))
+    }
+
+    /// Resolves what the identifier at the offset refers to, returning also
+    /// the range of the identifier
+    fn resolve(&self, offset: usize) -> Option<(Definition<'_>, Range)> {
+        let module = self.module.as_ref()?;
+        let index = self.token_at(offset)?;
+        let token = &self.tokens[index];
+        let TokenKind::Identifier(name) = &token.value else {
+            return None;
+        };
+        let previous = self.token_value(index.checked_sub(1));
+        let next = self.token_value(Some(index + 1));
+
+        let definition = match previous {
+            Some(TokenKind::Symbol(Symbol::Dot)) => {
+                let is_call = next == Some(&TokenKind::Symbol(Symbol::LeftParen));
+                self.resolve_member(module, index, name, is_call)
+            }
+            Some(TokenKind::Symbol(Symbol::DoubleColon)) => {
+                let Some(TokenKind::Identifier(type_name)) = self.token_value(index.checked_sub(2))
+                else {
+                    return None;
+                };
+                let function_name = mangle(self.struct_name(index, type_name), name);
+                module
+                    .functions
+                    .iter()
+                    .find(|function| function.name == function_name)
+                    .map(Definition::Function)
+            }
+            _ => self.resolve_name(module, index, name, next),
+        };
+        Some((definition?, token.range))
+    }
+
+    /// Resolves a method or a field, looking at the type of the receiver when
+    /// it is a variable
+    fn resolve_member<'a>(
+        &self,
+        module: &'a Module,
+        index: usize,
+        name: &str,
+        is_call: bool,
+    ) -> Option<Definition<'a>> {
+        let receiver = match index.checked_sub(2).map(|i| &self.tokens[i]) {
+            Some(Token {
+                value: TokenKind::Identifier(receiver),
+                range,
+            }) => self.find_variable(receiver, range.start),
+            _ => None,
+        };
+        let struct_name = receiver.and_then(|(variable, _)| {
+            match variable.typ.get_pointee().unwrap_or(&variable.typ) {
+                Type::Struct(struct_name) => Some(struct_name.clone()),
+                _ => None,
+            }
+        });
+
+        if is_call {
+            let suffix = mangle("", name);
+            module
+                .functions
+                .iter()
+                .find(|function| match &struct_name {
+                    Some(struct_name) => function.name == mangle(struct_name, name),
+                    None => function.name.ends_with(&suffix),
+                })
+                .map(Definition::Function)
+        } else {
+            module
+                .structs
+                .iter()
+                .find(|struct_dec| {
+                    struct_name
+                        .as_ref()
+                        .is_none_or(|struct_name| struct_dec.name == *struct_name)
+                        && struct_dec.get_field(name).is_some()
+                })
+                .map(|struct_dec| Definition::Field(struct_dec, name.into()))
+        }
+    }
+
+    /// Resolves a plain identifier, which may be a field in a struct literal
+    /// or declaration, a local variable, or an item of the module
+    fn resolve_name<'a>(
+        &self,
+        module: &'a Module,
+        index: usize,
+        name: &str,
+        next: Option<&TokenKind>,
+    ) -> Option<Definition<'a>> {
+        let offset = self.tokens[index].range.start;
+        if next == Some(&TokenKind::Symbol(Symbol::Colon)) {
+            let struct_dec = self.enclosing_struct(index).and_then(|struct_name| {
+                module
+                    .structs
+                    .iter()
+                    .find(|struct_dec| struct_dec.name == struct_name)
+            });
+            if let Some(struct_dec) = struct_dec.filter(|s| s.get_field(name).is_some()) {
+                return Some(Definition::Field(struct_dec, name.into()));
+            }
+        }
+        if let Some((variable, is_parameter)) = self.find_variable(name, offset) {
+            return Some(Definition::Variable(variable, is_parameter));
+        }
+
+        let struct_name = self.struct_name(index, name);
+        if let Some(function) = module.functions.iter().find(|f| f.name == name) {
+            Some(Definition::Function(function))
+        } else if let Some(constant) = module.constants.iter().find(|c| c.name == name) {
+            Some(Definition::Constant(constant))
+        } else if let Some(alias) = module.type_aliases.iter().find(|a| a.name == name) {
+            Some(Definition::TypeAlias(alias))
+        } else {
+            module
+                .structs
+                .iter()
+                .find(|struct_dec| struct_dec.name == struct_name)
+                .map(Definition::Struct)
+        }
+    }
+
+    /// Returns the value of a constant, if it can be evaluated
+    fn eval_constant(&self, module: &Module, constant: &Constant) -> Option<i16> {
+        let constants = module
+            .constants
+            .iter()
+            .map(|constant| (constant.name.clone(), constant.clone()))
+            .collect();
+        let functions = module
+            .functions
+            .iter()
+            .map(|function| (function.name.clone(), function.clone()))
+            .collect();
+        let structs = module
+            .structs
+            .iter()
+            .map(|struct_dec| (struct_dec.name.clone(), struct_dec.clone()))
+            .collect();
+        Evaluator::new(&constants, &functions, &structs, None)
+            .eval_expression(&constant.value)
+            .ok()
+    }
+
+    /// Returns the declaration of the identifier at the offset, written in
+    /// Cal, and the range of the identifier
+    pub fn hover(&self, offset: usize) -> Option<(String, Range)> {
+        let (definition, range) = self.resolve(offset)?;
+        let text = match definition {
+            Definition::Variable(variable, is_parameter) => {
+                let mut text = format!(
+                    "{}{}{}",
+                    if is_parameter { "" } else { "let " },
+                    if variable.mutable { "mut " } else { "" },
+                    variable.name
+                );
+                if variable.typ != Type::Void {
+                    text += &format!(": {}", variable.typ);
+                }
+                text
+            }
+            Definition::Function(function) => function.signature(),
+            Definition::Constant(constant) => {
+                let module = self.module.as_ref()?;
+                match self.eval_constant(module, constant) {
+                    Some(value) => format!("const {}: {} = {}", constant.name, constant.typ, value),
+                    None => format!("const {}: {}", constant.name, constant.typ),
+                }
+            }
+            Definition::Struct(struct_dec) => {
+                let fields: Vec<String> = struct_dec
+                    .fields
+                    .iter()
+                    .map(|field| format!("{}: {}", field.name, field.typ))
+                    .collect();
+                format!("struct {} {{ {} }}", struct_dec.name, fields.join(", "))
+            }
+            Definition::Field(struct_dec, name) => {
+                let field = struct_dec.get_field(&name)?;
+                format!("{}\n{}: {}", struct_dec.name, field.name, field.typ)
+            }
+            Definition::TypeAlias(alias) => format!("type {} = {}", alias.name, alias.typ),
+        };
+        Some((text, range))
+    }
+
+    /// Returns the range where the identifier at the offset is declared
+    pub fn definition(&self, offset: usize) -> Option<Range> {
+        let (definition, _) = self.resolve(offset)?;
+        match definition {
+            Definition::Variable(variable, _) => Some(variable.range),
+            Definition::Function(function) => Some(function.range),
+            Definition::Constant(Constant { name, .. })
+            | Definition::Struct(StructDec { name, .. })
+            | Definition::TypeAlias(TypeAlias { name, .. }) => self.declarations.get(name).copied(),
+            Definition::Field(struct_dec, name) => self
+                .declarations
+                .get(&mangle(&struct_dec.name, &name))
+                .copied(),
+        }
+    }
+
+    /// Returns the items declared by the module, in order of declaration
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        let Some(module) = &self.module else {
+            return vec![];
+        };
+        let declared = |name: &str| self.declarations.get(name).copied().unwrap_or_default();
+        let symbol = |name: &str, kind, detail: String, range| DocumentSymbol {
+            name: name.into(),
+            kind,
+            detail,
+            range,
+            children: vec![],
+        };
+
+        let mut ret = vec![];
+        for struct_dec in &module.structs {
+            let mut struct_symbol = symbol(
+                &struct_dec.name,
+                SymbolKind::Struct,
+                String::new(),
+                declared(&struct_dec.name),
+            );
+            struct_symbol.children = struct_dec
+                .fields
+                .iter()
+                .map(|field| {
+                    symbol(
+                        &field.name,
+                        SymbolKind::Field,
+                        field.typ.to_string(),
+                        declared(&mangle(&struct_dec.name, &field.name)),
+                    )
+                })
+                .collect();
+            ret.push(struct_symbol);
+        }
+        for constant in &module.constants {
+            let detail = constant.typ.to_string();
+            ret.push(symbol(
+                &constant.name,
+                SymbolKind::Constant,
+                detail,
+                declared(&constant.name),
+            ));
+        }
+        for static_item in &module.statics {
+            let detail = static_item.typ.to_string();
+            ret.push(symbol(
+                &static_item.name,
+                SymbolKind::Static,
+                detail,
+                declared(&static_item.name),
+            ));
+        }
+        for alias in &module.type_aliases {
+            let detail = alias.typ.to_string();
+            ret.push(symbol(
+                &alias.name,
+                SymbolKind::TypeAlias,
+                detail,
+                declared(&alias.name),
+            ));
+        }
+        for function in &module.functions {
+            let kind = if function.name.contains('.') {
+                SymbolKind::Method
+            } else {
+                SymbolKind::Function
+            };
+            let name = function.name.replace('.', "::");
+            ret.push(symbol(&name, kind, function.signature(), function.range));
+        }
+        ret.sort_by_key(|symbol| symbol.range.start);
+        ret
+    }
+}
+
+/// Converts an offset in the code into a zero-based line and a character
+/// within the line, counted in UTF-16 code units as editors do
+pub fn offset_to_position(code: &str, offset: usize) -> (usize, usize) {
+    let before = &code[..offset.min(code.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character = before[line_start..].encode_utf16().count();
+    (line, character)
+}
+
+/// Converts a zero-based line and a character in UTF-16 code units into an
+/// offset in the code, clamping positions past the end of a line
+pub fn position_to_offset(code: &str, line: usize, character: usize) -> usize {
+    let line_start: usize = code
+        .split_inclusive('\n')
+        .take(line)
+        .map(|line| line.len())
+        .sum();
+    let mut units = 0;
+    for (i, c) in code[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    code.len()
+}
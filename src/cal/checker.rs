@@ -9,6 +9,7 @@ use crate::{
     generator::generate,
     parser::Parser,
     tokenizer::{Range, Tokenize},
+    warning::{self, CalWarning},
 };
 
 #[derive(Copy, Clone, Default)]
@@ -58,12 +59,44 @@ impl From<CalError> for JsCalError {
     }
 }
 
+impl From<CalWarning> for JsCalError {
+    fn from(warning: CalWarning) -> Self {
+        CalError::from(warning).into()
+    }
+}
+
+/// Warnings found by the checker, which are reported with the same
+/// message and range of an error
+#[wasm_bindgen]
+pub struct JsCalWarnings {
+    warnings: Vec<JsCalError>,
+}
+
+#[wasm_bindgen]
+impl JsCalWarnings {
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.warnings.len()
+    }
+
+    pub fn get(&self, index: usize) -> JsCalError {
+        self.warnings[index].clone()
+    }
+}
+
 /// The checker combines both the tokenizer and the parser.
 /// This is useful for using this in the Cal Language Server.
+/// When warnings are denied, the first warning is returned as an error.
 #[wasm_bindgen]
-pub fn check(code: &str) -> Result<(), JsCalError> {
+pub fn check(code: &str, deny_warnings: bool) -> Result<JsCalWarnings, JsCalError> {
     let tokens = code.tokenize()?;
     let module = Parser::new(tokens).parse_module()?;
+    let mut warnings = warning::check(&module);
     generate(module)?;
-    Ok(())
+    if deny_warnings && !warnings.is_empty() {
+        return Err(warnings.remove(0).into());
+    }
+    Ok(JsCalWarnings {
+        warnings: warnings.into_iter().map(JsCalError::from).collect(),
+    })
 }
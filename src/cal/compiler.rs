@@ -2,16 +2,156 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
+use std::str::FromStr;
+
 use crate::{
-    asm::instruction::AsmInstruction, error::CalError, generator::Generator, parser::parse,
-    tokenizer::tokenize, VmTranslator,
+    asm::{instruction::AsmInstruction, source_map::SourceMap},
+    error::CalError,
+    generator::Generator,
+    hack::HackGenerator,
+    parser::parse,
+    stdlib,
+    structure::Module,
+    tokenizer::{tokenize, Range},
+    warning::{self, CalWarning},
+    VmTranslator,
 };
 
-/// Compiles Cal source code and returns a series of asm instructions
-pub fn compile(input: &str) -> Result<Vec<AsmInstruction>, CalError> {
+/// How VM instructions are lowered to asm instructions
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Through the stack in memory, by `VmTranslator`
+    #[default]
+    Vm,
+    /// With values held in registers, by `HackGenerator`
+    Hack,
+}
+
+impl FromStr for Backend {
+    type Err = CalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vm" => Ok(Backend::Vm),
+            "hack" => Ok(Backend::Hack),
+            _ => Err(CalError::new(
+                format!("Unknown backend `{}`, expected vm or hack", s),
+                Range::default(),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CompileOptions {
+    /// Turns the first warning into an error
+    pub deny_warnings: bool,
+    /// Libraries shipped with the compiler to link into the program
+    pub link: Vec<String>,
+    /// Lowering of the VM instructions generated from the code
+    pub backend: Backend,
+    /// Level of the optimisations run on the intermediate representation,
+    /// from `0` for none to `2` for all of them
+    pub opt_level: u8,
+}
+
+/// Result of a successful compilation
+pub struct Compilation {
+    pub instructions: Vec<AsmInstruction>,
+    pub warnings: Vec<CalWarning>,
+    /// Functions left out as they are never called
+    pub pruned: Vec<String>,
+    /// Origin of each instruction in ROM, which is only known for the `Vm`
+    /// backend
+    pub source_map: SourceMap,
+    /// Functions rewritten by the optimiser, which the source map only
+    /// knows by their declaration
+    pub rewritten: Vec<String>,
+}
+
+/// Names of the items declared by a module
+fn item_names(module: &Module) -> impl Iterator<Item = &String> {
+    module
+        .functions
+        .iter()
+        .map(|function| &function.name)
+        .chain(module.constants.iter().map(|constant| &constant.name))
+        .chain(module.statics.iter().map(|static_item| &static_item.name))
+        .chain(module.structs.iter().map(|struct_dec| &struct_dec.name))
+        .chain(module.type_aliases.iter().map(|alias| &alias.name))
+}
+
+/// Appends a library to the linked modules, after the libraries it depends
+/// on, making sure none of its items is already declared
+fn link_library(modules: &mut Vec<Module>, name: &str) -> Result<(), CalError> {
+    if modules.iter().any(|module| module.name == name) {
+        return Ok(());
+    }
+    for dependency in stdlib::dependencies(name) {
+        link_library(modules, dependency)?;
+    }
+    let library = stdlib::library(name)?;
+    for item in item_names(&library) {
+        if let Some(other) = modules
+            .iter()
+            .find(|module| item_names(module).any(|other| other == item))
+        {
+            return Err(CalError::new(
+                format!(
+                    "`{}` is declared by both `{}` and library `{}`",
+                    item, other.name, name
+                ),
+                Range::default(),
+            ));
+        }
+    }
+    modules.push(library);
+    Ok(())
+}
+
+/// Returns the program module followed by the libraries it links, and the
+/// ones they depend on, making sure no item is declared by more than one of
+/// them
+pub fn link(module: Module, libraries: &[String]) -> Result<Vec<Module>, CalError> {
+    let mut modules = vec![module];
+    for name in libraries {
+        link_library(&mut modules, name)?;
+    }
+    Ok(modules)
+}
+
+/// Compiles Cal source code and returns a series of asm instructions, along
+/// with the warnings found in the code
+pub fn compile(input: &str, options: &CompileOptions) -> Result<Compilation, CalError> {
     let module = parse(tokenize(input)?)?;
-    let vm_instructions = Generator::default().gen(&[module])?;
-    Ok(VmTranslator::default().translate(vm_instructions))
+    let mut warnings = warning::check(&module);
+    let mut generator = Generator::default().with_opt_level(options.opt_level);
+    let vm_instructions = generator.gen(&link(module, &options.link)?)?;
+    if options.deny_warnings && !warnings.is_empty() {
+        return Err(warnings.remove(0).into());
+    }
+
+    let data = generator.get_data().clone();
+    let mut source_map = SourceMap::default();
+    Ok(Compilation {
+        instructions: match options.backend {
+            Backend::Vm => {
+                let mut translator = VmTranslator::default()
+                    .with_data(data)
+                    .with_origins(generator.get_origins().to_vec());
+                let instructions = translator.translate(vm_instructions);
+                source_map = translator.get_source_map().clone();
+                instructions
+            }
+            Backend::Hack => HackGenerator::default()
+                .with_data(data)
+                .gen(&vm_instructions),
+        },
+        warnings,
+        pruned: generator.get_pruned().to_vec(),
+        source_map,
+        rewritten: generator.get_rewritten().to_vec(),
+    })
 }
 
 pub trait Compile {
@@ -21,6 +161,6 @@ pub trait Compile {
 
 impl Compile for str {
     fn compile(&self) -> Result<Vec<AsmInstruction>, CalError> {
-        compile(self)
+        Ok(compile(self, &CompileOptions::default())?.instructions)
     }
 }
@@ -0,0 +1,71 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::{HashMap, HashSet};
+
+use crate::vm::instruction::VmInstruction;
+
+/// Instructions of a function, from its declaration to the next one
+struct Chunk {
+    /// Name of the function, or `None` for the instructions before the first
+    /// function, which are always executed
+    name: Option<String>,
+    instructions: Vec<VmInstruction>,
+}
+
+fn split(instructions: Vec<VmInstruction>) -> Vec<Chunk> {
+    let mut chunks = vec![Chunk {
+        name: None,
+        instructions: vec![],
+    }];
+    for instruction in instructions {
+        if let VmInstruction::Function(name, _) = &instruction {
+            chunks.push(Chunk {
+                name: Some(name.clone()),
+                instructions: vec![],
+            });
+        }
+        chunks.last_mut().unwrap().instructions.push(instruction);
+    }
+    chunks
+}
+
+/// Names of the functions called by some instructions
+fn callees(instructions: &[VmInstruction]) -> impl Iterator<Item = &String> {
+    instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            VmInstruction::Call(name, _) => Some(name),
+            _ => None,
+        })
+}
+
+/// Removes the functions which can not be reached by following the calls
+/// from the instructions before the first function, which call `main`.
+/// Returns the remaining instructions and the names of the removed functions
+pub fn eliminate_dead_code(instructions: Vec<VmInstruction>) -> (Vec<VmInstruction>, Vec<String>) {
+    let chunks = split(instructions);
+    let call_graph: HashMap<&String, Vec<&String>> = chunks
+        .iter()
+        .filter_map(|chunk| Some((chunk.name.as_ref()?, callees(&chunk.instructions).collect())))
+        .collect();
+
+    let mut reachable: HashSet<&String> = HashSet::new();
+    let mut pending: Vec<&String> = callees(&chunks[0].instructions).collect();
+    while let Some(name) = pending.pop() {
+        if reachable.insert(name) {
+            pending.extend(call_graph.get(name).into_iter().flatten());
+        }
+    }
+
+    let mut ret = vec![];
+    let mut pruned = vec![];
+    for chunk in &chunks {
+        match &chunk.name {
+            Some(name) if !reachable.contains(name) => pruned.push(name.clone()),
+            _ => ret.extend(chunk.instructions.iter().cloned()),
+        }
+    }
+    (ret, pruned)
+}
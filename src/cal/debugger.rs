@@ -0,0 +1,538 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Source-level debugging of Cal programs, running on the simulated
+//! computer or on the VM emulator. Programs are compiled without
+//! optimisations, so that every variable lives in the frame of its function
+
+use std::collections::HashMap;
+
+use crate::{
+    asm::source_map::{Origin, SourceMap},
+    compiler::link,
+    error::CalError,
+    generator::Generator,
+    interpreter::{Interpreter, Value},
+    parser::{parse, Parser},
+    segment::Segment,
+    structure::{Type, Variable},
+    symboltable::SymbolTable,
+    tokenizer::{tokenize, Range},
+    vm::{instruction::VmInstruction, translator::VmTranslator},
+    Computer, VmEmulator,
+};
+
+/// Words of RAM, holding the stack and the data section
+const RAM_SIZE: usize = 16384;
+
+/// Base of the stack, where `main` leaves the value it returns
+const STACK_BASE: u16 = 256;
+
+/// A machine running a program, which the debugger drives one instruction
+/// at a time
+pub trait Target: Sized {
+    /// Loads the VM instructions generated for a program, returning the
+    /// target along with the origin of the code it runs
+    fn load(instructions: Vec<VmInstruction>, generator: &Generator) -> (Self, SourceMap);
+
+    /// Executes the next instruction
+    fn step(&mut self);
+
+    /// Position of the next instruction, in the code the source map is for
+    fn get_position(&self) -> usize;
+
+    /// Position of the call which saved a return address in a frame
+    fn get_call_position(&self, return_address: usize) -> usize;
+
+    fn read(&self, address: usize) -> i16;
+}
+
+impl Target for Computer {
+    fn load(instructions: Vec<VmInstruction>, generator: &Generator) -> (Self, SourceMap) {
+        let mut translator = VmTranslator::default()
+            .with_data(generator.get_data().clone())
+            .with_origins(generator.get_origins().to_vec());
+        let mut computer = Computer::default();
+        computer.set_instructions(translator.translate(instructions));
+        (computer, translator.get_source_map().clone())
+    }
+
+    fn step(&mut self) {
+        self.ticktock();
+    }
+
+    fn get_position(&self) -> usize {
+        self.get_pc()
+    }
+
+    /// Calls return right after their jump to the function
+    fn get_call_position(&self, return_address: usize) -> usize {
+        return_address.saturating_sub(1)
+    }
+
+    fn read(&self, address: usize) -> i16 {
+        self.get_memory().ram[address]
+    }
+}
+
+impl Target for VmEmulator {
+    fn load(instructions: Vec<VmInstruction>, generator: &Generator) -> (Self, SourceMap) {
+        let mut emulator = VmEmulator::default();
+        emulator.load_data(generator.get_data());
+        emulator.load(instructions);
+        let mut source_map = SourceMap::default();
+        for origin in generator.get_origins() {
+            source_map.push(Some(origin.clone()));
+        }
+        (emulator, source_map)
+    }
+
+    fn step(&mut self) {
+        VmEmulator::step(self);
+    }
+
+    fn get_position(&self) -> usize {
+        self.get_instruction_index()
+    }
+
+    /// The emulator saves the index of the call instruction itself
+    fn get_call_position(&self, return_address: usize) -> usize {
+        return_address
+    }
+
+    fn read(&self, address: usize) -> i16 {
+        self.ram[address]
+    }
+}
+
+/// Where to stop the program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// At the statements starting on a line, counted from 1
+    Line(usize),
+    /// When entering a function
+    Function(String),
+}
+
+/// How far to run the program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Until a breakpoint, or the end of the program
+    Continue,
+    /// To the next statement, entering functions called
+    In,
+    /// To the next statement of the current function or of its callers
+    Over,
+    /// To the next statement after the current function returns
+    Out,
+}
+
+/// Why the program stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// At the statement a step was for
+    Step,
+    /// At the breakpoint with this index
+    Breakpoint(usize),
+    /// After `main` returned this value
+    Finished(Value),
+}
+
+/// A call being executed, as found on the stack
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Statement being executed, which for callers is the call
+    pub origin: Origin,
+    /// Base address of the local segment
+    pub local: u16,
+    /// Base address of the argument segment
+    pub argument: u16,
+}
+
+/// Runs a program on a target, stopping at its statements and breakpoints,
+/// and decodes the variables of the functions being executed
+pub struct Debugger<T: Target> {
+    target: T,
+    source_map: SourceMap,
+    /// Whether each position is the first of a statement
+    starts: Vec<bool>,
+    /// Offset of each line of the code
+    lines: Vec<usize>,
+
+    /// Range of the name of each function of the program, which is also
+    /// the origin of its entry and of its implicit return
+    functions: HashMap<String, Range>,
+    /// Position of the entry of each function of the program
+    entries: HashMap<String, usize>,
+    /// Symbol table of each function of the program
+    frames: HashMap<String, SymbolTable>,
+    return_type: Type,
+    /// Decodes values and evaluates expressions on a copy of the RAM
+    interpreter: Interpreter,
+
+    breakpoints: Vec<Breakpoint>,
+    /// Expressions evaluated whenever the program stops
+    watches: Vec<String>,
+    finished: bool,
+}
+
+impl<T: Target> Debugger<T> {
+    /// Compiles a program along with the libraries it links, and loads it
+    /// into a new target
+    pub fn new(code: &str, libraries: &[String]) -> Result<Self, CalError> {
+        let module = parse(tokenize(code)?)?;
+        let functions: HashMap<String, Range> = module
+            .functions
+            .iter()
+            .map(|function| (function.name.clone(), function.range))
+            .collect();
+        let return_type = module
+            .functions
+            .iter()
+            .find(|function| function.name == "main")
+            .map_or(Type::Void, |main| main.return_type.clone());
+
+        let modules = link(module, libraries)?;
+        let mut interpreter = Interpreter::default();
+        for module in &modules {
+            interpreter.load(module)?;
+        }
+        let mut generator = Generator::default();
+        let instructions = generator.gen(&modules)?;
+        let frames = functions
+            .keys()
+            .filter_map(|name| Some((name.clone(), generator.get_frame(name)?.clone())))
+            .collect();
+
+        let (target, source_map) = T::load(instructions, &generator);
+        let starts = (0..source_map.len())
+            .map(|position| {
+                position == 0 || source_map.get(position - 1) != source_map.get(position)
+            })
+            .collect();
+        let entries = functions
+            .keys()
+            .filter_map(|name| Some((name.clone(), source_map.addresses(name).next()?)))
+            .collect();
+        let lines = std::iter::once(0)
+            .chain(code.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Ok(Self {
+            target,
+            source_map,
+            starts,
+            lines,
+            functions,
+            entries,
+            frames,
+            return_type,
+            interpreter,
+            breakpoints: vec![],
+            watches: vec![],
+            finished: false,
+        })
+    }
+
+    pub fn get_target(&self) -> &T {
+        &self.target
+    }
+
+    pub fn get_source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    /// Returns the line, counted from 1, where a range of the code starts
+    pub fn get_line(&self, range: Range) -> usize {
+        self.lines.partition_point(|start| *start <= range.start)
+    }
+
+    /// Returns the origin of the next instruction to execute
+    pub fn get_origin(&self) -> Option<&Origin> {
+        self.source_map.get(self.target.get_position())
+    }
+
+    /// Whether an origin is a statement of the program, rather than the
+    /// entry of a function or code of a library
+    fn is_statement(&self, origin: &Origin) -> bool {
+        self.functions
+            .get(&origin.function)
+            .is_some_and(|entry| origin.range != *entry && origin.range.end > origin.range.start)
+    }
+
+    /// Adds a breakpoint, returning its index. Lines need to have statements
+    /// and functions need to be part of the program
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> Result<usize, CalError> {
+        match &breakpoint {
+            Breakpoint::Line(line) => {
+                let found = (0..self.source_map.len()).any(|position| {
+                    self.source_map.get(position).is_some_and(|origin| {
+                        self.is_statement(origin) && self.get_line(origin.range) == *line
+                    })
+                });
+                if !found {
+                    return Err(CalError::new(
+                        format!("No statement at line {}", line),
+                        Range::default(),
+                    ));
+                }
+            }
+            Breakpoint::Function(name) => {
+                if !self.entries.contains_key(name) {
+                    return Err(CalError::new(
+                        format!("Unknown function `{}`", name),
+                        Range::default(),
+                    ));
+                }
+            }
+        }
+        self.breakpoints.push(breakpoint);
+        Ok(self.breakpoints.len() - 1)
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Result<Breakpoint, CalError> {
+        if index >= self.breakpoints.len() {
+            return Err(CalError::new(
+                format!("No breakpoint {}", index),
+                Range::default(),
+            ));
+        }
+        Ok(self.breakpoints.remove(index))
+    }
+
+    pub fn get_breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Adds an expression to evaluate whenever the program stops, returning
+    /// its index
+    pub fn add_watch(&mut self, expression: &str) -> Result<usize, CalError> {
+        Parser::new(tokenize(expression)?).parse_expression(false)?;
+        self.watches.push(expression.to_string());
+        Ok(self.watches.len() - 1)
+    }
+
+    pub fn remove_watch(&mut self, index: usize) -> Result<String, CalError> {
+        if index >= self.watches.len() {
+            return Err(CalError::new(
+                format!("No watch {}", index),
+                Range::default(),
+            ));
+        }
+        Ok(self.watches.remove(index))
+    }
+
+    pub fn get_watches(&self) -> &[String] {
+        &self.watches
+    }
+
+    fn find_breakpoint(&self, hit: impl Fn(&Breakpoint) -> bool) -> Option<usize> {
+        self.breakpoints.iter().position(hit)
+    }
+
+    /// Runs the program until it reaches the start of a statement where
+    /// the step ends, or hits a breakpoint, or `main` returns. Steps leaving
+    /// a function end in the statement of the caller it returns to
+    pub fn run(&mut self, step: Step) -> Stop {
+        if self.finished {
+            return Stop::Finished(self.get_result());
+        }
+        // Nothing is running before `main` is called
+        let depth = self.get_backtrace().len();
+
+        loop {
+            let previous = self.target.get_position();
+            self.target.step();
+            let position = self.target.get_position();
+            let Some(origin) = self.source_map.get(position) else {
+                continue;
+            };
+            let before = self.source_map.get(previous);
+            if before == Some(origin) {
+                continue;
+            }
+
+            // The prologue calls `main`, and loops forever once it returns
+            if origin.function.is_empty() {
+                if before.is_some_and(|before| !before.function.is_empty()) {
+                    self.finished = true;
+                    return Stop::Finished(self.get_result());
+                }
+                continue;
+            }
+
+            // The entry of a function without locals is already a statement
+            if let Some(index) = self.find_breakpoint(|breakpoint| match breakpoint {
+                Breakpoint::Function(name) => self.entries.get(name) == Some(&position),
+                Breakpoint::Line(_) => false,
+            }) {
+                return Stop::Breakpoint(index);
+            }
+            if !self.is_statement(origin) {
+                continue;
+            }
+
+            // Returning into the middle of a statement of a caller only ends
+            // a step leaving the function it started in
+            if !self.starts[position] {
+                if step != Step::Continue && self.get_backtrace().len() < depth {
+                    return Stop::Step;
+                }
+                continue;
+            }
+            let line = self.get_line(origin.range);
+            if let Some(index) =
+                self.find_breakpoint(|breakpoint| *breakpoint == Breakpoint::Line(line))
+            {
+                return Stop::Breakpoint(index);
+            }
+
+            let stop = match step {
+                Step::Continue => false,
+                _ if depth == 0 => true,
+                Step::In => true,
+                Step::Over => self.get_backtrace().len() <= depth,
+                Step::Out => self.get_backtrace().len() < depth,
+            };
+            if stop {
+                return Stop::Step;
+            }
+        }
+    }
+
+    /// Reconstructs the calls being executed, from the innermost one to
+    /// `main`, following the return address, `LCL` and `ARG` each call
+    /// saves on the stack right below the locals of the function called
+    pub fn get_backtrace(&self) -> Vec<Frame> {
+        let mut ret = vec![];
+        let mut position = self.target.get_position();
+        let mut local = self.target.read(Segment::Local.get_base_address()) as u16;
+        let mut argument = self.target.read(Segment::Argument.get_base_address()) as u16;
+        while let Some(origin) = self.source_map.get(position) {
+            if origin.function.is_empty() || local < 5 {
+                break;
+            }
+            ret.push(Frame {
+                origin: origin.clone(),
+                local,
+                argument,
+            });
+            let frame = local as usize;
+            let return_address = self.target.read(frame - 5) as u16 as usize;
+            position = self.target.get_call_position(return_address);
+            argument = self.target.read(frame - 3) as u16;
+            local = self.target.read(frame - 4) as u16;
+
+            // Frames of callers are below, anything else is not a frame
+            if local as usize >= frame {
+                break;
+            }
+        }
+        ret
+    }
+
+    /// Copies the RAM of the target into the memory of the interpreter
+    fn sync(&mut self) {
+        let memory = self.interpreter.get_memory_mut();
+        for (address, word) in memory.iter_mut().take(RAM_SIZE).enumerate() {
+            *word = self.target.read(address);
+        }
+    }
+
+    /// Variables of a segment of a frame, along with their addresses. Locals
+    /// are those declared before the statement being executed, and are not
+    /// shadowed by later ones
+    fn get_variables(&self, frame: &Frame, segment: Segment) -> Vec<(Variable, u16)> {
+        let Some(symbol_table) = self.frames.get(&frame.origin.function) else {
+            return vec![];
+        };
+        let (base, end) = match segment {
+            Segment::Argument => (frame.argument, usize::MAX),
+            _ => (frame.local, frame.origin.range.start),
+        };
+        let entries: Vec<_> = symbol_table
+            .get_entries()
+            .iter()
+            .filter(|entry| entry.segment == segment && entry.variable.range.start < end)
+            .collect();
+        entries
+            .iter()
+            .enumerate()
+            .filter(|(i, entry)| {
+                let name = &entry.variable.name;
+                !entries[i + 1..]
+                    .iter()
+                    .any(|later| later.variable.name == *name)
+            })
+            .map(|(_, entry)| (entry.variable.clone(), base + entry.offset))
+            .collect()
+    }
+
+    /// Reads the values of the variables of a segment of a frame
+    fn read_variables(
+        &mut self,
+        frame: &Frame,
+        segment: Segment,
+    ) -> Result<Vec<(Variable, Value)>, CalError> {
+        self.sync();
+        self.get_variables(frame, segment)
+            .into_iter()
+            .map(|(variable, address)| {
+                let value = self.interpreter.read_value(&variable.typ, address)?;
+                Ok((variable, value))
+            })
+            .collect()
+    }
+
+    /// Returns the arguments of the function of a frame, with their values
+    pub fn get_arguments(&mut self, frame: &Frame) -> Result<Vec<(Variable, Value)>, CalError> {
+        self.read_variables(frame, Segment::Argument)
+    }
+
+    /// Returns the local variables of a frame in scope, with their values
+    pub fn get_locals(&mut self, frame: &Frame) -> Result<Vec<(Variable, Value)>, CalError> {
+        self.read_variables(frame, Segment::Local)
+    }
+
+    /// Evaluates a Cal expression in a frame, which can read its variables
+    /// and the statics, and call functions without affecting the program
+    pub fn evaluate(&mut self, expression: &str, frame: &Frame) -> Result<Value, CalError> {
+        let expr = Parser::new(tokenize(expression)?).parse_expression(false)?;
+        self.sync();
+        let mut variables = self.get_variables(frame, Segment::Argument);
+        variables.extend(self.get_variables(frame, Segment::Local));
+        let stack_pointer = self.target.read(Segment::Stack.get_base_address()) as u16;
+        self.interpreter.eval_bound(variables, stack_pointer, &expr)
+    }
+
+    /// Evaluates the watch expressions in the innermost frame
+    pub fn get_watch_values(&mut self) -> Vec<Result<Value, CalError>> {
+        let Some(frame) = self.get_backtrace().into_iter().next() else {
+            return vec![];
+        };
+        let watches = self.watches.clone();
+        watches
+            .iter()
+            .map(|expression| self.evaluate(expression, &frame))
+            .collect()
+    }
+
+    /// Whether `main` returned, after which running only reports its result
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Value returned by `main`, once the program is finished
+    fn get_result(&mut self) -> Value {
+        self.sync();
+        self.interpreter
+            .read_value(&self.return_type, STACK_BASE)
+            .unwrap_or_else(|_| Value::void())
+    }
+
+    /// Formats a value the way it would be written in Cal
+    pub fn format_value(&self, value: &Value) -> String {
+        self.interpreter.format_value(value)
+    }
+}
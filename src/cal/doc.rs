@@ -0,0 +1,221 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::str::FromStr;
+
+use crate::{
+    error::CalError,
+    structure::{Function, Module, StructDec},
+    tokenizer::Range,
+};
+
+/// Format of the documentation pages
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+impl DocFormat {
+    /// Extension of the files written in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DocFormat::Markdown => "md",
+            DocFormat::Html => "html",
+        }
+    }
+}
+
+impl FromStr for DocFormat {
+    type Err = CalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(DocFormat::Markdown),
+            "html" => Ok(DocFormat::Html),
+            _ => Err(CalError::new(
+                format!(
+                    "Unknown documentation format `{}`, expected markdown or html",
+                    s
+                ),
+                Range::default(),
+            )),
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Builds a page, writing each element in the chosen format
+struct Page {
+    format: DocFormat,
+    text: String,
+}
+
+impl Page {
+    fn new(format: DocFormat, title: &str) -> Self {
+        let mut ret = Self {
+            format,
+            text: String::new(),
+        };
+        if format == DocFormat::Html {
+            ret.text += &format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n",
+                escape_html(title)
+            );
+        }
+        ret
+    }
+
+    fn heading(&mut self, level: usize, code: &str) {
+        self.text += &match self.format {
+            DocFormat::Markdown => format!("{} `{}`\n\n", "#".repeat(level), code),
+            DocFormat::Html => format!(
+                "<h{0} id=\"{1}\"><code>{1}</code></h{0}>\n",
+                level,
+                escape_html(code)
+            ),
+        };
+    }
+
+    fn section(&mut self, title: &str) {
+        self.text += &match self.format {
+            DocFormat::Markdown => format!("## {}\n\n", title),
+            DocFormat::Html => format!("<h2>{}</h2>\n", title),
+        };
+    }
+
+    fn code_block(&mut self, code: &str) {
+        self.text += &match self.format {
+            DocFormat::Markdown => format!("```cal\n{}\n```\n\n", code),
+            DocFormat::Html => format!("<pre><code>{}</code></pre>\n", escape_html(code)),
+        };
+    }
+
+    /// Doc comments are Markdown, in HTML their paragraphs are kept as text
+    fn doc(&mut self, doc: &str) {
+        if doc.is_empty() {
+            return;
+        }
+        match self.format {
+            DocFormat::Markdown => self.text += &format!("{}\n\n", doc),
+            DocFormat::Html => {
+                for paragraph in doc.split("\n\n") {
+                    self.text += &format!("<p>{}</p>\n", escape_html(paragraph.trim()));
+                }
+            }
+        }
+    }
+
+    fn table(&mut self, headers: [&str; 2], rows: &[(String, String)]) {
+        if rows.is_empty() {
+            return;
+        }
+        match self.format {
+            DocFormat::Markdown => {
+                self.text += &format!("| {} | {} |\n| --- | --- |\n", headers[0], headers[1]);
+                for (name, typ) in rows {
+                    self.text += &format!("| `{}` | `{}` |\n", name, typ);
+                }
+                self.text += "\n";
+            }
+            DocFormat::Html => {
+                self.text += &format!(
+                    "<table>\n<tr><th>{}</th><th>{}</th></tr>\n",
+                    headers[0], headers[1]
+                );
+                for (name, typ) in rows {
+                    self.text += &format!(
+                        "<tr><td><code>{}</code></td><td><code>{}</code></td></tr>\n",
+                        escape_html(name),
+                        escape_html(typ)
+                    );
+                }
+                self.text += "</table>\n";
+            }
+        }
+    }
+
+    fn link(&mut self, text: &str, target: &str) {
+        self.text += &match self.format {
+            DocFormat::Markdown => format!("- [`{}`]({})\n", text, target),
+            DocFormat::Html => format!(
+                "<li><a href=\"{}\"><code>{}</code></a></li>\n",
+                escape_html(target),
+                escape_html(text)
+            ),
+        };
+    }
+
+    fn finish(mut self) -> String {
+        if self.format == DocFormat::Html {
+            self.text += "</body>\n</html>\n";
+        }
+        self.text
+    }
+}
+
+fn document_struct(page: &mut Page, struct_dec: &StructDec) {
+    page.heading(3, &struct_dec.name);
+    page.doc(&struct_dec.doc);
+    let fields: Vec<(String, String)> = struct_dec
+        .fields
+        .iter()
+        .map(|field| (field.name.clone(), field.typ.to_string()))
+        .collect();
+    page.table(["Field", "Type"], &fields);
+}
+
+fn document_function(page: &mut Page, function: &Function) {
+    page.heading(3, &function.name.replace('.', "::"));
+    page.code_block(&function.signature());
+    page.doc(&function.doc);
+    let parameters: Vec<(String, String)> = function
+        .parameters
+        .iter()
+        .map(|parameter| (parameter.name.clone(), parameter.typ.to_string()))
+        .collect();
+    page.table(["Parameter", "Type"], &parameters);
+}
+
+/// Returns a page documenting the structs and functions of a module, in the
+/// order they are declared
+pub fn document(name: &str, module: &Module, format: DocFormat) -> String {
+    let mut page = Page::new(format, name);
+    page.heading(1, name);
+    if !module.structs.is_empty() {
+        page.section("Structs");
+        for struct_dec in &module.structs {
+            document_struct(&mut page, struct_dec);
+        }
+    }
+    if !module.functions.is_empty() {
+        page.section("Functions");
+        for function in &module.functions {
+            document_function(&mut page, function);
+        }
+    }
+    page.finish()
+}
+
+/// Returns a page linking the pages of the modules
+pub fn index(names: &[String], format: DocFormat) -> String {
+    let mut page = Page::new(format, "Modules");
+    page.section("Modules");
+    if format == DocFormat::Html {
+        page.text += "<ul>\n";
+    }
+    for name in names {
+        page.link(name, &format!("{}.{}", name, format.extension()));
+    }
+    if format == DocFormat::Html {
+        page.text += "</ul>\n";
+    }
+    page.finish()
+}
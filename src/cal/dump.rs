@@ -0,0 +1,627 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+
+use crate::{
+    expression::{Expression, Literal, Term},
+    json::Json,
+    statement::Statement,
+    structure::{Constant, Function, Module, Static, StructDec, Type, TypeAlias, Variable},
+    tokenizer::{Range, Token, TokenKind},
+    vm::instruction::VmInstruction,
+};
+
+/// Serializes compiler data structures into JSON, with their ranges
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        self.as_slice().to_json()
+    }
+}
+
+/// An S-expression, either an atom or a list of S-expressions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    pub fn atom(atom: impl ToString) -> Self {
+        Sexp::Atom(atom.to_string())
+    }
+
+    /// Builds a list starting with a `head` atom
+    pub fn list(head: &str, items: impl IntoIterator<Item = Sexp>) -> Self {
+        let mut list = vec![Sexp::atom(head)];
+        list.extend(items);
+        Sexp::List(list)
+    }
+
+    /// Returns a representation where lists which do not fit in `width`
+    /// columns have their elements on separate, indented lines
+    pub fn pretty(&self, width: usize) -> String {
+        let mut ret = String::new();
+        self.write_pretty(&mut ret, 0, width);
+        ret
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize, width: usize) {
+        let flat = self.to_string();
+        match self {
+            Sexp::List(items) if indent + flat.len() > width && items.len() > 1 => {
+                // Leading atoms, such as the head and a name, stay on the first line
+                let head_count = items
+                    .iter()
+                    .take_while(|item| matches!(item, Sexp::Atom(_)))
+                    .count()
+                    .max(1);
+                let head: Vec<String> = items[..head_count].iter().map(Sexp::to_string).collect();
+                out.push('(');
+                out.push_str(&head.join(" "));
+                for item in &items[head_count..] {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent + 2));
+                    item.write_pretty(out, indent + 2, width);
+                }
+                out.push(')');
+            }
+            _ => out.push_str(&flat),
+        }
+    }
+}
+
+/// S-expressions are displayed on a single line
+impl fmt::Display for Sexp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sexp::Atom(atom) => write!(f, "{}", atom),
+            Sexp::List(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Serializes compiler data structures into S-expressions, which are more
+/// compact and readable than JSON, but do not carry any range
+pub trait ToSexp {
+    fn to_sexp(&self) -> Sexp;
+}
+
+impl ToJson for Range {
+    fn to_json(&self) -> Json {
+        Json::object([("start", self.start.into()), ("end", self.end.into())])
+    }
+}
+
+impl ToJson for Token {
+    fn to_json(&self) -> Json {
+        let (kind, value) = match &self.value {
+            TokenKind::Keyword(keyword) => ("keyword", keyword.to_string().into()),
+            TokenKind::Symbol(symbol) => ("symbol", symbol.to_string().into()),
+            TokenKind::Identifier(name) => ("identifier", name.as_str().into()),
+            TokenKind::Integer(integer) => ("integer", (*integer).into()),
+            TokenKind::Char(c) => ("char", c.to_string().into()),
+            TokenKind::Comment(comment) => ("comment", comment.as_str().into()),
+        };
+        Json::object([
+            ("kind", kind.into()),
+            ("value", value),
+            ("range", self.range.to_json()),
+        ])
+    }
+}
+
+/// Tokens also carry their range, as it is most of their information
+impl ToSexp for Token {
+    fn to_sexp(&self) -> Sexp {
+        let (kind, value) = match &self.value {
+            TokenKind::Keyword(keyword) => ("keyword", keyword.to_string()),
+            TokenKind::Symbol(symbol) => ("symbol", format!("{:?}", symbol.to_string())),
+            TokenKind::Identifier(name) => ("identifier", name.clone()),
+            TokenKind::Integer(integer) => ("integer", integer.to_string()),
+            TokenKind::Char(c) => ("char", format!("{:?}", c)),
+            TokenKind::Comment(comment) => ("comment", format!("{:?}", comment)),
+        };
+        let range = format!("{}..{}", self.range.start, self.range.end);
+        Sexp::list(kind, [Sexp::atom(value), Sexp::atom(range)])
+    }
+}
+
+/// Types are serialized to JSON with the same syntax they are written in Cal
+impl ToJson for Type {
+    fn to_json(&self) -> Json {
+        self.to_string().into()
+    }
+}
+
+impl ToSexp for Type {
+    fn to_sexp(&self) -> Sexp {
+        match self {
+            Type::Array(elem_type, count) => {
+                Sexp::list("array", [elem_type.to_sexp(), Sexp::atom(count)])
+            }
+            Type::ArrayExpr(elem_type, count) => {
+                Sexp::list("array", [elem_type.to_sexp(), count.to_sexp()])
+            }
+            Type::Ref(pointee) => Sexp::list("ref", [pointee.to_sexp()]),
+            Type::MutRef(pointee) => Sexp::list("mut-ref", [pointee.to_sexp()]),
+            Type::Tuple(types) => Sexp::list("tuple", types.iter().map(Type::to_sexp)),
+            _ => Sexp::atom(self),
+        }
+    }
+}
+
+impl ToJson for Literal {
+    fn to_json(&self) -> Json {
+        match self {
+            Literal::I16(integer) => (*integer).into(),
+            Literal::Bool(boolean) => (*boolean).into(),
+            Literal::Char(c) => c.to_string().into(),
+            Literal::Array(literals) => literals.to_json(),
+        }
+    }
+}
+
+impl ToSexp for Literal {
+    fn to_sexp(&self) -> Sexp {
+        match self {
+            Literal::I16(integer) => Sexp::atom(integer),
+            Literal::Bool(boolean) => Sexp::atom(boolean),
+            Literal::Char(c) => Sexp::atom(format!("{:?}", c)),
+            Literal::Array(literals) => Sexp::list("array", literals.iter().map(Literal::to_sexp)),
+        }
+    }
+}
+
+impl ToJson for Term {
+    fn to_json(&self) -> Json {
+        match self {
+            Term::Literal(literal) => {
+                Json::object([("kind", "literal".into()), ("value", literal.to_json())])
+            }
+            Term::Call(name, arguments) => Json::object([
+                ("kind", "call".into()),
+                ("name", name.as_str().into()),
+                ("arguments", arguments.to_json()),
+            ]),
+            Term::Variable(name) => {
+                Json::object([("kind", "variable".into()), ("name", name.as_str().into())])
+            }
+            Term::Index(target, index) => Json::object([
+                ("kind", "index".into()),
+                ("target", target.to_json()),
+                ("index", index.to_json()),
+            ]),
+            Term::Field(target, name) => Json::object([
+                ("kind", "field".into()),
+                ("target", target.to_json()),
+                ("name", name.as_str().into()),
+            ]),
+            Term::MethodCall(receiver, name, arguments) => Json::object([
+                ("kind", "method_call".into()),
+                ("receiver", receiver.to_json()),
+                ("name", name.as_str().into()),
+                ("arguments", arguments.to_json()),
+            ]),
+            Term::Struct(name, fields) => Json::object([
+                ("kind", "struct".into()),
+                ("name", name.as_str().into()),
+                (
+                    "fields",
+                    fields
+                        .iter()
+                        .map(|(name, value)| {
+                            Json::object([
+                                ("name", name.as_str().into()),
+                                ("value", value.to_json()),
+                            ])
+                        })
+                        .collect::<Vec<_>>()
+                        .into(),
+                ),
+            ]),
+            Term::Array(elements) => {
+                Json::object([("kind", "array".into()), ("elements", elements.to_json())])
+            }
+            Term::Tuple(elements) => {
+                Json::object([("kind", "tuple".into()), ("elements", elements.to_json())])
+            }
+            Term::Expression(expression) => Json::object([
+                ("kind", "parenthesized".into()),
+                ("expression", expression.to_json()),
+            ]),
+            Term::SizeOf(typ) => {
+                Json::object([("kind", "size_of".into()), ("type", typ.to_json())])
+            }
+            Term::UnaryOp(op, term) => Json::object([
+                ("kind", "unary".into()),
+                ("op", op.to_string().into()),
+                ("term", term.to_json()),
+            ]),
+        }
+    }
+}
+
+impl ToSexp for Term {
+    fn to_sexp(&self) -> Sexp {
+        let expressions = |expressions: &[Expression]| {
+            expressions
+                .iter()
+                .map(Expression::to_sexp)
+                .collect::<Vec<_>>()
+        };
+        match self {
+            Term::Literal(literal) => literal.to_sexp(),
+            Term::Call(name, arguments) => Sexp::list(
+                "call",
+                [vec![Sexp::atom(name)], expressions(arguments)].concat(),
+            ),
+            Term::Variable(name) => Sexp::atom(name),
+            Term::Index(target, index) => Sexp::list("index", [target.to_sexp(), index.to_sexp()]),
+            Term::Field(target, name) => Sexp::list("field", [target.to_sexp(), Sexp::atom(name)]),
+            Term::MethodCall(receiver, name, arguments) => Sexp::list(
+                "method-call",
+                [
+                    vec![receiver.to_sexp(), Sexp::atom(name)],
+                    expressions(arguments),
+                ]
+                .concat(),
+            ),
+            Term::Struct(name, fields) => Sexp::list(
+                "struct",
+                std::iter::once(Sexp::atom(name)).chain(
+                    fields
+                        .iter()
+                        .map(|(name, value)| Sexp::List(vec![Sexp::atom(name), value.to_sexp()])),
+                ),
+            ),
+            Term::Array(elements) => Sexp::list("array", expressions(elements)),
+            Term::Tuple(elements) => Sexp::list("tuple", expressions(elements)),
+            Term::Expression(expression) => expression.to_sexp(),
+            Term::SizeOf(typ) => Sexp::list("size-of", [typ.to_sexp()]),
+            Term::UnaryOp(op, term) => Sexp::list(&op.to_string(), [term.to_sexp()]),
+        }
+    }
+}
+
+impl ToJson for Expression {
+    fn to_json(&self) -> Json {
+        let mut members = vec![("term".to_string(), self.term.to_json())];
+        if let Some((op, rhs)) = &self.op_and_expr {
+            members.push(("op".into(), op.to_string().into()));
+            members.push(("rhs".into(), rhs.to_json()));
+        }
+        members.push(("range".into(), self.range.to_json()));
+        Json::Object(members)
+    }
+}
+
+/// Binary operations become `(op lhs rhs)`
+impl ToSexp for Expression {
+    fn to_sexp(&self) -> Sexp {
+        match &self.op_and_expr {
+            Some((op, rhs)) => Sexp::list(&op.to_string(), [self.term.to_sexp(), rhs.to_sexp()]),
+            None => self.term.to_sexp(),
+        }
+    }
+}
+
+impl ToJson for Variable {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("name", self.name.as_str().into()),
+            ("type", self.typ.to_json()),
+            ("mutable", self.mutable.into()),
+            ("range", self.range.to_json()),
+        ])
+    }
+}
+
+impl ToSexp for Variable {
+    fn to_sexp(&self) -> Sexp {
+        let mut ret = vec![];
+        if self.mutable {
+            ret.push(Sexp::atom("mut"));
+        }
+        ret.push(Sexp::atom(&self.name));
+        ret.push(self.typ.to_sexp());
+        Sexp::List(ret)
+    }
+}
+
+impl ToJson for Statement {
+    fn to_json(&self) -> Json {
+        match self {
+            Statement::Expression(expression) => Json::object([
+                ("kind", "expression".into()),
+                ("expression", expression.to_json()),
+            ]),
+            Statement::Return(value) => Json::object([
+                ("kind", "return".into()),
+                (
+                    "value",
+                    value.as_ref().map_or(Json::Null, Expression::to_json),
+                ),
+            ]),
+            Statement::Let(variable, value) => Json::object([
+                ("kind", "let".into()),
+                ("variable", variable.to_json()),
+                ("value", value.to_json()),
+            ]),
+            Statement::LetTuple(variables, value) => Json::object([
+                ("kind", "let_tuple".into()),
+                ("variables", variables.to_json()),
+                ("value", value.to_json()),
+            ]),
+            Statement::If(if_statement) => Json::object([
+                ("kind", "if".into()),
+                ("predicate", if_statement.predicate.to_json()),
+                ("if_branch", if_statement.if_branch.to_json()),
+                ("else_branch", if_statement.else_branch.to_json()),
+            ]),
+            Statement::While(while_statement) => Json::object([
+                ("kind", "while".into()),
+                ("predicate", while_statement.predicate.to_json()),
+                ("body", while_statement.body.to_json()),
+            ]),
+        }
+    }
+}
+
+impl ToSexp for Statement {
+    fn to_sexp(&self) -> Sexp {
+        let block = |head: &str, statements: &[Statement]| {
+            Sexp::list(head, statements.iter().map(Statement::to_sexp))
+        };
+        match self {
+            Statement::Expression(expression) => expression.to_sexp(),
+            Statement::Return(value) => Sexp::list("return", value.iter().map(Expression::to_sexp)),
+            Statement::Let(variable, value) => {
+                Sexp::list("let", [variable.to_sexp(), value.to_sexp()])
+            }
+            Statement::LetTuple(variables, value) => Sexp::list(
+                "let",
+                [
+                    Sexp::List(variables.iter().map(Variable::to_sexp).collect()),
+                    value.to_sexp(),
+                ],
+            ),
+            Statement::If(if_statement) => {
+                let mut ret = vec![
+                    if_statement.predicate.to_sexp(),
+                    block("then", &if_statement.if_branch),
+                ];
+                if !if_statement.else_branch.is_empty() {
+                    ret.push(block("else", &if_statement.else_branch));
+                }
+                Sexp::list("if", ret)
+            }
+            Statement::While(while_statement) => Sexp::list(
+                "while",
+                std::iter::once(while_statement.predicate.to_sexp())
+                    .chain(while_statement.body.iter().map(Statement::to_sexp)),
+            ),
+        }
+    }
+}
+
+impl ToJson for Function {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("name", self.name.as_str().into()),
+            ("const", self.is_const.into()),
+            ("inline", self.inline.into()),
+            ("parameters", self.parameters.to_json()),
+            ("return_type", self.return_type.to_json()),
+            ("body", self.body_statements.to_json()),
+            ("doc", self.doc.as_str().into()),
+            ("range", self.range.to_json()),
+        ])
+    }
+}
+
+impl ToSexp for Function {
+    fn to_sexp(&self) -> Sexp {
+        let head = if self.is_const { "const-fn" } else { "fn" };
+        let parameters = Sexp::List(self.parameters.iter().map(Variable::to_sexp).collect());
+        Sexp::list(
+            head,
+            [
+                Sexp::atom(&self.name),
+                parameters,
+                self.return_type.to_sexp(),
+            ]
+            .into_iter()
+            .chain(self.body_statements.iter().map(Statement::to_sexp)),
+        )
+    }
+}
+
+impl ToJson for StructDec {
+    fn to_json(&self) -> Json {
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| {
+                Json::object([
+                    ("name", field.name.as_str().into()),
+                    ("type", field.typ.to_json()),
+                ])
+            })
+            .collect::<Vec<_>>();
+        Json::object([
+            ("name", self.name.as_str().into()),
+            ("fields", fields.into()),
+            ("doc", self.doc.as_str().into()),
+        ])
+    }
+}
+
+impl ToSexp for StructDec {
+    fn to_sexp(&self) -> Sexp {
+        Sexp::list(
+            "struct",
+            std::iter::once(Sexp::atom(&self.name)).chain(
+                self.fields
+                    .iter()
+                    .map(|field| Sexp::List(vec![Sexp::atom(&field.name), field.typ.to_sexp()])),
+            ),
+        )
+    }
+}
+
+impl ToJson for Constant {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("name", self.name.as_str().into()),
+            ("type", self.typ.to_json()),
+            ("value", self.value.to_json()),
+        ])
+    }
+}
+
+impl ToSexp for Constant {
+    fn to_sexp(&self) -> Sexp {
+        Sexp::list(
+            "const",
+            [
+                Sexp::atom(&self.name),
+                self.typ.to_sexp(),
+                self.value.to_sexp(),
+            ],
+        )
+    }
+}
+
+impl ToJson for Static {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("name", self.name.as_str().into()),
+            ("type", self.typ.to_json()),
+            ("value", self.value.to_json()),
+        ])
+    }
+}
+
+impl ToSexp for Static {
+    fn to_sexp(&self) -> Sexp {
+        Sexp::list(
+            "static",
+            [
+                Sexp::atom(&self.name),
+                self.typ.to_sexp(),
+                self.value.to_sexp(),
+            ],
+        )
+    }
+}
+
+impl ToJson for TypeAlias {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("name", self.name.as_str().into()),
+            ("type", self.typ.to_json()),
+        ])
+    }
+}
+
+impl ToSexp for TypeAlias {
+    fn to_sexp(&self) -> Sexp {
+        Sexp::list("type", [Sexp::atom(&self.name), self.typ.to_sexp()])
+    }
+}
+
+impl ToJson for Module {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("name", self.name.as_str().into()),
+            ("type_aliases", self.type_aliases.to_json()),
+            ("constants", self.constants.to_json()),
+            ("statics", self.statics.to_json()),
+            ("structs", self.structs.to_json()),
+            ("functions", self.functions.to_json()),
+        ])
+    }
+}
+
+impl ToSexp for Module {
+    fn to_sexp(&self) -> Sexp {
+        let items = self
+            .type_aliases
+            .iter()
+            .map(TypeAlias::to_sexp)
+            .chain(self.constants.iter().map(Constant::to_sexp))
+            .chain(self.statics.iter().map(Static::to_sexp))
+            .chain(self.structs.iter().map(StructDec::to_sexp))
+            .chain(self.functions.iter().map(Function::to_sexp));
+        Sexp::list(
+            "module",
+            std::iter::once(Sexp::atom(&self.name)).chain(items),
+        )
+    }
+}
+
+impl ToJson for VmInstruction {
+    fn to_json(&self) -> Json {
+        let text = self.to_string();
+        let op = text.split_whitespace().next().unwrap_or_default();
+        let mut members = vec![("op".to_string(), op.into())];
+        let mut add = |name: &str, value: Json| members.push((name.to_string(), value));
+        match self {
+            VmInstruction::Push(segment, index) | VmInstruction::Pop(segment, index) => {
+                add("segment", segment.to_string().into());
+                add("index", (*index as usize).into());
+            }
+            VmInstruction::Label(label)
+            | VmInstruction::Goto(label)
+            | VmInstruction::IfGoto(label) => {
+                add("label", label.as_str().into());
+            }
+            VmInstruction::Function(name, local_count) => {
+                add("name", name.as_str().into());
+                add("local_count", (*local_count as usize).into());
+            }
+            VmInstruction::Call(name, arg_count) => {
+                add("name", name.as_str().into());
+                add("arg_count", (*arg_count as usize).into());
+            }
+            VmInstruction::Return(word_count) => {
+                add("word_count", (*word_count as usize).into());
+            }
+            _ => (),
+        }
+        Json::Object(members)
+    }
+}
+
+/// Instructions become lists of the words they are written with
+impl ToSexp for VmInstruction {
+    fn to_sexp(&self) -> Sexp {
+        Sexp::List(
+            self.to_string()
+                .split_whitespace()
+                .map(Sexp::atom)
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,516 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{cell::Cell, collections::HashMap};
+
+use crate::{
+    error::CalError,
+    expression::{Expression, Literal, Operator, Term},
+    statement::Statement,
+    structure::{Constant, Function, StructDec, Type, Variable},
+    symboltable::SymbolTable,
+    tokenizer::Range,
+};
+
+/// Maximum depth of nested calls and constants while evaluating
+const RECURSION_LIMIT: u16 = 256;
+
+/// Maximum number of calls and loop iterations while evaluating
+const STEP_LIMIT: u32 = 1 << 16;
+
+/// Result of executing statements: either execution continues normally,
+/// carrying the value of the last expression, or a function returns
+pub enum Flow<V> {
+    Normal(Option<V>),
+    Return(Option<V>),
+}
+
+/// Executes statements by walking their AST. The evaluator and the
+/// interpreter share the control flow, and only differ in the values they
+/// compute and in where they bind variables.
+pub trait Execute {
+    type Value;
+
+    fn eval(&mut self, expr: &Expression) -> Result<Self::Value, CalError>;
+
+    /// Evaluates the predicate of an `if` or a `while`
+    fn eval_predicate(&mut self, expr: &Expression) -> Result<bool, CalError>;
+
+    fn exec_let(
+        &mut self,
+        statement: &Statement,
+        variable: &Variable,
+        expr: &Expression,
+    ) -> Result<(), CalError>;
+
+    fn exec_let_tuple(
+        &mut self,
+        statement: &Statement,
+        variables: &[Variable],
+        expr: &Expression,
+    ) -> Result<(), CalError>;
+
+    /// Counts a loop iteration
+    fn step(&mut self) -> Result<(), CalError> {
+        Ok(())
+    }
+
+    fn exec_statement(&mut self, statement: &Statement) -> Result<Flow<Self::Value>, CalError> {
+        match statement {
+            Statement::Expression(expr) => {
+                let value = self.eval(expr)?;
+                match &expr.op_and_expr {
+                    Some((Operator::Assign, _)) => Ok(Flow::Normal(None)),
+                    _ => Ok(Flow::Normal(Some(value))),
+                }
+            }
+            Statement::Return(None) => Ok(Flow::Return(None)),
+            Statement::Return(Some(expr)) => Ok(Flow::Return(Some(self.eval(expr)?))),
+            Statement::Let(variable, expr) => {
+                self.exec_let(statement, variable, expr)?;
+                Ok(Flow::Normal(None))
+            }
+            Statement::LetTuple(variables, expr) => {
+                self.exec_let_tuple(statement, variables, expr)?;
+                Ok(Flow::Normal(None))
+            }
+            Statement::If(if_stat) => {
+                if self.eval_predicate(&if_stat.predicate)? {
+                    self.exec_statements(&if_stat.if_branch)
+                } else {
+                    self.exec_statements(&if_stat.else_branch)
+                }
+            }
+            Statement::While(while_stat) => {
+                while self.eval_predicate(&while_stat.predicate)? {
+                    self.step()?;
+                    if let Flow::Return(value) = self.exec_statements(&while_stat.body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+                Ok(Flow::Normal(None))
+            }
+        }
+    }
+
+    fn exec_statements(&mut self, statements: &[Statement]) -> Result<Flow<Self::Value>, CalError> {
+        let mut last = None;
+        for statement in statements {
+            match self.exec_statement(statement)? {
+                Flow::Normal(value) => last = value,
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal(last))
+    }
+}
+
+/// Parameters and variables of a `const fn` being evaluated
+struct ConstFrame<'e, 'a> {
+    evaluator: &'e Evaluator<'a>,
+    locals: HashMap<String, i16>,
+}
+
+impl Execute for ConstFrame<'_, '_> {
+    type Value = i16;
+
+    fn eval(&mut self, expr: &Expression) -> Result<i16, CalError> {
+        self.evaluator.eval_expression_with(expr, &mut self.locals)
+    }
+
+    fn eval_predicate(&mut self, expr: &Expression) -> Result<bool, CalError> {
+        Ok(self.eval(expr)? != 0)
+    }
+
+    fn exec_let(
+        &mut self,
+        _statement: &Statement,
+        variable: &Variable,
+        expr: &Expression,
+    ) -> Result<(), CalError> {
+        let value = self.eval(expr)?;
+        self.locals.insert(variable.name.clone(), value);
+        Ok(())
+    }
+
+    fn exec_let_tuple(
+        &mut self,
+        _statement: &Statement,
+        _variables: &[Variable],
+        _expr: &Expression,
+    ) -> Result<(), CalError> {
+        Err(CalError::new(
+            "Can not destructure tuples at compile time".into(),
+            Range::default(),
+        ))
+    }
+
+    fn step(&mut self) -> Result<(), CalError> {
+        self.evaluator.step()
+    }
+}
+
+/// Evaluates expressions at compile time by interpreting the AST of constants
+/// and `const fn`s. Values are single words with the same 16-bit wrap
+/// semantics of the VM, where `true` is `-1` and `false` is `0`.
+pub struct Evaluator<'a> {
+    constants: &'a HashMap<String, Constant>,
+    functions: &'a HashMap<String, Function>,
+    structs: &'a HashMap<String, StructDec>,
+
+    /// Variables of the function being generated, which shadow constants
+    shadowing: Option<&'a SymbolTable>,
+
+    depth: Cell<u16>,
+    steps: Cell<u32>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(
+        constants: &'a HashMap<String, Constant>,
+        functions: &'a HashMap<String, Function>,
+        structs: &'a HashMap<String, StructDec>,
+        shadowing: Option<&'a SymbolTable>,
+    ) -> Self {
+        Self {
+            constants,
+            functions,
+            structs,
+            shadowing,
+            depth: Cell::new(0),
+            steps: Cell::new(0),
+        }
+    }
+
+    fn not_constant(term: &Term) -> CalError {
+        CalError::new(
+            format!("Can not evaluate `{:?}` at compile time", term),
+            Range::default(),
+        )
+    }
+
+    /// Resolves a type by evaluating the length of its arrays and checking
+    /// that every struct it refers to is defined
+    pub fn resolve_type(&self, typ: &Type) -> Result<Type, CalError> {
+        match typ {
+            Type::Array(elem_type, count) => {
+                Ok(Type::Array(Box::new(self.resolve_type(elem_type)?), *count))
+            }
+            Type::ArrayExpr(elem_type, count_expr) => {
+                let count = self.eval_expression(count_expr)?;
+                if count <= 0 {
+                    return Err(CalError::new(
+                        format!("Expected positive array length, found {}", count),
+                        Range::default(),
+                    ));
+                }
+                Ok(Type::Array(
+                    Box::new(self.resolve_type(elem_type)?),
+                    count as u16,
+                ))
+            }
+            Type::Ref(ref_type) => Ok(Type::Ref(Box::new(self.resolve_type(ref_type)?))),
+            Type::MutRef(ref_type) => Ok(Type::MutRef(Box::new(self.resolve_type(ref_type)?))),
+            Type::Tuple(types) => Ok(Type::Tuple(
+                types
+                    .iter()
+                    .map(|typ| self.resolve_type(typ))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Type::Struct(name) if !self.structs.contains_key(name) => Err(CalError::new(
+                format!("Undefined type `{}`", name),
+                Range::default(),
+            )),
+            typ => Ok(typ.clone()),
+        }
+    }
+
+    /// Returns the size in words of a type
+    pub fn get_type_size_in_words(&self, typ: &Type) -> Result<u16, CalError> {
+        match typ {
+            Type::Void => Ok(0),
+            Type::I16 | Type::Bool | Type::Char | Type::Ref(_) | Type::MutRef(_) => Ok(1),
+            Type::Array(elem_type, count) => Ok(self.get_type_size_in_words(elem_type)? * count),
+            Type::ArrayExpr(_, _) => self.get_type_size_in_words(&self.resolve_type(typ)?),
+            Type::Tuple(types) => types
+                .iter()
+                .map(|typ| self.get_type_size_in_words(typ))
+                .sum(),
+            Type::Struct(name) => match self.structs.get(name) {
+                Some(struct_dec) => struct_dec
+                    .fields
+                    .iter()
+                    .map(|field| self.get_type_size_in_words(&field.typ))
+                    .sum(),
+                None => Err(CalError::new(
+                    format!("Undefined type `{}`", name),
+                    Range::default(),
+                )),
+            },
+        }
+    }
+
+    /// Applies a binary operator to two values
+    pub fn eval_operator(op: Operator, lhs: i16, rhs: i16) -> Result<i16, CalError> {
+        let from_bool = |b: bool| if b { -1 } else { 0 };
+        match op {
+            Operator::Add => Ok(lhs.wrapping_add(rhs)),
+            Operator::Sub => Ok(lhs.wrapping_sub(rhs)),
+            Operator::Mul => Ok(lhs.wrapping_mul(rhs)),
+            Operator::Div | Operator::Mod if rhs == 0 => Err(CalError::new(
+                "Division by zero in constant expression".into(),
+                Range::default(),
+            )),
+            Operator::Div => Ok(lhs.wrapping_div(rhs)),
+            Operator::Mod => Ok(lhs.wrapping_rem(rhs)),
+            Operator::Eq => Ok(from_bool(lhs == rhs)),
+            Operator::Ne => Ok(from_bool(lhs != rhs)),
+            Operator::Lt => Ok(from_bool(lhs < rhs)),
+            Operator::Gt => Ok(from_bool(lhs > rhs)),
+            Operator::And => Ok(lhs & rhs),
+            Operator::Or => Ok(lhs | rhs),
+            Operator::Assign => Err(CalError::new(
+                "Can not assign in a constant expression".into(),
+                Range::default(),
+            )),
+        }
+    }
+
+    fn eval_literal(literal: &Literal) -> Result<i16, CalError> {
+        match literal {
+            Literal::I16(integer) => Ok(*integer),
+            Literal::Bool(true) => Ok(-1),
+            Literal::Bool(false) => Ok(0),
+            Literal::Char(c) => Ok(*c as i16),
+            Literal::Array(_) => Err(Self::not_constant(&Term::Literal(literal.clone()))),
+        }
+    }
+
+    /// Enters a nested constant or call, failing when too deep
+    fn enter(&self) -> Result<(), CalError> {
+        if self.depth.get() >= RECURSION_LIMIT {
+            return Err(CalError::new(
+                "Recursion limit reached while evaluating a constant expression".into(),
+                Range::default(),
+            ));
+        }
+        self.depth.set(self.depth.get() + 1);
+        Ok(())
+    }
+
+    /// Counts a call or a loop iteration, failing when too many
+    fn step(&self) -> Result<(), CalError> {
+        self.steps.set(self.steps.get() + 1);
+        if self.steps.get() > STEP_LIMIT {
+            return Err(CalError::new(
+                "Step limit reached while evaluating a constant expression".into(),
+                Range::default(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    fn eval_constant(&self, constant: &Constant) -> Result<i16, CalError> {
+        self.enter()?;
+        // Constants can not see the locals of the function being generated
+        let evaluator = Evaluator::new(self.constants, self.functions, self.structs, None);
+        evaluator.depth.set(self.depth.get());
+        evaluator.steps.set(self.steps.get());
+        let ret = evaluator.eval_expression(&constant.value);
+        self.steps.set(evaluator.steps.get());
+        self.leave();
+        ret
+    }
+
+    fn eval_variable(&self, name: &str, locals: &HashMap<String, i16>) -> Result<i16, CalError> {
+        if let Some(value) = locals.get(name) {
+            return Ok(*value);
+        }
+        if matches!(self.shadowing, Some(symbol_table) if symbol_table.get(name).is_some()) {
+            return Err(Self::not_constant(&Term::Variable(name.into())));
+        }
+        match self.constants.get(name) {
+            Some(constant) => self.eval_constant(constant),
+            None => Err(Self::not_constant(&Term::Variable(name.into()))),
+        }
+    }
+
+    /// Calls a `const fn` with the values of its arguments
+    fn eval_call(&self, name: &str, args: Vec<i16>) -> Result<i16, CalError> {
+        let Some(function) = self
+            .functions
+            .get(name)
+            .filter(|function| function.is_const)
+        else {
+            return Err(CalError::new(
+                format!("Can not call non-const function `{}` at compile time", name),
+                Range::default(),
+            ));
+        };
+        if function.parameters.len() != args.len() {
+            return Err(CalError::new(
+                format!(
+                    "Function `{}` takes {} arguments, found {}",
+                    name,
+                    function.parameters.len(),
+                    args.len()
+                ),
+                Range::default(),
+            ));
+        }
+
+        let mut frame = ConstFrame {
+            evaluator: self,
+            locals: function
+                .parameters
+                .iter()
+                .map(|parameter| parameter.name.clone())
+                .zip(args)
+                .collect(),
+        };
+
+        self.step()?;
+        self.enter()?;
+        let flow = frame.exec_statements(&function.body_statements);
+        self.leave();
+
+        match flow? {
+            Flow::Normal(Some(value)) | Flow::Return(Some(value)) => Ok(value),
+            _ => Err(CalError::new(
+                format!("Function `{}` does not return a value", name),
+                Range::default(),
+            )),
+        }
+    }
+
+    fn eval_term(&self, term: &Term, locals: &mut HashMap<String, i16>) -> Result<i16, CalError> {
+        match term {
+            Term::Literal(literal) => Self::eval_literal(literal),
+            Term::Variable(name) => self.eval_variable(name, locals),
+            Term::Call(name, expressions) => {
+                let args = expressions
+                    .iter()
+                    .map(|expr| self.eval_expression_with(expr, locals))
+                    .collect::<Result<_, _>>()?;
+                self.eval_call(name, args)
+            }
+            Term::Expression(expr) => self.eval_expression_with(expr, locals),
+            Term::SizeOf(typ) => Ok(self.get_type_size_in_words(typ)? as i16),
+            _ => Err(Self::not_constant(term)),
+        }
+    }
+
+    fn eval_expression_with(
+        &self,
+        expr: &Expression,
+        locals: &mut HashMap<String, i16>,
+    ) -> Result<i16, CalError> {
+        match &expr.op_and_expr {
+            Some((Operator::Assign, rhs)) => {
+                let Term::Variable(name) = expr.term.as_ref() else {
+                    return Err(Self::not_constant(&expr.term));
+                };
+                if !locals.contains_key(name) {
+                    return Err(Self::not_constant(&expr.term));
+                }
+                let value = self.eval_expression_with(rhs, locals)?;
+                locals.insert(name.clone(), value);
+                Ok(value)
+            }
+            Some((op, rhs)) => {
+                let lhs = self.eval_term(&expr.term, locals)?;
+                let rhs = self.eval_expression_with(rhs, locals)?;
+                Self::eval_operator(*op, lhs, rhs)
+            }
+            None => self.eval_term(&expr.term, locals),
+        }
+    }
+
+    /// Evaluates the initializer of a static, of a resolved type, into the
+    /// words it is laid out with
+    pub fn eval_words(&self, typ: &Type, expr: &Expression) -> Result<Vec<i16>, CalError> {
+        let mismatch = || {
+            CalError::new(
+                format!("Expected `{}`, found `{:?}`", typ, expr.term),
+                expr.range,
+            )
+        };
+        let elements = |expressions: &[Expression], types: &[Type]| {
+            if expressions.len() != types.len() {
+                return Err(CalError::new(
+                    format!(
+                        "Expected {} elements, found {}",
+                        types.len(),
+                        expressions.len()
+                    ),
+                    expr.range,
+                ));
+            }
+            let mut ret = vec![];
+            for (expr, typ) in expressions.iter().zip(types) {
+                ret.extend(self.eval_words(typ, expr)?);
+            }
+            Ok(ret)
+        };
+        match (typ, expr.term.as_ref(), &expr.op_and_expr) {
+            (_, Term::Expression(inner), None) => self.eval_words(typ, inner),
+            (Type::Array(elem_type, count), Term::Array(expressions), None) => {
+                elements(expressions, &vec![*elem_type.clone(); *count as usize])
+            }
+            (Type::Array(elem_type, count), Term::Literal(Literal::Array(literals)), None) => {
+                let expressions: Vec<Expression> = literals
+                    .iter()
+                    .map(|literal| {
+                        Expression::new(Box::new(Term::Literal(literal.clone())), None)
+                            .with_range(expr.range)
+                    })
+                    .collect();
+                elements(&expressions, &vec![*elem_type.clone(); *count as usize])
+            }
+            (Type::Tuple(types), Term::Tuple(expressions), None) => elements(expressions, types),
+            (Type::Struct(name), Term::Struct(struct_name, initializers), None)
+                if name == struct_name =>
+            {
+                let struct_dec = &self.structs[name];
+                let mut expressions = vec![];
+                for field in &struct_dec.fields {
+                    match initializers.iter().find(|(name, _)| *name == field.name) {
+                        Some((_, expr)) => expressions.push(expr.clone()),
+                        None => {
+                            return Err(CalError::new(
+                                format!("Missing field `{}` of `{}`", field.name, name),
+                                expr.range,
+                            ))
+                        }
+                    }
+                }
+                let types: Vec<Type> = struct_dec
+                    .fields
+                    .iter()
+                    .map(|field| self.resolve_type(&field.typ))
+                    .collect::<Result<_, _>>()?;
+                elements(&expressions, &types)
+            }
+            (Type::I16 | Type::Bool | Type::Char, _, _) => {
+                Ok(vec![self.eval_expression(expr).map_err(|err| {
+                    if err.range == Range::default() {
+                        CalError::new(err.message, expr.range)
+                    } else {
+                        err
+                    }
+                })?])
+            }
+            _ => Err(mismatch()),
+        }
+    }
+
+    /// Evaluates an expression at compile time
+    pub fn eval_expression(&self, expr: &Expression) -> Result<i16, CalError> {
+        self.eval_expression_with(expr, &mut HashMap::new())
+    }
+}
@@ -2,7 +2,13 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use crate::{error::CalError, tokenizer::Symbol};
+use std::fmt;
+
+use crate::{
+    error::CalError,
+    structure::Type,
+    tokenizer::{Range, Symbol},
+};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Operator {
@@ -52,10 +58,33 @@ impl Operator {
     }
 }
 
+/// Operators are displayed as they are written in Cal
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Lt => "<",
+            Operator::Gt => ">",
+            Operator::Assign => "=",
+            Operator::And => "&",
+            Operator::Or => "|",
+            Operator::Mod => "%",
+        };
+        write!(f, "{}", text)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum UnaryOperator {
     /// `&`
     Ref,
+    /// `&mut`
+    MutRef,
 }
 
 impl UnaryOperator {
@@ -67,6 +96,15 @@ impl UnaryOperator {
     }
 }
 
+impl fmt::Display for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnaryOperator::Ref => write!(f, "&"),
+            UnaryOperator::MutRef => write!(f, "&mut"),
+        }
+    }
+}
+
 /// An enum for literals will come in handy when defining arrays
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Literal {
@@ -82,25 +120,60 @@ pub enum Term {
     /// Call a function with a list of arguments
     Call(String, Vec<Expression>),
     Variable(String),
-    /// Call the index operator on a variable, where
-    /// index is the result of an expression.
-    Index(String, Expression),
+    /// Call the index operator on a place, which is a variable or another
+    /// index or field access, where index is the result of an expression.
+    Index(Box<Term>, Expression),
+    /// Access a field of a struct, or an element of a tuple, of a place.
+    /// References are followed automatically.
+    Field(Box<Term>, String),
+    /// Call a method on a receiver term, which is passed by reference as the
+    /// first argument of the mangled function `Type.method`
+    MethodCall(Box<Term>, String, Vec<Expression>),
+    /// Struct literal with a list of field initializers
+    Struct(String, Vec<(String, Expression)>),
+    /// Array whose elements are not all literals
+    Array(Vec<Expression>),
+    /// Tuple expression, whose elements are accessed as fields `.0`, `.1`, ...
+    Tuple(Vec<Expression>),
+    /// Expression between parentheses
+    Expression(Box<Expression>),
+    /// Size in words of a type, known at compile time
+    SizeOf(Type),
 
     /// Apply an unary operator to the term to its right
     UnaryOp(UnaryOperator, Box<Term>),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, Eq)]
 pub struct Expression {
     pub term: Box<Term>,
 
     /// The term on the left may be followed by an operator and another
     /// expression on the right
     pub op_and_expr: Option<(Operator, Box<Expression>)>,
+
+    /// Range of the source code of the whole expression
+    pub range: Range,
 }
 
 impl Expression {
     pub fn new(term: Box<Term>, op_and_expr: Option<(Operator, Box<Expression>)>) -> Self {
-        Self { term, op_and_expr }
+        Self {
+            term,
+            op_and_expr,
+            range: Range::default(),
+        }
+    }
+
+    pub fn with_range(mut self, range: Range) -> Self {
+        self.range = range;
+        self
+    }
+}
+
+/// Expressions are equal regardless of where they are in the source code
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.term == other.term && self.op_and_expr == other.op_and_expr
     }
 }
@@ -0,0 +1,454 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashSet;
+
+use crate::{
+    error::CalError,
+    parser::parse,
+    structure::Module,
+    tokenizer::{tokenize, Keyword, Range, Symbol, Token, TokenKind},
+};
+
+/// Number of spaces for each level of indentation
+const INDENT: usize = 4;
+
+/// Array literals longer than this are laid out one element per line
+const MAX_WIDTH: usize = 100;
+
+/// Kind of a pair of brackets, or braces, which decides the layout of the
+/// tokens between them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Group {
+    /// Statements or items, one per line
+    Block,
+    /// Fields of a struct declaration, one per line
+    Fields,
+    /// Field initializers of a struct literal, on the same line
+    StructLiteral,
+    Paren,
+    Index,
+    ArrayType,
+    /// Array literal, which is broken one element per line when too long
+    Array {
+        broken: bool,
+    },
+    /// Attribute of an item, on a line of its own
+    Attribute,
+}
+
+/// Pretty-prints Cal code by walking its tokens, so that everything but
+/// whitespace, including comments, is kept as it is written
+struct Formatter<'a> {
+    code: &'a str,
+    /// Tokens and comments in order of appearance
+    tokens: Vec<Token>,
+    struct_names: HashSet<String>,
+
+    out: String,
+    indent: usize,
+    groups: Vec<Group>,
+
+    /// A new line is started before writing the next token
+    newline: bool,
+    /// The next token is written right after the previous one
+    glue: bool,
+    /// The new line follows a comment in the middle of a statement, so it
+    /// is indented one level deeper
+    continuation: bool,
+    /// Kind of the last token written, comments excluded
+    last: Option<TokenKind>,
+    /// Whether the last `&` written is an unary operator
+    last_unary: bool,
+    /// End of the last token or comment in the code
+    last_end: usize,
+}
+
+/// Returns whether a token can be the end of an operand, in which case a
+/// following `&` is a binary operator and a following `[` is an index
+fn is_operand_end(kind: &Option<TokenKind>) -> bool {
+    matches!(
+        kind,
+        Some(
+            TokenKind::Identifier(_)
+                | TokenKind::Integer(_)
+                | TokenKind::Char(_)
+                | TokenKind::Keyword(Keyword::True | Keyword::False)
+                | TokenKind::Symbol(Symbol::RightParen | Symbol::RightBracket)
+        )
+    )
+}
+
+impl<'a> Formatter<'a> {
+    fn new(code: &'a str, tokens: Vec<Token>, module: &Module) -> Self {
+        let mut struct_names: HashSet<String> = module
+            .structs
+            .iter()
+            .map(|struct_dec| struct_dec.name.clone())
+            .collect();
+        struct_names.insert("Self".into());
+
+        Self {
+            code,
+            tokens,
+            struct_names,
+            out: String::new(),
+            indent: 0,
+            groups: vec![],
+            newline: false,
+            glue: true,
+            continuation: false,
+            last: None,
+            last_unary: false,
+            last_end: 0,
+        }
+    }
+
+    /// Returns the text of a token, as written in the code for literals
+    fn text(&self, token: &Token) -> String {
+        match &token.value {
+            TokenKind::Keyword(keyword) => keyword.to_string(),
+            TokenKind::Symbol(symbol) => symbol.to_string(),
+            TokenKind::Identifier(identifier) => identifier.clone(),
+            TokenKind::Comment(comment) => comment.clone(),
+            TokenKind::Integer(_) | TokenKind::Char(_) => {
+                self.code[token.range.start..token.range.end].into()
+            }
+        }
+    }
+
+    fn kind(&self, index: usize) -> Option<&TokenKind> {
+        self.tokens.get(index).map(|token| &token.value)
+    }
+
+    /// Returns the index of the token closing the group opened at `index`,
+    /// along with whether a `;` is found directly within the group
+    fn find_closing(&self, index: usize) -> (usize, bool) {
+        let mut depth = 0;
+        let mut semicolon = false;
+        for (i, token) in self.tokens.iter().enumerate().skip(index) {
+            match token.value {
+                TokenKind::Symbol(Symbol::LeftParen | Symbol::LeftBracket | Symbol::LeftBrace) => {
+                    depth += 1
+                }
+                TokenKind::Symbol(
+                    Symbol::RightParen | Symbol::RightBracket | Symbol::RightBrace,
+                ) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return (i, semicolon);
+                    }
+                }
+                TokenKind::Symbol(Symbol::Semicolon) if depth == 1 => semicolon = true,
+                _ => (),
+            }
+        }
+        (self.tokens.len(), semicolon)
+    }
+
+    /// Returns the width of a group written on a single line, or `None` if it
+    /// contains comments and it can not be on a single line
+    fn flat_width(&self, open: usize, close: usize) -> Option<usize> {
+        let mut width = 0;
+        for token in &self.tokens[open..=close.min(self.tokens.len() - 1)] {
+            width += match &token.value {
+                TokenKind::Comment(_) => return None,
+                TokenKind::Symbol(Symbol::Comma) => 2,
+                TokenKind::Symbol(
+                    Symbol::Eq
+                    | Symbol::Assign
+                    | Symbol::Lt
+                    | Symbol::Gt
+                    | Symbol::Ne
+                    | Symbol::Plus
+                    | Symbol::Minus
+                    | Symbol::Asterisk
+                    | Symbol::Slash
+                    | Symbol::VerticalBar
+                    | Symbol::Percent,
+                ) => 3,
+                _ => self.text(token).len(),
+            };
+        }
+        Some(width)
+    }
+
+    fn column(&self) -> usize {
+        if self.newline {
+            (self.indent + self.continuation as usize) * INDENT
+        } else {
+            self.out.len() - self.out.rfind('\n').map_or(0, |i| i + 1)
+        }
+    }
+
+    fn line_break(&mut self) {
+        self.newline = true;
+    }
+
+    /// Writes some text, preceded by a new line or by a space if needed
+    fn write(&mut self, text: &str, range: Range) {
+        if self.newline {
+            if !self.out.is_empty() {
+                self.out.push('\n');
+                // Keep one empty line where there was at least one
+                let gap = &self.code[self.last_end.min(range.start)..range.start];
+                let after_brace = self.out.trim_end().ends_with('{');
+                if gap.matches('\n').count() > 1 && !after_brace && text != "}" {
+                    self.out.push('\n');
+                }
+            }
+            let indent = self.indent + self.continuation as usize;
+            self.out.push_str(&" ".repeat(indent * INDENT));
+            self.newline = false;
+            self.continuation = false;
+        } else if !self.glue {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+        self.glue = false;
+        self.last_end = range.end;
+    }
+
+    /// Removes a trailing comma written before a closing bracket
+    fn remove_trailing_comma(&mut self) {
+        if self.out.ends_with(',') {
+            self.out.pop();
+        }
+    }
+
+    fn format_comment(&mut self, index: usize) {
+        let token = self.tokens[index].clone();
+        let text = self.text(&token);
+        let line_start = self.code[..token.range.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let trailing = !self.code[line_start..token.range.start].trim().is_empty();
+        // A comment in the middle of a statement pushes the rest of it one
+        // level deeper, while one after the tail of a block does not
+        let closing = self.tokens[index + 1..]
+            .iter()
+            .find(|token| !matches!(token.value, TokenKind::Comment(_)))
+            .is_some_and(|token| token.value == TokenKind::Symbol(Symbol::RightBrace));
+        let continuation =
+            !closing && (self.continuation || (!self.newline && !self.out.is_empty()));
+        if trailing && !self.out.is_empty() {
+            // Stays at the end of the line of the previous token
+            self.out.push(' ');
+            self.out.push_str(&text);
+            self.last_end = token.range.end;
+        } else {
+            if !self.out.is_empty() {
+                self.line_break();
+            }
+            self.write(&text, token.range);
+        }
+        self.line_break();
+        self.continuation = continuation;
+    }
+
+    /// Decides the kind of group opened by a `{`
+    fn brace_group(&self, index: usize) -> Group {
+        let before = |n: usize| index.checked_sub(n).and_then(|i| self.kind(i));
+        match (before(2), before(1)) {
+            (Some(TokenKind::Keyword(Keyword::Struct)), _) => Group::Fields,
+            (
+                Some(
+                    TokenKind::Keyword(Keyword::Impl | Keyword::Mut)
+                    | TokenKind::Symbol(Symbol::RightArrow | Symbol::Ampersand),
+                ),
+                _,
+            ) => Group::Block,
+            (_, Some(TokenKind::Identifier(name))) if self.struct_names.contains(name) => {
+                Group::StructLiteral
+            }
+            _ => Group::Block,
+        }
+    }
+
+    fn format_token(&mut self, index: usize) {
+        let token = self.tokens[index].clone();
+        let text = self.text(&token);
+        let range = token.range;
+        let TokenKind::Symbol(symbol) = token.value else {
+            if token.value == TokenKind::Keyword(Keyword::Mut) && self.last_unary {
+                self.glue = true;
+            }
+            self.write(&text, range);
+            self.glue = token.value == TokenKind::Keyword(Keyword::SizeOf);
+            self.last = Some(token.value);
+            self.last_unary = false;
+            return;
+        };
+
+        let operand_end = is_operand_end(&self.last);
+        let mut unary = false;
+        match symbol {
+            Symbol::LeftBrace => {
+                let group = self.brace_group(index);
+                self.write(&text, range);
+                let next = self.kind(index + 1);
+                if group != Group::StructLiteral
+                    && next == Some(&TokenKind::Symbol(Symbol::RightBrace))
+                {
+                    // Empty block
+                    self.glue = true;
+                } else if group != Group::StructLiteral {
+                    self.indent += 1;
+                    self.line_break();
+                }
+                self.groups.push(group);
+            }
+            Symbol::RightBrace => match self.groups.pop() {
+                Some(Group::StructLiteral) => {
+                    self.remove_trailing_comma();
+                    self.glue = self.last == Some(TokenKind::Symbol(Symbol::LeftBrace));
+                    self.write(&text, range);
+                }
+                group => {
+                    if self.last != Some(TokenKind::Symbol(Symbol::LeftBrace)) {
+                        if group == Some(Group::Fields)
+                            && self.last != Some(TokenKind::Symbol(Symbol::Comma))
+                        {
+                            self.glue = true;
+                            self.write(",", range);
+                        }
+                        self.indent = self.indent.saturating_sub(1);
+                        self.line_break();
+                    }
+                    self.write(&text, range);
+                    match self.kind(index + 1) {
+                        Some(TokenKind::Keyword(Keyword::Else))
+                        | Some(TokenKind::Symbol(
+                            Symbol::Semicolon | Symbol::Comma | Symbol::RightParen | Symbol::Dot,
+                        )) => (),
+                        _ => self.line_break(),
+                    }
+                }
+            },
+            Symbol::LeftParen | Symbol::LeftBracket => {
+                let group = if symbol == Symbol::LeftParen {
+                    Group::Paren
+                } else if self.last == Some(TokenKind::Symbol(Symbol::Hash)) {
+                    Group::Attribute
+                } else if operand_end {
+                    Group::Index
+                } else {
+                    let (close, semicolon) = self.find_closing(index);
+                    if semicolon {
+                        Group::ArrayType
+                    } else {
+                        let width = self.flat_width(index, close);
+                        let broken = width.is_none_or(|width| self.column() + width > MAX_WIDTH);
+                        Group::Array { broken }
+                    }
+                };
+                // Calls, indexing, `sizeof` and attributes are glued to what
+                // they follow
+                if operand_end || group == Group::Attribute {
+                    self.glue = true;
+                }
+                self.write(&text, range);
+                self.glue = true;
+                if group == (Group::Array { broken: true }) {
+                    self.indent += 1;
+                    self.line_break();
+                }
+                self.groups.push(group);
+            }
+            Symbol::RightParen | Symbol::RightBracket => {
+                let group = self.groups.pop();
+                match group {
+                    Some(Group::Array { broken: true }) => {
+                        if self.last != Some(TokenKind::Symbol(Symbol::Comma)) {
+                            self.glue = true;
+                            self.write(",", range);
+                        }
+                        self.indent = self.indent.saturating_sub(1);
+                        self.line_break();
+                    }
+                    Some(Group::Array { broken: false }) => {
+                        self.remove_trailing_comma();
+                        self.glue = true;
+                    }
+                    _ => self.glue = true,
+                }
+                self.write(&text, range);
+                if group == Some(Group::Attribute) {
+                    self.line_break();
+                }
+            }
+            Symbol::Semicolon => {
+                self.glue = true;
+                self.write(&text, range);
+                if self.groups.last() != Some(&Group::ArrayType) {
+                    self.line_break();
+                }
+            }
+            Symbol::Comma => {
+                self.glue = true;
+                self.write(&text, range);
+                if matches!(
+                    self.groups.last(),
+                    Some(Group::Fields | Group::Array { broken: true })
+                ) {
+                    self.line_break();
+                }
+            }
+            Symbol::Colon => {
+                self.glue = true;
+                self.write(&text, range);
+            }
+            Symbol::Hash => {
+                self.write(&text, range);
+                self.glue = true;
+            }
+            Symbol::Dot | Symbol::DoubleColon => {
+                self.glue = true;
+                self.write(&text, range);
+                self.glue = true;
+            }
+            Symbol::Ampersand if !operand_end => {
+                // Reference, which is glued to what follows
+                self.write(&text, range);
+                self.glue = true;
+                unary = true;
+            }
+            _ => self.write(&text, range),
+        }
+        self.last = Some(token.value);
+        self.last_unary = unary;
+    }
+
+    fn format(mut self) -> String {
+        for index in 0..self.tokens.len() {
+            if let TokenKind::Comment(_) = self.tokens[index].value {
+                self.format_comment(index);
+            } else {
+                self.format_token(index);
+            }
+        }
+        self.out.push('\n');
+        self.out
+    }
+}
+
+/// Formats Cal code in canonical style. The formatted code is parsed again
+/// and an error is returned if it does not mean the same as the original.
+pub fn format(code: &str) -> Result<String, CalError> {
+    let tokens = tokenize(code)?;
+    let mut all_tokens: Vec<Token> = (*tokens).clone().collect();
+    all_tokens.extend(tokens.comments().iter().cloned());
+    all_tokens.sort_by_key(|token| token.range.start);
+
+    let module = parse(tokens)?;
+    let formatted = Formatter::new(code, all_tokens, &module).format();
+
+    match formatted.parse::<Module>() {
+        Ok(formatted_module) if formatted_module == module => Ok(formatted),
+        _ => Err(CalError::new(
+            "Formatting would change the meaning of the code".into(),
+            Range::default(),
+        )),
+    }
+}
@@ -2,26 +2,110 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
+use std::collections::{HashMap, HashSet};
+
 use crate::{
+    asm::source_map::Origin,
+    dce::eliminate_dead_code,
     error::CalError,
+    evaluator::Evaluator,
     expression::{Expression, Literal, Operator, Term, UnaryOperator},
+    ir,
     preamble::preamble,
     segment::Segment,
     statement::{IfStatement, Statement, WhileStatement},
-    structure::{Function, Module, Type, Variable},
+    structure::{mangle, Constant, Field, Function, Module, StructDec, Type, Variable},
     symboltable::{SymbolEntry, SymbolTable},
     tokenizer::Range,
-    vm::instruction::VmInstruction,
+    vm::{data::DataSection, instruction::VmInstruction},
 };
 
+/// VM instructions, each with the range of the statement it comes from
+type Tagged = Vec<(VmInstruction, Range)>;
+
+/// Range of the code of a statement, which is that of its expression, or
+/// of its predicate for `if` and `while`
+fn statement_range(statement: &Statement) -> Range {
+    match statement {
+        Statement::Expression(expr) | Statement::Return(Some(expr)) => expr.range,
+        Statement::Return(None) => Range::default(),
+        Statement::Let(variable, expr) => Range::new(variable.range.start, expr.range.end),
+        Statement::LetTuple(variables, expr) => {
+            let start = variables
+                .first()
+                .map_or(expr.range.start, |v| v.range.start);
+            Range::new(start, expr.range.end)
+        }
+        Statement::If(if_stat) => if_stat.predicate.range,
+        Statement::While(while_stat) => while_stat.predicate.range,
+    }
+}
+
+/// Tags every instruction with the same range
+fn tag(instructions: Vec<VmInstruction>, range: Range) -> Tagged {
+    instructions
+        .into_iter()
+        .map(|instruction| (instruction, range))
+        .collect()
+}
+
+/// Splits instructions into the prologue, before the first function, and
+/// the functions, returning the name and the index range of each chunk
+fn chunks(instructions: &[VmInstruction]) -> Vec<(Option<&str>, std::ops::Range<usize>)> {
+    let mut ret = vec![(None, 0..0)];
+    for (i, instruction) in instructions.iter().enumerate() {
+        if let VmInstruction::Function(name, _) = instruction {
+            ret.push((Some(name.as_str()), i..i));
+        }
+        ret.last_mut().unwrap().1.end = i + 1;
+    }
+    ret
+}
+
 /// Generates VM instructions from parsed code.
 #[derive(Default)]
 pub struct Generator {
     symbol_tables: Vec<SymbolTable>,
     label_count: u32,
+
+    /// Structs declared by the modules we are generating
+    structs: HashMap<String, StructDec>,
+    /// Functions declared by the modules we are generating
+    functions: HashMap<String, Function>,
+    /// Constants declared by the modules we are generating
+    constants: HashMap<String, Constant>,
+    /// Statics declared by the modules we are generating, with their types
+    /// resolved and their addresses in the data section
+    statics: HashMap<String, (Type, u16)>,
+    /// Initial values of the statics
+    data: DataSection,
+    /// Functions left out of the program, as `main` never calls them
+    pruned: Vec<String>,
+    /// Level of the optimisations run on the intermediate representation
+    opt_level: u8,
+    /// Origin of each instruction of the functions generated so far, or of
+    /// the program after the last call to `gen`
+    origins: Vec<Origin>,
+    /// Functions rewritten by the optimiser in the last call to `gen`, whose
+    /// instructions only map to their declaration
+    rewritten: Vec<String>,
+    /// Symbol table of each function generated, telling where its variables
+    /// are in its frame
+    frames: HashMap<String, SymbolTable>,
+
+    /// Parameters and local variables declared so far, with their types
+    /// resolved or inferred
+    declarations: Vec<Variable>,
+    /// Return type of the function being generated, resolved
+    return_type: Option<Type>,
 }
 
 impl Generator {
+    pub fn with_opt_level(mut self, opt_level: u8) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
     /// Generate a label at VM instructions level
     fn next_label(&mut self) -> String {
         let ret = format!("VM_LABEL{}", self.label_count);
@@ -29,19 +113,72 @@ impl Generator {
         ret
     }
 
-    /// Returns the size in bytes of the type
-    #[allow(clippy::only_used_in_recursion)]
-    fn get_type_size(&self, typ: &Type) -> u16 {
-        match typ {
-            Type::Void => 0,
-            Type::I16 | Type::Bool | Type::Char | Type::Ref(_) => 2,
-            Type::Array(elem_type, count) => self.get_type_size(elem_type.as_ref()) * count,
+    /// Returns an evaluator of constant expressions, where local variables
+    /// of the function being generated shadow constants
+    fn evaluator(&self) -> Evaluator<'_> {
+        Evaluator::new(
+            &self.constants,
+            &self.functions,
+            &self.structs,
+            self.symbol_tables.last(),
+        )
+    }
+
+    /// Resolves the array lengths of a type, returning an error if the type
+    /// refers to an undefined struct
+    fn resolve_type(&self, typ: &Type) -> Result<Type, CalError> {
+        self.evaluator().resolve_type(typ)
+    }
+
+    /// Returns the offset in words and the type of a field of a struct, or of
+    /// an element of a tuple, in which case the field is its position
+    fn get_field_offset_and_type(&self, typ: &Type, field: &str) -> Result<(u16, Type), CalError> {
+        let members: Vec<(String, Type)> = match typ {
+            Type::Struct(struct_name) => match self.structs.get(struct_name) {
+                Some(struct_dec) => struct_dec
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.clone(), field.typ.clone()))
+                    .collect(),
+                None => {
+                    return Err(CalError::new(
+                        format!("Undefined type `{}`", struct_name),
+                        Range::default(),
+                    ))
+                }
+            },
+            Type::Tuple(types) => types
+                .iter()
+                .enumerate()
+                .map(|(i, typ)| (i.to_string(), typ.clone()))
+                .collect(),
+            _ => {
+                return Err(CalError::new(
+                    format!("Expected struct or tuple, found {:?}", typ),
+                    Range::default(),
+                ))
+            }
+        };
+
+        let mut offset = 0;
+        for (name, field_type) in members {
+            if name == field {
+                return Ok((offset, field_type));
+            }
+            offset += self.get_type_size_in_words(&field_type);
         }
+
+        Err(CalError::new(
+            format!("No field `{}` in `{:?}`", field, typ),
+            Range::default(),
+        ))
     }
 
     /// Returns the size in words of the type
     fn get_type_size_in_words(&self, typ: &Type) -> u16 {
-        self.get_type_size(typ) / 2
+        self.evaluator()
+            .get_type_size_in_words(typ)
+            .unwrap_or_default()
     }
 
     fn get_current_symbol_table(&self) -> &SymbolTable {
@@ -55,10 +192,7 @@ impl Generator {
     #[allow(clippy::only_used_in_recursion)]
     fn gen_literal(&self, literal: &Literal) -> Result<Vec<VmInstruction>, CalError> {
         match literal {
-            Literal::I16(integer) => {
-                let integer = unsafe { std::mem::transmute::<i16, u16>(*integer) };
-                Ok(vec![VmInstruction::Push(Segment::Constant, integer)])
-            }
+            Literal::I16(integer) => Ok(Self::gen_constant(*integer)),
             Literal::Bool(false) => Ok(vec![VmInstruction::Push(Segment::Constant, 0)]),
             Literal::Bool(true) => Ok(vec![
                 VmInstruction::Push(Segment::Constant, 0),
@@ -75,6 +209,20 @@ impl Generator {
         }
     }
 
+    /// Generates VM instructions to push a constant value onto the VM stack.
+    /// Negative values do not fit into a constant push, hence their
+    /// complement is pushed and then negated.
+    fn gen_constant(value: i16) -> Vec<VmInstruction> {
+        if value < 0 {
+            vec![
+                VmInstruction::Push(Segment::Constant, !value as u16),
+                VmInstruction::Not,
+            ]
+        } else {
+            vec![VmInstruction::Push(Segment::Constant, value as u16)]
+        }
+    }
+
     /// Generates VM instructions to push a variable's onto the VM stack
     fn gen_variable(&self, name: &str) -> Result<Vec<VmInstruction>, CalError> {
         if let Some(entry) = self.get_current_symbol_table().get(name) {
@@ -84,6 +232,12 @@ impl Generator {
                 ret.push(VmInstruction::Push(entry.segment, entry.offset + i));
             }
             Ok(ret)
+        } else if let Some(constant) = self.constants.get(name) {
+            Ok(Self::gen_constant(
+                self.evaluator().eval_expression(&constant.value)?,
+            ))
+        } else if self.statics.contains_key(name) {
+            self.gen_place(&Term::Variable(name.into()))
         } else {
             Err(CalError::new(
                 format!("Undefined variable `{}`", name),
@@ -93,20 +247,14 @@ impl Generator {
     }
 
     /// Generates VM Instruction needed to push the address of an element of an
-    /// array, at the specified index, after the index expression has been
-    /// already generated
+    /// array variable, at the specified index, after the index expression has
+    /// been already generated. The index is multiplied by the stride of the
+    /// array, which is the size in words of its elements.
     fn gen_ref_index_impl(
         entry: &SymbolEntry,
         elem_size: u16,
     ) -> Result<Vec<VmInstruction>, CalError> {
-        let mut ret = vec![];
-
-        if elem_size > 1 {
-            ret.extend(vec![
-                VmInstruction::Push(Segment::Constant, elem_size),
-                VmInstruction::Call("mul".into(), 2),
-            ]);
-        }
+        let mut ret = Self::gen_stride(elem_size);
 
         ret.extend(vec![
             VmInstruction::Push(Segment::Constant, entry.offset),
@@ -120,99 +268,92 @@ impl Generator {
         Ok(ret)
     }
 
-    /// Generates VM instructions to index into an array.
-    /// - `reference`:
-    ///   - `true`: Pushes onto the stack the pointer to the element we are accessing
-    ///   - `false`: Pushes onto the stack the element we are accessing
-    fn gen_index_impl(
-        &self,
-        entry: &SymbolEntry,
-        elem_type: &Type,
-        reference: bool,
-    ) -> Result<Vec<VmInstruction>, CalError> {
-        let elem_size = self.get_type_size_in_words(elem_type);
-        let mut ret = Self::gen_ref_index_impl(entry, elem_size)?;
-
-        if !reference {
-            // Put element address into the pointer segment for accessing it
-            ret.push(VmInstruction::Pop(Segment::Pointer, 0));
-            // Push onto the stack all the words of the element we are indexing
-            for i in 0..elem_size {
-                ret.push(VmInstruction::Push(Segment::This, i));
-            }
-        }
-
-        Ok(ret)
-    }
-
-    /// Generates VM instructions to index into a reference to an array.
-    fn gen_index_of_ref_impl(
-        &self,
-        entry: &SymbolEntry,
-        ref_type: &Type,
-    ) -> Result<Vec<VmInstruction>, CalError> {
-        let mut ret = vec![];
-
-        let Type::Array(elem_type, _) = ref_type else {
-            unreachable!()
-        };
-        let elem_size = self.get_type_size_in_words(elem_type.as_ref());
+    /// Generates VM instructions to multiply the index at the top of the stack
+    /// by the size of the elements of an array
+    fn gen_stride(elem_size: u16) -> Vec<VmInstruction> {
         if elem_size > 1 {
-            ret.extend(vec![
+            vec![
                 VmInstruction::Push(Segment::Constant, elem_size),
                 VmInstruction::Call("mul".into(), 2),
-            ]);
+            ]
+        } else {
+            vec![]
         }
-        // Reference is already a pointer to the beginning of the array
-        ret.extend(vec![
-            VmInstruction::Push(entry.segment, entry.offset),
-            VmInstruction::Add,                      // pointer + index expression
-            VmInstruction::Pop(Segment::Pointer, 0), // put address in pointer
-        ]);
+    }
 
-        // Push onto the stack all the words of the element we are indexing
-        for i in 0..elem_size {
-            ret.push(VmInstruction::Push(Segment::This, i));
+    /// Generates VM instructions to push onto the stack the address of the
+    /// object a place refers to, returning them together with its type.
+    /// When the place holds a reference, the reference is followed.
+    fn gen_pointee_ref(&self, term: &Term) -> Result<(Vec<VmInstruction>, Type), CalError> {
+        match self.get_term_type(term)?.get_pointee() {
+            // Reference is already a pointer to the object
+            Some(ref_type) => Ok((self.gen_term(term)?, ref_type.clone())),
+            None => self.gen_place_ref(term),
         }
-
-        Ok(ret)
     }
 
-    /// Generate VM instructions to index into an array. The array can be
-    /// either an array or a reference to an array.
-    /// - `reference`:
-    ///   - `true`: Pushes onto the stack the pointer to the element we are accessing
-    ///   - `false`: Pushes onto the stack the element we are accessing
-    fn gen_index(
-        &self,
-        name: &str,
-        index_expr: &Expression,
-        reference: bool,
-    ) -> Result<Vec<VmInstruction>, CalError> {
-        if let Some(entry) = self.get_current_symbol_table().get(name) {
-            let mut ret = self.gen_expression(index_expr)?;
-            // At this point the index is at the top of the stack
-            // It should be multiplied by the size of the element of the array
-            match &entry.variable.typ {
-                Type::Array(elem_type, _) => {
-                    ret.extend(self.gen_index_impl(entry, elem_type, reference)?);
-                    Ok(ret)
+    /// Generates VM instructions to push onto the stack the address of a place
+    /// expression, returning them together with the type of the place.
+    /// A place is a variable, or any chain of index and field accesses on it.
+    fn gen_place_ref(&self, term: &Term) -> Result<(Vec<VmInstruction>, Type), CalError> {
+        match term {
+            Term::Variable(name) => {
+                Ok((self.gen_variable_ref(name)?, self.get_variable_type(name)?))
+            }
+            Term::Index(base, index_expr) => {
+                // At this point the index is at the top of the stack
+                // It should be multiplied by the size of the element of the array
+                let mut ret = self.gen_expression(index_expr)?;
+
+                if let Term::Variable(name) = base.as_ref() {
+                    if let Some(entry) = self.get_current_symbol_table().get(name) {
+                        if let Type::Array(elem_type, _) = &entry.variable.typ {
+                            let elem_size = self.get_type_size_in_words(elem_type);
+                            ret.extend(Self::gen_ref_index_impl(entry, elem_size)?);
+                            return Ok((ret, elem_type.as_ref().clone()));
+                        }
+                    }
                 }
-                Type::Ref(ref_type) if matches!(ref_type.as_ref(), Type::Array(_, _)) => {
-                    ret.extend(self.gen_index_of_ref_impl(entry, ref_type)?);
-                    Ok(ret)
+
+                let (base_ref, base_type) = self.gen_pointee_ref(base)?;
+                let Type::Array(elem_type, _) = base_type else {
+                    return Err(CalError::new(
+                        format!("Expected array, found {:?}", base_type),
+                        Range::default(),
+                    ));
+                };
+                ret.extend(Self::gen_stride(self.get_type_size_in_words(&elem_type)));
+                ret.extend(base_ref);
+                ret.push(VmInstruction::Add); // array address + index expression
+                Ok((ret, *elem_type))
+            }
+            Term::Field(base, field) => {
+                let (mut ret, base_type) = self.gen_pointee_ref(base)?;
+                let (offset, field_type) = self.get_field_offset_and_type(&base_type, field)?;
+                if offset > 0 {
+                    ret.extend(vec![
+                        VmInstruction::Push(Segment::Constant, offset),
+                        VmInstruction::Add, // struct address + field offset
+                    ]);
                 }
-                _ => Err(CalError::new(
-                    format!("Expected array, found {:?}", entry.variable.typ),
-                    Range::default(),
-                )),
+                Ok((ret, field_type))
             }
-        } else {
-            Err(CalError::new(
-                format!("Undefined variable `{}`", name),
+            _ => Err(CalError::new(
+                format!("Expected place expression, found {:?}", term),
                 Range::default(),
-            ))
+            )),
+        }
+    }
+
+    /// Generates VM instructions to push all the words of a place expression
+    fn gen_place(&self, term: &Term) -> Result<Vec<VmInstruction>, CalError> {
+        let (mut ret, typ) = self.gen_place_ref(term)?;
+        // Put address into the pointer segment for accessing it
+        ret.push(VmInstruction::Pop(Segment::Pointer, 0));
+        for i in 0..self.get_type_size_in_words(&typ) {
+            ret.push(VmInstruction::Push(Segment::This, i));
         }
+        Ok(ret)
     }
 
     /// Generate VM instructions to push the address of a variable onto the stack
@@ -227,6 +368,8 @@ impl Generator {
                 VmInstruction::Push(Segment::Constant, offset),
                 VmInstruction::Add,
             ])
+        } else if let Some((_, address)) = self.statics.get(name) {
+            Ok(vec![VmInstruction::Push(Segment::Constant, *address)])
         } else {
             Err(CalError::new(
                 format!("Undefined variable `{}`", name),
@@ -235,35 +378,359 @@ impl Generator {
         }
     }
 
+    /// Generates VM instructions to push the address of the receiver of a
+    /// method call, returning them together with the name of its struct
+    fn gen_receiver(&self, receiver: &Term) -> Result<(Vec<VmInstruction>, String), CalError> {
+        match self.gen_pointee_ref(receiver)? {
+            (ret, Type::Struct(struct_name)) => Ok((ret, struct_name)),
+            (_, typ) => Err(CalError::new(
+                format!("Expected struct as method receiver, found {:?}", typ),
+                Range::default(),
+            )),
+        }
+    }
+
+    /// Returns the number of words occupied by the arguments of a call
+    fn get_arguments_size_in_words(&self, name: &str, expressions: &[Expression]) -> u16 {
+        if let Some(function) = self.functions.get(name) {
+            function
+                .parameters
+                .iter()
+                .map(|parameter| self.get_type_size_in_words(&parameter.typ))
+                .sum()
+        } else {
+            // Built-in functions take one word for each argument
+            expressions.len() as u16
+        }
+    }
+
+    /// Returns the return type of a function, including built-in ones
+    fn get_return_type(&self, name: &str) -> Result<Type, CalError> {
+        match name {
+            "peek" | "mul" | "div" | "mod" => Ok(Type::I16),
+            "poke" => Ok(Type::Void),
+            _ => match self.functions.get(name) {
+                Some(function) => Ok(function.return_type.clone()),
+                None => Err(CalError::new(
+                    format!("Undefined function `{}`", name),
+                    Range::default(),
+                )),
+            },
+        }
+    }
+
+    fn get_variable_type(&self, name: &str) -> Result<Type, CalError> {
+        match self.get_current_symbol_table().get(name) {
+            Some(entry) => Ok(entry.variable.typ.clone()),
+            None if self.constants.contains_key(name) => Ok(self.constants[name].typ.clone()),
+            None if self.statics.contains_key(name) => Ok(self.statics[name].0.clone()),
+            None => Err(CalError::new(
+                format!("Undefined variable `{}`", name),
+                Range::default(),
+            )),
+        }
+    }
+
+    fn get_literal_type(literal: &Literal) -> Type {
+        match literal {
+            Literal::I16(_) => Type::I16,
+            Literal::Bool(_) => Type::Bool,
+            Literal::Char(_) => Type::Char,
+            Literal::Array(values) => {
+                let elem_type = values.first().map_or(Type::Void, Self::get_literal_type);
+                Type::Array(Box::new(elem_type), values.len() as u16)
+            }
+        }
+    }
+
+    /// Infers the type of the object a term refers to, following references
+    fn get_pointee_type(&self, term: &Term) -> Result<Type, CalError> {
+        let typ = self.get_term_type(term)?;
+        Ok(typ.get_pointee().cloned().unwrap_or(typ))
+    }
+
+    /// Infers the type of a term
+    pub fn get_term_type(&self, term: &Term) -> Result<Type, CalError> {
+        match term {
+            Term::Literal(literal) => Ok(Self::get_literal_type(literal)),
+            Term::Call(name, _) => self.get_return_type(name),
+            Term::Variable(name) => self.get_variable_type(name),
+            Term::Index(base, _) => match self.get_pointee_type(base)? {
+                Type::Array(elem_type, _) => Ok(*elem_type),
+                typ => Err(CalError::new(
+                    format!("Expected array, found {:?}", typ),
+                    Range::default(),
+                )),
+            },
+            Term::Field(base, field) => {
+                let typ = self.get_pointee_type(base)?;
+                Ok(self.get_field_offset_and_type(&typ, field)?.1)
+            }
+            Term::MethodCall(receiver, method, _) => match self.get_pointee_type(receiver)? {
+                Type::Struct(struct_name) => self.get_return_type(&mangle(&struct_name, method)),
+                typ => Err(CalError::new(
+                    format!("Expected struct as method receiver, found {:?}", typ),
+                    Range::default(),
+                )),
+            },
+            Term::Struct(name, _) => Ok(Type::Struct(name.clone())),
+            Term::Array(expressions) => {
+                let elem_type = match expressions.first() {
+                    Some(expr) => self.get_expression_type(expr)?,
+                    None => Type::Void,
+                };
+                Ok(Type::Array(Box::new(elem_type), expressions.len() as u16))
+            }
+            Term::Tuple(expressions) => Ok(Type::Tuple(
+                expressions
+                    .iter()
+                    .map(|expr| self.get_expression_type(expr))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Term::Expression(expr) => self.get_expression_type(expr),
+            Term::SizeOf(_) => Ok(Type::I16),
+            Term::UnaryOp(UnaryOperator::Ref, rhs) => {
+                Ok(Type::Ref(Box::new(self.get_term_type(rhs)?)))
+            }
+            Term::UnaryOp(UnaryOperator::MutRef, rhs) => {
+                Ok(Type::MutRef(Box::new(self.get_term_type(rhs)?)))
+            }
+        }
+    }
+
+    /// Infers the type of an expression
+    pub fn get_expression_type(&self, expr: &Expression) -> Result<Type, CalError> {
+        match &expr.op_and_expr {
+            Some((Operator::Eq | Operator::Ne | Operator::Lt | Operator::Gt, _)) => Ok(Type::Bool),
+            Some((Operator::Assign, _)) => Ok(Type::Void),
+            _ => self.get_term_type(&expr.term),
+        }
+    }
+
+    /// Returns an error if a place can not be assigned or mutably borrowed,
+    /// which is when it is not rooted in a mutable variable, or when it is
+    /// behind an immutable reference
+    fn check_mutable_place(&self, term: &Term, action: &str) -> Result<(), CalError> {
+        match term {
+            Term::Variable(name) => {
+                let Some(entry) = self.get_current_symbol_table().get(name) else {
+                    if self.statics.contains_key(name) {
+                        return Err(CalError::new(
+                            format!("Cannot {} static `{}`, which is read-only", action, name),
+                            Range::default(),
+                        ));
+                    }
+                    return Err(CalError::new(
+                        format!("Cannot {} `{}`, which is not a variable", action, name),
+                        Range::default(),
+                    ));
+                };
+                match &entry.variable.typ {
+                    // Assigning a reference writes through it
+                    Type::MutRef(_) => Ok(()),
+                    Type::Ref(_) => Err(CalError::new(
+                        format!(
+                            "Cannot {} `{}`, which is a `&` reference, declare it as `&mut`",
+                            action, name
+                        ),
+                        Range::default(),
+                    )),
+                    _ if entry.variable.mutable => Ok(()),
+                    _ => Err(CalError::new(
+                        format!(
+                            "Cannot {} immutable variable `{}`, declare it as `mut {}`",
+                            action, name, name
+                        ),
+                        Range::default(),
+                    )),
+                }
+            }
+            Term::Index(base, _) | Term::Field(base, _) => match self.get_term_type(base)? {
+                Type::MutRef(_) => Ok(()),
+                Type::Ref(_) => Err(CalError::new(
+                    format!(
+                        "Cannot {} a place behind a `&` reference, use `&mut` instead",
+                        action
+                    ),
+                    Range::default(),
+                )),
+                _ => self.check_mutable_place(base, action),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error if an expression evaluates to a `&` reference while a
+    /// `&mut` reference is expected, even as an element of a tuple or array
+    fn check_ref_coercion(&self, expected: &Type, expr: &Expression) -> Result<(), CalError> {
+        match self.get_expression_type(expr) {
+            Ok(found) if expected.rejects_shared_ref(&found) => Err(CalError::new(
+                "Expected `&mut` reference, found `&` reference".into(),
+                expr.range,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error if the arguments do not match the parameters of a
+    /// function, where the receiver of a method is skipped
+    fn check_arguments(
+        &self,
+        parameters: &[Variable],
+        expressions: &[Expression],
+    ) -> Result<(), CalError> {
+        for (parameter, expr) in parameters.iter().zip(expressions) {
+            self.check_ref_coercion(&parameter.typ, expr)?;
+        }
+        Ok(())
+    }
+
+    fn gen_call(
+        &self,
+        name: &str,
+        expressions: &[Expression],
+    ) -> Result<Vec<VmInstruction>, CalError> {
+        if let Some(function) = self.functions.get(name) {
+            self.check_arguments(&function.parameters, expressions)?;
+        }
+        let mut ret = vec![];
+        for expr in expressions {
+            ret.extend(self.gen_expression(expr)?);
+        }
+        let arguments_size = self.get_arguments_size_in_words(name, expressions);
+        ret.push(VmInstruction::Call(name.into(), arguments_size));
+        Ok(ret)
+    }
+
+    /// Generates VM instructions for a method call, which is a call to the
+    /// mangled function `Type.method` with the receiver address as argument 0
+    fn gen_method_call(
+        &self,
+        receiver: &Term,
+        method: &str,
+        expressions: &[Expression],
+    ) -> Result<Vec<VmInstruction>, CalError> {
+        let (mut ret, struct_name) = self.gen_receiver(receiver)?;
+        let name = mangle(&struct_name, method);
+        let Some(function) = self.functions.get(&name) else {
+            return Err(CalError::new(
+                format!("No method `{}` found for `{}`", method, struct_name),
+                Range::default(),
+            ));
+        };
+        let receiver_type = match function.parameters.first() {
+            Some(parameter) if parameter.name == "self" => &parameter.typ,
+            _ => {
+                return Err(CalError::new(
+                    format!("`{}` is an associated function, not a method", name),
+                    Range::default(),
+                ))
+            }
+        };
+        if let Type::MutRef(_) = receiver_type {
+            // The receiver may already be a reference to the object
+            match self.get_term_type(receiver)? {
+                Type::MutRef(_) => (),
+                Type::Ref(_) => {
+                    return Err(CalError::new(
+                        format!(
+                            "Cannot call `{}`, which takes `&mut self`, behind a `&` reference",
+                            name
+                        ),
+                        Range::default(),
+                    ))
+                }
+                _ => self.check_mutable_place(receiver, "mutably borrow")?,
+            }
+        }
+        self.check_arguments(&function.parameters[1..], expressions)?;
+        for expr in expressions {
+            ret.extend(self.gen_expression(expr)?);
+        }
+        let arguments_size = self.get_arguments_size_in_words(&name, expressions);
+        ret.push(VmInstruction::Call(name, arguments_size));
+        Ok(ret)
+    }
+
+    /// Generates VM instructions for a struct literal, pushing its fields in
+    /// the order they are declared
+    fn gen_struct_literal(
+        &self,
+        name: &str,
+        initializers: &[(String, Expression)],
+    ) -> Result<Vec<VmInstruction>, CalError> {
+        let Some(struct_dec) = self.structs.get(name) else {
+            return Err(CalError::new(
+                format!("Undefined type `{}`", name),
+                Range::default(),
+            ));
+        };
+
+        for (field_name, _) in initializers {
+            if struct_dec.get_field(field_name).is_none() {
+                return Err(CalError::new(
+                    format!("No field `{}` in struct `{}`", field_name, name),
+                    Range::default(),
+                ));
+            }
+        }
+
+        let mut ret = vec![];
+        for field in &struct_dec.fields {
+            let Some((_, expression)) = initializers.iter().find(|(n, _)| *n == field.name) else {
+                return Err(CalError::new(
+                    format!(
+                        "Missing field `{}` in initializer of `{}`",
+                        field.name, name
+                    ),
+                    Range::default(),
+                ));
+            };
+            self.check_ref_coercion(&field.typ, expression)?;
+            ret.extend(self.gen_expression(expression)?);
+        }
+        Ok(ret)
+    }
+
     fn gen_unary_operator(
         &self,
         unary_op: UnaryOperator,
         rhs: &Term,
     ) -> Result<Vec<VmInstruction>, CalError> {
-        match unary_op {
-            UnaryOperator::Ref => match rhs {
-                Term::Variable(name) => self.gen_variable_ref(name),
-                Term::Index(name, index_expr) => self.gen_index(name, index_expr, true),
-                _ => Err(CalError::new(
-                    format!("Expected variable after `&`, found {:?}", rhs),
-                    Range::default(),
-                )),
-            },
+        match rhs {
+            Term::Variable(_) | Term::Index(_, _) | Term::Field(_, _) => {
+                if unary_op == UnaryOperator::MutRef {
+                    self.check_mutable_place(rhs, "mutably borrow")?;
+                }
+                Ok(self.gen_place_ref(rhs)?.0)
+            }
+            _ => Err(CalError::new(
+                format!("Expected variable after `&`, found {:?}", rhs),
+                Range::default(),
+            )),
         }
     }
 
     fn gen_term(&self, term: &Term) -> Result<Vec<VmInstruction>, CalError> {
         match term {
             Term::Literal(literal) => self.gen_literal(literal),
-            Term::Call(name, expressions) => {
+            Term::Call(name, expressions) => self.gen_call(name, expressions),
+            Term::Index(_, _) | Term::Field(_, _) => self.gen_place(term),
+            Term::MethodCall(receiver, method, expressions) => {
+                self.gen_method_call(receiver, method, expressions)
+            }
+            Term::Struct(name, initializers) => self.gen_struct_literal(name, initializers),
+            Term::Array(expressions) | Term::Tuple(expressions) => {
                 let mut ret = vec![];
                 for expr in expressions {
                     ret.extend(self.gen_expression(expr)?);
                 }
-                ret.push(VmInstruction::Call(name.clone(), expressions.len() as u16));
                 Ok(ret)
             }
-            Term::Index(name, expr) => self.gen_index(name, expr, false),
+            Term::Expression(expr) => self.gen_expression(expr),
+            Term::SizeOf(typ) => Ok(Self::gen_constant(
+                self.evaluator().get_type_size_in_words(typ)? as i16,
+            )),
             Term::Variable(name) => self.gen_variable(name),
             Term::UnaryOp(unary_op, rhs) => self.gen_unary_operator(*unary_op, rhs.as_ref()),
         }
@@ -297,10 +764,10 @@ impl Generator {
     ) -> Result<Vec<VmInstruction>, CalError> {
         let mut ret = vec![];
 
-        if let Type::Ref(typ) = &variable.typ {
+        if let Some(typ) = variable.typ.get_pointee() {
             ret.push(VmInstruction::Push(segment, offset));
             ret.push(VmInstruction::Pop(Segment::Pointer, 0));
-            let word_count = self.get_type_size_in_words(typ.as_ref());
+            let word_count = self.get_type_size_in_words(typ);
             for i in 0..word_count {
                 ret.push(VmInstruction::Pop(Segment::This, word_count - i - 1));
             }
@@ -335,54 +802,24 @@ impl Generator {
         }
     }
 
-    /// Generates VM instructions to copy the stack backwards into the memory
-    /// segment representing a certain index term
-    pub fn gen_copy_stack_into_index(
+    /// Generates VM instructions to assign an expression to a place, which
+    /// is an index or a field access
+    pub fn gen_assign_expression_to_place(
         &self,
-        entry: &SymbolEntry,
-        index_expr: &Expression,
-    ) -> Result<Vec<VmInstruction>, CalError> {
-        // Push index expression onto the stack
-        let mut ret = self.gen_expression(index_expr)?;
-
-        if let Type::Ref(_) = &entry.variable.typ {
-            // Reference is already a pointer to the beginning of the array
-            ret.push(VmInstruction::Push(entry.segment, entry.offset));
-            ret.push(VmInstruction::Add); // pointer + index expression
-            ret.push(VmInstruction::Pop(Segment::Pointer, 0)); // put address in pointer
-            ret.push(VmInstruction::Pop(Segment::This, 0)); // put rhs in *pointer
-        } else {
-            ret.extend(vec![
-                VmInstruction::Push(Segment::Constant, entry.offset),
-                VmInstruction::Add, // offset + index expression
-                VmInstruction::Push(Segment::Constant, entry.segment.get_base_address() as u16),
-                VmInstruction::Pop(Segment::Pointer, 0),
-                VmInstruction::Push(Segment::This, 0),
-                VmInstruction::Add, // *segment + offset + expression
-                VmInstruction::Pop(Segment::Pointer, 0),
-                VmInstruction::Pop(Segment::This, 0), // *pointer = rhs
-            ]);
-        }
-        Ok(ret)
-    }
-
-    pub fn gen_assign_expression_to_index(
-        &self,
-        name: &str,
-        index_expr: &Expression,
+        term: &Term,
         rhs: &Expression,
     ) -> Result<Vec<VmInstruction>, CalError> {
-        if let Some(entry) = self.get_current_symbol_table().get(name) {
-            // Push rhs onto the stack
-            let mut ret = self.gen_expression(rhs)?;
-            ret.extend(self.gen_copy_stack_into_index(entry, index_expr)?);
-            Ok(ret)
-        } else {
-            Err(CalError::new(
-                format!("Undefined variable `{}`", name),
-                Range::default(),
-            ))
+        // Push rhs onto the stack
+        let mut ret = self.gen_expression(rhs)?;
+        let (place_ref, place_type) = self.gen_place_ref(term)?;
+        ret.extend(place_ref);
+        ret.push(VmInstruction::Pop(Segment::Pointer, 0)); // put address in pointer
+                                                           // Copy the stack backwards into the place
+        let word_count = self.get_type_size_in_words(&place_type);
+        for i in 0..word_count {
+            ret.push(VmInstruction::Pop(Segment::This, word_count - i - 1));
         }
+        Ok(ret)
     }
 
     pub fn gen_assign_expression(
@@ -393,9 +830,7 @@ impl Generator {
         // Get variable name from previous term
         match term {
             Term::Variable(name) => self.gen_assign_expression_to_variable(name, rhs),
-            Term::Index(name, index_expr) => {
-                self.gen_assign_expression_to_index(name, index_expr, rhs)
-            }
+            Term::Index(_, _) | Term::Field(_, _) => self.gen_assign_expression_to_place(term, rhs),
             _ => Err(CalError::new(
                 format!("Expected variable to the left of `=`, found {:?}", term),
                 Range::default(),
@@ -404,21 +839,37 @@ impl Generator {
     }
 
     pub fn gen_expression(&self, expr: &Expression) -> Result<Vec<VmInstruction>, CalError> {
-        if let Some((op, rhs)) = &expr.op_and_expr {
+        // Fold expressions whose value is known at compile time
+        let is_literal = matches!(*expr.term, Term::Literal(_)) && expr.op_and_expr.is_none();
+        if !is_literal {
+            if let Ok(value) = self.evaluator().eval_expression(expr) {
+                return Ok(Self::gen_constant(value));
+            }
+        }
+
+        let ret = if let Some((op, rhs)) = &expr.op_and_expr {
             if *op == Operator::Assign {
                 // Special case for assign expression
-                self.gen_assign_expression(expr.term.as_ref(), rhs.as_ref())
+                self.check_mutable_place(expr.term.as_ref(), "assign to")
+                    .and_then(|_| self.gen_assign_expression(expr.term.as_ref(), rhs.as_ref()))
             } else {
                 // Common case
-                let mut ret = self.gen_term(expr.term.as_ref())?;
-                ret.extend(self.gen_expression(rhs.as_ref())?);
-                ret.extend(self.gen_operator(op));
-                Ok(ret)
+                self.gen_term(expr.term.as_ref()).and_then(|mut ret| {
+                    ret.extend(self.gen_expression(rhs.as_ref())?);
+                    ret.extend(self.gen_operator(op));
+                    Ok(ret)
+                })
             }
         } else {
             // Generate instructions for the term only
             self.gen_term(expr.term.as_ref())
-        }
+        };
+
+        // Errors without a range are located at the innermost expression
+        ret.map_err(|err| match err.range {
+            range if range == Range::default() => CalError::new(err.message, expr.range),
+            _ => err,
+        })
     }
 
     pub fn gen_return(
@@ -427,6 +878,9 @@ impl Generator {
     ) -> Result<Vec<VmInstruction>, CalError> {
         let mut ret = vec![];
         if let Some(expr) = expr {
+            if let Some(return_type) = &self.return_type {
+                self.check_ref_coercion(return_type, expr)?;
+            }
             ret.extend(self.gen_expression(expr)?);
         }
         // Return is not known at this point. Let `gen_function` set it before returning.
@@ -439,70 +893,151 @@ impl Generator {
         variable: &Variable,
         assign_expression: &Expression,
     ) -> Result<Vec<VmInstruction>, CalError> {
+        let variable = &Variable {
+            typ: self.resolve_type(&variable.typ)?,
+            ..variable.clone()
+        };
+        self.check_ref_coercion(&variable.typ, assign_expression)?;
         let mut ret = vec![];
         ret.extend(self.gen_expression(assign_expression)?);
-        let offset = self.get_current_symbol_table_mut().insert_local(variable);
-        ret.extend(self.gen_copy_stack_into_variable(variable, Segment::Local, offset)?);
+        let size_in_words = self.get_type_size_in_words(&variable.typ);
+        let offset = self
+            .get_current_symbol_table_mut()
+            .insert_local(variable, size_in_words);
+        self.declarations.push(variable.clone());
+        if variable.typ.get_pointee().is_some() {
+            // A reference is bound to an address, rather than written through
+            ret.push(VmInstruction::Pop(Segment::Local, offset));
+        } else {
+            ret.extend(self.gen_copy_stack_into_variable(variable, Segment::Local, offset)?);
+        }
+        Ok(ret)
+    }
+
+    /// Generates VM instructions for a destructuring let, where variables with
+    /// `Void` type have their type inferred from the tuple expression
+    pub fn gen_let_tuple(
+        &mut self,
+        variables: &[Variable],
+        assign_expression: &Expression,
+    ) -> Result<Vec<VmInstruction>, CalError> {
+        let Type::Tuple(types) = self.get_expression_type(assign_expression)? else {
+            return Err(CalError::new(
+                format!(
+                    "Expected tuple to destructure, found {:?}",
+                    assign_expression
+                ),
+                Range::default(),
+            ));
+        };
+        if types.len() != variables.len() {
+            return Err(CalError::new(
+                format!(
+                    "Expected a tuple of {} elements, found {}",
+                    variables.len(),
+                    types.len()
+                ),
+                Range::default(),
+            ));
+        }
+
+        let mut ret = self.gen_expression(assign_expression)?;
+
+        // Locals are allocated contiguously, just like the elements of the tuple
+        let mut first_offset = None;
+        let mut word_count = 0;
+        for (variable, typ) in variables.iter().zip(types) {
+            let typ = if variable.typ == Type::Void {
+                typ
+            } else {
+                self.resolve_type(&variable.typ)?
+            };
+            let variable = Variable {
+                typ,
+                ..variable.clone()
+            };
+            let size_in_words = self.get_type_size_in_words(&variable.typ);
+            let offset = self
+                .get_current_symbol_table_mut()
+                .insert_local(&variable, size_in_words);
+            self.declarations.push(variable);
+            first_offset.get_or_insert(offset);
+            word_count += size_in_words;
+        }
+
+        let first_offset = first_offset.unwrap_or_default();
+        for i in 0..word_count {
+            ret.push(VmInstruction::Pop(
+                Segment::Local,
+                first_offset + word_count - i - 1,
+            ));
+        }
         Ok(ret)
     }
 
     /// Generates VM instructions for an if statement
-    pub fn gen_if(&mut self, if_stat: &IfStatement) -> Result<Vec<VmInstruction>, CalError> {
+    pub fn gen_if(&mut self, if_stat: &IfStatement) -> Result<Tagged, CalError> {
         let else_label = self.next_label();
         let endif_label = self.next_label();
+        let range = if_stat.predicate.range;
 
-        let mut ret = self.gen_expression(&if_stat.predicate)?;
-        ret.push(VmInstruction::Not);
-        ret.push(VmInstruction::IfGoto(else_label.clone()));
+        let mut ret = tag(self.gen_expression(&if_stat.predicate)?, range);
+        ret.push((VmInstruction::Not, range));
+        ret.push((VmInstruction::IfGoto(else_label.clone()), range));
+
+        // Jumping out of a branch belongs to its last statement, so that
+        // stepping through the code does not come back to the predicate
+        let last_range = |ret: &Tagged| ret.last().map_or(range, |(_, range)| *range);
 
         ret.extend(self.gen_statements(&if_stat.if_branch)?);
-        ret.push(VmInstruction::Goto(endif_label.clone()));
+        ret.push((VmInstruction::Goto(endif_label.clone()), last_range(&ret)));
 
-        ret.push(VmInstruction::Label(else_label));
+        ret.push((VmInstruction::Label(else_label), range));
         ret.extend(self.gen_statements(&if_stat.else_branch)?);
 
-        ret.push(VmInstruction::Label(endif_label));
+        ret.push((VmInstruction::Label(endif_label), last_range(&ret)));
 
         Ok(ret)
     }
 
     /// Generates VM instructions for a while statement
-    pub fn gen_while(
-        &mut self,
-        while_stat: &WhileStatement,
-    ) -> Result<Vec<VmInstruction>, CalError> {
+    pub fn gen_while(&mut self, while_stat: &WhileStatement) -> Result<Tagged, CalError> {
         let while_label = self.next_label();
         let endwhile_label = self.next_label();
+        let range = while_stat.predicate.range;
 
-        let mut ret = vec![VmInstruction::Label(while_label.clone())];
-        ret.extend(self.gen_expression(&while_stat.predicate)?);
-        ret.push(VmInstruction::Not);
-        ret.push(VmInstruction::IfGoto(endwhile_label.clone()));
+        let mut ret = vec![(VmInstruction::Label(while_label.clone()), range)];
+        ret.extend(tag(self.gen_expression(&while_stat.predicate)?, range));
+        ret.push((VmInstruction::Not, range));
+        ret.push((VmInstruction::IfGoto(endwhile_label.clone()), range));
 
         ret.extend(self.gen_statements(&while_stat.body)?);
-        ret.push(VmInstruction::Goto(while_label));
+        ret.push((VmInstruction::Goto(while_label), range));
 
-        ret.push(VmInstruction::Label(endwhile_label));
+        ret.push((VmInstruction::Label(endwhile_label), range));
 
         Ok(ret)
     }
 
-    pub fn gen_statement(&mut self, statement: &Statement) -> Result<Vec<VmInstruction>, CalError> {
-        match statement {
-            Statement::Return(expr) => self.gen_return(expr),
-            Statement::Expression(expression) => self.gen_expression(expression),
+    /// Generates VM instructions for a statement, tagged with the range of
+    /// the statement they come from, which is nested for `if` and `while`
+    pub fn gen_statement(&mut self, statement: &Statement) -> Result<Tagged, CalError> {
+        let range = statement_range(statement);
+        Ok(match statement {
+            Statement::Return(expr) => tag(self.gen_return(expr)?, range),
+            Statement::Expression(expression) => tag(self.gen_expression(expression)?, range),
             Statement::Let(variable, assign_expression) => {
-                self.gen_let(variable, assign_expression)
+                tag(self.gen_let(variable, assign_expression)?, range)
             }
-            Statement::If(ifstat) => self.gen_if(ifstat),
-            Statement::While(whilestat) => self.gen_while(whilestat),
-        }
+            Statement::LetTuple(variables, assign_expression) => {
+                tag(self.gen_let_tuple(variables, assign_expression)?, range)
+            }
+            Statement::If(ifstat) => self.gen_if(ifstat)?,
+            Statement::While(whilestat) => self.gen_while(whilestat)?,
+        })
     }
 
-    pub fn gen_statements(
-        &mut self,
-        statements: &[Statement],
-    ) -> Result<Vec<VmInstruction>, CalError> {
+    pub fn gen_statements(&mut self, statements: &[Statement]) -> Result<Tagged, CalError> {
         let mut ret = vec![];
         for statement in statements {
             ret.extend(self.gen_statement(statement)?);
@@ -510,58 +1045,191 @@ impl Generator {
         Ok(ret)
     }
 
-    /// Calculates and returns the size of the local segment
-    fn get_local_size_in_words(&self, statements: &[Statement]) -> u16 {
-        let mut ret = 0;
-        for s in statements {
-            if let Statement::Let(variable, _) = s {
-                ret += self.get_type_size_in_words(&variable.typ);
-            }
-        }
-        ret
-    }
-
     /// Generates VM instructions for a function
     pub fn gen_function(&mut self, function: &Function) -> Result<Vec<VmInstruction>, CalError> {
         // New symbol table
         self.symbol_tables.push(SymbolTable::default());
 
-        let local_size_in_words = self.get_local_size_in_words(&function.body_statements);
-
-        let mut ret = vec![VmInstruction::Function(
-            function.name.clone(),
-            local_size_in_words,
+        // Size of the local segment is known after generating the body
+        let mut ret = vec![(
+            VmInstruction::Function(function.name.clone(), 0),
+            function.range,
         )];
 
         // Add function arguments to symbol table
         for arg in &function.parameters {
-            self.get_current_symbol_table_mut().insert_argument(arg);
+            let arg = Variable {
+                typ: self.resolve_type(&arg.typ)?,
+                ..arg.clone()
+            };
+            let size_in_words = self.get_type_size_in_words(&arg.typ);
+            self.get_current_symbol_table_mut()
+                .insert_argument(&arg, size_in_words);
+            self.declarations.push(arg);
         }
+        let return_type = self.resolve_type(&function.return_type)?;
+        self.return_type = Some(return_type.clone());
 
         ret.extend(self.gen_statements(&function.body_statements)?);
+        // The last expression is returned as well
+        if let Some(Statement::Expression(expr)) = function.body_statements.last() {
+            self.check_ref_coercion(&return_type, expr)?;
+        }
+        self.return_type = None;
+
+        let local_size_in_words = self.get_current_symbol_table().get_local_count();
+        ret[0].0 = VmInstruction::Function(function.name.clone(), local_size_in_words);
 
         // Set the return type size to all return instruction
-        let return_type_size_in_words = self.get_type_size_in_words(&function.return_type);
+        let return_type_size_in_words = self.get_type_size_in_words(&return_type);
 
-        ret.iter_mut().for_each(|instr| {
+        ret.iter_mut().for_each(|(instr, _)| {
             if let VmInstruction::Return(size_in_words) = instr {
                 *size_in_words = return_type_size_in_words;
             }
         });
 
         // Add a return if missing
-        if !matches!(ret.last(), Some(VmInstruction::Return(_))) {
-            ret.push(VmInstruction::Return(return_type_size_in_words));
+        if !matches!(ret.last(), Some((VmInstruction::Return(_), _))) {
+            ret.push((
+                VmInstruction::Return(return_type_size_in_words),
+                function.range,
+            ));
         }
 
-        // Clear symbol table for this function
-        self.symbol_tables.pop();
+        // Keep the symbol table of this function, which is done with
+        let symbol_table = self.symbol_tables.pop().unwrap();
+        self.frames.insert(function.name.clone(), symbol_table);
 
+        let (ret, ranges): (Vec<VmInstruction>, Vec<Range>) = ret.into_iter().unzip();
+        self.origins.extend(
+            ranges
+                .into_iter()
+                .map(|range| Origin::new(&function.name, range)),
+        );
         Ok(ret)
     }
 
+    /// Makes sure a struct does not contain itself by value, through the
+    /// structs of its fields or of the elements of their arrays and tuples,
+    /// as it would have no size. `path` holds the structs being visited
+    fn check_struct_cycle(&self, name: &str, path: &mut Vec<String>) -> Result<(), CalError> {
+        fn contained(typ: &Type, ret: &mut Vec<String>) {
+            match typ {
+                Type::Struct(name) => ret.push(name.clone()),
+                Type::Array(elem_type, _) | Type::ArrayExpr(elem_type, _) => {
+                    contained(elem_type, ret)
+                }
+                Type::Tuple(types) => types.iter().for_each(|typ| contained(typ, ret)),
+                _ => (),
+            }
+        }
+
+        let Some(struct_dec) = self.structs.get(name) else {
+            return Ok(());
+        };
+        path.push(name.to_string());
+        for field in &struct_dec.fields {
+            let mut structs = vec![];
+            contained(&field.typ, &mut structs);
+            for other in structs {
+                if path.contains(&other) {
+                    return Err(CalError::new(
+                        format!(
+                            "Field `{}` of `{}` contains `{}` by value, which would make it infinitely large",
+                            field.name, name, other
+                        ),
+                        field.range,
+                    ));
+                }
+                self.check_struct_cycle(&other, path)?;
+            }
+        }
+        path.pop();
+        Ok(())
+    }
+
+    /// Registers constants, structs and functions declared by a module, so
+    /// that they can be referred to by any module we are generating. Types
+    /// are registered with their array lengths resolved.
+    fn register_module(&mut self, module: &Module) -> Result<(), CalError> {
+        for constant in &module.constants {
+            self.constants
+                .insert(constant.name.clone(), constant.clone());
+        }
+        for struct_dec in &module.structs {
+            self.structs
+                .insert(struct_dec.name.clone(), struct_dec.clone());
+        }
+        for function in &module.functions {
+            self.functions
+                .insert(function.name.clone(), function.clone());
+        }
+
+        for constant in &module.constants {
+            let typ = self.resolve_type(&constant.typ)?;
+            // Report errors of constants even if they are never used
+            self.evaluator().eval_expression(&constant.value)?;
+            self.constants.get_mut(&constant.name).unwrap().typ = typ;
+        }
+        for struct_dec in &module.structs {
+            let fields = struct_dec
+                .fields
+                .iter()
+                .map(|field| {
+                    Ok(
+                        Field::new(field.name.clone(), self.resolve_type(&field.typ)?)
+                            .with_range(field.range),
+                    )
+                })
+                .collect::<Result<_, CalError>>()?;
+            self.structs.get_mut(&struct_dec.name).unwrap().fields = fields;
+        }
+        for struct_dec in &module.structs {
+            self.check_struct_cycle(&struct_dec.name, &mut vec![])?;
+        }
+        for static_item in &module.statics {
+            // Modules may be registered more than once
+            if self.statics.contains_key(&static_item.name) {
+                continue;
+            }
+            let typ = self.resolve_type(&static_item.typ)?;
+            let words = self.evaluator().eval_words(&typ, &static_item.value)?;
+            let Some(address) = self.data.alloc(&words) else {
+                return Err(CalError::new(
+                    format!(
+                        "Statics exceed the data section of {} words at `{}`",
+                        DataSection::CAPACITY,
+                        static_item.name
+                    ),
+                    static_item.value.range,
+                ));
+            };
+            self.statics
+                .insert(static_item.name.clone(), (typ, address));
+        }
+        for function in &module.functions {
+            let parameters = function
+                .parameters
+                .iter()
+                .map(|arg| {
+                    Ok(Variable {
+                        typ: self.resolve_type(&arg.typ)?,
+                        ..arg.clone()
+                    })
+                })
+                .collect::<Result<_, CalError>>()?;
+            let return_type = self.resolve_type(&function.return_type)?;
+            let registered = self.functions.get_mut(&function.name).unwrap();
+            registered.parameters = parameters;
+            registered.return_type = return_type;
+        }
+        Ok(())
+    }
+
     /// Generates VM instructions for a module
     pub fn gen_module(&mut self, module: &Module) -> Result<Vec<VmInstruction>, CalError> {
+        self.register_module(module)?;
         let mut ret = vec![];
         for function in &module.functions {
             ret.extend(self.gen_function(function)?);
@@ -569,14 +1237,125 @@ impl Generator {
         Ok(ret)
     }
 
-    /// Generates VM instructions for a series of modules
+    /// Generates every function of a module, collecting all the errors
+    /// instead of stopping at the first one. Errors without a range are
+    /// reported at the name of the function they occur in.
+    pub fn check_module(&mut self, module: &Module) -> Vec<CalError> {
+        if let Err(err) = self.register_module(module) {
+            return vec![err];
+        }
+        let mut errors = vec![];
+        for function in &module.functions {
+            if let Err(mut err) = self.gen_function(function) {
+                if err.range == Range::default() {
+                    err.range = function.range;
+                }
+                errors.push(err);
+                // Drop the symbol table of the function which failed
+                self.symbol_tables.clear();
+            }
+        }
+        errors
+    }
+
+    /// Returns the data section holding the initial values of the statics,
+    /// which is initialized before calling `main`
+    pub fn get_data(&self) -> &DataSection {
+        &self.data
+    }
+
+    /// Returns the names of the functions, builtins included, which were left
+    /// out of the program by the last call to `gen`
+    pub fn get_pruned(&self) -> &[String] {
+        &self.pruned
+    }
+
+    /// Returns the names of the functions marked `#[inline]`
+    pub fn get_inline(&self) -> HashSet<String> {
+        self.functions
+            .values()
+            .filter(|function| function.inline)
+            .map(|function| function.name.clone())
+            .collect()
+    }
+
+    /// Returns the origin of each instruction generated by the last call to
+    /// `gen`. Instructions of the preamble only map to their function, and
+    /// so do those of functions rewritten by the optimiser
+    pub fn get_origins(&self) -> &[Origin] {
+        &self.origins
+    }
+
+    /// Returns the names of the functions of the modules which the
+    /// optimiser rewrote in the last call to `gen`, so that their origins
+    /// do not tell their statements apart
+    pub fn get_rewritten(&self) -> &[String] {
+        &self.rewritten
+    }
+
+    /// Returns the symbol table of a function generated so far
+    pub fn get_frame(&self, function: &str) -> Option<&SymbolTable> {
+        self.frames.get(function)
+    }
+
+    /// Returns parameters and local variables declared so far
+    pub fn get_declarations(&self) -> &[Variable] {
+        &self.declarations
+    }
+
+    /// Generates VM instructions for a series of modules, optimised at the
+    /// level of the generator, leaving out the functions which are not
+    /// reachable from `main`
     pub fn gen(&mut self, modules: &[Module]) -> Result<Vec<VmInstruction>, CalError> {
         let mut instructions = preamble();
+        for module in modules {
+            self.register_module(module)?;
+        }
+        (self.origins, _) = self.function_origins(&instructions, &[], &[]);
         for module in modules {
             instructions.extend(self.gen_module(module)?);
         }
+        let generated = instructions.clone();
+        let instructions = ir::optimize(instructions, self.opt_level, self.get_inline());
+        let (instructions, pruned) = eliminate_dead_code(instructions);
+        (self.origins, self.rewritten) =
+            self.function_origins(&instructions, &generated, &self.origins);
+        self.pruned = pruned;
         Ok(instructions)
     }
+
+    /// Origins of instructions, given those of the instructions they were
+    /// turned into by optimising and removing dead code. Functions which are
+    /// not found unchanged among the latter only map to themselves, and
+    /// those declared by the modules are returned as rewritten
+    fn function_origins(
+        &self,
+        instructions: &[VmInstruction],
+        generated: &[VmInstruction],
+        origins: &[Origin],
+    ) -> (Vec<Origin>, Vec<String>) {
+        let before: HashMap<Option<&str>, std::ops::Range<usize>> =
+            chunks(generated).into_iter().collect();
+        let mut ret = vec![];
+        let mut rewritten = vec![];
+        for (name, range) in chunks(instructions) {
+            match before.get(&name) {
+                Some(before) if generated[before.clone()] == instructions[range.clone()] => {
+                    ret.extend_from_slice(&origins[before.clone()])
+                }
+                _ => {
+                    let name = name.unwrap_or_default();
+                    let function_range = self.functions.get(name).map(|f| f.range);
+                    if function_range.is_some() && before.contains_key(&Some(name)) {
+                        rewritten.push(name.to_string());
+                    }
+                    let origin = Origin::new(name, function_range.unwrap_or_default());
+                    ret.extend(std::iter::repeat_n(origin, range.len()));
+                }
+            }
+        }
+        (ret, rewritten)
+    }
 }
 
 pub fn generate(module: Module) -> Result<Vec<VmInstruction>, CalError> {
@@ -0,0 +1,754 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::mem::take;
+
+use crate::{
+    asm::instruction::{AsmInstruction, Comp, Dest, Jump},
+    segment::Segment,
+    vm::{data::DataSection, instruction::VmInstruction, translator::VmTranslator},
+    Assembler,
+};
+
+use AsmInstruction as I;
+
+/// Where an element on top of the stack is held
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operand {
+    /// A constant, not loaded anywhere yet
+    Constant(i16),
+    /// The D register
+    D,
+    /// One of the scratch registers from R13 to R15
+    Register(u16),
+    /// The top of the stack in memory, once no element is held elsewhere
+    Stack,
+}
+
+/// Binary operators computing their result in D
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Sub,
+    And,
+    Or,
+}
+
+impl Operator {
+    /// Returns the comp of `D op A`, or `D op M`, or the other way round
+    fn comp(self, memory: bool, reversed: bool) -> Comp {
+        match (self, memory, reversed) {
+            (Operator::Add, false, _) => Comp::DPlusA,
+            (Operator::Add, true, _) => Comp::DPlusM,
+            (Operator::Sub, false, false) => Comp::DMinusA,
+            (Operator::Sub, true, false) => Comp::DMinusM,
+            (Operator::Sub, false, true) => Comp::AMinusD,
+            (Operator::Sub, true, true) => Comp::MMinusD,
+            (Operator::And, false, _) => Comp::DAndA,
+            (Operator::And, true, _) => Comp::DAndM,
+            (Operator::Or, false, _) => Comp::DOrA,
+            (Operator::Or, true, _) => Comp::DOrM,
+        }
+    }
+
+    fn fold(self, x: i16, y: i16) -> i16 {
+        match self {
+            Operator::Add => x.wrapping_add(y),
+            Operator::Sub => x.wrapping_sub(y),
+            Operator::And => x & y,
+            Operator::Or => x | y,
+        }
+    }
+}
+
+/// Jump taken when a comparison holds, testing the sign of `x - y`
+fn compare_jump(instruction: &VmInstruction) -> Option<Jump> {
+    match instruction {
+        VmInstruction::Eq => Some(Jump::Eq),
+        VmInstruction::Lt => Some(Jump::Lt),
+        VmInstruction::Gt => Some(Jump::Gt),
+        _ => None,
+    }
+}
+
+fn negate(jump: Jump) -> Jump {
+    match jump {
+        Jump::Eq => Jump::Ne,
+        Jump::Ne => Jump::Eq,
+        Jump::Lt => Jump::Ge,
+        Jump::Ge => Jump::Lt,
+        Jump::Gt => Jump::Le,
+        Jump::Le => Jump::Gt,
+        Jump::Jump => Jump::No,
+        Jump::No => Jump::Jump,
+    }
+}
+
+fn holds(jump: Jump, value: i16) -> bool {
+    match jump {
+        Jump::No => false,
+        Jump::Gt => value > 0,
+        Jump::Eq => value == 0,
+        Jump::Ge => value >= 0,
+        Jump::Lt => value < 0,
+        Jump::Ne => value != 0,
+        Jump::Le => value <= 0,
+        Jump::Jump => true,
+    }
+}
+
+fn c(dest: Dest, comp: Comp) -> I {
+    I::C(dest, comp, Jump::No)
+}
+
+/// Comp of a constant, when a C-instruction can compute it
+fn constant_comp(value: i16) -> Option<Comp> {
+    match value {
+        -1 => Some(Comp::MinusOne),
+        0 => Some(Comp::Zero),
+        1 => Some(Comp::One),
+        _ => None,
+    }
+}
+
+/// Loads a constant into A, where values which do not fit into an
+/// A-instruction are loaded as their complement
+fn load_a(value: i16) -> Vec<I> {
+    if value < 0 {
+        vec![I::A(!value as u16), c(Dest::A, Comp::NotA)]
+    } else {
+        vec![I::A(value as u16)]
+    }
+}
+
+/// Generates Hack assembly from the VM instructions of `Generator` without
+/// going through the stack in memory. Pushed values are held by a stack of
+/// operands, as constants, in D, or in the scratch registers from R13 to R15,
+/// until an instruction consumes them. Operands are flushed to memory before
+/// labels, jumps, and calls, so that frames and calling conventions are the
+/// same as the ones of `VmTranslator`.
+#[derive(Default)]
+pub struct HackGenerator {
+    /// Labels generated so far
+    label_count: u32,
+
+    /// Data initialized at boot time, before the first VM instruction
+    data: DataSection,
+
+    /// Elements on top of the stack, from the bottom, which are not in memory
+    operands: Vec<Operand>,
+
+    /// Instructions generated so far
+    instructions: Vec<I>,
+}
+
+impl HackGenerator {
+    /// Operands held at most, before flushing them to memory
+    const MAX_OPERANDS: usize = 8;
+
+    /// Offsets up to this are reached by incrementing A, which takes fewer
+    /// instructions than adding them
+    const INCREMENT_THRESHOLD: u16 = 8;
+
+    const SCRATCH: [Segment; 3] = [Segment::R13, Segment::R14, Segment::R15];
+
+    pub fn with_data(mut self, data: DataSection) -> Self {
+        self.data = data;
+        self
+    }
+
+    fn next_label(&mut self) -> String {
+        let ret = format!("HACK_LABEL{}", self.label_count);
+        self.label_count += 1;
+        ret
+    }
+
+    fn emit(&mut self, instructions: impl IntoIterator<Item = I>) {
+        self.instructions.extend(instructions);
+    }
+
+    fn pop(&mut self) -> Operand {
+        self.operands.pop().unwrap_or(Operand::Stack)
+    }
+
+    fn free_register(&self) -> Option<u16> {
+        Self::SCRATCH
+            .iter()
+            .map(|register| register.get_base_address() as u16)
+            .find(|register| !self.operands.contains(&Operand::Register(*register)))
+    }
+
+    /// Pushes D on top of the stack in memory
+    fn push_d(&mut self) {
+        self.emit([
+            I::A(0),
+            c(Dest::AM, Comp::MPlusOne),
+            c(Dest::A, Comp::AMinusOne),
+            c(Dest::M, Comp::D),
+        ]);
+    }
+
+    /// Writes all the operands to the stack in memory. The one in D, if any,
+    /// goes first to its slot, so that D can load the others
+    fn flush(&mut self) {
+        let mut operands = take(&mut self.operands);
+        if operands.first() == Some(&Operand::D) {
+            self.push_d();
+            operands.remove(0);
+        }
+        if let Some(index) = operands.iter().position(|operand| *operand == Operand::D) {
+            self.emit([I::A(0), c(Dest::A, Comp::M)]);
+            self.emit((0..index).map(|_| c(Dest::A, Comp::APlusOne)));
+            self.emit([c(Dest::M, Comp::D)]);
+        }
+        for operand in operands {
+            match operand {
+                Operand::D => self.emit([I::A(0), c(Dest::M, Comp::MPlusOne)]),
+                Operand::Constant(value) if constant_comp(value).is_some() => self.emit([
+                    I::A(0),
+                    c(Dest::AM, Comp::MPlusOne),
+                    c(Dest::A, Comp::AMinusOne),
+                    c(Dest::M, constant_comp(value).unwrap()),
+                ]),
+                operand => {
+                    self.load_d(operand);
+                    self.push_d();
+                }
+            }
+        }
+    }
+
+    /// Makes D available, moving the operand it holds, unless it is one of the
+    /// topmost `keep` ones, into a free register, or flushing all operands
+    fn save_d(&mut self, keep: usize) {
+        let len = self.operands.len();
+        if len >= Self::MAX_OPERANDS {
+            self.flush();
+            return;
+        }
+        let Some(index) = self.operands[..len.saturating_sub(keep)]
+            .iter()
+            .position(|operand| *operand == Operand::D)
+        else {
+            return;
+        };
+        match self.free_register() {
+            Some(register) => {
+                self.emit([I::A(register), c(Dest::M, Comp::D)]);
+                self.operands[index] = Operand::Register(register);
+            }
+            None => self.flush(),
+        }
+    }
+
+    /// Loads an operand into D, which has to be free
+    fn load_d(&mut self, operand: Operand) {
+        match operand {
+            Operand::Constant(value) => self.emit(VmTranslator::gen_load_d(value)),
+            Operand::D => (),
+            Operand::Register(register) => self.emit([I::A(register), c(Dest::D, Comp::M)]),
+            Operand::Stack => {
+                self.emit([I::A(0), c(Dest::AM, Comp::MMinusOne), c(Dest::D, Comp::M)])
+            }
+        }
+    }
+
+    /// Loads a constant operand into A, or points A at any other operand
+    /// except D. Returns whether the operand is then in M
+    fn select(&mut self, operand: Operand) -> bool {
+        match operand {
+            Operand::Constant(value) => {
+                self.emit(load_a(value));
+                false
+            }
+            Operand::Register(register) => {
+                self.emit([I::A(register)]);
+                true
+            }
+            Operand::Stack => {
+                self.emit([I::A(0), c(Dest::AM, Comp::MMinusOne)]);
+                true
+            }
+            Operand::D => unreachable!("D can not be selected"),
+        }
+    }
+
+    /// Points A at `segment[index]`, where `segment` holds a base address
+    fn address(&mut self, segment: Segment, index: u16) {
+        let base = segment.get_base_address() as u16;
+        match index {
+            0 => self.emit([I::A(base), c(Dest::A, Comp::M)]),
+            _ if index <= Self::INCREMENT_THRESHOLD => {
+                self.emit([I::A(base), c(Dest::A, Comp::MPlusOne)]);
+                self.emit((1..index).map(|_| c(Dest::A, Comp::APlusOne)));
+            }
+            _ => self.emit([
+                I::A(base),
+                c(Dest::D, Comp::M),
+                I::A(index),
+                c(Dest::A, Comp::DPlusA),
+            ]),
+        }
+    }
+
+    fn gen_push(&mut self, segment: Segment, index: u16) {
+        if segment == Segment::Constant {
+            if self.operands.len() >= Self::MAX_OPERANDS {
+                self.flush();
+            }
+            self.operands.push(Operand::Constant(index as i16));
+            return;
+        }
+        self.save_d(0);
+        match segment {
+            Segment::Local | Segment::Argument | Segment::This | Segment::That => {
+                self.address(segment, index)
+            }
+            Segment::Pointer | Segment::Temp | Segment::Static => {
+                self.emit([I::A(segment.get_base_address() as u16 + index)])
+            }
+            _ => unimplemented!(),
+        }
+        self.emit([c(Dest::D, Comp::M)]);
+        self.operands.push(Operand::D);
+    }
+
+    fn gen_pop(&mut self, segment: Segment, index: u16) {
+        self.save_d(1);
+        let value = self.pop();
+        let comp = match value {
+            Operand::Constant(value) => constant_comp(value),
+            _ => None,
+        };
+        let comp = comp.unwrap_or_else(|| {
+            self.load_d(value);
+            Comp::D
+        });
+        match segment {
+            Segment::Local | Segment::Argument | Segment::This | Segment::That
+                if index <= Self::INCREMENT_THRESHOLD =>
+            {
+                self.address(segment, index);
+                self.emit([c(Dest::M, comp)]);
+            }
+            Segment::Local | Segment::Argument | Segment::This | Segment::That => {
+                // The words above the stack are free, so they keep the value
+                // and its address
+                self.emit([
+                    I::A(0),
+                    c(Dest::A, Comp::M),
+                    c(Dest::M, comp),
+                    I::A(segment.get_base_address() as u16),
+                    c(Dest::D, Comp::M),
+                    I::A(index),
+                    c(Dest::D, Comp::DPlusA),
+                    I::A(0),
+                    c(Dest::A, Comp::MPlusOne),
+                    c(Dest::M, Comp::D),
+                    c(Dest::A, Comp::AMinusOne),
+                    c(Dest::D, Comp::M),
+                    c(Dest::A, Comp::APlusOne),
+                    c(Dest::A, Comp::M),
+                    c(Dest::M, Comp::D),
+                ]);
+            }
+            Segment::Pointer | Segment::Temp | Segment::Static => self.emit([
+                I::A(segment.get_base_address() as u16 + index),
+                c(Dest::M, comp),
+            ]),
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Computes `x op y` into D, where `y` is on top of `x`
+    fn gen_operator(&mut self, operator: Operator) {
+        self.save_d(2);
+        let y = self.pop();
+        let x = self.pop();
+        match (x, y) {
+            (Operand::Constant(x), Operand::Constant(y)) => {
+                self.emit(VmTranslator::gen_load_d(operator.fold(x, y)))
+            }
+            (x, Operand::D) => {
+                if x == Operand::Constant(0) && operator == Operator::Sub {
+                    self.emit([c(Dest::D, Comp::MinusD)]);
+                } else {
+                    let memory = self.select(x);
+                    self.emit([c(Dest::D, operator.comp(memory, true))]);
+                }
+            }
+            (x, Operand::Stack) => {
+                self.load_d(Operand::Stack);
+                let memory = self.select(x);
+                self.emit([c(Dest::D, operator.comp(memory, true))]);
+            }
+            (x, Operand::Constant(y)) => {
+                self.load_d(x);
+                match (operator, y) {
+                    (Operator::Add | Operator::Sub | Operator::Or, 0) | (Operator::And, -1) => (),
+                    (Operator::Add, 1) | (Operator::Sub, -1) => {
+                        self.emit([c(Dest::D, Comp::DPlusOne)])
+                    }
+                    (Operator::Add, -1) | (Operator::Sub, 1) => {
+                        self.emit([c(Dest::D, Comp::DMinusOne)])
+                    }
+                    (Operator::Add, _) if y < 0 && y != i16::MIN => {
+                        self.emit([I::A(-y as u16), c(Dest::D, Comp::DMinusA)])
+                    }
+                    (Operator::Sub, _) if y < 0 && y != i16::MIN => {
+                        self.emit([I::A(-y as u16), c(Dest::D, Comp::DPlusA)])
+                    }
+                    _ => {
+                        self.emit(load_a(y));
+                        self.emit([c(Dest::D, operator.comp(false, false))]);
+                    }
+                }
+            }
+            (x, y) => {
+                self.load_d(x);
+                let memory = self.select(y);
+                self.emit([c(Dest::D, operator.comp(memory, false))]);
+            }
+        }
+        self.operands.push(Operand::D);
+    }
+
+    /// Leaves `x - y` on top, for comparing `x` against `y` with a jump
+    fn gen_difference(&mut self) {
+        let len = self.operands.len();
+        match self.operands[len.saturating_sub(2)..] {
+            [Operand::Constant(x), Operand::Constant(y)] => {
+                self.operands.truncate(len - 2);
+                self.operands.push(Operand::Constant(x.wrapping_sub(y)));
+            }
+            [.., Operand::Constant(0)] => {
+                self.operands.pop();
+            }
+            _ => self.gen_operator(Operator::Sub),
+        }
+    }
+
+    /// Leaves on top a value with the sign of `x - y`, for ordering `x` and
+    /// `y` with a jump without overflowing when they have opposite signs
+    fn gen_ordering(&mut self) {
+        let len = self.operands.len();
+        match self.operands[len.saturating_sub(2)..] {
+            [Operand::Constant(x), Operand::Constant(y)] => {
+                self.operands.truncate(len - 2);
+                self.operands.push(Operand::Constant(x.cmp(&y) as i16));
+            }
+            [.., Operand::Constant(0)] => {
+                self.operands.pop();
+            }
+            [.., Operand::Constant(y)] => {
+                // Only `x - y` for `x` of the other sign than `y` can
+                // overflow, and then `x` alone orders them
+                self.save_d(2);
+                self.operands.pop();
+                let x = self.pop();
+                self.load_d(x);
+                let end_label = self.next_label();
+                if y > 0 {
+                    self.emit([
+                        I::Symbol(end_label.clone()),
+                        I::C(Dest::Null, Comp::D, Jump::Lt),
+                    ]);
+                    self.emit(load_a(y));
+                    self.emit([c(Dest::D, Comp::DMinusA)]);
+                } else {
+                    let negative_label = self.next_label();
+                    self.emit([
+                        I::Symbol(negative_label.clone()),
+                        I::C(Dest::Null, Comp::D, Jump::Lt),
+                        c(Dest::D, Comp::One),
+                        I::Symbol(end_label.clone()),
+                        I::C(Dest::Null, Comp::Zero, Jump::Jump),
+                        I::Label(negative_label),
+                    ]);
+                    self.emit(load_a(y));
+                    self.emit([c(Dest::D, Comp::DMinusA)]);
+                }
+                self.emit([I::Label(end_label)]);
+                self.operands.push(Operand::D);
+            }
+            _ => {
+                // Ordering needs `x` in D and `y` in R13
+                self.flush();
+                let labels = [self.next_label(), self.next_label(), self.next_label()];
+                self.emit([
+                    I::A(0),
+                    c(Dest::AM, Comp::MMinusOne),
+                    c(Dest::D, Comp::M),
+                    I::A(Segment::R13.get_base_address() as u16),
+                    c(Dest::M, Comp::D),
+                ]);
+                self.load_d(Operand::Stack);
+                self.emit(VmTranslator::gen_ordering(labels));
+                self.operands.push(Operand::D);
+            }
+        }
+    }
+
+    /// Generates a comparison, returning how many instructions it consumed
+    /// with a `not` or an `if-goto` following it
+    fn gen_compare(&mut self, jump: Jump, next: &[VmInstruction]) -> usize {
+        match jump {
+            Jump::Eq => self.gen_difference(),
+            _ => self.gen_ordering(),
+        }
+        let (jump, consumed) = match next.first() {
+            Some(VmInstruction::Not) => (negate(jump), 2),
+            _ => (jump, 1),
+        };
+        if let Some(VmInstruction::IfGoto(label)) = next.get(consumed - 1) {
+            self.gen_if(jump, label.clone());
+            return consumed + 1;
+        }
+        self.save_d(1);
+        let difference = self.pop();
+        if let Operand::Constant(difference) = difference {
+            self.operands
+                .push(Operand::Constant(-(holds(jump, difference) as i16)));
+            return consumed;
+        }
+        let true_label = self.next_label();
+        let end_label = self.next_label();
+        self.load_d(difference);
+        self.emit([
+            I::Symbol(true_label.clone()),
+            I::C(Dest::Null, Comp::D, jump),
+            c(Dest::D, Comp::Zero),
+            I::Symbol(end_label.clone()),
+            I::C(Dest::Null, Comp::Zero, Jump::Jump),
+            I::Label(true_label),
+            c(Dest::D, Comp::MinusOne),
+            I::Label(end_label),
+        ]);
+        self.operands.push(Operand::D);
+        consumed
+    }
+
+    /// Jumps to `label` when the topmost operand satisfies `jump`
+    fn gen_if(&mut self, jump: Jump, label: String) {
+        match self.operands.last() {
+            Some(Operand::Constant(value)) => {
+                let value = *value;
+                self.operands.pop();
+                if holds(jump, value) {
+                    self.gen_goto(label);
+                }
+                return;
+            }
+            Some(_) if self.operands.len() > 1 => self.flush(),
+            _ => (),
+        }
+        let condition = self.pop();
+        self.load_d(condition);
+        self.emit([I::Symbol(label), I::C(Dest::Null, Comp::D, jump)]);
+    }
+
+    fn gen_unary(&mut self, comp_d: Comp, comp_m: Comp, fold: fn(i16) -> i16) {
+        self.save_d(1);
+        match self.pop() {
+            Operand::Constant(value) => {
+                self.operands.push(Operand::Constant(fold(value)));
+                return;
+            }
+            Operand::D => self.emit([c(Dest::D, comp_d)]),
+            operand => {
+                self.select(operand);
+                self.emit([c(Dest::D, comp_m)]);
+            }
+        }
+        self.operands.push(Operand::D);
+    }
+
+    fn gen_goto(&mut self, label: String) {
+        self.flush();
+        self.emit([I::Symbol(label), I::C(Dest::Null, Comp::Zero, Jump::Jump)]);
+    }
+
+    /// Zeroes the locals above the stack, then moves the stack pointer past
+    /// them
+    fn gen_function(&mut self, function: String, local_count: u16) {
+        self.flush();
+        self.emit([I::Label(function)]);
+        match local_count {
+            0 => (),
+            1 => self.emit([
+                I::A(0),
+                c(Dest::AM, Comp::MPlusOne),
+                c(Dest::A, Comp::AMinusOne),
+                c(Dest::M, Comp::Zero),
+            ]),
+            _ => {
+                self.emit([I::A(0), c(Dest::A, Comp::M), c(Dest::M, Comp::Zero)]);
+                for _ in 1..local_count {
+                    self.emit([c(Dest::A, Comp::APlusOne), c(Dest::M, Comp::Zero)]);
+                }
+                self.emit([c(Dest::D, Comp::APlusOne), I::A(0), c(Dest::M, Comp::D)]);
+            }
+        }
+    }
+
+    /// Saves the frame of the caller like `VmTranslator`, writing each word
+    /// while moving the stack pointer
+    fn gen_call(&mut self, function: String, arg_count: u16) {
+        self.flush();
+        let return_label = self.next_label();
+        self.emit([
+            I::Symbol(return_label.clone()),
+            c(Dest::D, Comp::A),
+            I::A(0),
+            c(Dest::A, Comp::M),
+            c(Dest::M, Comp::D),
+        ]);
+        for segment in [
+            Segment::Local,
+            Segment::Argument,
+            Segment::This,
+            Segment::That,
+        ] {
+            self.emit([
+                I::A(segment.get_base_address() as u16),
+                c(Dest::D, Comp::M),
+                I::A(0),
+                c(Dest::AM, Comp::MPlusOne),
+                c(Dest::M, Comp::D),
+            ]);
+        }
+        self.emit([
+            I::A(0),
+            c(Dest::DM, Comp::MPlusOne),
+            I::A(Segment::Local.get_base_address() as u16),
+            c(Dest::M, Comp::D),
+            I::A(5 + arg_count),
+            c(Dest::D, Comp::DMinusA),
+            I::A(Segment::Argument.get_base_address() as u16),
+            c(Dest::M, Comp::D),
+            I::Symbol(function),
+            I::C(Dest::Null, Comp::Zero, Jump::Jump),
+            I::Label(return_label),
+        ]);
+    }
+
+    /// Returns up to a word straight from its operand, discarding the others.
+    /// Longer values are returned through the stack like `VmTranslator` does
+    fn gen_return(&mut self, return_size_in_words: u16) {
+        if return_size_in_words > 1 {
+            self.flush();
+            self.emit(VmTranslator::gen_return(return_size_in_words));
+            return;
+        }
+        let local = Segment::Local.get_base_address() as u16;
+        let argument = Segment::Argument.get_base_address() as u16;
+        let mut value = self.pop();
+        self.operands.clear();
+        if value == Operand::D {
+            self.emit([I::A(13), c(Dest::M, Comp::D)]);
+            value = Operand::Register(13);
+        }
+        let return_address = if value == Operand::Register(14) {
+            15
+        } else {
+            14
+        };
+        self.emit([
+            I::A(local),
+            c(Dest::D, Comp::M),
+            I::A(5),
+            c(Dest::A, Comp::DMinusA),
+            c(Dest::D, Comp::M),
+            I::A(return_address),
+            c(Dest::M, Comp::D),
+        ]);
+        if return_size_in_words == 1 {
+            self.load_d(value);
+            self.emit([
+                I::A(argument),
+                c(Dest::A, Comp::M),
+                c(Dest::M, Comp::D),
+                I::A(argument),
+                c(Dest::D, Comp::MPlusOne),
+            ]);
+        } else {
+            self.emit([I::A(argument), c(Dest::D, Comp::M)]);
+        }
+        self.emit([I::A(0), c(Dest::M, Comp::D)]);
+        for segment in [Segment::That, Segment::This, Segment::Argument] {
+            self.emit([
+                I::A(local),
+                c(Dest::AM, Comp::MMinusOne),
+                c(Dest::D, Comp::M),
+                I::A(segment.get_base_address() as u16),
+                c(Dest::M, Comp::D),
+            ]);
+        }
+        self.emit([
+            I::A(local),
+            c(Dest::A, Comp::MMinusOne),
+            c(Dest::D, Comp::M),
+            I::A(local),
+            c(Dest::M, Comp::D),
+            I::A(return_address),
+            c(Dest::A, Comp::M),
+            I::C(Dest::Null, Comp::Zero, Jump::Jump),
+        ]);
+    }
+
+    /// Generates the first of some VM instructions, along with the ones
+    /// following it which it can be combined with. Returns how many
+    /// instructions it consumed
+    fn gen_instruction(&mut self, instructions: &[VmInstruction]) -> usize {
+        let next = &instructions[1..];
+        match &instructions[0] {
+            VmInstruction::Push(segment, index) => self.gen_push(*segment, *index),
+            VmInstruction::Pop(segment, index) => self.gen_pop(*segment, *index),
+            VmInstruction::Add => self.gen_operator(Operator::Add),
+            VmInstruction::Sub => self.gen_operator(Operator::Sub),
+            VmInstruction::And => self.gen_operator(Operator::And),
+            VmInstruction::Or => self.gen_operator(Operator::Or),
+            instruction @ (VmInstruction::Eq | VmInstruction::Lt | VmInstruction::Gt) => {
+                return self.gen_compare(compare_jump(instruction).unwrap(), next)
+            }
+            VmInstruction::Neg => self.gen_unary(Comp::MinusD, Comp::MinusM, i16::wrapping_neg),
+            VmInstruction::Not => {
+                if let Some(VmInstruction::IfGoto(label)) = next.first() {
+                    self.gen_if(Jump::Eq, label.clone());
+                    return 2;
+                }
+                self.gen_unary(Comp::NotD, Comp::NotM, |value| !value)
+            }
+            VmInstruction::Label(label) => {
+                self.flush();
+                self.emit([I::Label(label.clone())]);
+            }
+            VmInstruction::Goto(label) => self.gen_goto(label.clone()),
+            VmInstruction::IfGoto(label) => self.gen_if(Jump::Ne, label.clone()),
+            VmInstruction::Function(function, local_count) => {
+                self.gen_function(function.clone(), *local_count)
+            }
+            VmInstruction::Call(function, arg_count) => self.gen_call(function.clone(), *arg_count),
+            VmInstruction::Return(return_size_in_words) => self.gen_return(*return_size_in_words),
+        }
+        1
+    }
+
+    /// Generates a program from VM instructions, starting with the same boot
+    /// code as `VmTranslator`
+    pub fn gen(&mut self, vm_instructions: &[VmInstruction]) -> Vec<I> {
+        let mut asm_instructions = VmTranslator::default()
+            .with_data(take(&mut self.data))
+            .gen_boot();
+        let mut index = 0;
+        while index < vm_instructions.len() {
+            index += self.gen_instruction(&vm_instructions[index..]);
+        }
+        self.flush();
+        asm_instructions.extend(take(&mut self.instructions));
+
+        let mut assembler = Assembler::default();
+        assembler.resolve(asm_instructions)
+    }
+}
@@ -0,0 +1,1045 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+
+use crate::{
+    error::CalError,
+    evaluator::{Evaluator, Execute, Flow},
+    expression::{Expression, Literal, Operator, Term, UnaryOperator},
+    statement::Statement,
+    structure::{mangle, Constant, Field, Function, Module, StructDec, Type, Variable},
+    tokenizer::Range,
+    vm::data::DataSection,
+};
+
+/// Number of words of the simulated memory: RAM, screen, and keyboard
+const MEMORY_SIZE: usize = 24577;
+
+/// Address where the stack starts, as in the VM
+const STACK_BASE: u16 = 256;
+
+/// Address where the heap starts, which the stack can not grow into
+const STACK_END: u16 = 2048;
+
+/// Words the VM saves when calling a function. They are reserved after the
+/// arguments, so that variables end up close to where the VM puts them.
+const FRAME_SIZE: u16 = 5;
+
+/// A sequence of words together with its type
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Value {
+    pub typ: Type,
+    pub words: Vec<i16>,
+}
+
+impl Value {
+    pub fn new(typ: Type, words: Vec<i16>) -> Self {
+        Self { typ, words }
+    }
+
+    pub fn void() -> Self {
+        Self::new(Type::Void, vec![])
+    }
+}
+
+impl From<i16> for Value {
+    fn from(integer: i16) -> Self {
+        Self::new(Type::I16, vec![integer])
+    }
+}
+
+/// Whether a place can be written
+#[derive(Clone, Debug)]
+enum Access {
+    Mutable,
+    /// Rooted in the immutable variable with this name
+    Immutable(String),
+    /// Behind a `&` reference
+    Shared,
+    /// Rooted in the static with this name
+    Static(String),
+}
+
+/// A place is a location in memory holding a value of a certain type
+struct Place {
+    address: u16,
+    typ: Type,
+    access: Access,
+}
+
+/// A variable of the function being executed
+struct Local {
+    variable: Variable,
+    address: u16,
+}
+
+/// Variables of a function call
+#[derive(Default)]
+struct Frame {
+    locals: HashMap<String, Local>,
+    /// Address of the words allocated by each `let`, so that executing the
+    /// same statement again, like in a loop, does not grow the stack
+    slots: HashMap<*const Statement, u16>,
+}
+
+/// Executes Cal code by walking its AST. Values have the same layout in words
+/// and the same 16-bit wrap semantics of the VM, while variables live in a
+/// simulated memory so that references, `peek`, and `poke` work like in the
+/// compiled code.
+pub struct Interpreter {
+    constants: HashMap<String, Constant>,
+    functions: HashMap<String, Function>,
+    structs: HashMap<String, StructDec>,
+    /// Statics live in the data section, as in the compiled code
+    statics: HashMap<String, Local>,
+    data: DataSection,
+
+    memory: Vec<i16>,
+    stack_pointer: u16,
+    /// The first frame holds variables defined outside of any function
+    frames: Vec<Frame>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self {
+            constants: HashMap::new(),
+            functions: HashMap::new(),
+            structs: HashMap::new(),
+            statics: HashMap::new(),
+            data: DataSection::default(),
+            memory: vec![0; MEMORY_SIZE],
+            stack_pointer: STACK_BASE,
+            frames: vec![Frame::default()],
+        }
+    }
+}
+
+impl Interpreter {
+    fn evaluator(&self) -> Evaluator<'_> {
+        Evaluator::new(&self.constants, &self.functions, &self.structs, None)
+    }
+
+    fn resolve_type(&self, typ: &Type) -> Result<Type, CalError> {
+        self.evaluator().resolve_type(typ)
+    }
+
+    fn get_type_size_in_words(&self, typ: &Type) -> Result<u16, CalError> {
+        self.evaluator().get_type_size_in_words(typ)
+    }
+
+    /// Loads constants, structs, statics and functions declared by a module,
+    /// which replace previous ones with the same name
+    pub fn load(&mut self, module: &Module) -> Result<(), CalError> {
+        for constant in &module.constants {
+            self.constants
+                .insert(constant.name.clone(), constant.clone());
+        }
+        for struct_dec in &module.structs {
+            self.structs
+                .insert(struct_dec.name.clone(), struct_dec.clone());
+        }
+        for function in &module.functions {
+            self.functions
+                .insert(function.name.clone(), function.clone());
+        }
+
+        for constant in &module.constants {
+            let typ = self.resolve_type(&constant.typ)?;
+            self.evaluator().eval_expression(&constant.value)?;
+            self.constants.get_mut(&constant.name).unwrap().typ = typ;
+        }
+        for struct_dec in &module.structs {
+            let fields = struct_dec
+                .fields
+                .iter()
+                .map(|field| {
+                    Ok(Field::new(
+                        field.name.clone(),
+                        self.resolve_type(&field.typ)?,
+                    ))
+                })
+                .collect::<Result<_, CalError>>()?;
+            self.structs.get_mut(&struct_dec.name).unwrap().fields = fields;
+        }
+        for static_item in &module.statics {
+            let typ = self.resolve_type(&static_item.typ)?;
+            let words = self.evaluator().eval_words(&typ, &static_item.value)?;
+            let Some(address) = self.data.alloc(&words) else {
+                return Err(CalError::new(
+                    format!(
+                        "Statics exceed the data section of {} words at `{}`",
+                        DataSection::CAPACITY,
+                        static_item.name
+                    ),
+                    static_item.value.range,
+                ));
+            };
+            self.write(address, &words)?;
+            let variable = Variable::new(static_item.name.clone(), typ);
+            self.statics
+                .insert(static_item.name.clone(), Local { variable, address });
+        }
+        for function in &module.functions {
+            let parameters = function
+                .parameters
+                .iter()
+                .map(|arg| {
+                    Ok(Variable {
+                        typ: self.resolve_type(&arg.typ)?,
+                        ..arg.clone()
+                    })
+                })
+                .collect::<Result<_, CalError>>()?;
+            let return_type = self.resolve_type(&function.return_type)?;
+            let loaded = self.functions.get_mut(&function.name).unwrap();
+            loaded.parameters = parameters;
+            loaded.return_type = return_type;
+        }
+        Ok(())
+    }
+
+    pub fn get_memory(&self) -> &[i16] {
+        &self.memory
+    }
+
+    pub fn get_memory_mut(&mut self) -> &mut [i16] {
+        &mut self.memory
+    }
+
+    /// Reads a value of a type from memory
+    pub fn read_value(&self, typ: &Type, address: u16) -> Result<Value, CalError> {
+        let word_count = self.get_type_size_in_words(typ)?;
+        Ok(Value::new(typ.clone(), self.read(address, word_count)?))
+    }
+
+    fn read(&self, address: u16, word_count: u16) -> Result<Vec<i16>, CalError> {
+        let start = address as usize;
+        match self.memory.get(start..start + word_count as usize) {
+            Some(words) => Ok(words.to_vec()),
+            None => Err(CalError::new(
+                format!("Address {} is out of memory", address),
+                Range::default(),
+            )),
+        }
+    }
+
+    fn write(&mut self, address: u16, words: &[i16]) -> Result<(), CalError> {
+        let start = address as usize;
+        match self.memory.get_mut(start..start + words.len()) {
+            Some(memory) => {
+                memory.copy_from_slice(words);
+                Ok(())
+            }
+            None => Err(CalError::new(
+                format!("Address {} is out of memory", address),
+                Range::default(),
+            )),
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Reserves words on top of the stack, returning their address
+    fn push_words(&mut self, word_count: u16) -> Result<u16, CalError> {
+        let address = self.stack_pointer;
+        if address as u32 + word_count as u32 > STACK_END as u32 {
+            return Err(CalError::new("Stack overflow".into(), Range::default()));
+        }
+        self.stack_pointer += word_count;
+        Ok(address)
+    }
+
+    /// Returns the address of the words allocated by a `let` statement
+    fn allocate(&mut self, statement: &Statement, word_count: u16) -> Result<u16, CalError> {
+        let key = statement as *const Statement;
+        if let Some(address) = self.frame().slots.get(&key) {
+            return Ok(*address);
+        }
+        let address = self.push_words(word_count)?;
+        self.frame_mut().slots.insert(key, address);
+        Ok(address)
+    }
+
+    /// Stores a value into the place of a new variable
+    fn define(&mut self, variable: Variable, address: u16, value: &[i16]) -> Result<(), CalError> {
+        self.write(address, value)?;
+        self.frame_mut()
+            .locals
+            .insert(variable.name.clone(), Local { variable, address });
+        Ok(())
+    }
+
+    /// Returns the offset in words and the type of a field of a struct, or of
+    /// an element of a tuple, in which case the field is its position
+    fn get_field_offset_and_type(&self, typ: &Type, field: &str) -> Result<(u16, Type), CalError> {
+        let members: Vec<(String, Type)> = match typ {
+            Type::Struct(struct_name) => match self.structs.get(struct_name) {
+                Some(struct_dec) => struct_dec
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.clone(), field.typ.clone()))
+                    .collect(),
+                None => {
+                    return Err(CalError::new(
+                        format!("Undefined type `{}`", struct_name),
+                        Range::default(),
+                    ))
+                }
+            },
+            Type::Tuple(types) => types
+                .iter()
+                .enumerate()
+                .map(|(i, typ)| (i.to_string(), typ.clone()))
+                .collect(),
+            _ => {
+                return Err(CalError::new(
+                    format!("Expected struct or tuple, found {:?}", typ),
+                    Range::default(),
+                ))
+            }
+        };
+
+        let mut offset = 0;
+        for (name, field_type) in members {
+            if name == field {
+                return Ok((offset, field_type));
+            }
+            offset += self.get_type_size_in_words(&field_type)?;
+        }
+
+        Err(CalError::new(
+            format!("No field `{}` in `{:?}`", field, typ),
+            Range::default(),
+        ))
+    }
+
+    /// Returns an error if a place can not be written
+    fn check_access(place: &Place, action: &str) -> Result<(), CalError> {
+        match &place.access {
+            Access::Mutable => Ok(()),
+            Access::Immutable(name) => Err(CalError::new(
+                format!(
+                    "Cannot {} immutable variable `{}`, declare it as `mut {}`",
+                    action, name, name
+                ),
+                Range::default(),
+            )),
+            Access::Shared => Err(CalError::new(
+                format!(
+                    "Cannot {} a place behind a `&` reference, use `&mut` instead",
+                    action
+                ),
+                Range::default(),
+            )),
+            Access::Static(name) => Err(CalError::new(
+                format!("Cannot {} static `{}`, which is read-only", action, name),
+                Range::default(),
+            )),
+        }
+    }
+
+    /// Returns an error if a `&` reference is passed where a `&mut` reference
+    /// is expected, even as an element of a tuple or array
+    fn check_ref_coercion(expected: &Type, value: &Value) -> Result<(), CalError> {
+        if expected.rejects_shared_ref(&value.typ) {
+            return Err(CalError::new(
+                "Expected `&mut` reference, found `&` reference".into(),
+                Range::default(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns an error if a value does not fit into a place of that type
+    fn check_size(&self, typ: &Type, value: &Value) -> Result<(), CalError> {
+        if self.get_type_size_in_words(typ)? as usize != value.words.len() {
+            return Err(CalError::new(
+                format!("Expected {:?}, found {:?}", typ, value.typ),
+                Range::default(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the place a place expression refers to. A place is a variable,
+    /// or any chain of index and field accesses on it.
+    fn eval_place(&mut self, term: &Term) -> Result<Place, CalError> {
+        match term {
+            Term::Variable(name) => match self.frame().locals.get(name) {
+                Some(local) => Ok(Place {
+                    address: local.address,
+                    typ: local.variable.typ.clone(),
+                    access: if local.variable.mutable {
+                        Access::Mutable
+                    } else {
+                        Access::Immutable(name.clone())
+                    },
+                }),
+                None => match self.statics.get(name) {
+                    Some(static_item) => Ok(Place {
+                        address: static_item.address,
+                        typ: static_item.variable.typ.clone(),
+                        access: Access::Static(name.clone()),
+                    }),
+                    None => Err(CalError::new(
+                        format!("Undefined variable `{}`", name),
+                        Range::default(),
+                    )),
+                },
+            },
+            Term::Index(base, index_expr) => {
+                let index = self.eval_word(index_expr)?;
+                let place = self.eval_pointee_place(base)?;
+                let Type::Array(elem_type, _) = place.typ else {
+                    return Err(CalError::new(
+                        format!("Expected array, found {:?}", place.typ),
+                        Range::default(),
+                    ));
+                };
+                let stride = self.get_type_size_in_words(&elem_type)?;
+                Ok(Place {
+                    address: place
+                        .address
+                        .wrapping_add((index as u16).wrapping_mul(stride)),
+                    typ: *elem_type,
+                    access: place.access,
+                })
+            }
+            Term::Field(base, field) => {
+                let place = self.eval_pointee_place(base)?;
+                let (offset, typ) = self.get_field_offset_and_type(&place.typ, field)?;
+                Ok(Place {
+                    address: place.address.wrapping_add(offset),
+                    typ,
+                    access: place.access,
+                })
+            }
+            _ => Err(CalError::new(
+                format!("Expected place expression, found {:?}", term),
+                Range::default(),
+            )),
+        }
+    }
+
+    /// Returns the place of the object a term refers to. When the term holds
+    /// a reference, the reference is followed.
+    fn eval_pointee_place(&mut self, term: &Term) -> Result<Place, CalError> {
+        let value = match term {
+            Term::Variable(_) | Term::Index(_, _) | Term::Field(_, _) => {
+                let place = self.eval_place(term)?;
+                if place.typ.get_pointee().is_none() {
+                    return Ok(place);
+                }
+                self.read_place(&place)?
+            }
+            _ => self.eval_term(term)?,
+        };
+        match value.typ {
+            Type::Ref(pointee) => Ok(Place {
+                address: value.words[0] as u16,
+                typ: *pointee,
+                access: Access::Shared,
+            }),
+            Type::MutRef(pointee) => Ok(Place {
+                address: value.words[0] as u16,
+                typ: *pointee,
+                access: Access::Mutable,
+            }),
+            _ => Err(CalError::new(
+                format!("Expected place expression, found {:?}", term),
+                Range::default(),
+            )),
+        }
+    }
+
+    fn read_place(&self, place: &Place) -> Result<Value, CalError> {
+        let words = self.read(place.address, self.get_type_size_in_words(&place.typ)?)?;
+        Ok(Value::new(place.typ.clone(), words))
+    }
+
+    fn write_place(&mut self, place: &Place, value: &Value) -> Result<(), CalError> {
+        self.check_size(&place.typ, value)?;
+        self.write(place.address, &value.words)
+    }
+
+    fn eval_literal(literal: &Literal) -> Value {
+        match literal {
+            Literal::I16(integer) => Value::from(*integer),
+            Literal::Bool(boolean) => Value::new(Type::Bool, vec![-(*boolean as i16)]),
+            Literal::Char(c) => Value::new(Type::Char, vec![*c as i16]),
+            Literal::Array(literals) => {
+                let values: Vec<Value> = literals.iter().map(Self::eval_literal).collect();
+                let elem_type = values.first().map_or(Type::Void, |value| value.typ.clone());
+                Value::new(
+                    Type::Array(Box::new(elem_type), values.len() as u16),
+                    values.into_iter().flat_map(|value| value.words).collect(),
+                )
+            }
+        }
+    }
+
+    fn eval_variable(&mut self, name: &str) -> Result<Value, CalError> {
+        if self.frame().locals.contains_key(name) {
+            let place = self.eval_place(&Term::Variable(name.into()))?;
+            self.read_place(&place)
+        } else if let Some(constant) = self.constants.get(name) {
+            let value = self.evaluator().eval_expression(&constant.value)?;
+            Ok(Value::new(constant.typ.clone(), vec![value]))
+        } else if self.statics.contains_key(name) {
+            let place = self.eval_place(&Term::Variable(name.into()))?;
+            self.read_place(&place)
+        } else {
+            Err(CalError::new(
+                format!("Undefined variable `{}`", name),
+                Range::default(),
+            ))
+        }
+    }
+
+    /// Applies a binary operator with the semantics of the VM, where dividing
+    /// by zero gives `-1` and a remainder of `lhs` like the preamble does
+    fn eval_operator(op: Operator, lhs: i16, rhs: i16) -> Result<i16, CalError> {
+        match (op, rhs) {
+            (Operator::Div, 0) => Ok(-1),
+            (Operator::Mod, 0) => Ok(lhs),
+            _ => Evaluator::eval_operator(op, lhs, rhs),
+        }
+    }
+
+    /// Calls one of the functions of the preamble, if that is the name
+    fn call_builtin(&mut self, name: &str, args: &[Value]) -> Result<Option<Value>, CalError> {
+        let arg_count = match name {
+            "peek" => 1,
+            "poke" | "mul" | "div" | "mod" => 2,
+            _ => return Ok(None),
+        };
+        let words: Vec<i16> = args.iter().flat_map(|arg| arg.words.clone()).collect();
+        if args.len() != arg_count || words.len() != arg_count {
+            return Err(CalError::new(
+                format!(
+                    "Function `{}` takes {} arguments, found {}",
+                    name,
+                    arg_count,
+                    args.len()
+                ),
+                Range::default(),
+            ));
+        }
+
+        let value = match name {
+            "peek" => Value::from(self.read(words[0] as u16, 1)?[0]),
+            "poke" => {
+                self.write(words[0] as u16, &words[1..])?;
+                Value::void()
+            }
+            "mul" => Value::from(Self::eval_operator(Operator::Mul, words[0], words[1])?),
+            "div" => Value::from(Self::eval_operator(Operator::Div, words[0], words[1])?),
+            _ => Value::from(Self::eval_operator(Operator::Mod, words[0], words[1])?),
+        };
+        Ok(Some(value))
+    }
+
+    /// Calls a function with the values of its arguments
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, CalError> {
+        if let Some(value) = self.call_builtin(name, &args)? {
+            return Ok(value);
+        }
+        let Some(function) = self.functions.get(name).cloned() else {
+            return Err(CalError::new(
+                format!("Undefined function `{}`", name),
+                Range::default(),
+            ));
+        };
+        if function.parameters.len() != args.len() {
+            return Err(CalError::new(
+                format!(
+                    "Function `{}` takes {} arguments, found {}",
+                    name,
+                    function.parameters.len(),
+                    args.len()
+                ),
+                Range::default(),
+            ));
+        }
+
+        let stack_pointer = self.stack_pointer;
+        self.frames.push(Frame::default());
+        let ret = self.exec_function(&function, args);
+        self.frames.pop();
+        self.stack_pointer = stack_pointer;
+        ret
+    }
+
+    fn exec_function(&mut self, function: &Function, args: Vec<Value>) -> Result<Value, CalError> {
+        for (parameter, arg) in function.parameters.iter().zip(args) {
+            Self::check_ref_coercion(&parameter.typ, &arg)?;
+            self.check_size(&parameter.typ, &arg)?;
+            let address = self.push_words(arg.words.len() as u16)?;
+            self.define(parameter.clone(), address, &arg.words)?;
+        }
+        self.push_words(FRAME_SIZE)?;
+
+        let value = match self.exec_statements(&function.body_statements)? {
+            Flow::Normal(value) | Flow::Return(value) => value,
+        };
+        match (&function.return_type, value) {
+            (Type::Void, _) => Ok(Value::void()),
+            (return_type, Some(value)) => {
+                Self::check_ref_coercion(return_type, &value)?;
+                self.check_size(return_type, &value)?;
+                Ok(Value::new(return_type.clone(), value.words))
+            }
+            (_, None) => Err(CalError::new(
+                format!("Function `{}` does not return a value", function.name),
+                Range::default(),
+            )),
+        }
+    }
+
+    /// Calls a method on a receiver, which is passed by reference as the
+    /// first argument of the mangled function `Type.method`
+    fn eval_method_call(
+        &mut self,
+        receiver: &Term,
+        method: &str,
+        expressions: &[Expression],
+    ) -> Result<Value, CalError> {
+        let place = self.eval_pointee_place(receiver)?;
+        let Type::Struct(struct_name) = &place.typ else {
+            return Err(CalError::new(
+                format!("Expected struct as method receiver, found {:?}", place.typ),
+                Range::default(),
+            ));
+        };
+        let name = mangle(struct_name, method);
+        let Some(function) = self.functions.get(&name) else {
+            return Err(CalError::new(
+                format!("No method `{}` found for `{}`", method, struct_name),
+                Range::default(),
+            ));
+        };
+        let receiver_type = match function.parameters.first() {
+            Some(parameter) if parameter.name == "self" => parameter.typ.clone(),
+            _ => {
+                return Err(CalError::new(
+                    format!("`{}` is an associated function, not a method", name),
+                    Range::default(),
+                ))
+            }
+        };
+        if let Type::MutRef(_) = receiver_type {
+            match place.access {
+                Access::Shared => {
+                    return Err(CalError::new(
+                        format!(
+                            "Cannot call `{}`, which takes `&mut self`, behind a `&` reference",
+                            name
+                        ),
+                        Range::default(),
+                    ))
+                }
+                _ => Self::check_access(&place, "mutably borrow")?,
+            }
+        }
+
+        let mut args = vec![Value::new(receiver_type, vec![place.address as i16])];
+        for expr in expressions {
+            args.push(self.eval_expression(expr)?);
+        }
+        self.call(&name, args)
+    }
+
+    /// Evaluates a struct literal, whose fields are laid out in the order they
+    /// are declared
+    fn eval_struct_literal(
+        &mut self,
+        name: &str,
+        initializers: &[(String, Expression)],
+    ) -> Result<Value, CalError> {
+        let Some(struct_dec) = self.structs.get(name).cloned() else {
+            return Err(CalError::new(
+                format!("Undefined type `{}`", name),
+                Range::default(),
+            ));
+        };
+
+        for (field_name, _) in initializers {
+            if struct_dec.get_field(field_name).is_none() {
+                return Err(CalError::new(
+                    format!("No field `{}` in struct `{}`", field_name, name),
+                    Range::default(),
+                ));
+            }
+        }
+
+        let mut words = vec![];
+        for field in &struct_dec.fields {
+            let Some((_, expression)) = initializers.iter().find(|(n, _)| *n == field.name) else {
+                return Err(CalError::new(
+                    format!(
+                        "Missing field `{}` in initializer of `{}`",
+                        field.name, name
+                    ),
+                    Range::default(),
+                ));
+            };
+            let value = self.eval_expression(expression)?;
+            Self::check_ref_coercion(&field.typ, &value)?;
+            words.extend(value.words);
+        }
+        Ok(Value::new(Type::Struct(name.into()), words))
+    }
+
+    fn eval_unary_operator(
+        &mut self,
+        unary_op: UnaryOperator,
+        rhs: &Term,
+    ) -> Result<Value, CalError> {
+        match rhs {
+            Term::Variable(_) | Term::Index(_, _) | Term::Field(_, _) => {
+                let place = self.eval_place(rhs)?;
+                let typ = match unary_op {
+                    UnaryOperator::Ref => Type::Ref(Box::new(place.typ.clone())),
+                    UnaryOperator::MutRef => {
+                        Self::check_access(&place, "mutably borrow")?;
+                        Type::MutRef(Box::new(place.typ.clone()))
+                    }
+                };
+                Ok(Value::new(typ, vec![place.address as i16]))
+            }
+            _ => Err(CalError::new(
+                format!("Expected variable after `&`, found {:?}", rhs),
+                Range::default(),
+            )),
+        }
+    }
+
+    fn eval_term(&mut self, term: &Term) -> Result<Value, CalError> {
+        match term {
+            Term::Literal(literal) => Ok(Self::eval_literal(literal)),
+            Term::Variable(name) => self.eval_variable(name),
+            Term::Call(name, expressions) => {
+                let args = expressions
+                    .iter()
+                    .map(|expr| self.eval_expression(expr))
+                    .collect::<Result<_, _>>()?;
+                self.call(name, args)
+            }
+            Term::Index(_, _) | Term::Field(_, _) => {
+                let place = self.eval_place(term)?;
+                self.read_place(&place)
+            }
+            Term::MethodCall(receiver, method, expressions) => {
+                self.eval_method_call(receiver, method, expressions)
+            }
+            Term::Struct(name, initializers) => self.eval_struct_literal(name, initializers),
+            Term::Array(expressions) => {
+                let values = expressions
+                    .iter()
+                    .map(|expr| self.eval_expression(expr))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let elem_type = values.first().map_or(Type::Void, |value| value.typ.clone());
+                Ok(Value::new(
+                    Type::Array(Box::new(elem_type), values.len() as u16),
+                    values.into_iter().flat_map(|value| value.words).collect(),
+                ))
+            }
+            Term::Tuple(expressions) => {
+                let values = expressions
+                    .iter()
+                    .map(|expr| self.eval_expression(expr))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::new(
+                    Type::Tuple(values.iter().map(|value| value.typ.clone()).collect()),
+                    values.into_iter().flat_map(|value| value.words).collect(),
+                ))
+            }
+            Term::Expression(expr) => self.eval_expression(expr),
+            Term::SizeOf(typ) => Ok(Value::from(self.get_type_size_in_words(typ)? as i16)),
+            Term::UnaryOp(unary_op, rhs) => self.eval_unary_operator(*unary_op, rhs),
+        }
+    }
+
+    /// Evaluates an expression which should be a single word, like an index
+    /// or an operand
+    fn eval_word(&mut self, expr: &Expression) -> Result<i16, CalError> {
+        let value = self.eval_expression(expr)?;
+        match value.words[..] {
+            [word] => Ok(word),
+            _ => Err(CalError::new(
+                format!("Expected a single word value, found {:?}", value.typ),
+                expr.range,
+            )),
+        }
+    }
+
+    fn eval_assign(&mut self, term: &Term, rhs: &Expression) -> Result<(), CalError> {
+        let value = self.eval_expression(rhs)?;
+        let place = match term {
+            Term::Variable(name) => {
+                let Some(local) = self.frame().locals.get(name) else {
+                    return Err(CalError::new(
+                        format!("Cannot assign to `{}`, which is not a variable", name),
+                        Range::default(),
+                    ));
+                };
+                match &local.variable.typ {
+                    // Assigning a reference writes through it
+                    Type::MutRef(pointee) => Place {
+                        address: self.read(local.address, 1)?[0] as u16,
+                        typ: pointee.as_ref().clone(),
+                        access: Access::Mutable,
+                    },
+                    Type::Ref(_) => {
+                        return Err(CalError::new(
+                            format!(
+                            "Cannot assign to `{}`, which is a `&` reference, declare it as `&mut`",
+                            name
+                        ),
+                            Range::default(),
+                        ))
+                    }
+                    _ => self.eval_place(term)?,
+                }
+            }
+            Term::Index(_, _) | Term::Field(_, _) => self.eval_place(term)?,
+            _ => {
+                return Err(CalError::new(
+                    format!("Expected variable to the left of `=`, found {:?}", term),
+                    Range::default(),
+                ))
+            }
+        };
+        Self::check_access(&place, "assign to")?;
+        self.write_place(&place, &value)
+    }
+
+    /// Evaluates an expression, returning its value
+    pub fn eval_expression(&mut self, expr: &Expression) -> Result<Value, CalError> {
+        let ret = match &expr.op_and_expr {
+            Some((Operator::Assign, rhs)) => {
+                self.eval_assign(&expr.term, rhs).map(|_| Value::void())
+            }
+            Some((op, rhs)) => self.eval_term(&expr.term).and_then(|lhs| {
+                let [lhs_word] = lhs.words[..] else {
+                    return Err(CalError::new(
+                        format!("Expected a single word value, found {:?}", lhs.typ),
+                        Range::default(),
+                    ));
+                };
+                let rhs_word = self.eval_word(rhs)?;
+                let typ = match op {
+                    Operator::Eq | Operator::Ne | Operator::Lt | Operator::Gt => Type::Bool,
+                    _ => lhs.typ,
+                };
+                Ok(Value::new(
+                    typ,
+                    vec![Self::eval_operator(*op, lhs_word, rhs_word)?],
+                ))
+            }),
+            None => self.eval_term(&expr.term),
+        };
+
+        // Errors without a range are located at the innermost expression
+        ret.map_err(|err| match err.range {
+            range if range == Range::default() => CalError::new(err.message, expr.range),
+            _ => err,
+        })
+    }
+
+    /// Executes statements outside of any function, returning the value of
+    /// the last expression. Variables they define are kept for the
+    /// statements executed afterwards.
+    pub fn exec(&mut self, statements: &[Statement]) -> Result<Option<Value>, CalError> {
+        // Statements executed before may not exist anymore
+        self.frames[0].slots.clear();
+        match self.exec_statements(statements)? {
+            Flow::Normal(value) | Flow::Return(value) => Ok(value),
+        }
+    }
+
+    /// Calls the `main` function of the loaded modules
+    pub fn run(&mut self) -> Result<Value, CalError> {
+        self.call("main", vec![])
+    }
+
+    fn format_words(&self, typ: &Type, words: &[i16]) -> String {
+        let format_members = |types: Vec<&Type>| {
+            let mut ret = vec![];
+            let mut words = words;
+            for typ in types {
+                let size = self.get_type_size_in_words(typ).unwrap_or_default() as usize;
+                let (member, rest) = words.split_at(size.min(words.len()));
+                ret.push(self.format_words(typ, member));
+                words = rest;
+            }
+            ret
+        };
+
+        match (typ, words) {
+            (Type::I16, [word]) => word.to_string(),
+            (Type::Bool, [word]) => (*word != 0).to_string(),
+            (Type::Char, [word]) => format!("{:?}", *word as u8 as char),
+            (Type::Ref(_) | Type::MutRef(_), [word]) => format!("&{}", *word as u16),
+            (Type::Array(elem_type, count), _) => {
+                let elements = format_members(vec![elem_type.as_ref(); *count as usize]);
+                format!("[{}]", elements.join(", "))
+            }
+            (Type::Tuple(types), _) => {
+                let elements = format_members(types.iter().collect());
+                format!("({})", elements.join(", "))
+            }
+            (Type::Struct(name), _) => match self.structs.get(name) {
+                Some(struct_dec) => {
+                    let values = format_members(struct_dec.fields.iter().map(|f| &f.typ).collect());
+                    let fields: Vec<String> = struct_dec
+                        .fields
+                        .iter()
+                        .zip(values)
+                        .map(|(field, value)| format!("{}: {}", field.name, value))
+                        .collect();
+                    format!("{} {{ {} }}", name, fields.join(", "))
+                }
+                None => format!("{:?}", words),
+            },
+            _ => format!("{:?}", words),
+        }
+    }
+
+    /// Evaluates an expression where variables are bound to words already
+    /// in memory, like those of a program being debugged. Functions called
+    /// by the expression push their frames from `stack_pointer` on
+    pub fn eval_bound(
+        &mut self,
+        variables: Vec<(Variable, u16)>,
+        stack_pointer: u16,
+        expr: &Expression,
+    ) -> Result<Value, CalError> {
+        let locals = variables
+            .into_iter()
+            .map(|(variable, address)| (variable.name.clone(), Local { variable, address }))
+            .collect();
+        self.frames.push(Frame {
+            locals,
+            ..Default::default()
+        });
+        let stack_pointer = std::mem::replace(&mut self.stack_pointer, stack_pointer);
+        let ret = self.eval_expression(expr);
+        self.stack_pointer = stack_pointer;
+        self.frames.pop();
+        ret
+    }
+
+    /// Formats a value the way it would be written in Cal
+    pub fn format_value(&self, value: &Value) -> String {
+        self.format_words(&value.typ, &value.words)
+    }
+}
+
+impl Execute for Interpreter {
+    type Value = Value;
+
+    fn eval(&mut self, expr: &Expression) -> Result<Value, CalError> {
+        self.eval_expression(expr)
+    }
+
+    fn eval_predicate(&mut self, expr: &Expression) -> Result<bool, CalError> {
+        Ok(self.eval_word(expr)? != 0)
+    }
+
+    fn exec_let(
+        &mut self,
+        statement: &Statement,
+        variable: &Variable,
+        expr: &Expression,
+    ) -> Result<(), CalError> {
+        let variable = Variable {
+            typ: self.resolve_type(&variable.typ)?,
+            ..variable.clone()
+        };
+        let value = self.eval_expression(expr)?;
+        Self::check_ref_coercion(&variable.typ, &value)?;
+        self.check_size(&variable.typ, &value)?;
+        let address = self.allocate(statement, value.words.len() as u16)?;
+        self.define(variable, address, &value.words)
+    }
+
+    /// Executes a destructuring let, where variables with `Void` type have
+    /// their type inferred from the tuple
+    fn exec_let_tuple(
+        &mut self,
+        statement: &Statement,
+        variables: &[Variable],
+        expr: &Expression,
+    ) -> Result<(), CalError> {
+        let value = self.eval_expression(expr)?;
+        let Type::Tuple(types) = &value.typ else {
+            return Err(CalError::new(
+                format!("Expected tuple to destructure, found {:?}", value.typ),
+                expr.range,
+            ));
+        };
+        if types.len() != variables.len() {
+            return Err(CalError::new(
+                format!(
+                    "Expected a tuple of {} elements, found {}",
+                    variables.len(),
+                    types.len()
+                ),
+                expr.range,
+            ));
+        }
+
+        // Variables are allocated contiguously, just like the elements of the tuple
+        let mut address = self.allocate(statement, value.words.len() as u16)?;
+        let mut words = &value.words[..];
+        for (variable, typ) in variables.iter().zip(types) {
+            let variable = Variable {
+                typ: match variable.typ {
+                    Type::Void => typ.clone(),
+                    _ => self.resolve_type(&variable.typ)?,
+                },
+                ..variable.clone()
+            };
+            let size = self.get_type_size_in_words(&variable.typ)?;
+            if size as usize > words.len() {
+                return Err(CalError::new(
+                    format!("Expected {:?}, found {:?}", variable.typ, typ),
+                    expr.range,
+                ));
+            }
+            let (variable_words, rest) = words.split_at(size as usize);
+            self.define(variable, address, variable_words)?;
+            address += size;
+            words = rest;
+        }
+        Ok(())
+    }
+}
+
+/// Interprets a module, returning the value of its `main` function
+pub fn interpret(module: &Module) -> Result<Value, CalError> {
+    let mut interpreter = Interpreter::default();
+    interpreter.load(module)?;
+    interpreter.run()
+}
+
+pub trait Interpret {
+    fn interpret(&self) -> Result<Value, CalError>;
+}
+
+impl Interpret for str {
+    fn interpret(&self) -> Result<Value, CalError> {
+        let module: Module = self.parse()?;
+        interpret(&module)
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Inlining of calls to small functions, and to those marked `#[inline]`
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    pass, BasicBlock, BlockId, Code, Function, Instruction, Op, Target, Terminator, Value,
+};
+use crate::vm::segment::Segment;
+
+/// Functions up to this size are inlined at level `2`, which is about the
+/// cost of calling them
+pub const INLINE_SIZE: usize = 12;
+
+/// Size of a function, counting its instructions and terminators. Arguments
+/// are left out, as they become copies of the values passed
+pub fn size(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .map(|block| {
+            let instructions = block
+                .instructions
+                .iter()
+                .filter(|instruction| !matches!(instruction.op, Op::Arg(_)));
+            instructions.count() + 1
+        })
+        .sum()
+}
+
+/// Whether the body of a function can be copied into its callers, which
+/// is when it holds its locals and arguments in values and does not read
+/// the pointers it is called with
+fn can_inline(function: &Function) -> bool {
+    function.promoted
+        && function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .all(|instruction| match &instruction.op {
+                Op::Frame(_) => false,
+                Op::Load(segment, _) | Op::Store(segment, ..) => !matches!(
+                    segment,
+                    Segment::Local | Segment::Argument | Segment::Pointer
+                ),
+                _ => true,
+            })
+}
+
+/// Replaces a call, the instruction at `index` of a block, with a copy of
+/// the body of the callee. The rest of the block moves to a new block,
+/// taking the results of the call as parameters, where the returns of the
+/// callee jump to
+pub fn inline_call(caller: &mut Function, block: BlockId, index: usize, callee: &Function) {
+    let rest = caller.blocks[block.0].instructions.split_off(index + 1);
+    let Some(Instruction {
+        results,
+        op: Op::Call(_, args),
+    }) = caller.blocks[block.0].instructions.pop()
+    else {
+        panic!("Inlining an instruction which is not a call");
+    };
+
+    let continuation = BlockId(caller.blocks.len());
+    let start = continuation.0 + 1;
+    let terminator = std::mem::replace(
+        &mut caller.blocks[block.0].terminator,
+        Terminator::Jump(Target {
+            block: BlockId(start),
+            args: vec![],
+        }),
+    );
+    caller.blocks.push(BasicBlock {
+        params: results,
+        instructions: rest,
+        terminator,
+    });
+
+    // Values of the callee are numbered after those of the caller
+    let base = caller.types.len() as u32;
+    caller.types.extend(&callee.types);
+    let rename = |value: &mut Value| value.0 += base;
+    for block in &callee.blocks {
+        let mut block = block.clone();
+        block.params.iter_mut().for_each(rename);
+        for instruction in &mut block.instructions {
+            instruction.results.iter_mut().for_each(rename);
+            instruction.op.operands_mut().into_iter().for_each(rename);
+            if let Op::Arg(index) = instruction.op {
+                instruction.op = Op::Copy(args[index as usize]);
+            }
+        }
+        block.terminator.operands_mut().into_iter().for_each(rename);
+        for target in block.terminator.targets_mut() {
+            target.block.0 += start;
+        }
+        if let Terminator::Return(values) = block.terminator {
+            block.terminator = Terminator::Jump(Target {
+                block: continuation,
+                args: values,
+            });
+        }
+        caller.blocks.push(block);
+    }
+}
+
+/// Names of the functions each function calls, among those in the list
+fn callees(function: &Function, index: &HashMap<String, usize>) -> Vec<usize> {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instruction| match &instruction.op {
+            Op::Call(name, _) => index.get(name).copied(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Inlines calls to the functions marked `#[inline]` from level `1`, and
+/// also to those which are not bigger than `INLINE_SIZE` from level `2`.
+/// Recursive functions are never inlined. Callees are visited before their
+/// callers, so that what they inline is inlined along with them, and
+/// functions are optimised again after inlining
+pub fn inline(functions: &mut [Code], hints: &HashSet<String>, level: u8) {
+    let index: HashMap<String, usize> = functions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, code)| match code {
+            Code::Ir(function) => Some((function.name.clone(), i)),
+            Code::Vm(_) => None,
+        })
+        .collect();
+    let graph: Vec<Vec<usize>> = functions
+        .iter()
+        .map(|code| match code {
+            Code::Ir(function) => callees(function, &index),
+            Code::Vm(_) => vec![],
+        })
+        .collect();
+
+    // A function is recursive when it can reach itself
+    let recursive: Vec<bool> = (0..functions.len())
+        .map(|start| {
+            let mut visited = HashSet::new();
+            let mut pending = graph[start].clone();
+            while let Some(f) = pending.pop() {
+                if f == start {
+                    return true;
+                }
+                if visited.insert(f) {
+                    pending.extend(&graph[f]);
+                }
+            }
+            false
+        })
+        .collect();
+
+    // Callees come before their callers
+    let mut order = vec![];
+    let mut visited = vec![false; functions.len()];
+    fn visit(f: usize, graph: &[Vec<usize>], visited: &mut [bool], order: &mut Vec<usize>) {
+        if std::mem::replace(&mut visited[f], true) {
+            return;
+        }
+        for callee in &graph[f] {
+            visit(*callee, graph, visited, order);
+        }
+        order.push(f);
+    }
+    for f in 0..functions.len() {
+        visit(f, &graph, &mut visited, &mut order);
+    }
+
+    let inlinable = |function: &Function| {
+        !recursive[index[&function.name]]
+            && can_inline(function)
+            && (hints.contains(&function.name) || (level >= 2 && size(function) <= INLINE_SIZE))
+    };
+    for f in order {
+        let mut changed = false;
+        while let Code::Ir(caller) = &functions[f] {
+            // First call to a function to inline
+            let call = caller.blocks.iter().enumerate().find_map(|(b, block)| {
+                block
+                    .instructions
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, instruction)| {
+                        let Op::Call(name, _) = &instruction.op else {
+                            return None;
+                        };
+                        match &functions[*index.get(name)?] {
+                            Code::Ir(callee) if *name != caller.name && inlinable(callee) => {
+                                Some((BlockId(b), i, callee.clone()))
+                            }
+                            _ => None,
+                        }
+                    })
+            });
+            let Some((block, i, callee)) = call else {
+                break;
+            };
+            if let Code::Ir(caller) = &mut functions[f] {
+                inline_call(caller, block, i, &callee);
+                changed = true;
+            }
+        }
+        if let (true, Code::Ir(function)) = (changed, &mut functions[f]) {
+            pass::run(function, level);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::{HashMap, VecDeque};
+
+use super::{
+    pass, BasicBlock, BinaryOp, BlockId, Function, Instruction, Op, Target, Terminator, Ty,
+    UnaryOp, Value,
+};
+use crate::vm::{instruction::VmInstruction, segment::Segment};
+
+/// Number of words returned by each function, for those whose returns all
+/// agree on it
+pub fn return_counts(chunks: &[&[VmInstruction]]) -> HashMap<String, u16> {
+    let mut ret = HashMap::new();
+    for chunk in chunks {
+        let Some(VmInstruction::Function(name, _)) = chunk.first() else {
+            continue;
+        };
+        let mut counts = chunk.iter().filter_map(|instruction| match instruction {
+            VmInstruction::Return(count) => Some(*count),
+            _ => None,
+        });
+        if let Some(first) = counts.next() {
+            if counts.all(|count| count == first) {
+                ret.insert(name.clone(), first);
+            }
+        }
+    }
+    ret
+}
+
+/// Instructions between labels and jumps, which are executed in sequence
+#[derive(Default)]
+struct RawBlock<'a> {
+    body: Vec<&'a VmInstruction>,
+    /// Jump or return ending the block, if it does not fall through
+    end: Option<&'a VmInstruction>,
+}
+
+/// Splits the instructions of a function into blocks, returning them with
+/// the index of the block each label marks
+fn split(instructions: &[VmInstruction]) -> Option<(Vec<RawBlock<'_>>, HashMap<&str, usize>)> {
+    let mut blocks = vec![];
+    let mut labels = HashMap::new();
+    let mut current = RawBlock::default();
+    for instruction in instructions {
+        match instruction {
+            VmInstruction::Label(label) => {
+                if !current.body.is_empty() {
+                    blocks.push(std::mem::take(&mut current));
+                }
+                labels.insert(label.as_str(), blocks.len());
+            }
+            VmInstruction::Goto(_) | VmInstruction::IfGoto(_) | VmInstruction::Return(_) => {
+                current.end = Some(instruction);
+                blocks.push(std::mem::take(&mut current));
+            }
+            VmInstruction::Function(..) => return None,
+            _ => current.body.push(instruction),
+        }
+    }
+    if !current.body.is_empty() || labels.values().any(|&block| block == blocks.len()) {
+        blocks.push(current);
+    }
+    Some((blocks, labels))
+}
+
+/// Whether the code takes the address of the local or the argument segment,
+/// by reading the pointer to it into `pointer`
+fn takes_frame_address(instructions: &[VmInstruction]) -> bool {
+    instructions.windows(2).any(|pair| {
+        matches!(
+            pair,
+            [
+                VmInstruction::Push(Segment::Constant, 1 | 2),
+                VmInstruction::Pop(Segment::Pointer, _)
+            ]
+        )
+    })
+}
+
+/// Words of memory which are held in values while lifting
+struct Variables {
+    promoted: bool,
+    local_count: u16,
+    arg_count: u16,
+}
+
+impl Variables {
+    fn len(&self) -> usize {
+        (self.local_count + self.arg_count) as usize + 2
+    }
+
+    fn index(&self, segment: Segment, index: u16) -> Option<usize> {
+        match segment {
+            Segment::Local if self.promoted && index < self.local_count => Some(index as usize),
+            Segment::Argument if self.promoted && index < self.arg_count => {
+                Some((self.local_count + index) as usize)
+            }
+            Segment::Pointer if index < 2 => Some(self.pointer(index)),
+            _ => None,
+        }
+    }
+
+    fn pointer(&self, index: u16) -> usize {
+        (self.local_count + self.arg_count + index) as usize
+    }
+}
+
+/// State of a block being lifted
+struct State {
+    vars: Vec<Value>,
+    stack: Vec<Value>,
+}
+
+/// Lifts the instructions of a function, from its `Function` instruction to
+/// the next one, given the number of words each function returns. Returns
+/// `None` when the code does not follow the conventions of the generator,
+/// for instance when the depth of the stack differs along two paths
+pub fn lift(chunk: &[VmInstruction], returns: &HashMap<String, u16>) -> Option<Function> {
+    let Some(VmInstruction::Function(name, local_count)) = chunk.first() else {
+        return None;
+    };
+    let (raw, labels) = split(&chunk[1..])?;
+    let promoted = !takes_frame_address(chunk);
+    let arg_count = chunk
+        .iter()
+        .filter_map(|instruction| match instruction {
+            VmInstruction::Push(Segment::Argument, index)
+            | VmInstruction::Pop(Segment::Argument, index) => Some(index + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let vars = Variables {
+        promoted,
+        local_count: if promoted { *local_count } else { 0 },
+        arg_count: if promoted { arg_count } else { 0 },
+    };
+
+    let mut function = Function {
+        name: name.clone(),
+        local_count: *local_count,
+        promoted,
+        blocks: vec![],
+        types: vec![],
+    };
+
+    // Block 0 initialises the variables and goes to the first raw block,
+    // so raw block `i` becomes block `i + 1`
+    let mut entry = vec![];
+    let mut initial = vec![];
+    for _ in 0..vars.local_count {
+        initial.push(function.push(&mut entry, Op::Const(0)));
+    }
+    for index in 0..vars.arg_count {
+        initial.push(function.push(&mut entry, Op::Arg(index)));
+    }
+    for index in 0..2 {
+        initial.push(function.push(&mut entry, Op::Load(Segment::Pointer, index)));
+    }
+
+    let mut blocks: Vec<Option<BasicBlock>> = vec![None; raw.len() + 1];
+    let mut params: Vec<Option<Vec<Value>>> = vec![None; raw.len() + 1];
+    let mut pending = VecDeque::new();
+
+    // Makes sure a block is going to be lifted, with a parameter for each
+    // variable and each word on the stack
+    let reach = |function: &mut Function,
+                 params: &mut Vec<Option<Vec<Value>>>,
+                 pending: &mut VecDeque<usize>,
+                 block: usize,
+                 args: Vec<Value>|
+     -> Option<Target> {
+        if block >= params.len() {
+            return None;
+        }
+        match &params[block] {
+            Some(existing) if existing.len() != args.len() => return None,
+            Some(_) => (),
+            None => {
+                params[block] = Some(
+                    (0..args.len())
+                        .map(|_| function.new_value(Ty::Word))
+                        .collect(),
+                );
+                pending.push_back(block);
+            }
+        }
+        Some(Target {
+            block: BlockId(block),
+            args,
+        })
+    };
+
+    let target = reach(&mut function, &mut params, &mut pending, 1, initial)?;
+    blocks[0] = Some(BasicBlock {
+        params: vec![],
+        instructions: entry,
+        terminator: Terminator::Jump(target),
+    });
+
+    while let Some(index) = pending.pop_front() {
+        let block_params = params[index].clone().unwrap();
+        let mut state = State {
+            vars: block_params[..vars.len()].to_vec(),
+            stack: block_params[vars.len()..].to_vec(),
+        };
+        let mut instructions = vec![];
+        let block = &raw[index - 1];
+        for instruction in &block.body {
+            lift_instruction(
+                &mut function,
+                &vars,
+                &mut state,
+                &mut instructions,
+                instruction,
+                returns,
+            )?;
+        }
+
+        let mut label_target = |label: &String, state: &State| {
+            let block = *labels.get(label.as_str())? + 1;
+            let args = [state.vars.clone(), state.stack.clone()].concat();
+            reach(&mut function, &mut params, &mut pending, block, args)
+        };
+        let terminator = match block.end {
+            Some(VmInstruction::Goto(label)) => Terminator::Jump(label_target(label, &state)?),
+            Some(VmInstruction::IfGoto(label)) => {
+                let cond = state.stack.pop()?;
+                let then = label_target(label, &state)?;
+                let args = [state.vars.clone(), state.stack.clone()].concat();
+                let other = reach(&mut function, &mut params, &mut pending, index + 1, args)?;
+                Terminator::Branch(cond, then, other)
+            }
+            Some(VmInstruction::Return(count)) => {
+                let count = *count as usize;
+                if state.stack.len() < count {
+                    return None;
+                }
+                Terminator::Return(state.stack.split_off(state.stack.len() - count))
+            }
+            _ => {
+                let args = [state.vars.clone(), state.stack.clone()].concat();
+                Terminator::Jump(reach(
+                    &mut function,
+                    &mut params,
+                    &mut pending,
+                    index + 1,
+                    args,
+                )?)
+            }
+        };
+        blocks[index] = Some(BasicBlock {
+            params: block_params,
+            instructions,
+            terminator,
+        });
+    }
+
+    // Leave out the blocks which are never reached
+    let ids: Vec<Option<BlockId>> = blocks
+        .iter()
+        .scan(0, |next, block| {
+            Some(block.as_ref().map(|_| {
+                *next += 1;
+                BlockId(*next - 1)
+            }))
+        })
+        .collect();
+    function.blocks = blocks.into_iter().flatten().collect();
+    for block in &mut function.blocks {
+        for target in block.terminator.targets_mut() {
+            target.block = ids[target.block.0].unwrap();
+        }
+    }
+
+    pass::propagate_copies(&mut function);
+    pass::eliminate_dead_code(&mut function);
+    Some(function)
+}
+
+fn lift_instruction(
+    function: &mut Function,
+    vars: &Variables,
+    state: &mut State,
+    instructions: &mut Vec<Instruction>,
+    instruction: &VmInstruction,
+    returns: &HashMap<String, u16>,
+) -> Option<()> {
+    let binary = |op| {
+        move |function: &mut Function, state: &mut State, instructions: &mut Vec<Instruction>| {
+            let y = state.stack.pop()?;
+            let x = state.stack.pop()?;
+            let value = function.push(instructions, Op::Binary(op, x, y));
+            state.stack.push(value);
+            Some(())
+        }
+    };
+    let unary = |op| {
+        move |function: &mut Function, state: &mut State, instructions: &mut Vec<Instruction>| {
+            let x = state.stack.pop()?;
+            let value = function.push(instructions, Op::Unary(op, x));
+            state.stack.push(value);
+            Some(())
+        }
+    };
+
+    match instruction {
+        VmInstruction::Push(Segment::Constant, constant) => {
+            let value = function.push(instructions, Op::Const(*constant as i16));
+            state.stack.push(value);
+        }
+        VmInstruction::Push(segment, index) => {
+            let value = match (vars.index(*segment, *index), segment) {
+                (Some(var), _) => state.vars[var],
+                (None, Segment::This) => {
+                    let address = state.vars[vars.pointer(0)];
+                    function.push(instructions, Op::LoadPtr(address, *index))
+                }
+                (None, Segment::That) => {
+                    let address = state.vars[vars.pointer(1)];
+                    function.push(instructions, Op::LoadPtr(address, *index))
+                }
+                (None, Segment::Local | Segment::Argument | Segment::Static | Segment::Temp) => {
+                    function.push(instructions, Op::Load(*segment, *index))
+                }
+                _ => return None,
+            };
+            state.stack.push(value);
+        }
+        VmInstruction::Pop(segment, index) => {
+            let value = state.stack.pop()?;
+            match (vars.index(*segment, *index), segment) {
+                (Some(var), _) => state.vars[var] = value,
+                (None, Segment::This | Segment::That) => {
+                    let pointer = if *segment == Segment::This { 0 } else { 1 };
+                    let address = state.vars[vars.pointer(pointer)];
+                    instructions.push(Instruction {
+                        results: vec![],
+                        op: Op::StorePtr(address, *index, value),
+                    });
+                }
+                (None, Segment::Local | Segment::Argument | Segment::Static | Segment::Temp) => {
+                    instructions.push(Instruction {
+                        results: vec![],
+                        op: Op::Store(*segment, *index, value),
+                    });
+                }
+                _ => return None,
+            }
+        }
+        VmInstruction::Add => binary(BinaryOp::Add)(function, state, instructions)?,
+        VmInstruction::Sub => binary(BinaryOp::Sub)(function, state, instructions)?,
+        VmInstruction::And => binary(BinaryOp::And)(function, state, instructions)?,
+        VmInstruction::Or => binary(BinaryOp::Or)(function, state, instructions)?,
+        VmInstruction::Eq => binary(BinaryOp::Eq)(function, state, instructions)?,
+        VmInstruction::Lt => binary(BinaryOp::Lt)(function, state, instructions)?,
+        VmInstruction::Gt => binary(BinaryOp::Gt)(function, state, instructions)?,
+        VmInstruction::Neg => unary(UnaryOp::Neg)(function, state, instructions)?,
+        VmInstruction::Not => unary(UnaryOp::Not)(function, state, instructions)?,
+        VmInstruction::Call(name, arg_count) => {
+            let arg_count = *arg_count as usize;
+            if state.stack.len() < arg_count {
+                return None;
+            }
+            match BinaryOp::from_builtin(name) {
+                Some(op) if arg_count == 2 => binary(op)(function, state, instructions)?,
+                _ => {
+                    let args = state.stack.split_off(state.stack.len() - arg_count);
+                    let results: Vec<Value> = (0..*returns.get(name)?)
+                        .map(|_| function.new_value(Ty::Word))
+                        .collect();
+                    state.stack.extend(&results);
+                    instructions.push(Instruction {
+                        results,
+                        op: Op::Call(name.clone(), args),
+                    });
+                }
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
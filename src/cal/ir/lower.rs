@@ -0,0 +1,570 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::{HashMap, HashSet};
+
+use super::{BinaryOp, BlockId, Function, Op, Target, Terminator, UnaryOp, Value};
+use crate::vm::{instruction::VmInstruction, segment::Segment};
+
+/// Decides which values stay on the stack between their definition and
+/// their only use, and which are stored into a local slot
+struct Layout<'a> {
+    function: &'a Function,
+    /// Block and index of the instruction defining each value
+    definitions: HashMap<Value, (usize, usize)>,
+    uses: Vec<usize>,
+    /// Instructions emitted as part of the tree of their user, by block
+    inline: Vec<Vec<bool>>,
+}
+
+impl<'a> Layout<'a> {
+    fn new(function: &'a Function) -> Self {
+        let mut definitions = HashMap::new();
+        for (b, block) in function.blocks.iter().enumerate() {
+            for (i, instruction) in block.instructions.iter().enumerate() {
+                for result in &instruction.results {
+                    definitions.insert(*result, (b, i));
+                }
+            }
+        }
+        let mut layout = Self {
+            function,
+            definitions,
+            uses: function.use_counts(),
+            inline: vec![],
+        };
+        for b in 0..function.blocks.len() {
+            let inline = layout.stackify(b);
+            layout.inline.push(inline);
+        }
+        layout
+    }
+
+    fn op(&self, value: Value) -> Option<&Op> {
+        let (b, i) = self.definitions.get(&value)?;
+        Some(&self.function.blocks[*b].instructions[*i].op)
+    }
+
+    /// Whether a value is computed again wherever it is used
+    fn is_rematerialized(&self, value: Value) -> bool {
+        matches!(self.op(value), Some(Op::Const(_) | Op::Arg(_)))
+    }
+
+    fn is_inline(&self, value: Value) -> bool {
+        match self.definitions.get(&value) {
+            Some((b, i)) => self.inline[*b][*i],
+            None => false,
+        }
+    }
+
+    /// Whether a value needs a slot to be kept in
+    fn is_stored(&self, value: Value) -> bool {
+        self.uses[value.0 as usize] > 0 && !self.is_rematerialized(value) && !self.is_inline(value)
+    }
+
+    /// Marks the instructions of a block which can be emitted right where
+    /// their result is used, as long as this does not change the order of
+    /// instructions touching memory or calling functions
+    fn stackify(&self, b: usize) -> Vec<bool> {
+        let block = &self.function.blocks[b];
+        let count = block.instructions.len();
+        let mut inline = vec![false; count];
+        let operands = match &block.terminator {
+            Terminator::Jump(target) => target.args.clone(),
+            Terminator::Branch(cond, ..) => vec![*cond],
+            Terminator::Return(values) => values.clone(),
+        };
+        self.place(b, &operands, count, &mut count.clone(), false, &mut inline);
+        for i in (0..count).rev() {
+            if !inline[i] {
+                let operands = block.instructions[i].op.operands();
+                self.place(b, &operands, i, &mut i.clone(), false, &mut inline);
+            }
+        }
+        inline
+    }
+
+    /// Tries to move the definitions of the operands of the tree rooted at
+    /// `root` into it. Impure definitions must precede `limit`, the earliest
+    /// impure one moved so far, and can not cross impure instructions left
+    /// in place. `pure_only` is set while placing operands of a definition
+    /// which already crosses one
+    fn place(
+        &self,
+        b: usize,
+        operands: &[Value],
+        root: usize,
+        limit: &mut usize,
+        pure_only: bool,
+        inline: &mut Vec<bool>,
+    ) {
+        let instructions = &self.function.blocks[b].instructions;
+        // Whether moving an instruction to the root reorders it with another
+        // one left in place, when either of them writes
+        let crosses = |i: usize, inline: &Vec<bool>| {
+            let writes = instructions[i].op.writes();
+            (i + 1..root).any(|j| {
+                let op = &instructions[j].op;
+                !inline[j] && !op.is_pure() && (writes || op.writes())
+            })
+        };
+        for operand in operands.iter().rev() {
+            let Some(&(def_block, i)) = self.definitions.get(operand) else {
+                continue;
+            };
+            if def_block != b {
+                continue;
+            }
+            let instruction = &instructions[i];
+            if inline[i]
+                || self.uses[operand.0 as usize] != 1
+                || instruction.results.len() != 1
+                || self.is_rematerialized(*operand)
+            {
+                continue;
+            }
+            if instruction.op.is_pure() {
+                inline[i] = true;
+                let pure_only = pure_only || crosses(i, inline);
+                self.place(
+                    b,
+                    &instruction.op.operands(),
+                    root,
+                    limit,
+                    pure_only,
+                    inline,
+                );
+            } else if !pure_only && i < *limit && !crosses(i, inline) {
+                inline[i] = true;
+                *limit = i;
+                self.place(b, &instruction.op.operands(), root, limit, false, inline);
+            }
+        }
+    }
+
+    /// Values read from slots when emitting a value
+    fn leaves(&self, value: Value, ret: &mut Vec<Value>) {
+        if self.is_stored(value) {
+            ret.push(value);
+        } else if self.is_inline(value) {
+            for operand in self.op(value).unwrap().operands() {
+                self.leaves(operand, ret);
+            }
+        }
+    }
+
+    fn leaves_of(&self, values: &[Value]) -> Vec<Value> {
+        let mut ret = vec![];
+        for value in values {
+            self.leaves(*value, &mut ret);
+        }
+        ret
+    }
+
+    fn terminator_leaves(&self, terminator: &Terminator) -> Vec<Value> {
+        let mut terminator = terminator.clone();
+        let operands: Vec<Value> = terminator.operands_mut().into_iter().map(|v| *v).collect();
+        self.leaves_of(&operands)
+    }
+
+    /// Assigns a slot to each stored value, so that values alive at the same
+    /// time never share one. Arguments passed to parameters preferably get
+    /// the same slot, which saves copying them when jumping
+    fn allocate(&self) -> (HashMap<Value, u16>, u16) {
+        let blocks = &self.function.blocks;
+        let successors: Vec<Vec<usize>> = blocks
+            .iter()
+            .map(|block| {
+                block
+                    .terminator
+                    .targets()
+                    .iter()
+                    .map(|target| target.block.0)
+                    .collect()
+            })
+            .collect();
+
+        // Walks a block backwards from the values alive at its end, calling
+        // `interfere` with each value defined and the values alive after it
+        let walk = |b: usize,
+                    live: &mut HashSet<Value>,
+                    interfere: &mut dyn FnMut(Value, &HashSet<Value>)| {
+            let block = &blocks[b];
+            live.extend(self.terminator_leaves(&block.terminator));
+            for (i, instruction) in block.instructions.iter().enumerate().rev() {
+                if self.inline[b][i] {
+                    continue;
+                }
+                let results: Vec<Value> = instruction
+                    .results
+                    .iter()
+                    .copied()
+                    .filter(|result| self.is_stored(*result))
+                    .collect();
+                for result in &results {
+                    let mut alive = live.clone();
+                    alive.extend(&results);
+                    interfere(*result, &alive);
+                }
+                for result in &results {
+                    live.remove(result);
+                }
+                live.extend(self.leaves_of(&instruction.op.operands()));
+            }
+            let params: Vec<Value> = block
+                .params
+                .iter()
+                .copied()
+                .filter(|param| self.is_stored(*param))
+                .collect();
+            for param in &params {
+                let mut alive = live.clone();
+                alive.extend(&params);
+                interfere(*param, &alive);
+            }
+            for param in &params {
+                live.remove(param);
+            }
+        };
+
+        let mut live_in: Vec<HashSet<Value>> = vec![HashSet::new(); blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..blocks.len()).rev() {
+                let mut live: HashSet<Value> = successors[b]
+                    .iter()
+                    .flat_map(|s| live_in[*s].iter().copied())
+                    .collect();
+                walk(b, &mut live, &mut |_, _| ());
+                if live != live_in[b] {
+                    live_in[b] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut edges: HashMap<Value, HashSet<Value>> = HashMap::new();
+        let mut values = vec![];
+        for (b, block_successors) in successors.iter().enumerate() {
+            let mut live: HashSet<Value> = block_successors
+                .iter()
+                .flat_map(|s| live_in[*s].iter().copied())
+                .collect();
+            walk(b, &mut live, &mut |value, alive| {
+                values.push(value);
+                for other in alive {
+                    if *other != value {
+                        edges.entry(value).or_default().insert(*other);
+                        edges.entry(*other).or_default().insert(value);
+                    }
+                }
+            });
+        }
+
+        let mut hints: HashMap<Value, Vec<Value>> = HashMap::new();
+        for block in blocks {
+            for target in block.terminator.targets() {
+                let params = &blocks[target.block.0].params;
+                for (arg, param) in target.args.iter().zip(params) {
+                    hints.entry(*arg).or_default().push(*param);
+                    hints.entry(*param).or_default().push(*arg);
+                }
+            }
+        }
+
+        values.sort();
+        values.dedup();
+        let mut slots: HashMap<Value, u16> = HashMap::new();
+        let mut count = 0;
+        for value in values {
+            let taken: HashSet<u16> = edges
+                .get(&value)
+                .into_iter()
+                .flatten()
+                .filter_map(|other| slots.get(other).copied())
+                .collect();
+            let hinted = hints
+                .get(&value)
+                .into_iter()
+                .flatten()
+                .filter_map(|other| slots.get(other).copied())
+                .find(|slot| !taken.contains(slot));
+            let slot = hinted.unwrap_or_else(|| (0..).find(|slot| !taken.contains(slot)).unwrap());
+            count = count.max(slot + 1);
+            slots.insert(value, slot);
+        }
+        (slots, count)
+    }
+}
+
+/// Emits the VM instructions of a function
+struct Emitter<'a> {
+    layout: &'a Layout<'a>,
+    slots: HashMap<Value, u16>,
+    /// First local slot holding values
+    base: u16,
+    labels: Vec<String>,
+    /// Values last popped into `pointer 0` and `pointer 1`
+    pointers: [Option<Value>; 2],
+    /// Pointer to overwrite next
+    next_pointer: usize,
+    ret: Vec<VmInstruction>,
+}
+
+impl<'a> Emitter<'a> {
+    fn push_const(&mut self, c: i16) {
+        if c >= 0 {
+            self.ret
+                .push(VmInstruction::Push(Segment::Constant, c as u16));
+        } else {
+            // Constants are positive
+            self.ret
+                .push(VmInstruction::Push(Segment::Constant, !c as u16));
+            self.ret.push(VmInstruction::Not);
+        }
+    }
+
+    fn slot(&self, value: Value) -> Option<u16> {
+        self.slots.get(&value).map(|slot| self.base + slot)
+    }
+
+    /// Pushes a value onto the stack
+    fn value(&mut self, value: Value) {
+        if let Some(slot) = self.slot(value) {
+            self.ret.push(VmInstruction::Push(Segment::Local, slot));
+        } else {
+            let op = self.layout.op(value).unwrap().clone();
+            self.op(&op);
+        }
+    }
+
+    /// Returns the segment addressing words from a value, putting it into
+    /// a pointer unless it is already there
+    fn point(&mut self, address: Value) -> Segment {
+        let segments = [Segment::This, Segment::That];
+        if let Some(i) = self.pointers.iter().position(|p| *p == Some(address)) {
+            self.next_pointer = 1 - i;
+            return segments[i];
+        }
+        self.value(address);
+        let i = self.next_pointer;
+        self.ret
+            .push(VmInstruction::Pop(Segment::Pointer, i as u16));
+        self.pointers[i] = Some(address);
+        self.next_pointer = 1 - i;
+        segments[i]
+    }
+
+    /// Emits an operation, leaving its results on the stack
+    fn op(&mut self, op: &Op) {
+        match op {
+            Op::Const(c) => self.push_const(*c),
+            Op::Arg(index) => self
+                .ret
+                .push(VmInstruction::Push(Segment::Argument, *index)),
+            Op::Frame(segment) => {
+                let i = self.next_pointer;
+                self.ret.extend([
+                    VmInstruction::Push(Segment::Constant, segment.get_base_address() as u16),
+                    VmInstruction::Pop(Segment::Pointer, i as u16),
+                    VmInstruction::Push([Segment::This, Segment::That][i], 0),
+                ]);
+                self.pointers[i] = None;
+                self.next_pointer = 1 - i;
+            }
+            Op::Copy(x) => self.value(*x),
+            Op::Unary(op, x) => {
+                self.value(*x);
+                self.ret.push(match op {
+                    UnaryOp::Neg => VmInstruction::Neg,
+                    UnaryOp::Not => VmInstruction::Not,
+                });
+            }
+            Op::Binary(op, x, y) => {
+                self.value(*x);
+                self.value(*y);
+                self.ret.push(match op {
+                    BinaryOp::Add => VmInstruction::Add,
+                    BinaryOp::Sub => VmInstruction::Sub,
+                    BinaryOp::And => VmInstruction::And,
+                    BinaryOp::Or => VmInstruction::Or,
+                    BinaryOp::Eq => VmInstruction::Eq,
+                    BinaryOp::Lt => VmInstruction::Lt,
+                    BinaryOp::Gt => VmInstruction::Gt,
+                    _ => VmInstruction::Call(op.builtin().unwrap().into(), 2),
+                });
+            }
+            Op::Load(segment, index) => self.ret.push(VmInstruction::Push(*segment, *index)),
+            Op::Store(segment, index, x) => {
+                self.value(*x);
+                self.ret.push(VmInstruction::Pop(*segment, *index));
+            }
+            Op::LoadPtr(address, index) => {
+                let segment = self.point(*address);
+                self.ret.push(VmInstruction::Push(segment, *index));
+            }
+            Op::StorePtr(address, index, x) => {
+                self.value(*x);
+                let segment = self.point(*address);
+                self.ret.push(VmInstruction::Pop(segment, *index));
+            }
+            Op::Call(name, args) => {
+                for arg in args {
+                    self.value(*arg);
+                }
+                self.ret
+                    .push(VmInstruction::Call(name.clone(), args.len() as u16));
+                self.pointers = [None, None];
+            }
+        }
+    }
+
+    /// Arguments and parameters which need copying when jumping to a target
+    fn copies(&self, target: &Target) -> Vec<(Value, Value)> {
+        let params = &self.layout.function.blocks[target.block.0].params;
+        target
+            .args
+            .iter()
+            .copied()
+            .zip(params.iter().copied())
+            .filter(|(arg, param)| {
+                self.slot(*arg).is_none() || self.slot(*arg) != self.slot(*param)
+            })
+            .collect()
+    }
+
+    /// Copies the arguments of a target into its parameters, all at once
+    fn copy(&mut self, target: &Target) {
+        let copies = self.copies(target);
+        for (arg, _) in &copies {
+            self.value(*arg);
+        }
+        for (_, param) in copies.iter().rev() {
+            match self.slot(*param) {
+                Some(slot) => self.ret.push(VmInstruction::Pop(Segment::Local, slot)),
+                None => self.ret.push(VmInstruction::Pop(Segment::Temp, 0)),
+            }
+        }
+    }
+
+    fn label(&self, block: BlockId) -> String {
+        self.labels[block.0].clone()
+    }
+
+    fn block(&mut self, b: usize, edges: &mut Vec<(String, Target)>, label_count: &mut usize) {
+        let layout = self.layout;
+        let block = &layout.function.blocks[b];
+        self.ret.push(VmInstruction::Label(self.label(BlockId(b))));
+        self.pointers = [None, None];
+
+        for (i, instruction) in block.instructions.iter().enumerate() {
+            let used = instruction
+                .results
+                .iter()
+                .any(|result| layout.uses[result.0 as usize] > 0);
+            if layout.inline[b][i]
+                || matches!(instruction.op, Op::Const(_) | Op::Arg(_))
+                || (instruction.op.is_removable() && !used)
+            {
+                continue;
+            }
+            self.op(&instruction.op);
+            for result in instruction.results.iter().rev() {
+                match self.slot(*result) {
+                    Some(slot) => self.ret.push(VmInstruction::Pop(Segment::Local, slot)),
+                    None => self.ret.push(VmInstruction::Pop(Segment::Temp, 0)),
+                }
+            }
+        }
+
+        let next = BlockId(b + 1);
+        match &block.terminator {
+            Terminator::Return(values) => {
+                for value in values {
+                    self.value(*value);
+                }
+                self.ret.push(VmInstruction::Return(values.len() as u16));
+            }
+            Terminator::Jump(target) => {
+                self.copy(target);
+                if target.block != next {
+                    self.ret.push(VmInstruction::Goto(self.label(target.block)));
+                }
+            }
+            Terminator::Branch(cond, then, other) => {
+                self.value(*cond);
+                if self.copies(then).is_empty() {
+                    self.ret.push(VmInstruction::IfGoto(self.label(then.block)));
+                } else {
+                    let edge = format!("IR_LABEL{}", label_count);
+                    *label_count += 1;
+                    self.ret.push(VmInstruction::IfGoto(edge.clone()));
+                    edges.push((edge, then.clone()));
+                }
+                self.copy(other);
+                if other.block != next {
+                    self.ret.push(VmInstruction::Goto(self.label(other.block)));
+                }
+            }
+        }
+    }
+}
+
+/// Lowers a function to VM instructions
+pub fn lower(function: &Function, label_count: &mut usize) -> Vec<VmInstruction> {
+    let layout = Layout::new(function);
+    let (slots, slot_count) = layout.allocate();
+    let base = if function.promoted {
+        0
+    } else {
+        function.local_count
+    };
+    let labels = (0..function.blocks.len())
+        .map(|_| {
+            *label_count += 1;
+            format!("IR_LABEL{}", *label_count - 1)
+        })
+        .collect();
+    let mut emitter = Emitter {
+        layout: &layout,
+        slots,
+        base,
+        labels,
+        pointers: [None, None],
+        next_pointer: 0,
+        ret: vec![VmInstruction::Function(
+            function.name.clone(),
+            base + slot_count,
+        )],
+    };
+
+    let mut edges = vec![];
+    for b in 0..function.blocks.len() {
+        emitter.block(b, &mut edges, label_count);
+    }
+    for (label, target) in edges {
+        emitter.ret.push(VmInstruction::Label(label));
+        emitter.pointers = [None, None];
+        emitter.copy(&target);
+        emitter
+            .ret
+            .push(VmInstruction::Goto(emitter.label(target.block)));
+    }
+
+    // Only keep the labels which are jumped to
+    let targets: HashSet<String> = emitter
+        .ret
+        .iter()
+        .filter_map(|instruction| match instruction {
+            VmInstruction::Goto(label) | VmInstruction::IfGoto(label) => Some(label.clone()),
+            _ => None,
+        })
+        .collect();
+    emitter.ret.retain(|instruction| match instruction {
+        VmInstruction::Label(label) => targets.contains(label),
+        _ => true,
+    });
+    emitter.ret
+}
@@ -0,0 +1,566 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Intermediate representation in SSA form, where every value is defined
+//! once by an instruction or by a parameter of a basic block. Block
+//! parameters take the place of phi nodes: each jump passes the values its
+//! target expects as arguments.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::vm::{instruction::VmInstruction, segment::Segment};
+
+pub mod inline;
+pub mod lift;
+pub mod lower;
+pub mod pass;
+
+/// A value defined exactly once, displayed as `%n`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub u32);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+/// Index of a basic block in its function, displayed as `bn`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+/// Every value is a word, but the result of a comparison is known to be
+/// either `0` or `-1`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ty {
+    Word,
+    Bool,
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Word => write!(f, "word"),
+            Ty::Bool => write!(f, "bool"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl UnaryOp {
+    pub fn eval(self, x: i16) -> i16 {
+        match self {
+            UnaryOp::Neg => x.wrapping_neg(),
+            UnaryOp::Not => !x,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Eq,
+    Lt,
+    Gt,
+    /// Built-in `mul` function
+    Mul,
+    /// Built-in `div` function
+    Div,
+    /// Built-in `mod` function
+    Mod,
+}
+
+impl BinaryOp {
+    /// Evaluates the operation like the VM and its built-in functions do,
+    /// where comparisons do not overflow
+    pub fn eval(self, x: i16, y: i16) -> i16 {
+        let bool = |b: bool| if b { -1 } else { 0 };
+        match self {
+            BinaryOp::Add => x.wrapping_add(y),
+            BinaryOp::Sub => x.wrapping_sub(y),
+            BinaryOp::And => x & y,
+            BinaryOp::Or => x | y,
+            BinaryOp::Eq => bool(x == y),
+            BinaryOp::Lt => bool(x < y),
+            BinaryOp::Gt => bool(x > y),
+            BinaryOp::Mul => x.wrapping_mul(y),
+            BinaryOp::Div if y == 0 => -1,
+            BinaryOp::Div => x.wrapping_div(y),
+            BinaryOp::Mod if y == 0 => x,
+            BinaryOp::Mod => x.wrapping_rem(y),
+        }
+    }
+
+    pub fn is_commutative(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Add | BinaryOp::And | BinaryOp::Or | BinaryOp::Eq | BinaryOp::Mul
+        )
+    }
+
+    /// Name of the built-in function computing the operation, if any
+    pub fn builtin(self) -> Option<&'static str> {
+        match self {
+            BinaryOp::Mul => Some("mul"),
+            BinaryOp::Div => Some("div"),
+            BinaryOp::Mod => Some("mod"),
+            _ => None,
+        }
+    }
+
+    pub fn from_builtin(name: &str) -> Option<Self> {
+        match name {
+            "mul" => Some(BinaryOp::Mul),
+            "div" => Some(BinaryOp::Div),
+            "mod" => Some(BinaryOp::Mod),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+    Const(i16),
+    /// Word of the argument segment, which is never written to when the
+    /// arguments are held in values
+    Arg(u16),
+    /// Address of the local or the argument segment, which stays the same
+    /// while the function runs
+    Frame(Segment),
+    Unary(UnaryOp, Value),
+    Binary(BinaryOp, Value, Value),
+    Copy(Value),
+    /// Reads a word of a VM segment
+    Load(Segment, u16),
+    /// Writes a word of a VM segment
+    Store(Segment, u16, Value),
+    /// Reads the word at an address plus an offset
+    LoadPtr(Value, u16),
+    /// Writes the word at an address plus an offset
+    StorePtr(Value, u16, Value),
+    /// Calls a function, defining a value for each word it returns
+    Call(String, Vec<Value>),
+}
+
+impl Op {
+    /// Whether the operation neither touches memory nor calls anything, so
+    /// it can be moved, duplicated or removed freely
+    pub fn is_pure(&self) -> bool {
+        matches!(
+            self,
+            Op::Const(_) | Op::Arg(_) | Op::Frame(_) | Op::Unary(..) | Op::Binary(..) | Op::Copy(_)
+        )
+    }
+
+    /// Whether the operation writes memory, or the pointers it is read
+    /// through, so that it can not be moved past other loads
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            Op::Store(..) | Op::StorePtr(..) | Op::Call(..) | Op::Load(Segment::Pointer, _)
+        )
+    }
+
+    /// Whether the operation can be removed when its results are not used
+    pub fn is_removable(&self) -> bool {
+        self.is_pure() || matches!(self, Op::Load(..) | Op::LoadPtr(..))
+    }
+
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Op::Const(_) | Op::Arg(_) | Op::Frame(_) | Op::Load(..) => vec![],
+            Op::Unary(_, x) | Op::Copy(x) | Op::Store(_, _, x) | Op::LoadPtr(x, _) => vec![*x],
+            // The value to store is computed before the address
+            Op::StorePtr(address, _, x) => vec![*x, *address],
+            Op::Binary(_, x, y) => vec![*x, *y],
+            Op::Call(_, args) => args.clone(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Op::Const(_) | Op::Arg(_) | Op::Frame(_) | Op::Load(..) => vec![],
+            Op::Unary(_, x) | Op::Copy(x) | Op::Store(_, _, x) | Op::LoadPtr(x, _) => vec![x],
+            Op::StorePtr(address, _, x) => vec![x, address],
+            Op::Binary(_, x, y) => vec![x, y],
+            Op::Call(_, args) => args.iter_mut().collect(),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Const(c) => write!(f, "const {}", c),
+            Op::Arg(index) => write!(f, "arg {}", index),
+            Op::Frame(segment) => write!(f, "frame {}", segment),
+            Op::Unary(op, x) => write!(f, "{} {}", format!("{:?}", op).to_lowercase(), x),
+            Op::Binary(op, x, y) => {
+                write!(f, "{} {}, {}", format!("{:?}", op).to_lowercase(), x, y)
+            }
+            Op::Copy(x) => write!(f, "copy {}", x),
+            Op::Load(segment, index) => write!(f, "load {} {}", segment, index),
+            Op::Store(segment, index, x) => write!(f, "store {} {}, {}", segment, index, x),
+            Op::LoadPtr(address, offset) => write!(f, "load [{} + {}]", address, offset),
+            Op::StorePtr(address, offset, x) => {
+                write!(f, "store [{} + {}], {}", address, offset, x)
+            }
+            Op::Call(name, args) => write!(f, "call {}({})", name, list(args)),
+        }
+    }
+}
+
+/// Comma separated values
+fn list(values: &[Value]) -> String {
+    let values: Vec<String> = values.iter().map(Value::to_string).collect();
+    values.join(", ")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub results: Vec<Value>,
+    pub op: Op,
+}
+
+/// Block a jump goes to, with the arguments for its parameters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub block: BlockId,
+    pub args: Vec<Value>,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.args.is_empty() {
+            write!(f, "{}", self.block)
+        } else {
+            write!(f, "{}({})", self.block, list(&self.args))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Terminator {
+    Jump(Target),
+    /// Goes to the first target when the value is not `0`
+    Branch(Value, Target, Target),
+    Return(Vec<Value>),
+}
+
+impl Terminator {
+    pub fn targets(&self) -> Vec<&Target> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch(_, then, other) => vec![then, other],
+            Terminator::Return(_) => vec![],
+        }
+    }
+
+    pub fn targets_mut(&mut self) -> Vec<&mut Target> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch(_, then, other) => vec![then, other],
+            Terminator::Return(_) => vec![],
+        }
+    }
+
+    /// Values used by the terminator, including the arguments of its targets
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Jump(target) => target.args.iter_mut().collect(),
+            Terminator::Branch(cond, then, other) => std::iter::once(cond)
+                .chain(then.args.iter_mut())
+                .chain(other.args.iter_mut())
+                .collect(),
+            Terminator::Return(values) => values.iter_mut().collect(),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jump {}", target),
+            Terminator::Branch(cond, then, other) => {
+                write!(f, "branch {}, {}, {}", cond, then, other)
+            }
+            Terminator::Return(values) if values.is_empty() => write!(f, "return"),
+            Terminator::Return(values) => write!(f, "return {}", list(values)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub params: Vec<Value>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+/// A function lifted from VM code. The first block is the entry
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    /// Number of words of the local segment in the original VM code
+    pub local_count: u16,
+    /// Whether locals and arguments are held in values, which is only
+    /// possible when the function never takes their address
+    pub promoted: bool,
+    pub blocks: Vec<BasicBlock>,
+    /// Type of each value, indexed by its number
+    pub types: Vec<Ty>,
+}
+
+impl Function {
+    pub fn new_value(&mut self, ty: Ty) -> Value {
+        self.types.push(ty);
+        Value(self.types.len() as u32 - 1)
+    }
+
+    /// Type of the result of an operation
+    pub fn ty(&self, op: &Op) -> Ty {
+        let is_bool = |x: &Value| self.types[x.0 as usize] == Ty::Bool;
+        match op {
+            Op::Binary(BinaryOp::Eq | BinaryOp::Lt | BinaryOp::Gt, ..) => Ty::Bool,
+            Op::Binary(BinaryOp::And | BinaryOp::Or, x, y) if is_bool(x) && is_bool(y) => Ty::Bool,
+            Op::Unary(UnaryOp::Not, x) | Op::Copy(x) if is_bool(x) => Ty::Bool,
+            _ => Ty::Word,
+        }
+    }
+
+    /// Appends an instruction defining a new value to a list
+    pub fn push(&mut self, instructions: &mut Vec<Instruction>, op: Op) -> Value {
+        let value = self.new_value(self.ty(&op));
+        instructions.push(Instruction {
+            results: vec![value],
+            op,
+        });
+        value
+    }
+
+    /// Replaces the uses of values according to a map, following chains of
+    /// replacements
+    pub fn replace_uses(&mut self, map: &HashMap<Value, Value>) {
+        if map.is_empty() {
+            return;
+        }
+        let resolve = |mut value: Value| {
+            while let Some(next) = map.get(&value) {
+                value = *next;
+            }
+            value
+        };
+        for block in &mut self.blocks {
+            for instruction in &mut block.instructions {
+                for operand in instruction.op.operands_mut() {
+                    *operand = resolve(*operand);
+                }
+            }
+            for operand in block.terminator.operands_mut() {
+                *operand = resolve(*operand);
+            }
+        }
+    }
+
+    /// Number of uses of each value, indexed by its number
+    pub fn use_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.types.len()];
+        for block in &self.blocks {
+            for instruction in &block.instructions {
+                for operand in instruction.op.operands() {
+                    counts[operand.0 as usize] += 1;
+                }
+            }
+            let mut terminator = block.terminator.clone();
+            for operand in terminator.operands_mut() {
+                counts[operand.0 as usize] += 1;
+            }
+        }
+        counts
+    }
+
+    /// Operations defining values, by value
+    pub fn definitions(&self) -> HashMap<Value, &Op> {
+        self.blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .flat_map(|instruction| instruction.results.iter().map(|r| (*r, &instruction.op)))
+            .collect()
+    }
+
+    /// Predecessors of each block, once for every jump to it
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut ret = vec![vec![]; self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for target in block.terminator.targets() {
+                ret[target.block.0].push(BlockId(i));
+            }
+        }
+        ret
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "function {} {{", self.name)?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            let params: Vec<String> = block
+                .params
+                .iter()
+                .map(|param| format!("{}: {}", param, self.types[param.0 as usize]))
+                .collect();
+            if params.is_empty() {
+                writeln!(f, "{}:", BlockId(i))?;
+            } else {
+                writeln!(f, "{}({}):", BlockId(i), params.join(", "))?;
+            }
+            for instruction in &block.instructions {
+                write!(f, "    ")?;
+                let results: Vec<String> = instruction
+                    .results
+                    .iter()
+                    .map(|result| format!("{}: {}", result, self.types[result.0 as usize]))
+                    .collect();
+                if !results.is_empty() {
+                    write!(f, "{} = ", results.join(", "))?;
+                }
+                writeln!(f, "{}", instruction.op)?;
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Code of a function, which stays as VM instructions when it can not be
+/// lifted to the intermediate representation
+#[derive(Clone, Debug)]
+pub enum Code {
+    Ir(Function),
+    Vm(Vec<VmInstruction>),
+}
+
+/// A whole program, with the instructions preceding the first function
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub prologue: Vec<VmInstruction>,
+    pub functions: Vec<Code>,
+    /// Functions marked `#[inline]`
+    pub inline: HashSet<String>,
+}
+
+impl Program {
+    /// Lifts every function of a program which can be lifted
+    pub fn new(instructions: &[VmInstruction]) -> Self {
+        let first = instructions
+            .iter()
+            .position(|instruction| matches!(instruction, VmInstruction::Function(..)))
+            .unwrap_or(instructions.len());
+        let mut chunks: Vec<&[VmInstruction]> = vec![];
+        let mut start = first;
+        for i in first + 1..=instructions.len() {
+            if i == instructions.len() || matches!(instructions[i], VmInstruction::Function(..)) {
+                chunks.push(&instructions[start..i]);
+                start = i;
+            }
+        }
+
+        let returns = lift::return_counts(&chunks);
+        Self {
+            prologue: instructions[..first].to_vec(),
+            functions: chunks
+                .into_iter()
+                .map(|chunk| match lift::lift(chunk, &returns) {
+                    Some(function) => Code::Ir(function),
+                    None => Code::Vm(chunk.to_vec()),
+                })
+                .collect(),
+            inline: HashSet::new(),
+        }
+    }
+
+    /// Runs the optimisation passes of a level on every function: none at
+    /// `0`, propagation of constants and copies with removal of dead code,
+    /// tail calls and inlining of `#[inline]` functions at `1`, and also
+    /// strength reduction, common subexpression and dead store elimination
+    /// with inlining of small functions from `2`
+    pub fn optimize(&mut self, level: u8) {
+        if level == 0 {
+            return;
+        }
+        for code in &mut self.functions {
+            if let Code::Ir(function) = code {
+                pass::eliminate_tail_calls(function);
+                pass::run(function, level);
+            }
+        }
+        inline::inline(&mut self.functions, &self.inline, level);
+    }
+
+    /// Lowers the program back to VM instructions
+    pub fn lower(self) -> Vec<VmInstruction> {
+        let mut ret = self.prologue;
+        let mut label_count = 0;
+        for code in self.functions {
+            match code {
+                Code::Ir(function) => ret.extend(lower::lower(&function, &mut label_count)),
+                Code::Vm(instructions) => ret.extend(instructions),
+            }
+        }
+        ret
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for instruction in &self.prologue {
+            writeln!(f, "{}", instruction)?;
+        }
+        for code in &self.functions {
+            match code {
+                Code::Ir(function) => write!(f, "{}", function)?,
+                Code::Vm(instructions) => {
+                    for instruction in instructions {
+                        writeln!(f, "{}", instruction)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Optimises VM instructions at a level, going through the intermediate
+/// representation, given the functions marked `#[inline]`. Level `0` leaves
+/// them untouched
+pub fn optimize(
+    instructions: Vec<VmInstruction>,
+    level: u8,
+    inline: HashSet<String>,
+) -> Vec<VmInstruction> {
+    if level == 0 {
+        return instructions;
+    }
+    let mut program = Program::new(&instructions);
+    program.inline = inline;
+    program.optimize(level);
+    program.lower()
+}
@@ -0,0 +1,763 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    BasicBlock, BinaryOp, BlockId, Function, Instruction, Op, Target, Terminator, Ty, UnaryOp,
+    Value,
+};
+use crate::vm::segment::Segment;
+
+/// Runs the passes of an optimisation level on a function
+pub fn run(function: &mut Function, level: u8) {
+    if level == 0 {
+        return;
+    }
+    for _ in 0..2 {
+        propagate_constants(function);
+        propagate_copies(function);
+        if level >= 2 {
+            reduce_strength(function);
+            propagate_constants(function);
+            propagate_copies(function);
+            eliminate_common_subexpressions(function);
+            eliminate_dead_stores(function);
+        }
+        eliminate_dead_code(function);
+        simplify_cfg(function);
+    }
+}
+
+/// Arguments passed to each parameter of each block, one for every jump
+fn incoming(function: &Function) -> Vec<Vec<Vec<Value>>> {
+    let mut ret: Vec<Vec<Vec<Value>>> = function
+        .blocks
+        .iter()
+        .map(|block| vec![vec![]; block.params.len()])
+        .collect();
+    for block in &function.blocks {
+        for target in block.terminator.targets() {
+            for (i, arg) in target.args.iter().enumerate() {
+                ret[target.block.0][i].push(*arg);
+            }
+        }
+    }
+    ret
+}
+
+/// Removes the parameters of a block which are not kept, along with the
+/// arguments passed to them
+fn retain_params(function: &mut Function, block: BlockId, keep: &[bool]) {
+    let mut i = 0;
+    function.blocks[block.0].params.retain(|_| {
+        i += 1;
+        keep[i - 1]
+    });
+    for other in &mut function.blocks {
+        for target in other.terminator.targets_mut() {
+            if target.block == block {
+                let mut i = 0;
+                target.args.retain(|_| {
+                    i += 1;
+                    keep[i - 1]
+                });
+            }
+        }
+    }
+}
+
+/// Folds operations whose operands are constants, simplifies operations
+/// with neutral or absorbing constants, replaces parameters which always
+/// receive the same constant, folds constant offsets into addresses,
+/// recognises reads of the frame addresses and turns branches on constants
+/// into jumps
+pub fn propagate_constants(function: &mut Function) {
+    loop {
+        let mut changed = false;
+        let definitions: HashMap<Value, Op> = function
+            .definitions()
+            .into_iter()
+            .map(|(value, op)| (value, op.clone()))
+            .collect();
+        let constant = |value: &Value| match definitions.get(value) {
+            Some(Op::Const(c)) => Some(*c),
+            _ => None,
+        };
+        // Address plus a constant offset which fits in a segment index
+        let offset = |value: &Value, index: u16| match definitions.get(value) {
+            Some(Op::Binary(BinaryOp::Add, x, y)) => {
+                let (base, c) = match (constant(x), constant(y)) {
+                    (_, Some(c)) => (*x, c),
+                    (Some(c), _) => (*y, c),
+                    _ => return None,
+                };
+                let index = (index as i16).checked_add(c)?;
+                (index >= 0).then_some((base, index as u16))
+            }
+            _ => None,
+        };
+
+        for block in &mut function.blocks {
+            for instruction in &mut block.instructions {
+                let folded = match &instruction.op {
+                    Op::Unary(op, x) => constant(x).map(|x| Op::Const(op.eval(x))),
+                    Op::Binary(op, x, y) => match (constant(x), constant(y)) {
+                        (Some(x), Some(y)) => Some(Op::Const(op.eval(x, y))),
+                        (x_const, y_const) => simplify(*op, *x, *y, x_const, y_const),
+                    },
+                    Op::LoadPtr(address, 0) if matches!(constant(address), Some(1 | 2)) => {
+                        Some(Op::Frame(if constant(address) == Some(1) {
+                            Segment::Local
+                        } else {
+                            Segment::Argument
+                        }))
+                    }
+                    Op::LoadPtr(address, index) => {
+                        offset(address, *index).map(|(address, index)| Op::LoadPtr(address, index))
+                    }
+                    Op::StorePtr(address, index, x) => offset(address, *index)
+                        .map(|(address, index)| Op::StorePtr(address, index, *x)),
+                    _ => None,
+                };
+                if let Some(op) = folded {
+                    instruction.op = op;
+                    changed = true;
+                }
+            }
+            if let Terminator::Branch(cond, then, other) = &block.terminator {
+                if let Some(c) = constant(cond) {
+                    let target = if c != 0 { then } else { other };
+                    block.terminator = Terminator::Jump(target.clone());
+                    changed = true;
+                }
+            }
+        }
+
+        // Parameters receiving the same constant from every jump
+        let incoming = incoming(function);
+        for (b, block_incoming) in incoming.iter().enumerate() {
+            let mut keep = vec![true; block_incoming.len()];
+            let mut replacements = HashMap::new();
+            let mut instructions = vec![];
+            for (i, args) in block_incoming.iter().enumerate() {
+                let param = function.blocks[b].params[i];
+                let mut constants = args.iter().filter(|arg| **arg != param).map(&constant);
+                let Some(Some(c)) = constants.next() else {
+                    continue;
+                };
+                if constants.all(|other| other == Some(c)) {
+                    let value = function.push(&mut instructions, Op::Const(c));
+                    replacements.insert(param, value);
+                    keep[i] = false;
+                }
+            }
+            if !replacements.is_empty() {
+                let block = &mut function.blocks[b];
+                instructions.append(&mut block.instructions);
+                block.instructions = instructions;
+                retain_params(function, BlockId(b), &keep);
+                function.replace_uses(&replacements);
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Simplifies a binary operation where at most one operand is a constant
+fn simplify(
+    op: BinaryOp,
+    x: Value,
+    y: Value,
+    x_const: Option<i16>,
+    y_const: Option<i16>,
+) -> Option<Op> {
+    match (op, x_const, y_const) {
+        (BinaryOp::Add | BinaryOp::Or, Some(0), _) => Some(Op::Copy(y)),
+        (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or, _, Some(0)) => Some(Op::Copy(x)),
+        (BinaryOp::And, Some(-1), _) => Some(Op::Copy(y)),
+        (BinaryOp::And, _, Some(-1)) => Some(Op::Copy(x)),
+        (BinaryOp::And, Some(0), _) | (BinaryOp::And, _, Some(0)) => Some(Op::Const(0)),
+        (BinaryOp::Or, Some(-1), _) | (BinaryOp::Or, _, Some(-1)) => Some(Op::Const(-1)),
+        (BinaryOp::Sub, Some(0), _) => Some(Op::Unary(UnaryOp::Neg, y)),
+        (BinaryOp::And | BinaryOp::Or, ..) if x == y => Some(Op::Copy(x)),
+        (BinaryOp::Sub, ..) if x == y => Some(Op::Const(0)),
+        (BinaryOp::Eq, ..) if x == y => Some(Op::Const(-1)),
+        (BinaryOp::Lt | BinaryOp::Gt, ..) if x == y => Some(Op::Const(0)),
+        _ => None,
+    }
+}
+
+/// Replaces the uses of copies with their sources, as well as the uses of
+/// parameters which always receive the same value
+pub fn propagate_copies(function: &mut Function) {
+    let mut map: HashMap<Value, Value> = HashMap::new();
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        if let Op::Copy(x) = instruction.op {
+            map.insert(instruction.results[0], x);
+        }
+    }
+    let resolve = |map: &HashMap<Value, Value>, mut value: Value| {
+        while let Some(next) = map.get(&value) {
+            value = *next;
+        }
+        value
+    };
+
+    loop {
+        let incoming = incoming(function);
+        let mut changed = false;
+        for (b, block_incoming) in incoming.iter().enumerate() {
+            let mut keep = vec![true; block_incoming.len()];
+            for (i, args) in block_incoming.iter().enumerate() {
+                let param = function.blocks[b].params[i];
+                let mut sources = args
+                    .iter()
+                    .map(|arg| resolve(&map, *arg))
+                    .filter(|arg| *arg != param);
+                let Some(source) = sources.next() else {
+                    continue;
+                };
+                if sources.all(|other| other == source) {
+                    map.insert(param, source);
+                    keep[i] = false;
+                }
+            }
+            if keep.contains(&false) {
+                retain_params(function, BlockId(b), &keep);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    function.replace_uses(&map);
+    for block in &mut function.blocks {
+        block
+            .instructions
+            .retain(|instruction| !matches!(instruction.op, Op::Copy(_)));
+    }
+}
+
+/// Replaces multiplications by powers of two with additions, and
+/// multiplications, divisions and remainders by `0`, `1` or `-1` with
+/// cheaper operations
+pub fn reduce_strength(function: &mut Function) {
+    let constants: HashMap<Value, i16> = function
+        .definitions()
+        .into_iter()
+        .filter_map(|(value, op)| match op {
+            Op::Const(c) => Some((value, *c)),
+            _ => None,
+        })
+        .collect();
+
+    for b in 0..function.blocks.len() {
+        let instructions = std::mem::take(&mut function.blocks[b].instructions);
+        let mut ret = vec![];
+        for mut instruction in instructions {
+            let reduced = match instruction.op {
+                Op::Binary(BinaryOp::Mul, x, y) => match (constants.get(&x), constants.get(&y)) {
+                    (_, Some(c)) => Some(reduce_mul(function, &mut ret, x, *c)),
+                    (Some(c), _) => Some(reduce_mul(function, &mut ret, y, *c)),
+                    _ => None,
+                },
+                Op::Binary(op @ (BinaryOp::Div | BinaryOp::Mod), x, y) => {
+                    match (op, constants.get(&y)) {
+                        (BinaryOp::Div, Some(0)) => Some(Some(Op::Const(-1))),
+                        (BinaryOp::Div, Some(1)) => Some(Some(Op::Copy(x))),
+                        (BinaryOp::Div, Some(-1)) => Some(Some(Op::Unary(UnaryOp::Neg, x))),
+                        (BinaryOp::Mod, Some(0)) => Some(Some(Op::Copy(x))),
+                        (BinaryOp::Mod, Some(1 | -1)) => Some(Some(Op::Const(0))),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if let Some(Some(op)) = reduced {
+                instruction.op = op;
+            }
+            ret.push(instruction);
+        }
+        function.blocks[b].instructions = ret;
+    }
+}
+
+/// Returns an operation equivalent to multiplying a value by a constant,
+/// appending the additions it needs, if it is cheaper than the multiplication
+fn reduce_mul(
+    function: &mut Function,
+    instructions: &mut Vec<Instruction>,
+    x: Value,
+    c: i16,
+) -> Option<Op> {
+    match c {
+        0 => return Some(Op::Const(0)),
+        1 => return Some(Op::Copy(x)),
+        -1 => return Some(Op::Unary(UnaryOp::Neg, x)),
+        _ => (),
+    }
+    // Words wrap around, so multiplying by `c` is shifting by its bits
+    let (shift, negate) = if (c as u16).is_power_of_two() {
+        ((c as u16).trailing_zeros(), false)
+    } else if (c.wrapping_neg() as u16).is_power_of_two() {
+        ((c.wrapping_neg() as u16).trailing_zeros(), true)
+    } else {
+        return None;
+    };
+    let mut value = x;
+    for _ in 1..shift {
+        value = function.push(instructions, Op::Binary(BinaryOp::Add, value, value));
+    }
+    if negate {
+        value = function.push(instructions, Op::Binary(BinaryOp::Add, value, value));
+        Some(Op::Unary(UnaryOp::Neg, value))
+    } else {
+        Some(Op::Binary(BinaryOp::Add, value, value))
+    }
+}
+
+/// Memory word read or written by a load or a store
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Address {
+    Slot(Segment, u16),
+    Ptr(Value, u16),
+}
+
+/// Key of a pure operation, with the operands of commutative operations in
+/// a canonical order
+fn key(op: &Op) -> Option<Op> {
+    match op {
+        Op::Binary(op, x, y) if op.is_commutative() && y < x => Some(Op::Binary(*op, *y, *x)),
+        op if op.is_pure() => Some(op.clone()),
+        _ => None,
+    }
+}
+
+/// Immediate dominator of each reachable block, where the entry block is
+/// its own
+fn dominators(function: &Function) -> Vec<Option<usize>> {
+    // Reverse postorder
+    let mut order = vec![];
+    let mut visited = vec![false; function.blocks.len()];
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((block, next)) = stack.pop() {
+        let targets = function.blocks[block].terminator.targets();
+        if let Some(target) = targets.get(next) {
+            stack.push((block, next + 1));
+            if !visited[target.block.0] {
+                visited[target.block.0] = true;
+                stack.push((target.block.0, 0));
+            }
+        } else {
+            order.push(block);
+        }
+    }
+    order.reverse();
+    let mut rank = vec![usize::MAX; function.blocks.len()];
+    for (i, block) in order.iter().enumerate() {
+        rank[*block] = i;
+    }
+
+    let predecessors = function.predecessors();
+    let mut idom: Vec<Option<usize>> = vec![None; function.blocks.len()];
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in order.iter().skip(1) {
+            let mut new_idom: Option<usize> = None;
+            for predecessor in &predecessors[block] {
+                let mut other = predecessor.0;
+                if idom[other].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => other,
+                    Some(mut current) => {
+                        while current != other {
+                            while rank[current] > rank[other] {
+                                current = idom[current].unwrap();
+                            }
+                            while rank[other] > rank[current] {
+                                other = idom[other].unwrap();
+                            }
+                        }
+                        current
+                    }
+                });
+            }
+            if new_idom.is_some() && idom[block] != new_idom {
+                idom[block] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom
+}
+
+/// Replaces pure operations computed again in a block dominated by the one
+/// computing them first, and loads of words already loaded or stored in the
+/// same block, without a store or a call in between which could change them
+pub fn eliminate_common_subexpressions(function: &mut Function) {
+    let idom = dominators(function);
+    let mut children = vec![vec![]; function.blocks.len()];
+    for (block, dominator) in idom.iter().enumerate().skip(1) {
+        if let Some(dominator) = dominator {
+            children[*dominator].push(block);
+        }
+    }
+
+    let mut map: HashMap<Value, Value> = HashMap::new();
+    let mut pending = vec![(0, HashMap::new())];
+    while let Some((block, mut available)) = pending.pop() {
+        let mut memory: HashMap<Address, Value> = HashMap::new();
+        for instruction in &mut function.blocks[block].instructions {
+            for operand in instruction.op.operands_mut() {
+                while let Some(next) = map.get(operand) {
+                    *operand = *next;
+                }
+            }
+            if let Some(key) = key(&instruction.op) {
+                match available.get(&key) {
+                    Some(value) => {
+                        map.insert(instruction.results[0], *value);
+                    }
+                    None => {
+                        available.insert(key, instruction.results[0]);
+                    }
+                }
+                continue;
+            }
+            let load = match instruction.op {
+                Op::Load(segment, index) => Some(Address::Slot(segment, index)),
+                Op::LoadPtr(address, index) => Some(Address::Ptr(address, index)),
+                _ => None,
+            };
+            if let Some(address) = load {
+                match memory.get(&address) {
+                    Some(value) => {
+                        map.insert(instruction.results[0], *value);
+                    }
+                    None => {
+                        memory.insert(address, instruction.results[0]);
+                    }
+                }
+                continue;
+            }
+            match &instruction.op {
+                Op::Store(segment, index, x) => {
+                    // A pointer may address any word
+                    memory.retain(|address, _| matches!(address, Address::Slot(..)));
+                    memory.insert(Address::Slot(*segment, *index), *x);
+                }
+                Op::StorePtr(base, index, x) => {
+                    // Different offsets from the same address never alias
+                    memory.retain(|address, _| match address {
+                        Address::Ptr(other, other_index) => other == base && other_index != index,
+                        Address::Slot(..) => false,
+                    });
+                    memory.insert(Address::Ptr(*base, *index), *x);
+                }
+                Op::Call(..) => memory.clear(),
+                _ => (),
+            }
+        }
+        for child in &children[block] {
+            pending.push((*child, available.clone()));
+        }
+    }
+    function.replace_uses(&map);
+}
+
+/// Removes stores overwritten later in the same block before any load or
+/// call, and stores to locals right before returning
+pub fn eliminate_dead_stores(function: &mut Function) {
+    for block in &mut function.blocks {
+        let mut overwritten = HashSet::new();
+        let mut returning = matches!(block.terminator, Terminator::Return(_));
+        let mut dead = vec![false; block.instructions.len()];
+        for (i, instruction) in block.instructions.iter().enumerate().rev() {
+            let address = match instruction.op {
+                Op::Store(segment, index, _) => Address::Slot(segment, index),
+                Op::StorePtr(base, index, _) => Address::Ptr(base, index),
+                Op::Load(..) | Op::LoadPtr(..) | Op::Call(..) => {
+                    overwritten.clear();
+                    returning = false;
+                    continue;
+                }
+                _ => continue,
+            };
+            let frame = matches!(address, Address::Slot(Segment::Local, _));
+            dead[i] = !overwritten.insert(address) || (returning && frame);
+        }
+        let mut i = 0;
+        block.instructions.retain(|_| {
+            i += 1;
+            !dead[i - 1]
+        });
+    }
+}
+
+/// Removes operations whose results are never used, unless they have side
+/// effects, and parameters which are only passed to other unused ones
+pub fn eliminate_dead_code(function: &mut Function) {
+    let mut definitions: HashMap<Value, Vec<Value>> = HashMap::new();
+    let mut live = HashSet::new();
+    let mut pending = vec![];
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            let operands = instruction.op.operands();
+            if instruction.op.is_removable() {
+                for result in &instruction.results {
+                    definitions.insert(*result, operands.clone());
+                }
+            } else {
+                pending.extend(operands);
+            }
+        }
+        match &block.terminator {
+            Terminator::Branch(cond, ..) => pending.push(*cond),
+            Terminator::Return(values) => pending.extend(values),
+            Terminator::Jump(_) => (),
+        }
+    }
+    let incoming = incoming(function);
+    let mut params = HashMap::new();
+    for (b, block) in function.blocks.iter().enumerate() {
+        for (i, param) in block.params.iter().enumerate() {
+            params.insert(*param, &incoming[b][i]);
+        }
+    }
+
+    while let Some(value) = pending.pop() {
+        if !live.insert(value) {
+            continue;
+        }
+        if let Some(operands) = definitions.get(&value) {
+            pending.extend(operands);
+        }
+        if let Some(args) = params.get(&value) {
+            pending.extend(args.iter());
+        }
+    }
+
+    for b in 0..function.blocks.len() {
+        let keep: Vec<bool> = function.blocks[b]
+            .params
+            .iter()
+            .map(|param| live.contains(param))
+            .collect();
+        if keep.contains(&false) {
+            retain_params(function, BlockId(b), &keep);
+        }
+        function.blocks[b].instructions.retain(|instruction| {
+            !instruction.op.is_removable()
+                || instruction
+                    .results
+                    .iter()
+                    .any(|result| live.contains(result))
+        });
+    }
+}
+
+/// Turns calls of a function to itself, whose results are returned right
+/// away, into jumps back to its start, which reuse the frame instead of
+/// pushing a new one. The body of the entry block moves to a new block,
+/// taking the arguments as parameters when they are held in values, or
+/// finding them stored over the old ones in the argument segment otherwise
+pub fn eliminate_tail_calls(function: &mut Function) {
+    // The pointers the function starts with would be those of the caller
+    let reads_pointers = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .any(|instruction| matches!(instruction.op, Op::Load(Segment::Pointer, _)));
+    let tail_calls: Vec<(usize, Vec<Value>)> = function
+        .blocks
+        .iter()
+        .enumerate()
+        .filter_map(
+            |(b, block)| match (block.instructions.last(), &block.terminator) {
+                (
+                    Some(Instruction {
+                        results,
+                        op: Op::Call(name, args),
+                    }),
+                    Terminator::Return(values),
+                ) if *name == function.name && results == values => Some((b, args.clone())),
+                _ => None,
+            },
+        )
+        .collect();
+    if reads_pointers || tail_calls.is_empty() {
+        return;
+    }
+
+    let start = BlockId(function.blocks.len());
+    let mut body = std::mem::replace(
+        &mut function.blocks[0],
+        BasicBlock {
+            params: vec![],
+            instructions: vec![],
+            terminator: Terminator::Return(vec![]),
+        },
+    );
+    let mut entry = vec![];
+    let mut map = HashMap::new();
+    if function.promoted {
+        for index in 0..tail_calls[0].1.len() {
+            let param = function.new_value(Ty::Word);
+            body.params.push(param);
+            function.push(&mut entry, Op::Arg(index as u16));
+        }
+        // The old arguments are now the parameters of the body
+        body.instructions
+            .retain(|instruction| match instruction.op {
+                Op::Arg(index) => {
+                    map.insert(instruction.results[0], body.params[index as usize]);
+                    false
+                }
+                _ => true,
+            });
+    }
+    let args = entry.iter().map(|instruction| instruction.results[0]);
+    function.blocks[0] = BasicBlock {
+        params: vec![],
+        terminator: Terminator::Jump(Target {
+            block: start,
+            args: args.collect(),
+        }),
+        instructions: entry,
+    };
+    function.blocks.push(body);
+
+    for (b, args) in tail_calls {
+        // The entry block itself has moved
+        let block = &mut function.blocks[if b == 0 { start.0 } else { b }];
+        block.instructions.pop();
+        let args = if function.promoted {
+            args
+        } else {
+            for (index, arg) in args.into_iter().enumerate() {
+                block.instructions.push(Instruction {
+                    results: vec![],
+                    op: Op::Store(Segment::Argument, index as u16, arg),
+                });
+            }
+            vec![]
+        };
+        block.terminator = Terminator::Jump(Target { block: start, args });
+    }
+    function.replace_uses(&map);
+}
+
+/// Removes unreachable blocks, skips empty blocks which only jump elsewhere
+/// and merges blocks with the only block jumping to them
+pub fn simplify_cfg(function: &mut Function) {
+    // Jumps to empty blocks go straight to their target
+    for b in 0..function.blocks.len() {
+        for t in 0..function.blocks[b].terminator.targets().len() {
+            let mut visited = HashSet::new();
+            loop {
+                let target = function.blocks[b].terminator.targets()[t].clone();
+                let next = &function.blocks[target.block.0];
+                let Terminator::Jump(next_target) = &next.terminator else {
+                    break;
+                };
+                if !next.params.is_empty()
+                    || !next.instructions.is_empty()
+                    || target.block.0 == 0
+                    || !visited.insert(target.block)
+                {
+                    break;
+                }
+                let next_target = next_target.clone();
+                *function.blocks[b].terminator.targets_mut()[t] = next_target;
+            }
+        }
+        if let Terminator::Branch(_, then, other) = &function.blocks[b].terminator {
+            if then == other {
+                function.blocks[b].terminator = Terminator::Jump(then.clone());
+            }
+        }
+    }
+
+    // Blocks with a single predecessor jumping to them are merged into it
+    let mut map = HashMap::new();
+    for b in 0..function.blocks.len() {
+        loop {
+            let predecessors = function.predecessors();
+            let Terminator::Jump(target) = &function.blocks[b].terminator else {
+                break;
+            };
+            let next = target.block;
+            if next.0 == 0 || next.0 == b || predecessors[next.0].len() != 1 {
+                break;
+            }
+            let args = target.args.clone();
+            let merged = std::mem::replace(
+                &mut function.blocks[next.0],
+                BasicBlock {
+                    params: vec![],
+                    instructions: vec![],
+                    terminator: Terminator::Return(vec![]),
+                },
+            );
+            map.extend(merged.params.into_iter().zip(args));
+            let block = &mut function.blocks[b];
+            block.instructions.extend(merged.instructions);
+            block.terminator = merged.terminator;
+        }
+    }
+    function.replace_uses(&map);
+
+    remove_unreachable_blocks(function);
+}
+
+/// Removes the blocks which can not be reached from the entry
+fn remove_unreachable_blocks(function: &mut Function) {
+    let mut reachable = vec![false; function.blocks.len()];
+    let mut pending = vec![0];
+    while let Some(block) = pending.pop() {
+        if !std::mem::replace(&mut reachable[block], true) {
+            pending.extend(
+                function.blocks[block]
+                    .terminator
+                    .targets()
+                    .iter()
+                    .map(|target| target.block.0),
+            );
+        }
+    }
+
+    let mut ids = vec![];
+    let mut next = 0;
+    for reached in &reachable {
+        ids.push(BlockId(next));
+        if *reached {
+            next += 1;
+        }
+    }
+    let mut i = 0;
+    function.blocks.retain(|_| {
+        i += 1;
+        reachable[i - 1]
+    });
+    for block in &mut function.blocks {
+        for target in block.terminator.targets_mut() {
+            *target = Target {
+                block: ids[target.block.0],
+                args: std::mem::take(&mut target.args),
+            };
+        }
+    }
+}
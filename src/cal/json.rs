@@ -0,0 +1,289 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{fmt, iter::Peekable, str::Chars, str::FromStr};
+
+use crate::{error::CalError, tokenizer::Range};
+
+/// A JSON value, just enough for tools talking JSON such as the language
+/// server. Object members keep the order they are inserted in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Builds an object from a list of members
+    pub fn object<const N: usize>(members: [(&str, Json); N]) -> Self {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Returns the value of a member of an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(number) if *number >= 0.0 => Some(*number as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<i16> for Json {
+    fn from(value: i16) -> Self {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(values: Vec<Json>) -> Self {
+        Json::Array(values)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Values are written compactly, without any whitespace
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) => write!(f, "{}", number),
+            Json::String(string) => write_string(f, string),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A recursive descent parser of JSON text
+struct JsonParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn error<T>(&self, message: &str) -> Result<T, CalError> {
+        Err(CalError::new(
+            format!("Invalid JSON: {}", message),
+            Range::default(),
+        ))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn eat(&mut self, expected: char) -> Result<(), CalError> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => self.error(&format!("expected `{}`, found `{}`", expected, c)),
+            None => self.error(&format!("expected `{}`, found end of input", expected)),
+        }
+    }
+
+    fn eat_word(&mut self, word: &str, value: Json) -> Result<Json, CalError> {
+        for expected in word.chars() {
+            if self.chars.next() != Some(expected) {
+                return self.error(&format!("expected `{}`", word));
+            }
+        }
+        Ok(value)
+    }
+
+    fn parse_string(&mut self) -> Result<String, CalError> {
+        self.eat('"')?;
+        let mut ret = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(ret),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => ret.push('\n'),
+                    Some('r') => ret.push('\r'),
+                    Some('t') => ret.push('\t'),
+                    Some('b') => ret.push('\u{8}'),
+                    Some('f') => ret.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        let Ok(code) = u32::from_str_radix(&hex, 16) else {
+                            return self.error("invalid unicode escape");
+                        };
+                        ret.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    Some(c) => ret.push(c),
+                    None => return self.error("unterminated string"),
+                },
+                Some(c) => ret.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Json, CalError> {
+        let mut number = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            number.push(c);
+        }
+        match number.parse() {
+            Ok(number) => Ok(Json::Number(number)),
+            Err(_) => self.error(&format!("invalid number `{}`", number)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, CalError> {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some('n') => self.eat_word("null", Json::Null),
+            Some('t') => self.eat_word("true", Json::Bool(true)),
+            Some('f') => self.eat_word("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.parse_string()?)),
+            Some('[') => {
+                self.eat('[')?;
+                let mut values = vec![];
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&']').is_some() {
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.parse_value()?);
+                    self.skip_whitespace();
+                    if self.chars.next_if_eq(&']').is_some() {
+                        return Ok(Json::Array(values));
+                    }
+                    self.eat(',')?;
+                }
+            }
+            Some('{') => {
+                self.eat('{')?;
+                let mut members = vec![];
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&'}').is_some() {
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    let key = self.parse_string()?;
+                    self.eat(':')?;
+                    members.push((key, self.parse_value()?));
+                    self.skip_whitespace();
+                    if self.chars.next_if_eq(&'}').is_some() {
+                        return Ok(Json::Object(members));
+                    }
+                    self.eat(',')?;
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) => self.error(&format!("unexpected `{}`", c)),
+            None => self.error("unexpected end of input"),
+        }
+    }
+}
+
+impl FromStr for Json {
+    type Err = CalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = JsonParser {
+            chars: s.chars().peekable(),
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.chars.peek().is_some() {
+            return parser.error("trailing characters");
+        }
+        Ok(value)
+    }
+}
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: MIT
 
 pub mod error;
+pub mod warning;
 
 pub mod tokenizer;
 
@@ -11,11 +12,25 @@ pub mod parser;
 pub mod statement;
 pub mod structure;
 
+pub mod dce;
+pub mod debugger;
+pub mod evaluator;
+pub mod formatter;
 pub mod generator;
+pub mod hack;
+pub mod interpreter;
+pub mod ir;
 pub mod preamble;
+pub mod stdlib;
 pub mod symboltable;
 
 pub mod compiler;
+pub mod console;
+pub mod doc;
+
+pub mod analysis;
+pub mod dump;
+pub mod json;
 
 #[cfg(target_arch = "wasm32")]
 pub mod checker;
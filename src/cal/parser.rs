@@ -2,23 +2,73 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::str::FromStr;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
 use crate::{
     error::CalError,
     expression::{Expression, Literal, Operator, Term, UnaryOperator},
     statement::{IfStatement, Statement, WhileStatement},
-    structure::{Function, Module, Type, Variable},
+    structure::{
+        mangle, Constant, Field, Function, Module, Static, StructDec, Type, TypeAlias, Variable,
+    },
     tokenizer::*,
 };
 
 pub struct Parser {
     tokens: Tokens,
+
+    /// Names of the structs declared in the module, needed for telling apart
+    /// a struct literal from an identifier followed by a block
+    struct_names: HashSet<String>,
+
+    /// Name of the type of the `impl` block we are parsing, if any
+    impl_type: Option<String>,
+
+    /// Type aliases declared in the module, with the types they resolve to
+    type_aliases: HashMap<String, Type>,
 }
 
 impl Parser {
     pub fn new(tokens: Tokens) -> Parser {
-        Self { tokens }
+        Self {
+            tokens,
+            struct_names: HashSet::default(),
+            impl_type: None,
+            type_aliases: HashMap::default(),
+        }
+    }
+
+    /// Resolves `Self` to the type of the current `impl` block
+    fn resolve_self(&self, type_name: &str) -> String {
+        match &self.impl_type {
+            Some(impl_type) if type_name == "Self" => impl_type.clone(),
+            _ => type_name.into(),
+        }
+    }
+
+    /// Returns the text of the `///` comments between the last token consumed
+    /// and the next one, which document the item about to be parsed
+    fn parse_doc(&mut self) -> String {
+        let start = self.tokens.last_range().end;
+        let end = self
+            .tokens
+            .peek()
+            .map_or(usize::MAX, |token| token.range.start);
+        let lines: Vec<&str> = self
+            .tokens
+            .comments()
+            .iter()
+            .filter(|comment| comment.range.start >= start && comment.range.end <= end)
+            .filter_map(|comment| match &comment.value {
+                TokenKind::Comment(text) => text.strip_prefix("///"),
+                _ => None,
+            })
+            .map(|line| line.strip_prefix(' ').unwrap_or(line).trim_end())
+            .collect();
+        lines.join("\n")
     }
 
     fn parse_identifier(&mut self) -> Result<String, CalError> {
@@ -44,24 +94,64 @@ impl Parser {
         // Type of the element of the array
         let elem_type = self.parse_type()?;
         self.tokens.eat_symbol(Symbol::Semicolon)?;
-        match self.parse_int_literal() {
-            Ok(Literal::I16(count)) if count > 0 => {
-                self.tokens.eat_symbol(Symbol::RightBracket)?;
+        // Number of elements is a constant expression
+        let count = self.parse_expression(false)?;
+        self.tokens.eat_symbol(Symbol::RightBracket)?;
+        match (*count.term, count.op_and_expr) {
+            (Term::Literal(Literal::I16(count)), None) if count > 0 => {
                 Ok(Type::Array(Box::new(elem_type), count as u16))
             }
-            Ok(other_literal) => Err(CalError::new(
-                format!("Expected int literal, found {:?}", other_literal),
-                Range::default(),
+            (term, op_and_expr) => Ok(Type::ArrayExpr(
+                Box::new(elem_type),
+                Box::new(Expression::new(Box::new(term), op_and_expr)),
             )),
-            Err(err) => Err(err),
+        }
+    }
+
+    fn parse_tuple_type(&mut self) -> Result<Type, CalError> {
+        // Left paren is already consumed at this point
+        let mut types = vec![];
+        let mut trailing_comma = false;
+        while !self.tokens.peek_symbol(Symbol::RightParen) {
+            types.push(self.parse_type()?);
+            trailing_comma = self.tokens.peek_symbol(Symbol::Comma);
+            if trailing_comma {
+                self.tokens.skip();
+            } else {
+                break;
+            }
+        }
+        self.tokens.eat_symbol(Symbol::RightParen)?;
+
+        if types.is_empty() {
+            Ok(Type::Void)
+        } else if types.len() == 1 && !trailing_comma {
+            // Type between parentheses
+            Ok(types.pop().unwrap())
+        } else {
+            Ok(Type::Tuple(types))
         }
     }
 
     fn parse_ref_type(&mut self) -> Result<Type, CalError> {
         // Ampersend is alreay consumed at this point
+        let mutable = self.parse_mut();
         // Type of the reference
         let elem_type = self.parse_type()?;
-        Ok(Type::Ref(Box::new(elem_type)))
+        if mutable {
+            Ok(Type::MutRef(Box::new(elem_type)))
+        } else {
+            Ok(Type::Ref(Box::new(elem_type)))
+        }
+    }
+
+    /// Eats an optional `mut` keyword, returning whether it was there
+    fn parse_mut(&mut self) -> bool {
+        let mutable = self.tokens.peek_keyword(Keyword::Mut);
+        if mutable {
+            self.tokens.skip();
+        }
+        mutable
     }
 
     fn parse_type(&mut self) -> Result<Type, CalError> {
@@ -70,6 +160,14 @@ impl Parser {
                 TokenKind::Keyword(keyword) => Type::from_keyword(keyword),
                 TokenKind::Symbol(Symbol::LeftBracket) => self.parse_array_type(),
                 TokenKind::Symbol(Symbol::Ampersand) => self.parse_ref_type(),
+                TokenKind::Symbol(Symbol::LeftParen) => self.parse_tuple_type(),
+                TokenKind::Identifier(name) => {
+                    let name = self.resolve_self(&name);
+                    match self.type_aliases.get(&name) {
+                        Some(typ) => Ok(typ.clone()),
+                        None => Ok(Type::Struct(name)),
+                    }
+                }
                 _ => Err(CalError::new(
                     format!("Expected type, found {:?}", token.value),
                     token.range,
@@ -93,65 +191,36 @@ impl Parser {
         Ok(expressions)
     }
 
-    fn parse_int_literal(&mut self) -> Result<Literal, CalError> {
-        if let Some(token) = self.tokens.peek().cloned() {
-            if let TokenKind::Integer(int) = &token.value {
-                self.tokens.skip();
-                Ok(Literal::I16(*int))
-            } else {
-                Err(CalError::new(
-                    format!("Expected integer, found: {:?}", token.value),
-                    token.range,
-                ))
-            }
-        } else {
-            Err(CalError::new("Expected integer".into(), Range::default()))
-        }
-    }
-
-    fn parse_literal(&mut self) -> Result<Literal, CalError> {
-        if let Some(token) = self.tokens.next() {
-            match &token.value {
-                TokenKind::Keyword(Keyword::True) => Ok(Literal::Bool(true)),
-                TokenKind::Keyword(Keyword::False) => Ok(Literal::Bool(false)),
-                TokenKind::Integer(int) => Ok(Literal::I16(*int)),
-                TokenKind::Symbol(Symbol::LeftBracket) => Ok(self.parse_array_literal()?),
-                TokenKind::Char(c) => Ok(Literal::Char(*c)),
-                _ => Err(CalError::new(
-                    format!("Expected literal, found {:?}", token),
-                    Range::default(),
-                )),
-            }
-        } else {
-            Err(CalError::new("Expected literal".into(), Range::default()))
-        }
-    }
-
-    fn parse_array_literal(&mut self) -> Result<Literal, CalError> {
+    /// Parses an array whose elements are expressions, which is folded into
+    /// an array literal when all of its elements are literals
+    fn parse_array_term(&mut self) -> Result<Term, CalError> {
         // Left bracket is already consumed at this point
-        let mut values = vec![];
-
-        loop {
-            // Check whether array literal is finished
-            if self.tokens.peek_symbol(Symbol::RightBracket) {
-                self.tokens.skip();
-                return Ok(Literal::Array(values));
-            }
-
-            values.push(self.parse_literal()?);
-
-            if self.tokens.peek_symbol(Symbol::RightBracket) {
-                self.tokens.skip();
-                break; // Array literal is finished
-            } else {
+        let mut expressions = vec![];
+        while !self.tokens.peek_symbol(Symbol::RightBracket) {
+            expressions.push(self.parse_expression(false)?);
+            if !self.tokens.peek_symbol(Symbol::RightBracket) {
                 self.tokens.eat_symbol(Symbol::Comma)?;
             }
         }
-        Ok(Literal::Array(values))
+        self.tokens.eat_symbol(Symbol::RightBracket)?;
+
+        let literals: Option<Vec<Literal>> = expressions
+            .iter()
+            .map(
+                |expression| match (expression.term.as_ref(), &expression.op_and_expr) {
+                    (Term::Literal(literal), None) => Some(literal.clone()),
+                    _ => None,
+                },
+            )
+            .collect();
+        match literals {
+            Some(literals) => Ok(Term::Literal(Literal::Array(literals))),
+            None => Ok(Term::Array(expressions)),
+        }
     }
 
     fn parse_identifier_term(&mut self, identifier: &str) -> Result<Term, CalError> {
-        let Some(token) = self.tokens.peek() else {
+        let Some(token) = self.tokens.peek().cloned() else {
             return Ok(Term::Variable(identifier.into()));
         };
 
@@ -163,19 +232,131 @@ impl Parser {
                 self.tokens.eat_symbol(Symbol::RightParen)?;
                 Ok(Term::Call(identifier.into(), expression_list))
             }
-            TokenKind::Symbol(Symbol::LeftBracket) => {
+            TokenKind::Symbol(Symbol::DoubleColon) => {
+                // Associated function call
+                self.tokens.skip();
+                let function_name = self.parse_identifier()?;
+                self.tokens.eat_symbol(Symbol::LeftParen)?;
+                let expression_list = self.parse_expression_list()?;
+                self.tokens.eat_symbol(Symbol::RightParen)?;
+                let type_name = self.resolve_self(identifier);
+                Ok(Term::Call(
+                    mangle(&type_name, &function_name),
+                    expression_list,
+                ))
+            }
+            TokenKind::Symbol(Symbol::LeftBrace)
+                if self.struct_names.contains(&self.resolve_self(identifier)) =>
+            {
+                self.tokens.skip();
+                self.parse_struct_literal(self.resolve_self(identifier))
+            }
+            _ => self.parse_postfix(Term::Variable(identifier.into())),
+        }
+    }
+
+    /// Parses any chain of index operators, field accesses and method calls
+    /// following a term
+    fn parse_postfix(&mut self, mut term: Term) -> Result<Term, CalError> {
+        loop {
+            if self.tokens.peek_symbol(Symbol::LeftBracket) {
                 // Index operator
                 self.tokens.skip();
                 let index_expr = self.parse_expression(false)?;
                 self.tokens.eat_symbol(Symbol::RightBracket)?;
-                Ok(Term::Index(identifier.into(), index_expr))
+                term = Term::Index(Box::new(term), index_expr);
+            } else if self.tokens.peek_symbol(Symbol::Dot) {
+                self.tokens.skip();
+                let name = self.parse_field_name()?;
+                if self.tokens.peek_symbol(Symbol::LeftParen) {
+                    term = self.parse_method_call(term, name)?;
+                } else {
+                    term = Term::Field(Box::new(term), name);
+                }
+            } else {
+                return Ok(term);
             }
-            _ => Ok(Term::Variable(identifier.into())),
         }
     }
 
+    /// Parses the name of a struct field or the position of a tuple element
+    fn parse_field_name(&mut self) -> Result<String, CalError> {
+        if let Some(Token {
+            value: TokenKind::Integer(position),
+            ..
+        }) = self.tokens.peek()
+        {
+            let position = position.to_string();
+            self.tokens.skip();
+            Ok(position)
+        } else {
+            self.parse_identifier()
+        }
+    }
+
+    /// Parses either an expression between parentheses or a tuple expression
+    fn parse_parenthesized(&mut self) -> Result<Term, CalError> {
+        // Left paren is already consumed at this point
+        let mut expressions = vec![];
+        let mut trailing_comma = false;
+        while !self.tokens.peek_symbol(Symbol::RightParen) {
+            expressions.push(self.parse_expression(false)?);
+            trailing_comma = self.tokens.peek_symbol(Symbol::Comma);
+            if trailing_comma {
+                self.tokens.skip();
+            } else {
+                break;
+            }
+        }
+        self.tokens.eat_symbol(Symbol::RightParen)?;
+
+        if expressions.len() == 1 && !trailing_comma {
+            Ok(Term::Expression(Box::new(expressions.pop().unwrap())))
+        } else {
+            Ok(Term::Tuple(expressions))
+        }
+    }
+
+    fn parse_method_call(&mut self, receiver: Term, method: String) -> Result<Term, CalError> {
+        self.tokens.eat_symbol(Symbol::LeftParen)?;
+        let expression_list = self.parse_expression_list()?;
+        self.tokens.eat_symbol(Symbol::RightParen)?;
+        Ok(Term::MethodCall(
+            Box::new(receiver),
+            method,
+            expression_list,
+        ))
+    }
+
+    fn parse_struct_literal(&mut self, name: String) -> Result<Term, CalError> {
+        // Left brace is already consumed at this point
+        let mut initializers = vec![];
+        while !self.tokens.peek_symbol(Symbol::RightBrace) {
+            let field_name = self.parse_identifier()?;
+            self.tokens.eat_symbol(Symbol::Colon)?;
+            let expression = self.parse_expression(false)?;
+            initializers.push((field_name, expression));
+            if !self.tokens.peek_symbol(Symbol::RightBrace) {
+                self.tokens.eat_symbol(Symbol::Comma)?;
+            }
+        }
+        self.tokens.eat_symbol(Symbol::RightBrace)?;
+        Ok(Term::Struct(name, initializers))
+    }
+
+    fn parse_sizeof(&mut self) -> Result<Term, CalError> {
+        // Sizeof keyword is already consumed at this point
+        self.tokens.eat_symbol(Symbol::LeftParen)?;
+        let typ = self.parse_type()?;
+        self.tokens.eat_symbol(Symbol::RightParen)?;
+        Ok(Term::SizeOf(typ))
+    }
+
     fn parse_unary_operator(&mut self, sym: Symbol) -> Result<Term, CalError> {
-        let unary_op = UnaryOperator::from_symbol(sym)?;
+        let unary_op = match UnaryOperator::from_symbol(sym)? {
+            UnaryOperator::Ref if self.parse_mut() => UnaryOperator::MutRef,
+            unary_op => unary_op,
+        };
         let rhs = self.parse_term()?;
         Ok(Term::UnaryOp(unary_op, Box::new(rhs)))
     }
@@ -186,10 +367,10 @@ impl Parser {
                 TokenKind::Keyword(Keyword::True) => Ok(Term::Literal(Literal::Bool(true))),
                 TokenKind::Keyword(Keyword::False) => Ok(Term::Literal(Literal::Bool(false))),
                 TokenKind::Integer(int) => Ok(Term::Literal(Literal::I16(*int))),
-                TokenKind::Symbol(Symbol::LeftBracket) => {
-                    Ok(Term::Literal(self.parse_array_literal()?))
-                }
+                TokenKind::Symbol(Symbol::LeftBracket) => self.parse_array_term(),
                 TokenKind::Char(c) => Ok(Term::Literal(Literal::Char(*c))),
+                TokenKind::Symbol(Symbol::LeftParen) => self.parse_parenthesized(),
+                TokenKind::Keyword(Keyword::SizeOf) => self.parse_sizeof(),
                 TokenKind::Symbol(Symbol::Ampersand) => {
                     self.parse_unary_operator(Symbol::Ampersand)
                 }
@@ -230,6 +411,7 @@ impl Parser {
     }
 
     pub fn parse_expression(&mut self, assign_allow: bool) -> Result<Expression, CalError> {
+        let start = self.tokens.peek().map_or(0, |token| token.range.start);
         let term = self.parse_term()?;
 
         // Following a term there can be an operator
@@ -241,7 +423,8 @@ impl Parser {
             None
         };
 
-        Ok(Expression::new(Box::new(term), op_and_exprm))
+        let range = Range::new(start, self.tokens.last_range().end);
+        Ok(Expression::new(Box::new(term), op_and_exprm).with_range(range))
     }
 
     fn parse_return(&mut self) -> Result<Option<Expression>, CalError> {
@@ -256,12 +439,61 @@ impl Parser {
         }
     }
 
+    /// Parses a destructuring let, where the type annotation is optional
+    fn parse_let_tuple(&mut self) -> Result<Statement, CalError> {
+        // Let keyword is already consumed at this point
+        self.tokens.eat_symbol(Symbol::LeftParen)?;
+        let mut variables = vec![];
+        while !self.tokens.peek_symbol(Symbol::RightParen) {
+            let mutable = self.parse_mut();
+            let variable = Variable::from(self.parse_identifier()?).with_mutable(mutable);
+            variables.push(variable.with_range(self.tokens.last_range()));
+            if !self.tokens.peek_symbol(Symbol::RightParen) {
+                self.tokens.eat_symbol(Symbol::Comma)?;
+            }
+        }
+        self.tokens.eat_symbol(Symbol::RightParen)?;
+
+        if self.tokens.peek_symbol(Symbol::Colon) {
+            self.tokens.skip();
+            match self.parse_type()? {
+                Type::Tuple(types) if types.len() == variables.len() => {
+                    for (variable, typ) in variables.iter_mut().zip(types) {
+                        variable.typ = typ;
+                    }
+                }
+                typ => {
+                    return Err(CalError::new(
+                        format!(
+                            "Expected a tuple type of {} elements, found {:?}",
+                            variables.len(),
+                            typ
+                        ),
+                        Range::default(),
+                    ))
+                }
+            }
+        }
+
+        self.tokens.eat_symbol(Symbol::Assign)?;
+        let assign_expression = self.parse_expression(false)?;
+        self.tokens.eat_symbol(Symbol::Semicolon)?;
+        Ok(Statement::LetTuple(variables, assign_expression))
+    }
+
     pub fn parse_let(&mut self) -> Result<Statement, CalError> {
         self.tokens.eat_keyword(Keyword::Let)?;
+        if self.tokens.peek_symbol(Symbol::LeftParen) {
+            return self.parse_let_tuple();
+        }
+        let mutable = self.parse_mut();
         let variable_name = self.parse_identifier()?;
+        let range = self.tokens.last_range();
         self.tokens.eat_symbol(Symbol::Colon)?;
         let variable_type = self.parse_type()?;
-        let variable = Variable::new(variable_name, variable_type);
+        let variable = Variable::new(variable_name, variable_type)
+            .with_mutable(mutable)
+            .with_range(range);
         self.tokens.eat_symbol(Symbol::Assign)?;
         let assign_expression = self.parse_expression(false)?;
         self.tokens.eat_symbol(Symbol::Semicolon)?;
@@ -342,11 +574,34 @@ impl Parser {
     pub fn parse_parameters(&mut self) -> Result<Vec<Variable>, CalError> {
         let mut ret = vec![];
 
+        if let Some(impl_type) = self.impl_type.clone() {
+            if self.tokens.peek_symbol(Symbol::Ampersand) {
+                // Methods take their receiver by reference
+                self.tokens.skip();
+                let mutable = self.parse_mut();
+                self.tokens.eat_identifier("self")?;
+                let range = self.tokens.last_range();
+                let self_type = if mutable {
+                    Type::MutRef(Box::new(Type::Struct(impl_type)))
+                } else {
+                    Type::Ref(Box::new(Type::Struct(impl_type)))
+                };
+                ret.push(Variable::new("self".into(), self_type).with_range(range));
+                if self.tokens.peek_symbol(Symbol::Comma) {
+                    self.tokens.skip();
+                }
+            }
+        }
+
         while !self.tokens.peek_symbol(Symbol::RightParen) {
+            let mutable = self.parse_mut();
             let name = self.parse_identifier()?;
+            let range = self.tokens.last_range();
             self.tokens.eat_symbol(Symbol::Colon)?;
             let typ = self.parse_type()?;
-            let parameter = Variable::new(name, typ);
+            let parameter = Variable::new(name, typ)
+                .with_mutable(mutable)
+                .with_range(range);
             ret.push(parameter);
             if self.tokens.peek_symbol(Symbol::Comma) {
                 self.tokens.skip();
@@ -356,10 +611,38 @@ impl Parser {
         Ok(ret)
     }
 
+    /// Parses the attributes preceding a function, returning whether it is
+    /// marked `#[inline]`, which is the only one known
+    fn parse_attributes(&mut self) -> Result<bool, CalError> {
+        let mut inline = false;
+        while self.tokens.peek_symbol(Symbol::Hash) {
+            self.tokens.skip();
+            self.tokens.eat_symbol(Symbol::LeftBracket)?;
+            match self.parse_identifier()?.as_str() {
+                "inline" => inline = true,
+                name => {
+                    return Err(CalError::new(
+                        format!("Unknown attribute `{}`", name),
+                        self.tokens.last_range(),
+                    ))
+                }
+            }
+            self.tokens.eat_symbol(Symbol::RightBracket)?;
+        }
+        Ok(inline)
+    }
+
     pub fn parse_function(&mut self) -> Result<Function, CalError> {
+        let doc = self.parse_doc();
+        let inline = self.parse_attributes()?;
+        let is_const = self.tokens.peek_keyword(Keyword::Const);
+        if is_const {
+            self.tokens.skip();
+        }
         self.tokens.eat_keyword(Keyword::Function)?;
 
         let name = self.parse_identifier()?;
+        let range = self.tokens.last_range();
 
         self.tokens.eat_symbol(Symbol::LeftParen)?;
         let parameters = self.parse_parameters()?;
@@ -381,25 +664,241 @@ impl Parser {
             name,
             parameters,
             body_statements,
+            is_const,
+            inline,
+            range,
+            doc,
         })
     }
 
-    pub fn parse_module(&mut self) -> Result<Module, CalError> {
+    /// Parses a constant item `const NAME: T = expr;`
+    pub fn parse_constant(&mut self) -> Result<Constant, CalError> {
+        self.tokens.eat_keyword(Keyword::Const)?;
+        let name = self.parse_identifier()?;
+        self.tokens.eat_symbol(Symbol::Colon)?;
+        let typ = self.parse_type()?;
+        self.tokens.eat_symbol(Symbol::Assign)?;
+        let value = self.parse_expression(false)?;
+        self.tokens.eat_symbol(Symbol::Semicolon)?;
+        Ok(Constant::new(name, typ, value))
+    }
+
+    /// Parses a static item `static NAME: T = expr;`
+    pub fn parse_static(&mut self) -> Result<Static, CalError> {
+        self.tokens.eat_keyword(Keyword::Static)?;
+        let name = self.parse_identifier()?;
+        self.tokens.eat_symbol(Symbol::Colon)?;
+        let typ = self.parse_type()?;
+        self.tokens.eat_symbol(Symbol::Assign)?;
+        let value = self.parse_expression(false)?;
+        self.tokens.eat_symbol(Symbol::Semicolon)?;
+        Ok(Static::new(name, typ, value))
+    }
+
+    /// Parses a type alias `type Name = T;`
+    pub fn parse_type_alias(&mut self) -> Result<(TypeAlias, Range), CalError> {
+        self.tokens.eat_keyword(Keyword::Type)?;
+        let name = self.parse_identifier()?;
+        let range = self.tokens.last_range();
+        self.tokens.eat_symbol(Symbol::Assign)?;
+        let typ = self.parse_type()?;
+        self.tokens.eat_symbol(Symbol::Semicolon)?;
+        Ok((TypeAlias::new(name, typ), range))
+    }
+
+    pub fn parse_struct(&mut self) -> Result<StructDec, CalError> {
+        let doc = self.parse_doc();
+        self.tokens.eat_keyword(Keyword::Struct)?;
+        let name = self.parse_identifier()?;
+        self.tokens.eat_symbol(Symbol::LeftBrace)?;
+
+        let mut fields = vec![];
+        while !self.tokens.peek_symbol(Symbol::RightBrace) {
+            let field_name = self.parse_identifier()?;
+            let range = self.tokens.last_range();
+            self.tokens.eat_symbol(Symbol::Colon)?;
+            let typ = self.parse_type()?;
+            fields.push(Field::new(field_name, typ).with_range(range));
+            if !self.tokens.peek_symbol(Symbol::RightBrace) {
+                self.tokens.eat_symbol(Symbol::Comma)?;
+            }
+        }
+        self.tokens.eat_symbol(Symbol::RightBrace)?;
+
+        Ok(StructDec::new(name, fields).with_doc(doc))
+    }
+
+    /// Parses an `impl` block returning its functions, with names mangled
+    /// after the type they belong to
+    pub fn parse_impl(&mut self) -> Result<Vec<Function>, CalError> {
+        self.tokens.eat_keyword(Keyword::Impl)?;
+        let type_name = self.parse_identifier()?;
+        self.tokens.eat_symbol(Symbol::LeftBrace)?;
+
+        self.impl_type = Some(type_name.clone());
         let mut functions = vec![];
+        while !self.tokens.peek_symbol(Symbol::RightBrace) {
+            let mut function = self.parse_function()?;
+            function.name = mangle(&type_name, &function.name);
+            functions.push(function);
+        }
+        self.impl_type = None;
+
+        self.tokens.eat_symbol(Symbol::RightBrace)?;
+        Ok(functions)
+    }
+
+    /// Collects the names of all the structs declared in the module
+    fn collect_struct_names(&mut self) {
+        let mut tokens = (*self.tokens).clone();
+        while let Some(token) = tokens.next() {
+            if token.value == TokenKind::Keyword(Keyword::Struct) {
+                if let Some(Token {
+                    value: TokenKind::Identifier(name),
+                    ..
+                }) = tokens.next()
+                {
+                    self.struct_names.insert(name);
+                }
+            }
+        }
+    }
+
+    /// Collects the type aliases declared in the module, resolving the names
+    /// they refer to, so that they can be used before their declaration
+    fn collect_type_aliases(&mut self) -> Result<(), CalError> {
+        let mut names = vec![];
+        let mut aliases = HashMap::new();
+        let mut tokens = self.tokens.clone();
+        while let Some(token) = tokens.peek() {
+            if token.value == TokenKind::Keyword(Keyword::Type) {
+                // Names are not resolved yet, they are parsed as struct types
+                let (alias, range) = Parser::new(tokens.clone()).parse_type_alias()?;
+                if self.struct_names.contains(&alias.name) || aliases.contains_key(&alias.name) {
+                    return Err(CalError::new(
+                        format!("Type `{}` is defined multiple times", alias.name),
+                        range,
+                    ));
+                }
+                names.push(alias.name.clone());
+                aliases.insert(alias.name, (alias.typ, range));
+            }
+            tokens.skip();
+        }
+
+        // Resolve in order of declaration, so that errors are deterministic
+        for name in names {
+            let typ = Self::resolve_alias(&name, &aliases, &mut vec![])?;
+            self.type_aliases.insert(name, typ);
+        }
+        Ok(())
+    }
+
+    /// Resolves a type alias, where `path` is the chain of aliases being
+    /// resolved, which is used to detect cycles
+    fn resolve_alias(
+        name: &str,
+        aliases: &HashMap<String, (Type, Range)>,
+        path: &mut Vec<String>,
+    ) -> Result<Type, CalError> {
+        let (typ, range) = &aliases[name];
+        if path.iter().any(|alias| alias == name) {
+            path.push(name.into());
+            return Err(CalError::new(
+                format!("Type alias `{}` is recursive: {}", name, path.join(" -> ")),
+                *range,
+            ));
+        }
+        path.push(name.into());
+        let ret = Self::resolve_alias_type(typ, aliases, path);
+        path.pop();
+        ret
+    }
+
+    fn resolve_alias_type(
+        typ: &Type,
+        aliases: &HashMap<String, (Type, Range)>,
+        path: &mut Vec<String>,
+    ) -> Result<Type, CalError> {
+        let mut resolve = |typ: &Type| Self::resolve_alias_type(typ, aliases, path);
+        Ok(match typ {
+            Type::Struct(name) if aliases.contains_key(name) => {
+                Self::resolve_alias(name, aliases, path)?
+            }
+            Type::Array(elem_type, count) => Type::Array(Box::new(resolve(elem_type)?), *count),
+            Type::ArrayExpr(elem_type, count) => {
+                Type::ArrayExpr(Box::new(resolve(elem_type)?), count.clone())
+            }
+            Type::Ref(ref_type) => Type::Ref(Box::new(resolve(ref_type)?)),
+            Type::MutRef(ref_type) => Type::MutRef(Box::new(resolve(ref_type)?)),
+            Type::Tuple(types) => Type::Tuple(types.iter().map(resolve).collect::<Result<_, _>>()?),
+            typ => typ.clone(),
+        })
+    }
+
+    pub fn parse_module(&mut self) -> Result<Module, CalError> {
+        self.collect_struct_names();
+        self.collect_type_aliases()?;
+
+        let mut module = Module::new("main", vec![]);
+        // Doc comments of `impl` blocks, along with the type they belong to,
+        // which are appended to the doc comment of their struct
+        let mut impl_docs = vec![];
 
         while let Some(token) = self.tokens.peek() {
             match &token.value {
-                TokenKind::Keyword(Keyword::Function) => functions.push(self.parse_function()?),
+                TokenKind::Keyword(Keyword::Function) | TokenKind::Symbol(Symbol::Hash) => {
+                    module.functions.push(self.parse_function()?)
+                }
+                TokenKind::Keyword(Keyword::Const) => {
+                    // Either a constant item or a `const fn`
+                    let next = (*self.tokens).clone().nth(1).map(|token| token.value);
+                    if next == Some(TokenKind::Keyword(Keyword::Function)) {
+                        module.functions.push(self.parse_function()?)
+                    } else {
+                        module.constants.push(self.parse_constant()?)
+                    }
+                }
+                TokenKind::Keyword(Keyword::Static) => module.statics.push(self.parse_static()?),
+                TokenKind::Keyword(Keyword::Struct) => module.structs.push(self.parse_struct()?),
+                TokenKind::Keyword(Keyword::Type) => {
+                    module.type_aliases.push(self.parse_type_alias()?.0)
+                }
+                TokenKind::Keyword(Keyword::Impl) => {
+                    let doc = self.parse_doc();
+                    if let Some(TokenKind::Identifier(type_name)) =
+                        (*self.tokens).clone().nth(1).map(|token| token.value)
+                    {
+                        impl_docs.push((type_name, doc));
+                    }
+                    module.functions.extend(self.parse_impl()?)
+                }
                 _ => {
                     return Err(CalError::new(
-                        format!("Expected function, found {:?}", token.value),
+                        format!(
+                            "Expected function, constant, static, type, struct or impl, found {:?}",
+                            token.value
+                        ),
                         token.range,
                     ))
                 }
             }
         }
 
-        Ok(Module::new("main", functions))
+        for (type_name, doc) in impl_docs {
+            let struct_dec = module
+                .structs
+                .iter_mut()
+                .find(|struct_dec| struct_dec.name == type_name);
+            if let (Some(struct_dec), false) = (struct_dec, doc.is_empty()) {
+                if !struct_dec.doc.is_empty() {
+                    struct_dec.doc.push_str("\n\n");
+                }
+                struct_dec.doc.push_str(&doc);
+            }
+        }
+
+        Ok(module)
     }
 }
 
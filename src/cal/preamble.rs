@@ -39,110 +39,241 @@ fn poke() -> Vec<VmInstruction> {
     ]
 }
 
-/// Generates a bunch of instructions for the built-in multiplication function
+/// Generates a bunch of instructions for the built-in multiplication function,
+/// which adds `x` shifted by the position of each bit set in `|y|`, stopping
+/// as soon as no bits are left. The product wraps around like `i16`
 fn mul() -> Vec<VmInstruction> {
     vec![
-        VmInstruction::Function("mul".into(), 2),
-        VmInstruction::Push(Segment::Constant, 0),
-        VmInstruction::Pop(Segment::Local, 0), // sum
+        VmInstruction::Function("mul".into(), 4),
+        VmInstruction::Push(Segment::Argument, 0),
+        VmInstruction::Pop(Segment::Local, 1), // x shifted
+        VmInstruction::Push(Segment::Argument, 1),
+        VmInstruction::Pop(Segment::Local, 2), // |y|, where |i16::MIN| is i16::MIN
+        VmInstruction::Push(Segment::Argument, 1),
         VmInstruction::Push(Segment::Constant, 0),
-        VmInstruction::Pop(Segment::Local, 1), // i
-        VmInstruction::Label("MUL_WHILE".into()),
-        VmInstruction::Push(Segment::Local, 1),
-        VmInstruction::Push(Segment::Argument, 1), // y
         VmInstruction::Lt,
         VmInstruction::Not,
-        VmInstruction::IfGoto("MUL_END".into()),
-        VmInstruction::Push(Segment::Local, 0),
-        VmInstruction::Push(Segment::Argument, 0), // x
+        VmInstruction::IfGoto("MUL_POSITIVE".into()),
+        VmInstruction::Push(Segment::Argument, 1),
+        VmInstruction::Neg,
+        VmInstruction::Pop(Segment::Local, 2),
+        VmInstruction::Label("MUL_POSITIVE".into()),
+        VmInstruction::Push(Segment::Constant, 1),
+        VmInstruction::Pop(Segment::Local, 3), // mask
+        VmInstruction::Label("MUL_WHILE".into()),
+        VmInstruction::Push(Segment::Local, 2), // end if |y| & -mask == 0
+        VmInstruction::Push(Segment::Constant, 0),
+        VmInstruction::Push(Segment::Local, 3),
+        VmInstruction::Sub,
+        VmInstruction::And,
+        VmInstruction::Push(Segment::Constant, 0),
+        VmInstruction::Eq,
+        VmInstruction::IfGoto("MUL_SIGN".into()),
+        VmInstruction::Push(Segment::Local, 2),
+        VmInstruction::Push(Segment::Local, 3),
+        VmInstruction::And,
+        VmInstruction::Push(Segment::Constant, 0),
+        VmInstruction::Eq,
+        VmInstruction::IfGoto("MUL_NEXT".into()),
+        VmInstruction::Push(Segment::Local, 0), // sum + x shifted
+        VmInstruction::Push(Segment::Local, 1),
         VmInstruction::Add,
         VmInstruction::Pop(Segment::Local, 0),
+        VmInstruction::Label("MUL_NEXT".into()),
+        VmInstruction::Push(Segment::Local, 1),
         VmInstruction::Push(Segment::Local, 1),
-        VmInstruction::Push(Segment::Constant, 1),
         VmInstruction::Add,
         VmInstruction::Pop(Segment::Local, 1),
+        VmInstruction::Push(Segment::Local, 3),
+        VmInstruction::Push(Segment::Local, 3),
+        VmInstruction::Add,
+        VmInstruction::Pop(Segment::Local, 3),
         VmInstruction::Goto("MUL_WHILE".into()),
+        VmInstruction::Label("MUL_SIGN".into()),
+        VmInstruction::Push(Segment::Argument, 1),
+        VmInstruction::Push(Segment::Constant, 0),
+        VmInstruction::Lt,
+        VmInstruction::Not,
+        VmInstruction::IfGoto("MUL_END".into()),
+        VmInstruction::Push(Segment::Local, 0),
+        VmInstruction::Neg,
+        VmInstruction::Pop(Segment::Local, 0),
         VmInstruction::Label("MUL_END".into()),
         VmInstruction::Push(Segment::Local, 0),
         VmInstruction::Return(1),
     ]
 }
 
-/// Generates a bunch of instructions for the built-in division function
-fn div() -> Vec<VmInstruction> {
+/// Pushes `|x|` of an argument and pops it into a local, where `|i16::MIN|`
+/// is `i16::MIN` and read as unsigned afterwards
+fn abs(argument: u16, local: u16, label: String) -> Vec<VmInstruction> {
     vec![
-        VmInstruction::Function("div".into(), 2),
-        VmInstruction::Push(Segment::Constant, u16::MAX),
-        VmInstruction::Pop(Segment::Local, 1), // quotient
-        VmInstruction::Push(Segment::Argument, 1),
+        VmInstruction::Push(Segment::Argument, argument),
+        VmInstruction::Pop(Segment::Local, local),
+        VmInstruction::Push(Segment::Argument, argument),
         VmInstruction::Push(Segment::Constant, 0),
-        VmInstruction::Eq,
-        VmInstruction::IfGoto("DIV_END".into()),
-        VmInstruction::Push(Segment::Argument, 0),
-        VmInstruction::Pop(Segment::Local, 0), // remainder
-        VmInstruction::Push(Segment::Constant, 0),
-        VmInstruction::Pop(Segment::Local, 1), // quotient
-        VmInstruction::Label("DIV_WHILE".into()),
-        VmInstruction::Push(Segment::Local, 0), // end if remainder < divisor
-        VmInstruction::Push(Segment::Argument, 1),
         VmInstruction::Lt,
-        VmInstruction::IfGoto("DIV_END".into()),
-        VmInstruction::Push(Segment::Local, 0), // r - divisor
-        VmInstruction::Push(Segment::Argument, 1),
-        VmInstruction::Sub,
-        VmInstruction::Pop(Segment::Local, 0),
-        VmInstruction::Push(Segment::Local, 1), // increment quotient
-        VmInstruction::Push(Segment::Constant, 1),
-        VmInstruction::Add,
-        VmInstruction::Pop(Segment::Local, 1),
-        VmInstruction::Goto("DIV_WHILE".into()),
-        VmInstruction::Label("DIV_END".into()),
-        VmInstruction::Push(Segment::Local, 1),
-        VmInstruction::Return(1),
+        VmInstruction::Not,
+        VmInstruction::IfGoto(label.clone()),
+        VmInstruction::Push(Segment::Argument, argument),
+        VmInstruction::Neg,
+        VmInstruction::Pop(Segment::Local, local),
+        VmInstruction::Label(label),
     ]
 }
 
-/// Generates a bunch of instructions for the built-in modulo function
-fn modulo() -> Vec<VmInstruction> {
-    vec![
-        VmInstruction::Function("mod".into(), 2),
-        VmInstruction::Push(Segment::Constant, u16::MAX),
-        VmInstruction::Pop(Segment::Local, 1), // quotient
+/// Generates a long division of `|x|` by `|y|` as unsigned words, returning
+/// the quotient in local 0 or the remainder in local 1. The quotient is
+/// negative when the signs differ and the remainder takes the sign of `x`,
+/// so results truncate towards zero like `i16::wrapping_div` and
+/// `i16::wrapping_rem`. Dividing by zero gives `-1` and a remainder of `x`
+fn division(name: &str, prefix: &str, remainder: bool) -> Vec<VmInstruction> {
+    let label = |name: &str| format!("{}_{}", prefix, name);
+    let mut ret = vec![
+        VmInstruction::Function(name.into(), 5),
         VmInstruction::Push(Segment::Argument, 1),
         VmInstruction::Push(Segment::Constant, 0),
         VmInstruction::Eq,
-        VmInstruction::IfGoto("MOD_END".into()),
-        VmInstruction::Push(Segment::Argument, 0),
-        VmInstruction::Pop(Segment::Local, 0), // remainder
+        VmInstruction::IfGoto(label("ZERO")),
+    ];
+    ret.extend(abs(0, 2, label("X_POSITIVE"))); // dividend shifted out
+    ret.extend(abs(1, 3, label("Y_POSITIVE"))); // divisor
+    ret.extend([
+        VmInstruction::Push(Segment::Constant, 16),
+        VmInstruction::Pop(Segment::Local, 4), // bits left
+        // Skip leading zeros, which would only shift zeros into the remainder
+        VmInstruction::Label(label("ALIGN")),
+        VmInstruction::Push(Segment::Local, 2),
+        VmInstruction::Push(Segment::Constant, 0),
+        VmInstruction::Gt,
+        VmInstruction::Not,
+        VmInstruction::IfGoto(label("WHILE")),
+        VmInstruction::Push(Segment::Local, 2),
+        VmInstruction::Push(Segment::Local, 2),
+        VmInstruction::Add,
+        VmInstruction::Pop(Segment::Local, 2),
+        VmInstruction::Push(Segment::Local, 4),
+        VmInstruction::Push(Segment::Constant, 1),
+        VmInstruction::Sub,
+        VmInstruction::Pop(Segment::Local, 4),
+        VmInstruction::Goto(label("ALIGN")),
+        VmInstruction::Label(label("WHILE")),
+        VmInstruction::Push(Segment::Local, 4),
+        VmInstruction::Push(Segment::Constant, 0),
+        VmInstruction::Eq,
+        VmInstruction::IfGoto(label("SIGN")),
+        // Shift the top bit of the dividend into the remainder
+        VmInstruction::Push(Segment::Local, 1),
+        VmInstruction::Push(Segment::Local, 1),
+        VmInstruction::Add,
+        VmInstruction::Push(Segment::Local, 2),
         VmInstruction::Push(Segment::Constant, 0),
-        VmInstruction::Pop(Segment::Local, 1), // quotient
-        VmInstruction::Label("MOD_WHILE".into()),
-        VmInstruction::Push(Segment::Local, 0), // end if remainder < divisor
-        VmInstruction::Push(Segment::Argument, 1),
         VmInstruction::Lt,
-        VmInstruction::IfGoto("MOD_END".into()),
-        VmInstruction::Push(Segment::Local, 0), // r - divisor
-        VmInstruction::Push(Segment::Argument, 1),
         VmInstruction::Sub,
-        VmInstruction::Pop(Segment::Local, 0),
-        VmInstruction::Push(Segment::Local, 1), // increment quotient
-        VmInstruction::Push(Segment::Constant, 1),
+        VmInstruction::Pop(Segment::Local, 1),
+        VmInstruction::Push(Segment::Local, 2),
+        VmInstruction::Push(Segment::Local, 2),
         VmInstruction::Add,
+        VmInstruction::Pop(Segment::Local, 2),
+        VmInstruction::Push(Segment::Local, 0),
+        VmInstruction::Push(Segment::Local, 0),
+        VmInstruction::Add,
+        VmInstruction::Pop(Segment::Local, 0),
+        // Unsigned remainder >= divisor, where the divisor is at most 0x8000
+        VmInstruction::Push(Segment::Local, 1),
+        VmInstruction::Push(Segment::Constant, 0),
+        VmInstruction::Lt,
+        VmInstruction::IfGoto(label("SUBTRACT")),
+        VmInstruction::Push(Segment::Local, 3),
+        VmInstruction::Push(Segment::Constant, 0),
+        VmInstruction::Lt,
+        VmInstruction::IfGoto(label("NEXT")),
+        VmInstruction::Push(Segment::Local, 1),
+        VmInstruction::Push(Segment::Local, 3),
+        VmInstruction::Lt,
+        VmInstruction::IfGoto(label("NEXT")),
+        VmInstruction::Label(label("SUBTRACT")),
+        VmInstruction::Push(Segment::Local, 1),
+        VmInstruction::Push(Segment::Local, 3),
+        VmInstruction::Sub,
         VmInstruction::Pop(Segment::Local, 1),
-        VmInstruction::Goto("MOD_WHILE".into()),
-        VmInstruction::Label("MOD_END".into()),
         VmInstruction::Push(Segment::Local, 0),
+        VmInstruction::Push(Segment::Constant, 1),
+        VmInstruction::Add,
+        VmInstruction::Pop(Segment::Local, 0),
+        VmInstruction::Label(label("NEXT")),
+        VmInstruction::Push(Segment::Local, 4),
+        VmInstruction::Push(Segment::Constant, 1),
+        VmInstruction::Sub,
+        VmInstruction::Pop(Segment::Local, 4),
+        VmInstruction::Goto(label("WHILE")),
+        VmInstruction::Label(label("SIGN")),
+    ]);
+    let result = if remainder {
+        ret.extend([
+            VmInstruction::Push(Segment::Argument, 0),
+            VmInstruction::Push(Segment::Constant, 0),
+            VmInstruction::Lt,
+            VmInstruction::Not,
+        ]);
+        1
+    } else {
+        ret.extend([
+            VmInstruction::Push(Segment::Argument, 0),
+            VmInstruction::Push(Segment::Constant, 0),
+            VmInstruction::Lt,
+            VmInstruction::Push(Segment::Argument, 1),
+            VmInstruction::Push(Segment::Constant, 0),
+            VmInstruction::Lt,
+            VmInstruction::Eq,
+        ]);
+        0
+    };
+    ret.extend([
+        VmInstruction::IfGoto(label("END")),
+        VmInstruction::Push(Segment::Local, result),
+        VmInstruction::Neg,
+        VmInstruction::Pop(Segment::Local, result),
+        VmInstruction::Label(label("END")),
+        VmInstruction::Push(Segment::Local, result),
         VmInstruction::Return(1),
-    ]
+        VmInstruction::Label(label("ZERO")),
+    ]);
+    if remainder {
+        ret.push(VmInstruction::Push(Segment::Argument, 0));
+    } else {
+        ret.extend([
+            VmInstruction::Push(Segment::Constant, 0),
+            VmInstruction::Not,
+        ]);
+    }
+    ret.push(VmInstruction::Return(1));
+    ret
 }
 
-/// The preable is added at the beginning of the program
-pub fn preamble() -> Vec<VmInstruction> {
-    let mut ret = sys();
-    ret.extend(peek());
+/// Generates a bunch of instructions for the built-in division function
+fn div() -> Vec<VmInstruction> {
+    division("div", "DIV", false)
+}
+
+/// Generates a bunch of instructions for the built-in modulo function
+fn modulo() -> Vec<VmInstruction> {
+    division("mod", "MOD", true)
+}
+
+/// Built-in functions, which do not depend on the language calling them
+pub fn builtins() -> Vec<VmInstruction> {
+    let mut ret = peek();
     ret.extend(poke());
     ret.extend(mul());
     ret.extend(div());
     ret.extend(modulo());
     ret
 }
+
+/// The preable is added at the beginning of the program
+pub fn preamble() -> Vec<VmInstruction> {
+    let mut ret = sys();
+    ret.extend(builtins());
+    ret
+}
@@ -42,6 +42,9 @@ pub enum Statement {
     Expression(Expression),
     Return(Option<Expression>),
     Let(Variable, Expression),
+    /// Destructuring let, binding each element of a tuple to a variable.
+    /// Variables with `Void` type have their type inferred.
+    LetTuple(Vec<Variable>, Expression),
     If(IfStatement),
     While(WhileStatement),
 }
@@ -0,0 +1,60 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::{
+    console::font_static, error::CalError, parser::parse, structure::Module, tokenizer::tokenize,
+    tokenizer::Range,
+};
+
+/// Libraries shipped with the compiler, by name, which programs can link
+const LIBRARIES: [(&str, &str); 3] = [
+    ("graphics", include_str!("std/graphics.cal")),
+    ("console", include_str!("std/console.cal")),
+    ("keyboard", include_str!("std/keyboard.cal")),
+];
+
+/// Libraries using items of other libraries, which are linked along with them
+const DEPENDENCIES: [(&str, &[&str]); 1] = [("keyboard", &["console"])];
+
+/// Names of the libraries shipped with the compiler
+pub fn names() -> Vec<&'static str> {
+    LIBRARIES.iter().map(|(name, _)| *name).collect()
+}
+
+/// Names of the libraries a library depends on
+pub fn dependencies(name: &str) -> &'static [&'static str] {
+    DEPENDENCIES
+        .iter()
+        .find(|(library, _)| *library == name)
+        .map_or(&[], |(_, dependencies)| dependencies)
+}
+
+/// Returns the source code of a library, where the font of `console` is
+/// generated from the one of the emulator builtins
+pub fn source(name: &str) -> Option<String> {
+    let (_, source) = LIBRARIES.iter().find(|(library, _)| *library == name)?;
+    let mut ret = source.to_string();
+    if name == "console" {
+        ret += "\n";
+        ret += &font_static();
+    }
+    Some(ret)
+}
+
+/// Parses a library into a module with its name
+pub fn library(name: &str) -> Result<Module, CalError> {
+    let Some(code) = source(name) else {
+        return Err(CalError::new(
+            format!(
+                "Unknown library `{}`, expected one of {}",
+                name,
+                names().join(", ")
+            ),
+            Range::default(),
+        ));
+    };
+    let mut module = parse(tokenize(&code)?)?;
+    module.name = name.into();
+    Ok(module)
+}
@@ -2,30 +2,56 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
+use std::fmt;
+
 use crate::{
     error::CalError,
+    expression::{Expression, Literal, Term},
     statement::Statement,
     tokenizer::{Keyword, Range},
 };
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, Eq)]
 pub struct Variable {
     pub name: String,
     pub typ: Type,
+    /// Only mutable variables can be assigned after their declaration
+    pub mutable: bool,
+    /// Where the variable is declared
+    pub range: Range,
 }
 
 impl Variable {
     pub fn new(name: String, typ: Type) -> Self {
-        Self { name, typ }
+        Self {
+            name,
+            typ,
+            mutable: false,
+            range: Range::default(),
+        }
+    }
+
+    pub fn with_mutable(mut self, mutable: bool) -> Self {
+        self.mutable = mutable;
+        self
+    }
+
+    pub fn with_range(mut self, range: Range) -> Self {
+        self.range = range;
+        self
+    }
+}
+
+/// Variables are equal regardless of where they are declared
+impl PartialEq for Variable {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.typ == other.typ && self.mutable == other.mutable
     }
 }
 
 impl From<String> for Variable {
     fn from(name: String) -> Self {
-        Self {
-            name,
-            typ: Type::Void,
-        }
+        Self::new(name, Type::Void)
     }
 }
 
@@ -38,11 +64,46 @@ pub enum Type {
     /// An array is defined by the _type_ and the _number_ of its elements
     Array(Box<Type>, u16),
 
+    /// An array whose number of elements is a constant expression, which is
+    /// evaluated by the generator resolving it into an `Array`
+    ArrayExpr(Box<Type>, Box<Expression>),
+
     /// A reference is actually a pointer to an object
     Ref(Box<Type>),
+
+    /// A mutable reference is a pointer which allows writing to the object
+    MutRef(Box<Type>),
+
+    /// A tuple is defined by the types of its elements
+    Tuple(Vec<Type>),
+
+    /// A struct is identified by its name, its fields are found in its `StructDec`
+    Struct(String),
 }
 
 impl Type {
+    /// Returns the type of the object a reference points to
+    pub fn get_pointee(&self) -> Option<&Type> {
+        match self {
+            Type::Ref(pointee) | Type::MutRef(pointee) => Some(pointee),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a value of type `found` holds a `&` reference where
+    /// this type expects a `&mut` one, looking into arrays and tuples
+    pub fn rejects_shared_ref(&self, found: &Type) -> bool {
+        match (self, found) {
+            (Type::MutRef(_), Type::Ref(_)) => true,
+            (Type::Array(expected, _), Type::Array(found, _)) => expected.rejects_shared_ref(found),
+            (Type::Tuple(expected), Type::Tuple(found)) => expected
+                .iter()
+                .zip(found)
+                .any(|(expected, found)| expected.rejects_shared_ref(found)),
+            _ => false,
+        }
+    }
+
     pub fn from_keyword(keyword: Keyword) -> Result<Self, CalError> {
         match keyword {
             Keyword::I16 => Ok(Type::I16),
@@ -56,23 +117,121 @@ impl Type {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Types are displayed with the same syntax they are written in Cal
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "()"),
+            Type::I16 => write!(f, "i16"),
+            Type::Bool => write!(f, "bool"),
+            Type::Char => write!(f, "char"),
+            Type::Array(elem_type, count) => write!(f, "[{}; {}]", elem_type, count),
+            Type::ArrayExpr(elem_type, count) => match (count.term.as_ref(), &count.op_and_expr) {
+                (Term::Variable(name), None) => write!(f, "[{}; {}]", elem_type, name),
+                (Term::Literal(Literal::I16(count)), None) => {
+                    write!(f, "[{}; {}]", elem_type, count)
+                }
+                _ => write!(f, "[{}; _]", elem_type),
+            },
+            Type::Ref(pointee) => write!(f, "&{}", pointee),
+            Type::MutRef(pointee) => write!(f, "&mut {}", pointee),
+            Type::Tuple(types) => {
+                let types: Vec<String> = types.iter().map(Type::to_string).collect();
+                if types.len() == 1 {
+                    write!(f, "({},)", types[0])
+                } else {
+                    write!(f, "({})", types.join(", "))
+                }
+            }
+            Type::Struct(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq)]
 pub struct Function {
     pub return_type: Type,
     pub name: String,
     pub parameters: Vec<Variable>,
     pub body_statements: Vec<Statement>,
+    /// A `const fn` can be evaluated at compile time
+    pub is_const: bool,
+    /// An `#[inline]` function is inlined by the optimiser whatever its size
+    pub inline: bool,
+    /// Range of the name of the function
+    pub range: Range,
+    /// Text of the `///` comments preceding the function
+    pub doc: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+impl Function {
+    /// Returns the signature of the function as it is written in Cal
+    pub fn signature(&self) -> String {
+        let parameters: Vec<String> = self
+            .parameters
+            .iter()
+            .map(
+                |parameter| match (parameter.name.as_str(), &parameter.typ) {
+                    ("self", Type::Ref(_)) => "&self".into(),
+                    ("self", Type::MutRef(_)) => "&mut self".into(),
+                    ("self", _) => "self".into(),
+                    _ => format!("{}: {}", parameter.name, parameter.typ),
+                },
+            )
+            .collect();
+        let mut ret = format!(
+            "{}fn {}({})",
+            if self.is_const { "const " } else { "" },
+            self.name.replace('.', "::"),
+            parameters.join(", ")
+        );
+        if self.return_type != Type::Void {
+            ret += &format!(" -> {}", self.return_type);
+        }
+        ret
+    }
+}
+
+/// Functions are equal regardless of where they are declared and how they
+/// are documented
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.return_type == other.return_type
+            && self.name == other.name
+            && self.parameters == other.parameters
+            && self.body_statements == other.body_statements
+            && self.is_const == other.is_const
+            && self.inline == other.inline
+    }
+}
+
+#[derive(Clone, Debug, Eq)]
 pub struct Field {
     pub name: String,
     pub typ: Type,
+    /// Where the field is declared
+    pub range: Range,
 }
 
 impl Field {
     pub fn new(name: String, typ: Type) -> Self {
-        Self { name, typ }
+        Self {
+            name,
+            typ,
+            range: Range::default(),
+        }
+    }
+
+    pub fn with_range(mut self, range: Range) -> Self {
+        self.range = range;
+        self
+    }
+}
+
+/// Fields are equal regardless of where they are declared
+impl PartialEq for Field {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.typ == other.typ
     }
 }
 
@@ -80,20 +239,99 @@ impl Field {
 pub struct StructDec {
     pub name: String,
     pub fields: Vec<Field>,
+    /// Text of the `///` comments preceding the struct
+    pub doc: String,
+}
+
+impl StructDec {
+    pub fn new(name: String, fields: Vec<Field>) -> Self {
+        Self {
+            name,
+            fields,
+            doc: String::new(),
+        }
+    }
+
+    pub fn with_doc(mut self, doc: String) -> Self {
+        self.doc = doc;
+        self
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// A named constant, evaluated at compile time wherever it is used
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constant {
+    pub name: String,
+    pub typ: Type,
+    pub value: Expression,
+}
+
+impl Constant {
+    pub fn new(name: String, typ: Type, value: Expression) -> Self {
+        Self { name, typ, value }
+    }
+}
+
+/// A static is laid out in the data section of the memory and initialized
+/// at boot time, before calling `main`, so that large tables do not need to
+/// be built on the stack
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Static {
+    pub name: String,
+    pub typ: Type,
+    pub value: Expression,
+}
+
+impl Static {
+    pub fn new(name: String, typ: Type, value: Expression) -> Self {
+        Self { name, typ, value }
+    }
+}
+
+/// A type alias gives a name to another type, which it is replaced with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeAlias {
+    pub name: String,
+    pub typ: Type,
+}
+
+impl TypeAlias {
+    pub fn new(name: String, typ: Type) -> Self {
+        Self { name, typ }
+    }
+}
+
+/// Methods and associated functions of a type are mangled into plain
+/// functions named `Type.function`
+pub fn mangle(type_name: &str, function_name: &str) -> String {
+    format!("{}.{}", type_name, function_name)
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Module {
     pub name: String,
+    pub structs: Vec<StructDec>,
+    /// Functions of the module, including methods defined in `impl` blocks
     pub functions: Vec<Function>,
-    // TODO add constants and structs
+    pub constants: Vec<Constant>,
+    pub statics: Vec<Static>,
+    /// Type aliases are already resolved by the parser
+    pub type_aliases: Vec<TypeAlias>,
 }
 
 impl Module {
     pub fn new(name: impl Into<String>, functions: Vec<Function>) -> Self {
         Self {
             name: name.into(),
+            structs: vec![],
             functions,
+            constants: vec![],
+            statics: vec![],
+            type_aliases: vec![],
         }
     }
 }
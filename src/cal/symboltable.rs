@@ -6,6 +6,7 @@ use std::collections::HashMap;
 
 use crate::{segment::Segment, structure::Variable};
 
+#[derive(Clone, Debug)]
 pub struct SymbolEntry {
     pub variable: Variable,
     pub segment: Segment,
@@ -22,42 +23,59 @@ impl SymbolEntry {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default)]
 /// Each scope has its own symbol table, with its own number of local variables
 pub struct SymbolTable {
     local_count: u16,
     argument_count: u16,
-    variables: HashMap<String, SymbolEntry>,
+    /// Every variable in order of declaration, including shadowed ones
+    entries: Vec<SymbolEntry>,
+    /// Index of the entry of the variable visible with each name
+    variables: HashMap<String, usize>,
 }
 
 impl SymbolTable {
-    /// Inserts a new local variable in the symbol table and
-    /// returns the index of the newly inserted variable
-    pub fn insert_local(&mut self, variable: &Variable) -> u16 {
+    fn insert(&mut self, entry: SymbolEntry) {
+        self.variables
+            .insert(entry.variable.name.clone(), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    /// Inserts a new local variable occupying `size_in_words` words in the
+    /// symbol table and returns the index of the newly inserted variable
+    pub fn insert_local(&mut self, variable: &Variable, size_in_words: u16) -> u16 {
         let local_number = self.local_count;
         let entry = SymbolEntry::new(variable.clone(), Segment::Local, local_number);
-        self.variables.insert(variable.name.clone(), entry);
-        self.local_count += 1;
+        self.insert(entry);
+        self.local_count += size_in_words;
         local_number
     }
 
-    /// Inserts a new argument variable in the symbol table and
-    /// returns the index of the newly inserted variable
-    pub fn insert_argument(&mut self, variable: &Variable) {
+    /// Inserts a new argument variable occupying `size_in_words` words in the
+    /// symbol table
+    pub fn insert_argument(&mut self, variable: &Variable, size_in_words: u16) {
         let argument_number = self.argument_count;
         let entry = SymbolEntry::new(variable.clone(), Segment::Argument, argument_number);
-        self.variables.insert(variable.name.clone(), entry);
-        self.argument_count += 1;
+        self.insert(entry);
+        self.argument_count += size_in_words;
+    }
+
+    /// Returns the number of words allocated for local variables so far
+    pub fn get_local_count(&self) -> u16 {
+        self.local_count
     }
 
     /// Returns the segment and the offset of the variable with that `name`
     pub fn get_segment_and_offset(&self, name: &str) -> Option<(Segment, u16)> {
-        self.variables
-            .get(name)
-            .map(|entry| (entry.segment, entry.offset))
+        self.get(name).map(|entry| (entry.segment, entry.offset))
     }
 
     pub fn get(&self, name: &str) -> Option<&SymbolEntry> {
-        self.variables.get(name)
+        self.variables.get(name).map(|index| &self.entries[*index])
+    }
+
+    /// Returns all the variables declared, in order
+    pub fn get_entries(&self) -> &[SymbolEntry] {
+        &self.entries
     }
 }
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: MIT
 
 use std::{
+    fmt,
     iter::Peekable,
     ops::{Deref, DerefMut},
 };
@@ -41,24 +42,49 @@ pub enum Keyword {
     If,
     Else,
     While,
+    Struct,
+    Impl,
+    Const,
+    SizeOf,
+    Mut,
+    Type,
+    Static,
 }
 
 impl Keyword {
-    pub const MAP: [(&'static str, Keyword); 11] = [
-        ("fn ", Keyword::Function),
+    pub const MAP: [(&'static str, Keyword); 18] = [
+        ("fn", Keyword::Function),
         ("i16", Keyword::I16),
         ("char", Keyword::Char),
         ("return", Keyword::Return),
-        ("let ", Keyword::Let),
+        ("let", Keyword::Let),
         ("bool", Keyword::Bool),
         ("true", Keyword::True),
         ("false", Keyword::False),
         ("if", Keyword::If),
         ("else", Keyword::Else),
         ("while", Keyword::While),
+        ("struct", Keyword::Struct),
+        ("impl", Keyword::Impl),
+        ("const", Keyword::Const),
+        ("sizeof", Keyword::SizeOf),
+        ("mut", Keyword::Mut),
+        ("type", Keyword::Type),
+        ("static", Keyword::Static),
     ];
 }
 
+/// Keywords are displayed as they are written in Cal
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = Keyword::MAP
+            .iter()
+            .find(|(_, keyword)| keyword == self)
+            .unwrap();
+        write!(f, "{}", name)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Symbol {
     /// '('
@@ -79,6 +105,10 @@ pub enum Symbol {
     Semicolon,
     /// `:`,
     Colon,
+    /// `::`
+    DoubleColon,
+    /// `.`
+    Dot,
     /// `=`
     Assign,
     /// `,`
@@ -105,6 +135,42 @@ pub enum Symbol {
     VerticalBar,
     /// `%`
     Percent,
+    /// `#`, which starts an attribute
+    Hash,
+}
+
+/// Symbols are displayed as they are written in Cal
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Symbol::LeftParen => "(",
+            Symbol::RightParen => ")",
+            Symbol::LeftBracket => "[",
+            Symbol::RightBracket => "]",
+            Symbol::LeftBrace => "{",
+            Symbol::RightBrace => "}",
+            Symbol::RightArrow => "->",
+            Symbol::Semicolon => ";",
+            Symbol::Colon => ":",
+            Symbol::DoubleColon => "::",
+            Symbol::Dot => ".",
+            Symbol::Eq => "==",
+            Symbol::Assign => "=",
+            Symbol::Lt => "<",
+            Symbol::Gt => ">",
+            Symbol::Ne => "!=",
+            Symbol::Comma => ",",
+            Symbol::Plus => "+",
+            Symbol::Minus => "-",
+            Symbol::Asterisk => "*",
+            Symbol::Slash => "/",
+            Symbol::Ampersand => "&",
+            Symbol::VerticalBar => "|",
+            Symbol::Percent => "%",
+            Symbol::Hash => "#",
+        };
+        write!(f, "{}", text)
+    }
 }
 
 /// We have various kinds of tokens
@@ -116,6 +182,8 @@ pub enum TokenKind {
     Identifier(String),
     Integer(i16),
     Char(char),
+    /// A `//` or `/* */` comment, which is kept apart from the other tokens
+    Comment(String),
 }
 
 /// Useful for lexical analysys, with the tokenizer we transform series of
@@ -132,241 +200,221 @@ impl Token {
     }
 }
 
-/// Tries to strip a keyword from the input and, if succedes, returns
-/// the keyword and the new string stripped of that keyword
-fn strip_keyword(input: &str) -> Option<(Keyword, &str)> {
-    for (prefix, keyword) in &Keyword::MAP {
-        if let Some(stripped) = input.strip_prefix(prefix) {
-            return Some((*keyword, stripped));
-        }
-    }
-    None
+/// Scans the code one character at a time, producing tokens with the exact
+/// range of the text they are read from
+struct Lexer<'a> {
+    code: &'a str,
+    offset: usize,
 }
 
-/// Tries to strip a symbol from the input and, if succedes, returns
-/// the symbol and the new string stripped of that symbol
-fn strip_symbol(input: &str) -> Option<(Symbol, &str)> {
-    let mut chars = input.chars();
-    match chars.next() {
-        Some('(') => Some((Symbol::LeftParen, &input[1..])),
-        Some(')') => Some((Symbol::RightParen, &input[1..])),
-        Some('[') => Some((Symbol::LeftBracket, &input[1..])),
-        Some(']') => Some((Symbol::RightBracket, &input[1..])),
-        Some('{') => Some((Symbol::LeftBrace, &input[1..])),
-        Some('}') => Some((Symbol::RightBrace, &input[1..])),
-        Some('-') => {
-            if let Some('>') = chars.next() {
-                Some((Symbol::RightArrow, &input[2..]))
-            } else {
-                Some((Symbol::Minus, &input[1..]))
-            }
-        }
-        Some(';') => Some((Symbol::Semicolon, &input[1..])),
-        Some(':') => Some((Symbol::Colon, &input[1..])),
-        Some('=') => {
-            if let Some('=') = chars.next() {
-                Some((Symbol::Eq, &input[2..]))
-            } else {
-                Some((Symbol::Assign, &input[1..]))
-            }
-        }
-        Some('<') => Some((Symbol::Lt, &input[1..])),
-        Some('>') => Some((Symbol::Gt, &input[1..])),
-        Some('!') => {
-            if let Some('=') = chars.next() {
-                Some((Symbol::Ne, &input[2..]))
-            } else {
-                None
-            }
-        }
-        Some(',') => Some((Symbol::Comma, &input[1..])),
-        Some('+') => Some((Symbol::Plus, &input[1..])),
-        Some('*') => Some((Symbol::Asterisk, &input[1..])),
-        Some('/') => Some((Symbol::Slash, &input[1..])),
-        Some('&') => Some((Symbol::Ampersand, &input[1..])),
-        Some('|') => Some((Symbol::VerticalBar, &input[1..])),
-        Some('%') => Some((Symbol::Percent, &input[1..])),
-        _ => None,
+impl<'a> Lexer<'a> {
+    fn new(code: &'a str) -> Self {
+        Self { code, offset: 0 }
     }
-}
 
-/// Tries to strip a character from the input and, if it succedes, returns
-/// the character and the new string stripped of that character
-fn strip_character(input: &str) -> Option<(char, &str)> {
-    let mut chars = input.chars();
-    if let Some('\'') = chars.next() {
-        let character = chars.next().unwrap();
-        if let Some('\'') = chars.next() {
-            return Some((character, &input[3..]));
-        }
+    fn rest(&self) -> &'a str {
+        &self.code[self.offset..]
     }
-    None
-}
 
-/// Tries to strip an identifier from the input and, if succedes, returns
-/// the identifier and the new string stripped of that identifier
-fn strip_identifier(input: &str) -> Option<(&str, &str)> {
-    if let Some(first_char) = input.chars().next() {
-        if first_char.is_alphabetic() || first_char == '_' {
-            let mut cut_index = 0;
-
-            for c in input.chars() {
-                if c.is_alphanumeric() || c == '_' {
-                    cut_index += 1;
-                } else {
-                    break;
-                }
-            }
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
 
-            return Some(input.split_at(cut_index));
-        }
+    /// Advances while characters satisfy the predicate, returning the text
+    /// read from `start`
+    fn eat_while(&mut self, start: usize, predicate: impl Fn(char) -> bool) -> &'a str {
+        let len = self
+            .rest()
+            .find(|c: char| !predicate(c))
+            .unwrap_or(self.rest().len());
+        self.offset += len;
+        &self.code[start..self.offset]
     }
-    None
-}
 
-/// Tries to strip a binary integer (`0b0101`) from the input and, if succedes,
-/// returns the integer and the new string stripped of that integer
-fn strip_binary_integer(input: &str) -> Option<(i16, &str)> {
-    let mut chars = input.chars();
-    let Some('0') = chars.next() else {
-        return None;
-    };
-    let Some('b') = chars.next() else {
-        return None;
-    };
-
-    let mut cut_index = 2;
-    for c in chars {
-        if c == '0' || c == '1' {
-            cut_index += 1;
-        } else {
-            break;
-        }
+    fn error<T>(&self, message: String, start: usize) -> Result<T, CalError> {
+        Err(CalError::new(message, Range::new(start, self.offset)))
     }
-    if cut_index < 3 {
-        return None;
+
+    fn comment(&mut self, start: usize) -> Result<TokenKind, CalError> {
+        if self.rest().starts_with("//") {
+            let comment = self.eat_while(start, |c| c != '\n').trim_end();
+            self.offset = start + comment.len();
+            return Ok(TokenKind::Comment(comment.into()));
+        }
+        // Block comments do not nest
+        match self.rest()[2..].find("*/") {
+            Some(end) => {
+                self.offset += 2 + end + 2;
+                Ok(TokenKind::Comment(self.code[start..self.offset].into()))
+            }
+            None => {
+                self.offset = self.code.len();
+                self.error("Unterminated comment".into(), start)
+            }
+        }
     }
 
-    let (integer_str, stripped_input) = input.split_at(cut_index);
-    if let Ok(integer) = i16::from_str_radix(&integer_str[2..], 2) {
-        Some((integer, stripped_input))
-    } else {
-        None
+    /// Scans an identifier, which is a keyword if it matches one exactly
+    fn word(&mut self, start: usize) -> TokenKind {
+        let word = self.eat_while(start, |c| c.is_alphanumeric() || c == '_');
+        match Keyword::MAP.iter().find(|(name, _)| *name == word) {
+            Some((_, keyword)) => TokenKind::Keyword(*keyword),
+            None => TokenKind::Identifier(word.into()),
+        }
     }
-}
 
-/// Tries to strip a regular integer from the input and, if succedes, returns
-/// the integer and the new string stripped of that integer
-fn strip_regular_integer(input: &str) -> Option<(i16, &str)> {
-    let mut cut_index = 0;
-    for c in input.chars() {
-        if c.is_numeric() {
-            cut_index += 1;
-        } else {
-            break;
+    /// Scans a decimal, hexadecimal (`0x`), or binary (`0b`) integer, where
+    /// digits may be separated by `_`. Hexadecimal and binary integers are bit
+    /// patterns up to 16 bits, while decimal ones are at most `i16::MAX`.
+    fn integer(&mut self, start: usize) -> Result<TokenKind, CalError> {
+        let radix = match self.rest().get(..2) {
+            Some("0x" | "0X") => 16,
+            Some("0b" | "0B") => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.offset += 2;
+        }
+        let text = self.eat_while(start, |c| c.is_alphanumeric() || c == '_');
+        let digits: String = text[if radix == 10 { 0 } else { 2 }..]
+            .chars()
+            .filter(|c| *c != '_')
+            .collect();
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return self.error(format!("Invalid integer `{}`", text), start);
+        }
+        let value = match u16::from_str_radix(&digits, radix) {
+            Ok(value) if radix != 10 => Some(value as i16),
+            Ok(value) => i16::try_from(value).ok(),
+            Err(_) => None,
+        };
+        match value {
+            Some(value) => Ok(TokenKind::Integer(value)),
+            None => self.error(format!("Integer `{}` is out of range", text), start),
         }
     }
-    if cut_index == 0 {
-        return None;
+
+    fn character(&mut self, start: usize) -> Result<TokenKind, CalError> {
+        let mut chars = self.rest().chars();
+        chars.next();
+        match (chars.next(), chars.next()) {
+            (Some(c), Some('\'')) if c != '\'' => {
+                self.offset += 2 + c.len_utf8();
+                Ok(TokenKind::Char(c))
+            }
+            _ => {
+                self.offset += 1;
+                self.error("Unterminated character".into(), start)
+            }
+        }
     }
 
-    let (integer_str, stripped_input) = input.split_at(cut_index);
-    if let Ok(integer) = integer_str.parse() {
-        Some((integer, stripped_input))
-    } else {
-        None
+    fn symbol(&mut self, start: usize) -> Result<TokenKind, CalError> {
+        let mut chars = self.rest().chars();
+        let first = chars.next().unwrap_or_default();
+        let second = chars.next();
+        let (symbol, len) = match (first, second) {
+            ('-', Some('>')) => (Symbol::RightArrow, 2),
+            (':', Some(':')) => (Symbol::DoubleColon, 2),
+            ('=', Some('=')) => (Symbol::Eq, 2),
+            ('!', Some('=')) => (Symbol::Ne, 2),
+            ('(', _) => (Symbol::LeftParen, 1),
+            (')', _) => (Symbol::RightParen, 1),
+            ('[', _) => (Symbol::LeftBracket, 1),
+            (']', _) => (Symbol::RightBracket, 1),
+            ('{', _) => (Symbol::LeftBrace, 1),
+            ('}', _) => (Symbol::RightBrace, 1),
+            ('-', _) => (Symbol::Minus, 1),
+            (';', _) => (Symbol::Semicolon, 1),
+            (':', _) => (Symbol::Colon, 1),
+            ('.', _) => (Symbol::Dot, 1),
+            ('=', _) => (Symbol::Assign, 1),
+            ('<', _) => (Symbol::Lt, 1),
+            ('>', _) => (Symbol::Gt, 1),
+            (',', _) => (Symbol::Comma, 1),
+            ('+', _) => (Symbol::Plus, 1),
+            ('*', _) => (Symbol::Asterisk, 1),
+            ('/', _) => (Symbol::Slash, 1),
+            ('&', _) => (Symbol::Ampersand, 1),
+            ('|', _) => (Symbol::VerticalBar, 1),
+            ('%', _) => (Symbol::Percent, 1),
+            ('#', _) => (Symbol::Hash, 1),
+            (c, _) => {
+                self.offset += c.len_utf8();
+                return self.error(format!("Unexpected character `{}`", c), start);
+            }
+        };
+        self.offset += len;
+        Ok(TokenKind::Symbol(symbol))
     }
-}
 
-/// Tries to strip an integer from the input and, if succedes, returns
-/// the integer and the new string stripped of that integer
-fn strip_integer(input: &str) -> Option<(i16, &str)> {
-    // Try reading binary integer
-    let ret = strip_binary_integer(input);
-    if ret.is_some() {
-        ret
-    } else {
-        // Otherwise read a regular integer
-        strip_regular_integer(input)
+    /// Returns the next token, or `None` at the end of the code
+    fn next_token(&mut self) -> Option<Result<Token, CalError>> {
+        self.eat_while(self.offset, char::is_whitespace);
+        let start = self.offset;
+        let c = self.peek()?;
+        let value = if self.rest().starts_with("//") || self.rest().starts_with("/*") {
+            self.comment(start)
+        } else if c.is_alphabetic() || c == '_' {
+            Ok(self.word(start))
+        } else if c.is_ascii_digit() {
+            self.integer(start)
+        } else if c == '\'' {
+            self.character(start)
+        } else {
+            self.symbol(start)
+        };
+        Some(value.map(|value| Token::new(value, Range::new(start, self.offset))))
     }
 }
 
 /// This struct behaves like a peekable iterator of tokens with methods to _eat_
 /// tokens and effectively advance the iterator
+#[derive(Clone)]
 pub struct Tokens {
     tokens: Peekable<std::vec::IntoIter<Token>>,
+
+    /// Range of the last token returned by `next`
+    last_range: Range,
+
+    /// Comments are trivia, which the parser does not see
+    comments: Vec<Token>,
 }
 
 impl Tokens {
-    /// Converts a string into a vector of tokens
-    fn tokenize(code: &str) -> Result<Vec<Token>, CalError> {
+    /// Converts a string into a vector of tokens and a vector of comments
+    fn tokenize(code: &str) -> Result<(Vec<Token>, Vec<Token>), CalError> {
         let mut ret = vec![];
-
-        // Ignore white spaces at the beginning
-        let mut input = code.trim_start();
-
-        while !input.is_empty() {
-            // Ignore comments
-            if input.starts_with("//") {
-                if let Some((_, stripped_input)) = input.split_once('\n') {
-                    input = stripped_input;
-                } else {
-                    input = "";
-                }
-            } else if let Some((keyword, stripped_input)) = strip_keyword(input) {
-                // Check for keywords
-                input = stripped_input;
-                ret.push(Token::new(
-                    TokenKind::Keyword(keyword),
-                    Range::from_str(input, code),
-                ));
-            } else if let Some((symbol, stripped_input)) = strip_symbol(input) {
-                input = stripped_input;
-                ret.push(Token::new(
-                    TokenKind::Symbol(symbol),
-                    Range::from_str(input, code),
-                ));
-            } else if let Some((character, stripped_input)) = strip_character(input) {
-                input = stripped_input;
-                ret.push(Token::new(
-                    TokenKind::Char(character),
-                    Range::from_str(input, code),
-                ));
-            } else if let Some((identifier, stripped_input)) = strip_identifier(input) {
-                input = stripped_input;
-                ret.push(Token::new(
-                    TokenKind::Identifier(identifier.into()),
-                    Range::from_str(input, code),
-                ));
-            } else if let Some((integer, stripped_input)) = strip_integer(input) {
-                input = stripped_input;
-                ret.push(Token::new(
-                    TokenKind::Integer(integer),
-                    Range::from_str(input, code),
-                ));
+        let mut comments = vec![];
+        let mut lexer = Lexer::new(code);
+        while let Some(token) = lexer.next_token() {
+            let token = token?;
+            if let TokenKind::Comment(_) = token.value {
+                comments.push(token);
             } else {
-                return Err(CalError::new(
-                    format!("Failed to parse {}", input),
-                    Range::from_str(input, code),
-                ));
+                ret.push(token);
             }
-
-            // Ignore white spaces after stripping
-            input = input.trim_start();
         }
-
-        Ok(ret)
+        Ok((ret, comments))
     }
 
     pub fn new(input: &str) -> Result<Self, CalError> {
+        let (tokens, comments) = Self::tokenize(input)?;
         Ok(Self {
-            tokens: Self::tokenize(input)?.into_iter().peekable(),
+            tokens: tokens.into_iter().peekable(),
+            last_range: Range::default(),
+            comments,
         })
     }
 
+    /// Returns the comments found in the code, in order of appearance
+    pub fn comments(&self) -> &[Token] {
+        &self.comments
+    }
+
     /// Eats a keyword and advances to the next token
     pub fn eat_keyword(&mut self, keyword: Keyword) -> Result<(), CalError> {
-        if let Some(token) = self.tokens.next() {
+        if let Some(token) = self.next() {
             match &token.value {
                 TokenKind::Keyword(kw) if *kw == keyword => Ok(()),
                 _ => Err(CalError::new(
@@ -384,7 +432,7 @@ impl Tokens {
 
     /// Eats a symbol and advances to the next token
     pub fn eat_symbol(&mut self, symbol: Symbol) -> Result<(), CalError> {
-        if let Some(token) = self.tokens.next() {
+        if let Some(token) = self.next() {
             match &token.value {
                 TokenKind::Symbol(sym) if *sym == symbol => Ok(()),
                 _ => Err(CalError::new(
@@ -402,7 +450,7 @@ impl Tokens {
 
     /// Eats an identifier and advances to the next token
     pub fn eat_identifier(&mut self, ident: &str) -> Result<(), CalError> {
-        if let Some(token) = self.tokens.next() {
+        if let Some(token) = self.next() {
             match &token.value {
                 TokenKind::Identifier(id) if *id == ident => Ok(()),
                 _ => Err(CalError::new(
@@ -420,7 +468,7 @@ impl Tokens {
 
     /// Eats an integer and advances to the next token
     pub fn eat_integer(&mut self, int: i16) -> Result<(), CalError> {
-        if let Some(token) = self.tokens.next() {
+        if let Some(token) = self.next() {
             match &token.value {
                 TokenKind::Integer(i) if *i == int => Ok(()),
                 _ => Err(CalError::new(
@@ -438,7 +486,7 @@ impl Tokens {
 
     /// Eats a character and advances to the next token
     pub fn eat_character(&mut self, ch: char) -> Result<(), CalError> {
-        if let Some(token) = self.tokens.next() {
+        if let Some(token) = self.next() {
             match &token.value {
                 TokenKind::Char(c) if *c == ch => Ok(()),
                 _ => Err(CalError::new(
@@ -504,7 +552,24 @@ impl Tokens {
 
     /// Skips the next token
     pub fn skip(&mut self) {
-        self.tokens.next();
+        self.next();
+    }
+
+    /// Returns the next token, keeping track of its range. This shadows
+    /// `Peekable::next`, while the other methods of the iterator are still
+    /// reachable through `Deref`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.next();
+        if let Some(token) = &token {
+            self.last_range = token.range;
+        }
+        token
+    }
+
+    /// Returns the range of the last token consumed
+    pub fn last_range(&self) -> Range {
+        self.last_range
     }
 }
 
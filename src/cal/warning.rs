@@ -0,0 +1,450 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::CalError,
+    evaluator::Evaluator,
+    expression::{Expression, Literal, Operator, Term, UnaryOperator},
+    statement::Statement,
+    structure::{mangle, Constant, Function, Module, StructDec, Type, Variable},
+    symboltable::SymbolTable,
+    tokenizer::Range,
+};
+
+/// A warning does not prevent the compilation, unless warnings are denied
+#[derive(Clone, Debug)]
+pub struct CalWarning {
+    pub message: String,
+    pub range: Range,
+}
+
+impl CalWarning {
+    pub fn new(message: String, range: Range) -> Self {
+        Self { message, range }
+    }
+}
+
+impl From<CalWarning> for CalError {
+    fn from(warning: CalWarning) -> Self {
+        CalError::new(warning.message, warning.range)
+    }
+}
+
+/// A variable declared in the function being checked
+struct Local {
+    variable: Variable,
+    is_parameter: bool,
+    used: bool,
+    /// The block declaring it is still being checked, so that declaring the
+    /// same name shadows it
+    in_block: bool,
+}
+
+/// Walks a module looking for code which is valid but likely wrong
+#[derive(Default)]
+pub struct Linter {
+    constants: HashMap<String, Constant>,
+    functions: HashMap<String, Function>,
+    structs: HashMap<String, StructDec>,
+
+    /// Variables of the current function, in order of declaration
+    locals: Vec<Local>,
+    /// Same variables of `locals`, which shadow constants when evaluating
+    symbol_table: SymbolTable,
+
+    /// Functions and constants referred to by each function or constant.
+    /// Method calls are recorded as `.method`, as the type of the receiver
+    /// is not known at this point.
+    references: HashMap<String, HashSet<String>>,
+    /// Function or constant we are collecting references for
+    current_item: String,
+
+    warnings: Vec<CalWarning>,
+}
+
+impl Linter {
+    fn warn(&mut self, message: String, range: Range) {
+        self.warnings.push(CalWarning::new(message, range));
+    }
+
+    fn evaluator(&self) -> Evaluator<'_> {
+        Evaluator::new(
+            &self.constants,
+            &self.functions,
+            &self.structs,
+            Some(&self.symbol_table),
+        )
+    }
+
+    fn add_reference(&mut self, name: String) {
+        self.references
+            .entry(self.current_item.clone())
+            .or_default()
+            .insert(name);
+    }
+
+    fn declare(&mut self, variable: &Variable, is_parameter: bool) {
+        if self
+            .locals
+            .iter()
+            .any(|local| local.in_block && local.variable.name == variable.name)
+        {
+            self.warn(
+                format!("Variable `{}` shadows a previous variable", variable.name),
+                variable.range,
+            );
+        } else if self.constants.contains_key(&variable.name) {
+            self.warn(
+                format!("Variable `{}` shadows a constant", variable.name),
+                variable.range,
+            );
+        }
+        self.check_type(&variable.typ);
+        self.symbol_table.insert_local(variable, 1);
+        self.locals.push(Local {
+            variable: variable.clone(),
+            is_parameter,
+            used: false,
+            in_block: true,
+        });
+    }
+
+    /// Marks the last variable declared with that name as used, returning
+    /// whether it is a local variable
+    fn use_variable(&mut self, name: &str) -> bool {
+        match self
+            .locals
+            .iter_mut()
+            .rev()
+            .find(|local| local.variable.name == name)
+        {
+            Some(local) => {
+                local.used = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Array lengths are expressions which may refer to other items
+    fn check_type(&mut self, typ: &Type) {
+        match typ {
+            Type::Array(elem_type, _) | Type::Ref(elem_type) | Type::MutRef(elem_type) => {
+                self.check_type(elem_type)
+            }
+            Type::ArrayExpr(elem_type, count) => {
+                self.check_type(elem_type);
+                self.check_expression(count);
+            }
+            Type::Tuple(types) => types.iter().for_each(|typ| self.check_type(typ)),
+            _ => (),
+        }
+    }
+
+    fn check_term(&mut self, term: &Term) {
+        match term {
+            Term::Literal(_) => (),
+            Term::Variable(name) => {
+                if !self.use_variable(name) && self.constants.contains_key(name) {
+                    self.add_reference(name.clone());
+                }
+            }
+            Term::Call(name, expressions) => {
+                self.add_reference(name.clone());
+                expressions
+                    .iter()
+                    .for_each(|expr| self.check_expression(expr));
+            }
+            Term::MethodCall(receiver, method, expressions) => {
+                self.add_reference(mangle("", method));
+                self.check_term(receiver);
+                expressions
+                    .iter()
+                    .for_each(|expr| self.check_expression(expr));
+            }
+            Term::Index(base, index) => {
+                self.check_term(base);
+                self.check_expression(index);
+            }
+            Term::Field(base, _) | Term::UnaryOp(_, base) => self.check_term(base),
+            Term::Struct(_, initializers) => initializers
+                .iter()
+                .for_each(|(_, expr)| self.check_expression(expr)),
+            Term::Array(expressions) | Term::Tuple(expressions) => expressions
+                .iter()
+                .for_each(|expr| self.check_expression(expr)),
+            Term::Expression(expr) => self.check_expression(expr),
+            Term::SizeOf(typ) => self.check_type(typ),
+        }
+    }
+
+    fn check_expression(&mut self, expr: &Expression) {
+        match (expr.term.as_ref(), &expr.op_and_expr) {
+            (Term::Variable(name), Some((Operator::Assign, rhs))) => {
+                // Assigning a variable does not use it, unless it is a
+                // reference which is written through
+                let is_ref = self
+                    .locals
+                    .iter()
+                    .rev()
+                    .find(|local| local.variable.name == *name)
+                    .is_none_or(|local| local.variable.typ.get_pointee().is_some());
+                if is_ref {
+                    self.check_term(&expr.term);
+                }
+                self.check_expression(rhs);
+            }
+            (term, op_and_expr) => {
+                self.check_term(term);
+                if let Some((_, rhs)) = op_and_expr {
+                    self.check_expression(rhs);
+                }
+            }
+        }
+    }
+
+    /// Warns about a condition which is known at compile time
+    fn check_condition(&mut self, predicate: &Expression) {
+        if let Ok(value) = self.evaluator().eval_expression(predicate) {
+            self.warn(
+                format!("Condition is always {}", value != 0),
+                predicate.range,
+            );
+        }
+        self.check_expression(predicate);
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Expression(expr) | Statement::Return(Some(expr)) => {
+                self.check_expression(expr)
+            }
+            Statement::Return(None) => (),
+            Statement::Let(variable, expr) => {
+                self.check_expression(expr);
+                self.declare(variable, false);
+            }
+            Statement::LetTuple(variables, expr) => {
+                self.check_expression(expr);
+                let types = self.get_element_types(expr);
+                for (i, variable) in variables.iter().enumerate() {
+                    // Without a type annotation, take the one of the element
+                    // if known, so that writing through a reference uses it
+                    let mut variable = variable.clone();
+                    if let (Type::Void, Some(typ)) = (&variable.typ, types.get(i)) {
+                        variable.typ = typ.clone();
+                    }
+                    self.declare(&variable, false);
+                }
+            }
+            Statement::If(if_stat) => {
+                self.check_condition(&if_stat.predicate);
+                self.check_block(&if_stat.if_branch);
+                self.check_block(&if_stat.else_branch);
+            }
+            Statement::While(while_stat) => {
+                // `while true` is the way to write an infinite loop
+                let infinite = matches!(
+                    (
+                        while_stat.predicate.term.as_ref(),
+                        &while_stat.predicate.op_and_expr
+                    ),
+                    (Term::Literal(Literal::Bool(true)), None)
+                );
+                if infinite {
+                    self.check_expression(&while_stat.predicate);
+                } else {
+                    self.check_condition(&while_stat.predicate);
+                }
+                self.check_block(&while_stat.body);
+            }
+        }
+    }
+
+    /// Returns the types of the elements of a tuple, as far as they are known
+    /// without resolving the types of expressions
+    fn get_element_types(&self, expr: &Expression) -> Vec<Type> {
+        if expr.op_and_expr.is_some() {
+            return vec![];
+        }
+        match expr.term.as_ref() {
+            Term::Call(name, _) => match self.functions.get(name) {
+                Some(Function {
+                    return_type: Type::Tuple(types),
+                    ..
+                }) => types.clone(),
+                _ => vec![],
+            },
+            Term::Tuple(expressions) => expressions
+                .iter()
+                .map(|expr| match (expr.term.as_ref(), &expr.op_and_expr) {
+                    (Term::UnaryOp(UnaryOperator::Ref, _), None) => Type::Ref(Box::new(Type::Void)),
+                    (Term::UnaryOp(UnaryOperator::MutRef, _), None) => {
+                        Type::MutRef(Box::new(Type::Void))
+                    }
+                    _ => Type::Void,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Checks the statements of a nested block, after which the variables
+    /// it declares no longer clash with new ones of the same name
+    fn check_block(&mut self, statements: &[Statement]) {
+        let start = self.locals.len();
+        self.check_statements(statements);
+        for local in &mut self.locals[start..] {
+            local.in_block = false;
+        }
+    }
+
+    fn check_statements(&mut self, statements: &[Statement]) {
+        let mut returned = false;
+        for statement in statements {
+            if returned {
+                self.warn(
+                    "Unreachable statement".into(),
+                    get_statement_range(statement),
+                );
+                // Only the first unreachable statement is reported
+                returned = false;
+            }
+            self.check_statement(statement);
+            if matches!(statement, Statement::Return(_)) {
+                returned = true;
+            }
+        }
+    }
+
+    fn check_function(&mut self, function: &Function) {
+        self.current_item = function.name.clone();
+        self.locals.clear();
+        self.symbol_table = SymbolTable::default();
+
+        for parameter in &function.parameters {
+            self.declare(parameter, true);
+        }
+        self.check_type(&function.return_type);
+        self.check_statements(&function.body_statements);
+
+        let unused: Vec<_> = self
+            .locals
+            .iter()
+            .filter(|local| !local.used)
+            .filter(|local| local.variable.name != "self" && !local.variable.name.starts_with('_'))
+            .map(|local| {
+                let kind = if local.is_parameter {
+                    "parameter"
+                } else {
+                    "variable"
+                };
+                (
+                    format!("Unused {} `{}`", kind, local.variable.name),
+                    local.variable.range,
+                )
+            })
+            .collect();
+        for (message, range) in unused {
+            self.warn(message, range);
+        }
+    }
+
+    /// Warns about functions which are never called, directly or indirectly,
+    /// from `main`. Functions called to evaluate constants count as called.
+    fn check_reachability(&mut self, module: &Module) {
+        if !self.functions.contains_key("main") {
+            return;
+        }
+
+        let mut reached = HashSet::new();
+        // Array lengths of struct fields are evaluated whenever they are used
+        let mut to_visit: Vec<String> = self.structs.keys().cloned().collect();
+        to_visit.push(String::from("main"));
+        while let Some(item) = to_visit.pop() {
+            if !reached.insert(item.clone()) {
+                continue;
+            }
+            let Some(references) = self.references.get(&item) else {
+                continue;
+            };
+            for reference in references {
+                if reference.starts_with('.') {
+                    // Any method with that name could be called
+                    to_visit.extend(
+                        self.functions
+                            .keys()
+                            .filter(|name| name.ends_with(reference.as_str()))
+                            .cloned(),
+                    );
+                } else {
+                    to_visit.push(reference.clone());
+                }
+            }
+        }
+
+        for function in &module.functions {
+            if !reached.contains(&function.name) {
+                self.warn(
+                    format!("Function `{}` is never called from `main`", function.name),
+                    function.range,
+                );
+            }
+        }
+    }
+
+    /// Checks a module returning the warnings found
+    pub fn check(mut self, module: &Module) -> Vec<CalWarning> {
+        for constant in &module.constants {
+            self.constants
+                .insert(constant.name.clone(), constant.clone());
+        }
+        for struct_dec in &module.structs {
+            self.structs
+                .insert(struct_dec.name.clone(), struct_dec.clone());
+        }
+        for function in &module.functions {
+            self.functions
+                .insert(function.name.clone(), function.clone());
+        }
+
+        for constant in &module.constants {
+            self.current_item = constant.name.clone();
+            self.locals.clear();
+            self.check_type(&constant.typ);
+            self.check_expression(&constant.value);
+        }
+        for struct_dec in &module.structs {
+            self.current_item = struct_dec.name.clone();
+            for field in &struct_dec.fields {
+                self.check_type(&field.typ);
+            }
+        }
+        for function in &module.functions {
+            self.check_function(function);
+        }
+        self.check_reachability(module);
+
+        self.warnings
+    }
+}
+
+/// Returns the range of the source code of a statement
+fn get_statement_range(statement: &Statement) -> Range {
+    match statement {
+        Statement::Expression(expr) | Statement::Return(Some(expr)) => expr.range,
+        Statement::Return(None) => Range::default(),
+        Statement::Let(variable, _) => variable.range,
+        Statement::LetTuple(variables, expr) => variables.first().map_or(expr.range, |v| v.range),
+        Statement::If(if_stat) => if_stat.predicate.range,
+        Statement::While(while_stat) => while_stat.predicate.range,
+    }
+}
+
+/// Returns the warnings found in a module
+pub fn check(module: &Module) -> Vec<CalWarning> {
+    Linter::default().check(module)
+}
@@ -0,0 +1,70 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::{
+    asm::instruction::AsmInstruction,
+    jack::{error::JackError, generator::Generator, parser::parse, structure::Class},
+    preamble::builtins,
+    tokenizer::Range,
+    vm::instruction::VmInstruction,
+    VmTranslator,
+};
+
+/// A minimal operating system, providing the classes the generated code
+/// relies on, such as `Memory` for constructors and `String` for literals
+const OS: [&str; 5] = [
+    include_str!("os/Memory.jack"),
+    include_str!("os/Math.jack"),
+    include_str!("os/Array.jack"),
+    include_str!("os/String.jack"),
+    include_str!("os/Sys.jack"),
+];
+
+/// Compiles Jack sources into VM instructions, along with the classes of the
+/// operating system they do not define themselves. The program calls
+/// `Main.main` and then loops forever, just like a Cal program calls `main`.
+pub fn compile(sources: &[&str]) -> Result<Vec<VmInstruction>, JackError> {
+    let mut classes: Vec<Class> = vec![];
+    for source in sources {
+        for class in parse(source)? {
+            if classes.iter().any(|other| other.name == class.name) {
+                return Err(JackError::new(
+                    format!("Class `{}` is defined more than once", class.name),
+                    Range::default(),
+                ));
+            }
+            classes.push(class);
+        }
+    }
+    for os in OS {
+        for class in parse(os)? {
+            if classes.iter().all(|other| other.name != class.name) {
+                classes.push(class);
+            }
+        }
+    }
+
+    let mut ret = vec![
+        VmInstruction::Call("Main.main".into(), 0),
+        VmInstruction::Label("END".into()),
+        VmInstruction::Goto("END".into()),
+    ];
+    ret.extend(builtins());
+    let mut generator = Generator::new(&classes);
+    for class in &classes {
+        ret.extend(generator.gen_class(class)?);
+    }
+    Ok(ret)
+}
+
+pub trait Compile {
+    /// Compiles Jack source code and returns a series of asm instructions
+    fn compile(&self) -> Result<Vec<AsmInstruction>, JackError>;
+}
+
+impl Compile for [&str] {
+    fn compile(&self) -> Result<Vec<AsmInstruction>, JackError> {
+        Ok(VmTranslator::default().translate(compile(self)?))
+    }
+}
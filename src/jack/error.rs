@@ -0,0 +1,23 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::tokenizer::Range;
+
+#[derive(Clone, Debug)]
+pub struct JackError {
+    pub message: String,
+    pub range: Range,
+}
+
+impl JackError {
+    pub fn new(message: String, range: Range) -> Self {
+        Self { message, range }
+    }
+}
+
+impl From<String> for JackError {
+    fn from(message: String) -> Self {
+        Self::new(message, Range::default())
+    }
+}
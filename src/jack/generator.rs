@@ -0,0 +1,395 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+
+use crate::{
+    jack::{
+        error::JackError,
+        structure::{
+            Call, Class, Expression, Operator, Statement, Subroutine, SubroutineKind, Term, Type,
+            UnaryOperator, Variable,
+        },
+    },
+    segment::Segment,
+    tokenizer::Range,
+    vm::instruction::VmInstruction,
+};
+
+/// The static segment goes from `RAM[16]` to `RAM[255]`
+const STATIC_SIZE: u16 = 240;
+
+#[derive(Clone)]
+struct Symbol {
+    segment: Segment,
+    index: u16,
+    typ: Type,
+}
+
+/// Generates VM instructions from Jack classes, following the conventions of
+/// nand2tetris: objects are pointed to by `this`, arrays by `that`, and every
+/// subroutine returns one word, which is `0` for `void` subroutines.
+#[derive(Default)]
+pub struct Generator {
+    /// Kinds of the subroutines of all the classes, as `Class.subroutine`
+    subroutines: HashMap<String, SubroutineKind>,
+
+    /// Number of statics of the classes generated so far, as each class gets
+    /// its own part of the static segment
+    static_count: u16,
+
+    class_name: String,
+    /// Statics and fields of the current class
+    class_symbols: HashMap<String, Symbol>,
+    /// Parameters and locals of the current subroutine
+    symbols: HashMap<String, Symbol>,
+
+    /// Name of the current subroutine, used to generate unique labels
+    function_name: String,
+    subroutine_kind: Option<SubroutineKind>,
+    label_count: u32,
+}
+
+/// Inserts a variable in a symbol table, making sure it is not declared twice
+fn declare(
+    symbols: &mut HashMap<String, Symbol>,
+    variable: &Variable,
+    segment: Segment,
+    index: u16,
+) -> Result<(), JackError> {
+    let symbol = Symbol {
+        segment,
+        index,
+        typ: variable.typ.clone(),
+    };
+    if symbols.insert(variable.name.clone(), symbol).is_some() {
+        return Err(JackError::new(
+            format!("Variable `{}` is already declared", variable.name),
+            variable.range,
+        ));
+    }
+    Ok(())
+}
+
+impl Generator {
+    /// Creates a generator for a program made of these classes, which may
+    /// call each other
+    pub fn new(classes: &[Class]) -> Self {
+        let subroutines = classes
+            .iter()
+            .flat_map(|class| {
+                class.subroutines.iter().map(|subroutine| {
+                    (
+                        format!("{}.{}", class.name, subroutine.name),
+                        subroutine.kind,
+                    )
+                })
+            })
+            .collect();
+        Self {
+            subroutines,
+            ..Default::default()
+        }
+    }
+
+    fn next_label(&mut self, name: &str) -> String {
+        let ret = format!("{}.{}{}", self.function_name, name, self.label_count);
+        self.label_count += 1;
+        ret
+    }
+
+    fn get_symbol(&self, name: &str, range: Range) -> Result<&Symbol, JackError> {
+        let symbol = self
+            .symbols
+            .get(name)
+            .or_else(|| self.class_symbols.get(name))
+            .ok_or_else(|| JackError::new(format!("Undefined variable `{}`", name), range))?;
+        if symbol.segment == Segment::This && self.subroutine_kind == Some(SubroutineKind::Function)
+        {
+            return Err(JackError::new(
+                format!(
+                    "Cannot access field `{}` from function `{}`",
+                    name, self.function_name
+                ),
+                range,
+            ));
+        }
+        Ok(symbol)
+    }
+
+    fn gen_push_variable(&self, name: &str, range: Range) -> Result<VmInstruction, JackError> {
+        let symbol = self.get_symbol(name, range)?;
+        Ok(VmInstruction::Push(symbol.segment, symbol.index))
+    }
+
+    fn gen_call(&self, call: &Call) -> Result<Vec<VmInstruction>, JackError> {
+        let mut ret = vec![];
+        let (class_name, is_method) = match &call.receiver {
+            // A call without receiver refers to the current class
+            None => {
+                let function_name = format!("{}.{}", self.class_name, call.name);
+                let is_method =
+                    self.subroutines.get(&function_name) == Some(&SubroutineKind::Method);
+                if is_method {
+                    ret.push(VmInstruction::Push(Segment::Pointer, 0));
+                }
+                (self.class_name.clone(), is_method)
+            }
+            Some(receiver) => match self
+                .symbols
+                .get(receiver)
+                .or(self.class_symbols.get(receiver))
+            {
+                Some(Symbol {
+                    typ: Type::Class(class_name),
+                    ..
+                }) => {
+                    ret.push(self.gen_push_variable(receiver, call.range)?);
+                    (class_name.clone(), true)
+                }
+                Some(_) => {
+                    return Err(JackError::new(
+                        format!(
+                            "Cannot call `{}` on `{}`, which is not an object",
+                            call.name, receiver
+                        ),
+                        call.range,
+                    ))
+                }
+                None => (receiver.clone(), false),
+            },
+        };
+
+        let function_name = format!("{}.{}", class_name, call.name);
+        if !self.subroutines.contains_key(&function_name) {
+            return Err(JackError::new(
+                format!("Undefined subroutine `{}`", function_name),
+                call.range,
+            ));
+        }
+        for argument in &call.arguments {
+            ret.extend(self.gen_expression(argument)?);
+        }
+        let argument_count = call.arguments.len() as u16 + u16::from(is_method);
+        ret.push(VmInstruction::Call(function_name, argument_count));
+        Ok(ret)
+    }
+
+    fn gen_term(&self, term: &Term, range: Range) -> Result<Vec<VmInstruction>, JackError> {
+        let ret = match term {
+            Term::Integer(integer) => vec![VmInstruction::Push(Segment::Constant, *integer)],
+            Term::String(string) => {
+                // Strings are built one character at a time
+                let mut ret = vec![
+                    VmInstruction::Push(Segment::Constant, string.chars().count() as u16),
+                    VmInstruction::Call("String.new".into(), 1),
+                ];
+                for c in string.chars() {
+                    ret.push(VmInstruction::Push(Segment::Constant, c as u16));
+                    ret.push(VmInstruction::Call("String.appendChar".into(), 2));
+                }
+                ret
+            }
+            Term::True => vec![
+                VmInstruction::Push(Segment::Constant, 0),
+                VmInstruction::Not,
+            ],
+            Term::False | Term::Null => vec![VmInstruction::Push(Segment::Constant, 0)],
+            Term::This => vec![VmInstruction::Push(Segment::Pointer, 0)],
+            Term::Variable(name) => vec![self.gen_push_variable(name, range)?],
+            Term::Index(name, index) => {
+                let mut ret = vec![self.gen_push_variable(name, range)?];
+                ret.extend(self.gen_expression(index)?);
+                ret.extend([
+                    VmInstruction::Add,
+                    VmInstruction::Pop(Segment::Pointer, 1),
+                    VmInstruction::Push(Segment::That, 0),
+                ]);
+                ret
+            }
+            Term::Call(call) => self.gen_call(call)?,
+            Term::Expression(expression) => self.gen_expression(expression)?,
+            Term::UnaryOp(op, term) => {
+                let mut ret = self.gen_term(term, range)?;
+                ret.push(match op {
+                    UnaryOperator::Neg => VmInstruction::Neg,
+                    UnaryOperator::Not => VmInstruction::Not,
+                });
+                ret
+            }
+        };
+        Ok(ret)
+    }
+
+    pub fn gen_expression(&self, expression: &Expression) -> Result<Vec<VmInstruction>, JackError> {
+        let mut ret = self.gen_term(&expression.term, expression.range)?;
+        for (op, term) in &expression.ops {
+            ret.extend(self.gen_term(term, expression.range)?);
+            ret.push(match op {
+                Operator::Add => VmInstruction::Add,
+                Operator::Sub => VmInstruction::Sub,
+                // Multiplication and division are built-in functions
+                Operator::Mul => VmInstruction::Call("mul".into(), 2),
+                Operator::Div => VmInstruction::Call("div".into(), 2),
+                Operator::And => VmInstruction::And,
+                Operator::Or => VmInstruction::Or,
+                Operator::Lt => VmInstruction::Lt,
+                Operator::Gt => VmInstruction::Gt,
+                Operator::Eq => VmInstruction::Eq,
+            });
+        }
+        Ok(ret)
+    }
+
+    fn gen_statements(
+        &mut self,
+        statements: &[Statement],
+    ) -> Result<Vec<VmInstruction>, JackError> {
+        let mut ret = vec![];
+        for statement in statements {
+            ret.extend(self.gen_statement(statement)?);
+        }
+        Ok(ret)
+    }
+
+    pub fn gen_statement(
+        &mut self,
+        statement: &Statement,
+    ) -> Result<Vec<VmInstruction>, JackError> {
+        let mut ret = vec![];
+        match statement {
+            Statement::Let(name, None, value, range) => {
+                let symbol = self.get_symbol(name, *range)?;
+                let pop = VmInstruction::Pop(symbol.segment, symbol.index);
+                ret.extend(self.gen_expression(value)?);
+                ret.push(pop);
+            }
+            Statement::Let(name, Some(index), value, range) => {
+                ret.push(self.gen_push_variable(name, *range)?);
+                ret.extend(self.gen_expression(index)?);
+                ret.push(VmInstruction::Add);
+                // The value may use `that` as well, so the address is set after
+                ret.extend(self.gen_expression(value)?);
+                ret.extend([
+                    VmInstruction::Pop(Segment::Temp, 0),
+                    VmInstruction::Pop(Segment::Pointer, 1),
+                    VmInstruction::Push(Segment::Temp, 0),
+                    VmInstruction::Pop(Segment::That, 0),
+                ]);
+            }
+            Statement::If(condition, if_branch, else_branch) => {
+                let else_label = self.next_label("IF_ELSE");
+                let end_label = self.next_label("IF_END");
+                ret.extend(self.gen_expression(condition)?);
+                ret.push(VmInstruction::Not);
+                ret.push(VmInstruction::IfGoto(else_label.clone()));
+                ret.extend(self.gen_statements(if_branch)?);
+                ret.push(VmInstruction::Goto(end_label.clone()));
+                ret.push(VmInstruction::Label(else_label));
+                ret.extend(self.gen_statements(else_branch)?);
+                ret.push(VmInstruction::Label(end_label));
+            }
+            Statement::While(condition, body) => {
+                let while_label = self.next_label("WHILE");
+                let end_label = self.next_label("WHILE_END");
+                ret.push(VmInstruction::Label(while_label.clone()));
+                ret.extend(self.gen_expression(condition)?);
+                ret.push(VmInstruction::Not);
+                ret.push(VmInstruction::IfGoto(end_label.clone()));
+                ret.extend(self.gen_statements(body)?);
+                ret.push(VmInstruction::Goto(while_label));
+                ret.push(VmInstruction::Label(end_label));
+            }
+            Statement::Do(call) => {
+                // The returned value is discarded
+                ret.extend(self.gen_call(call)?);
+                ret.push(VmInstruction::Pop(Segment::Temp, 0));
+            }
+            Statement::Return(value) => {
+                match value {
+                    Some(value) => ret.extend(self.gen_expression(value)?),
+                    None => ret.push(VmInstruction::Push(Segment::Constant, 0)),
+                }
+                ret.push(VmInstruction::Return(1));
+            }
+        }
+        Ok(ret)
+    }
+
+    fn gen_subroutine(
+        &mut self,
+        subroutine: &Subroutine,
+        field_count: u16,
+    ) -> Result<Vec<VmInstruction>, JackError> {
+        self.function_name = format!("{}.{}", self.class_name, subroutine.name);
+        self.subroutine_kind = Some(subroutine.kind);
+        self.label_count = 0;
+
+        // The object a method is called on is its first argument
+        self.symbols.clear();
+        let first_argument = u16::from(subroutine.kind == SubroutineKind::Method);
+        for (i, parameter) in subroutine.parameters.iter().enumerate() {
+            let index = first_argument + i as u16;
+            declare(&mut self.symbols, parameter, Segment::Argument, index)?;
+        }
+        for (i, local) in subroutine.locals.iter().enumerate() {
+            declare(&mut self.symbols, local, Segment::Local, i as u16)?;
+        }
+
+        let mut ret = vec![VmInstruction::Function(
+            self.function_name.clone(),
+            subroutine.locals.len() as u16,
+        )];
+        match subroutine.kind {
+            SubroutineKind::Constructor => ret.extend([
+                VmInstruction::Push(Segment::Constant, field_count),
+                VmInstruction::Call("Memory.alloc".into(), 1),
+                VmInstruction::Pop(Segment::Pointer, 0),
+            ]),
+            SubroutineKind::Method => ret.extend([
+                VmInstruction::Push(Segment::Argument, 0),
+                VmInstruction::Pop(Segment::Pointer, 0),
+            ]),
+            SubroutineKind::Function => (),
+        }
+        ret.extend(self.gen_statements(&subroutine.statements)?);
+
+        // Add a return if missing
+        if !matches!(ret.last(), Some(VmInstruction::Return(_))) {
+            ret.push(VmInstruction::Push(Segment::Constant, 0));
+            ret.push(VmInstruction::Return(1));
+        }
+        Ok(ret)
+    }
+
+    /// Generates VM instructions for all the subroutines of a class
+    pub fn gen_class(&mut self, class: &Class) -> Result<Vec<VmInstruction>, JackError> {
+        self.class_name = class.name.clone();
+        self.class_symbols.clear();
+        for variable in &class.statics {
+            if self.static_count == STATIC_SIZE {
+                return Err(JackError::new(
+                    "Too many static variables".into(),
+                    variable.range,
+                ));
+            }
+            declare(
+                &mut self.class_symbols,
+                variable,
+                Segment::Static,
+                self.static_count,
+            )?;
+            self.static_count += 1;
+        }
+        for (i, variable) in class.fields.iter().enumerate() {
+            declare(&mut self.class_symbols, variable, Segment::This, i as u16)?;
+        }
+
+        let mut ret = vec![];
+        for subroutine in &class.subroutines {
+            ret.extend(self.gen_subroutine(subroutine, class.fields.len() as u16)?);
+        }
+        Ok(ret)
+    }
+}
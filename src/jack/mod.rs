@@ -0,0 +1,16 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! A frontend for Jack, the language of nand2tetris, targeting the same VM of Cal
+
+pub mod error;
+
+pub mod tokenizer;
+
+pub mod parser;
+pub mod structure;
+
+pub mod generator;
+
+pub mod compiler;
@@ -0,0 +1,392 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::{
+    jack::{
+        error::JackError,
+        structure::{
+            Call, Class, Expression, Operator, Statement, Subroutine, SubroutineKind, Term, Type,
+            UnaryOperator, Variable,
+        },
+        tokenizer::{tokenize, Keyword, Token, TokenKind},
+    },
+    tokenizer::Range,
+};
+
+/// A recursive descent parser for Jack classes
+pub struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self { tokens, index: 0 }
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.index).map(|token| &token.value)
+    }
+
+    fn peek_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&TokenKind::Symbol(symbol))
+    }
+
+    fn peek_keyword(&self, keyword: Keyword) -> bool {
+        self.peek() == Some(&TokenKind::Keyword(keyword))
+    }
+
+    /// Range of the next token, or an empty range after the last one
+    fn next_range(&self) -> Range {
+        match self.tokens.get(self.index) {
+            Some(token) => token.range,
+            None => {
+                let end = self.tokens.last().map_or(0, |token| token.range.end);
+                Range::new(end, end)
+            }
+        }
+    }
+
+    /// Range of the last token consumed
+    fn last_range(&self) -> Range {
+        self.index
+            .checked_sub(1)
+            .map_or_else(Range::default, |i| self.tokens[i].range)
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T, JackError> {
+        let found = match self.peek() {
+            Some(token) => format!("`{}`", token),
+            None => "end of file".into(),
+        };
+        Err(JackError::new(
+            format!("Expected {}, found {}", expected, found),
+            self.next_range(),
+        ))
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> Result<(), JackError> {
+        if !self.peek_symbol(symbol) {
+            return self.error(&format!("`{}`", symbol));
+        }
+        self.index += 1;
+        Ok(())
+    }
+
+    fn eat_keyword(&mut self, keyword: Keyword) -> Result<(), JackError> {
+        if !self.peek_keyword(keyword) {
+            return self.error(&format!("`{}`", TokenKind::Keyword(keyword)));
+        }
+        self.index += 1;
+        Ok(())
+    }
+
+    fn eat_identifier(&mut self) -> Result<String, JackError> {
+        let Some(TokenKind::Identifier(name)) = self.peek() else {
+            return self.error("identifier");
+        };
+        let name = name.clone();
+        self.index += 1;
+        Ok(name)
+    }
+
+    fn parse_type(&mut self) -> Result<Type, JackError> {
+        let typ = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Int)) => Type::Int,
+            Some(TokenKind::Keyword(Keyword::Char)) => Type::Char,
+            Some(TokenKind::Keyword(Keyword::Boolean)) => Type::Boolean,
+            Some(TokenKind::Identifier(name)) => Type::Class(name.clone()),
+            _ => return self.error("type"),
+        };
+        self.index += 1;
+        Ok(typ)
+    }
+
+    /// Parses `type name (, name)* ;`
+    fn parse_variables(&mut self) -> Result<Vec<Variable>, JackError> {
+        let typ = self.parse_type()?;
+        let mut ret = vec![];
+        loop {
+            let name = self.eat_identifier()?;
+            ret.push(Variable::new(name, typ.clone(), self.last_range()));
+            if !self.peek_symbol(',') {
+                break;
+            }
+            self.eat_symbol(',')?;
+        }
+        self.eat_symbol(';')?;
+        Ok(ret)
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Expression>, JackError> {
+        self.eat_symbol('(')?;
+        let mut ret = vec![];
+        if !self.peek_symbol(')') {
+            loop {
+                ret.push(self.parse_expression()?);
+                if !self.peek_symbol(',') {
+                    break;
+                }
+                self.eat_symbol(',')?;
+            }
+        }
+        self.eat_symbol(')')?;
+        Ok(ret)
+    }
+
+    /// Parses a call, whose first identifier is already consumed
+    fn parse_call(&mut self, first: String, start: usize) -> Result<Call, JackError> {
+        let (receiver, name) = if self.peek_symbol('.') {
+            self.eat_symbol('.')?;
+            (Some(first), self.eat_identifier()?)
+        } else {
+            (None, first)
+        };
+        let arguments = self.parse_arguments()?;
+        Ok(Call {
+            receiver,
+            name,
+            arguments,
+            range: Range::new(start, self.last_range().end),
+        })
+    }
+
+    fn parse_term(&mut self) -> Result<Term, JackError> {
+        let start = self.next_range().start;
+        let Some(token) = self.peek().cloned() else {
+            return self.error("term");
+        };
+        self.index += 1;
+        let term = match token {
+            TokenKind::Integer(integer) => Term::Integer(integer),
+            TokenKind::String(string) => Term::String(string),
+            TokenKind::Keyword(Keyword::True) => Term::True,
+            TokenKind::Keyword(Keyword::False) => Term::False,
+            TokenKind::Keyword(Keyword::Null) => Term::Null,
+            TokenKind::Keyword(Keyword::This) => Term::This,
+            TokenKind::Symbol('(') => {
+                let expression = self.parse_expression()?;
+                self.eat_symbol(')')?;
+                Term::Expression(Box::new(expression))
+            }
+            TokenKind::Symbol('-') => {
+                Term::UnaryOp(UnaryOperator::Neg, Box::new(self.parse_term()?))
+            }
+            TokenKind::Symbol('~') => {
+                Term::UnaryOp(UnaryOperator::Not, Box::new(self.parse_term()?))
+            }
+            TokenKind::Identifier(name) => {
+                if self.peek_symbol('[') {
+                    self.eat_symbol('[')?;
+                    let index = self.parse_expression()?;
+                    self.eat_symbol(']')?;
+                    Term::Index(name, Box::new(index))
+                } else if self.peek_symbol('(') || self.peek_symbol('.') {
+                    Term::Call(self.parse_call(name, start)?)
+                } else {
+                    Term::Variable(name)
+                }
+            }
+            _ => {
+                self.index -= 1;
+                return self.error("term");
+            }
+        };
+        Ok(term)
+    }
+
+    pub fn parse_expression(&mut self) -> Result<Expression, JackError> {
+        let start = self.next_range().start;
+        let term = self.parse_term()?;
+        let mut ops = vec![];
+        while let Some(TokenKind::Symbol(symbol)) = self.peek() {
+            let Some(op) = Operator::from_symbol(*symbol) else {
+                break;
+            };
+            self.index += 1;
+            ops.push((op, self.parse_term()?));
+        }
+        Ok(Expression {
+            term,
+            ops,
+            range: Range::new(start, self.last_range().end),
+        })
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Statement>, JackError> {
+        self.eat_symbol('{')?;
+        let mut ret = vec![];
+        while !self.peek_symbol('}') {
+            ret.push(self.parse_statement()?);
+        }
+        self.eat_symbol('}')?;
+        Ok(ret)
+    }
+
+    /// Parses `(expression)`
+    fn parse_condition(&mut self) -> Result<Expression, JackError> {
+        self.eat_symbol('(')?;
+        let ret = self.parse_expression()?;
+        self.eat_symbol(')')?;
+        Ok(ret)
+    }
+
+    pub fn parse_statement(&mut self) -> Result<Statement, JackError> {
+        match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Let)) => {
+                self.eat_keyword(Keyword::Let)?;
+                let name = self.eat_identifier()?;
+                let range = self.last_range();
+                let index = if self.peek_symbol('[') {
+                    self.eat_symbol('[')?;
+                    let index = self.parse_expression()?;
+                    self.eat_symbol(']')?;
+                    Some(index)
+                } else {
+                    None
+                };
+                self.eat_symbol('=')?;
+                let value = self.parse_expression()?;
+                self.eat_symbol(';')?;
+                Ok(Statement::Let(name, index, value, range))
+            }
+            Some(TokenKind::Keyword(Keyword::If)) => {
+                self.eat_keyword(Keyword::If)?;
+                let condition = self.parse_condition()?;
+                let if_branch = self.parse_block()?;
+                let else_branch = if self.peek_keyword(Keyword::Else) {
+                    self.eat_keyword(Keyword::Else)?;
+                    self.parse_block()?
+                } else {
+                    vec![]
+                };
+                Ok(Statement::If(condition, if_branch, else_branch))
+            }
+            Some(TokenKind::Keyword(Keyword::While)) => {
+                self.eat_keyword(Keyword::While)?;
+                let condition = self.parse_condition()?;
+                Ok(Statement::While(condition, self.parse_block()?))
+            }
+            Some(TokenKind::Keyword(Keyword::Do)) => {
+                self.eat_keyword(Keyword::Do)?;
+                let start = self.next_range().start;
+                let first = self.eat_identifier()?;
+                let call = self.parse_call(first, start)?;
+                self.eat_symbol(';')?;
+                Ok(Statement::Do(call))
+            }
+            Some(TokenKind::Keyword(Keyword::Return)) => {
+                self.eat_keyword(Keyword::Return)?;
+                let value = if self.peek_symbol(';') {
+                    None
+                } else {
+                    Some(self.parse_expression()?)
+                };
+                self.eat_symbol(';')?;
+                Ok(Statement::Return(value))
+            }
+            _ => self.error("statement"),
+        }
+    }
+
+    fn parse_subroutine(&mut self) -> Result<Subroutine, JackError> {
+        let kind = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Constructor)) => SubroutineKind::Constructor,
+            Some(TokenKind::Keyword(Keyword::Function)) => SubroutineKind::Function,
+            Some(TokenKind::Keyword(Keyword::Method)) => SubroutineKind::Method,
+            _ => return self.error("subroutine"),
+        };
+        self.index += 1;
+        let return_type = if self.peek_keyword(Keyword::Void) {
+            self.eat_keyword(Keyword::Void)?;
+            None
+        } else {
+            Some(self.parse_type()?)
+        };
+        let name = self.eat_identifier()?;
+
+        self.eat_symbol('(')?;
+        let mut parameters = vec![];
+        if !self.peek_symbol(')') {
+            loop {
+                let typ = self.parse_type()?;
+                let name = self.eat_identifier()?;
+                parameters.push(Variable::new(name, typ, self.last_range()));
+                if !self.peek_symbol(',') {
+                    break;
+                }
+                self.eat_symbol(',')?;
+            }
+        }
+        self.eat_symbol(')')?;
+
+        self.eat_symbol('{')?;
+        let mut locals = vec![];
+        while self.peek_keyword(Keyword::Var) {
+            self.eat_keyword(Keyword::Var)?;
+            locals.extend(self.parse_variables()?);
+        }
+        let mut statements = vec![];
+        while !self.peek_symbol('}') {
+            statements.push(self.parse_statement()?);
+        }
+        self.eat_symbol('}')?;
+
+        Ok(Subroutine {
+            kind,
+            return_type,
+            name,
+            parameters,
+            locals,
+            statements,
+        })
+    }
+
+    pub fn parse_class(&mut self) -> Result<Class, JackError> {
+        self.eat_keyword(Keyword::Class)?;
+        let name = self.eat_identifier()?;
+        self.eat_symbol('{')?;
+
+        let mut statics = vec![];
+        let mut fields = vec![];
+        loop {
+            if self.peek_keyword(Keyword::Static) {
+                self.eat_keyword(Keyword::Static)?;
+                statics.extend(self.parse_variables()?);
+            } else if self.peek_keyword(Keyword::Field) {
+                self.eat_keyword(Keyword::Field)?;
+                fields.extend(self.parse_variables()?);
+            } else {
+                break;
+            }
+        }
+
+        let mut subroutines = vec![];
+        while !self.peek_symbol('}') {
+            subroutines.push(self.parse_subroutine()?);
+        }
+        self.eat_symbol('}')?;
+
+        Ok(Class {
+            name,
+            statics,
+            fields,
+            subroutines,
+        })
+    }
+
+    /// Parses all the classes found in the tokens
+    pub fn parse_classes(&mut self) -> Result<Vec<Class>, JackError> {
+        let mut ret = vec![];
+        while self.peek().is_some() {
+            ret.push(self.parse_class()?);
+        }
+        Ok(ret)
+    }
+}
+
+/// Parses Jack code, which usually contains one class per file
+pub fn parse(code: &str) -> Result<Vec<Class>, JackError> {
+    Parser::new(tokenize(code)?).parse_classes()
+}
@@ -0,0 +1,144 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::tokenizer::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    /// Objects are references to instances of a class
+    Class(String),
+}
+
+/// A variable with its type, and where it is declared
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    pub typ: Type,
+    pub range: Range,
+}
+
+impl Variable {
+    pub fn new(name: String, typ: Type, range: Range) -> Self {
+        Self { name, typ, range }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operator {
+    /// `+`
+    Add,
+    /// `-`
+    Sub,
+    /// `*`
+    Mul,
+    /// `/`
+    Div,
+    /// `&`
+    And,
+    /// `|`
+    Or,
+    /// `<`
+    Lt,
+    /// `>`
+    Gt,
+    /// `=`
+    Eq,
+}
+
+impl Operator {
+    pub fn from_symbol(symbol: char) -> Option<Self> {
+        match symbol {
+            '+' => Some(Self::Add),
+            '-' => Some(Self::Sub),
+            '*' => Some(Self::Mul),
+            '/' => Some(Self::Div),
+            '&' => Some(Self::And),
+            '|' => Some(Self::Or),
+            '<' => Some(Self::Lt),
+            '>' => Some(Self::Gt),
+            '=' => Some(Self::Eq),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnaryOperator {
+    /// `-`
+    Neg,
+    /// `~`
+    Not,
+}
+
+/// Calls are either `function(..)`, which is a method of the current class,
+/// or `receiver.function(..)`, where the receiver is a variable or a class
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Call {
+    pub receiver: Option<String>,
+    pub name: String,
+    pub arguments: Vec<Expression>,
+    pub range: Range,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    Integer(u16),
+    String(String),
+    True,
+    False,
+    Null,
+    This,
+    Variable(String),
+    Index(String, Box<Expression>),
+    Call(Call),
+    Expression(Box<Expression>),
+    UnaryOp(UnaryOperator, Box<Term>),
+}
+
+/// Operators have no precedence in Jack, they are applied from left to right
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expression {
+    pub term: Term,
+    pub ops: Vec<(Operator, Term)>,
+    pub range: Range,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Statement {
+    /// Assigns a variable, or an element of an array when the index is present
+    Let(String, Option<Expression>, Expression, Range),
+    If(Expression, Vec<Statement>, Vec<Statement>),
+    While(Expression, Vec<Statement>),
+    Do(Call),
+    Return(Option<Expression>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subroutine {
+    pub kind: SubroutineKind,
+    /// `None` for `void` subroutines
+    pub return_type: Option<Type>,
+    pub name: String,
+    pub parameters: Vec<Variable>,
+    pub locals: Vec<Variable>,
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Class {
+    pub name: String,
+    pub statics: Vec<Variable>,
+    pub fields: Vec<Variable>,
+    pub subroutines: Vec<Subroutine>,
+}
@@ -0,0 +1,175 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+
+use crate::{jack::error::JackError, tokenizer::Range};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Keyword {
+    Class,
+    Constructor,
+    Function,
+    Method,
+    Field,
+    Static,
+    Var,
+    Int,
+    Char,
+    Boolean,
+    Void,
+    True,
+    False,
+    Null,
+    This,
+    Let,
+    Do,
+    If,
+    Else,
+    While,
+    Return,
+}
+
+impl Keyword {
+    pub const MAP: [(&'static str, Keyword); 21] = [
+        ("class", Keyword::Class),
+        ("constructor", Keyword::Constructor),
+        ("function", Keyword::Function),
+        ("method", Keyword::Method),
+        ("field", Keyword::Field),
+        ("static", Keyword::Static),
+        ("var", Keyword::Var),
+        ("int", Keyword::Int),
+        ("char", Keyword::Char),
+        ("boolean", Keyword::Boolean),
+        ("void", Keyword::Void),
+        ("true", Keyword::True),
+        ("false", Keyword::False),
+        ("null", Keyword::Null),
+        ("this", Keyword::This),
+        ("let", Keyword::Let),
+        ("do", Keyword::Do),
+        ("if", Keyword::If),
+        ("else", Keyword::Else),
+        ("while", Keyword::While),
+        ("return", Keyword::Return),
+    ];
+}
+
+/// Jack symbols are all single characters
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Symbol(char),
+    /// An integer constant, from `0` to `32767`
+    Integer(u16),
+    /// A string constant, without the double quotes
+    String(String),
+    Identifier(String),
+}
+
+/// Tokens are displayed as they are written in Jack
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Keyword(keyword) => {
+                let (name, _) = Keyword::MAP.iter().find(|(_, k)| k == keyword).unwrap();
+                write!(f, "{}", name)
+            }
+            TokenKind::Symbol(symbol) => write!(f, "{}", symbol),
+            TokenKind::Integer(integer) => write!(f, "{}", integer),
+            TokenKind::String(string) => write!(f, "\"{}\"", string),
+            TokenKind::Identifier(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub value: TokenKind,
+    pub range: Range,
+}
+
+impl Token {
+    pub fn new(value: TokenKind, range: Range) -> Self {
+        Self { value, range }
+    }
+}
+
+/// Transforms Jack code into a series of tokens, skipping whitespace and
+/// comments, which can be `// line`, `/* block */` or `/** doc */`
+pub fn tokenize(code: &str) -> Result<Vec<Token>, JackError> {
+    let mut ret = vec![];
+    let mut start = 0;
+    while start < code.len() {
+        let rest = &code[start..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            start += c.len_utf8();
+            continue;
+        }
+        if rest.starts_with("//") {
+            start += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        }
+        if let Some(comment) = rest.strip_prefix("/*") {
+            let Some(end) = comment.find("*/") else {
+                return Err(JackError::new(
+                    "Unterminated comment".into(),
+                    Range::new(start, code.len()),
+                ));
+            };
+            start += end + 4;
+            continue;
+        }
+
+        let (value, len) = if SYMBOLS.contains(c) {
+            (TokenKind::Symbol(c), 1)
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            match rest[..len].parse::<u16>() {
+                Ok(integer) if integer <= i16::MAX as u16 => (TokenKind::Integer(integer), len),
+                _ => {
+                    return Err(JackError::new(
+                        format!("Integer `{}` is out of range", &rest[..len]),
+                        Range::new(start, start + len),
+                    ))
+                }
+            }
+        } else if c == '"' {
+            let Some(len) = rest[1..]
+                .find(['"', '\n'])
+                .filter(|&i| rest.as_bytes()[i + 1] == b'"')
+            else {
+                return Err(JackError::new(
+                    "Unterminated string".into(),
+                    Range::new(start, start + rest.find('\n').unwrap_or(rest.len())),
+                ));
+            };
+            (TokenKind::String(rest[1..len + 1].into()), len + 2)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let value = match Keyword::MAP.iter().find(|(name, _)| *name == word) {
+                Some((_, keyword)) => TokenKind::Keyword(*keyword),
+                None => TokenKind::Identifier(word.into()),
+            };
+            (value, len)
+        } else {
+            return Err(JackError::new(
+                format!("Unexpected character `{}`", c),
+                Range::new(start, start + c.len_utf8()),
+            ));
+        };
+        ret.push(Token::new(value, Range::new(start, start + len)));
+        start += len;
+    }
+    Ok(ret)
+}
@@ -17,3 +17,4 @@ pub mod vm;
 pub use vm::*;
 pub mod cal;
 pub use cal::*;
+pub mod jack;
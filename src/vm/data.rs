@@ -0,0 +1,71 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+/// A run of consecutive words with the same value
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Run {
+    pub address: u16,
+    pub value: i16,
+    pub count: u16,
+}
+
+/// The data section holds the initial value of statics. It is laid out in the
+/// RAM above the stack, and it is initialized at boot time, before calling
+/// the entry point of the program. The Jack heap starts at its end, so the two
+/// never overlap.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DataSection {
+    words: Vec<i16>,
+}
+
+impl DataSection {
+    /// Address of the first word, just above the stack
+    pub const BASE: u16 = 2048;
+
+    /// Address past the last word, where the Jack heap starts
+    pub const END: u16 = 4096;
+
+    /// Number of words reserved for statics
+    pub const CAPACITY: usize = (Self::END - Self::BASE) as usize;
+
+    /// Appends some words to the section, returning their address, or `None`
+    /// when they do not fit, so that the section never exceeds its capacity
+    pub fn alloc(&mut self, words: &[i16]) -> Option<u16> {
+        if self.words.len() + words.len() > Self::CAPACITY {
+            return None;
+        }
+        let address = Self::BASE + self.words.len() as u16;
+        self.words.extend_from_slice(words);
+        Some(address)
+    }
+
+    pub fn get_words(&self) -> &[i16] {
+        &self.words
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Returns the words of the section as runs of equal values, skipping
+    /// zeros, which is what the memory is initialized with
+    pub fn runs(&self) -> Vec<Run> {
+        let mut ret: Vec<Run> = vec![];
+        for (i, &value) in self.words.iter().enumerate() {
+            let address = Self::BASE + i as u16;
+            match ret.last_mut() {
+                Some(run) if run.value == value && run.address + run.count == address => {
+                    run.count += 1
+                }
+                _ if value == 0 => (),
+                _ => ret.push(Run {
+                    address,
+                    value,
+                    count: 1,
+                }),
+            }
+        }
+        ret
+    }
+}
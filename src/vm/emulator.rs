@@ -5,8 +5,9 @@
 use std::collections::HashMap;
 
 use crate::{
+    console::{Console, SCREEN_SIZE},
     mem::fast::Ram16k,
-    vm::{instruction::VmInstruction, segment::Segment},
+    vm::{data::DataSection, instruction::VmInstruction, segment::Segment},
     Signal16,
 };
 
@@ -19,6 +20,9 @@ pub struct VmEmulator {
 
     pub ram: Ram16k,
 
+    /// Words of the screen memory map, which the console builtins draw on
+    pub screen: Vec<i16>,
+
     /// The symbol table maps labels (and function names) to their indices in
     /// the code so that we can jump to them when needed
     symbol_table: HashMap<String, usize>,
@@ -33,6 +37,7 @@ impl Default for VmEmulator {
             instructions: Default::default(),
             instruction_index: 0,
             ram,
+            screen: vec![0; SCREEN_SIZE],
             symbol_table: Default::default(),
         }
     }
@@ -54,6 +59,18 @@ impl VmEmulator {
         }
     }
 
+    /// Writes a data section into memory, as the translated code does at boot
+    pub fn load_data(&mut self, data: &DataSection) {
+        for (i, word) in data.get_words().iter().enumerate() {
+            self.ram[DataSection::BASE as usize + i] = *word;
+        }
+    }
+
+    /// Index of the next instruction to execute
+    pub fn get_instruction_index(&self) -> usize {
+        self.instruction_index
+    }
+
     pub fn get_segment_address(&self, segment: Segment) -> i16 {
         match segment {
             Segment::Pointer | Segment::Static | Segment::Temp => {
@@ -112,6 +129,43 @@ impl VmEmulator {
         self.ram.data[segment_address as usize] = element;
     }
 
+    /// Carries out a method of the `Console` struct of the `console` library
+    /// on the screen of the emulator, instead of running its code. Arguments
+    /// are on the stack, the first one being the address of the console.
+    /// Returns whether `function` is one of these builtins
+    fn console_builtin(&mut self, function: &str, arg_count: u16) -> bool {
+        if !matches!(
+            function,
+            "Console.print_char" | "Console.print_str" | "Console.print_int" | "Console.newline"
+        ) {
+            return false;
+        }
+        let stack_pointer = self.ram[Segment::Stack.get_base_address()] as usize;
+        let arguments: Vec<i16> = (stack_pointer - arg_count as usize..stack_pointer)
+            .map(|address| self.ram[address])
+            .collect();
+        let address = arguments[0] as usize;
+        let mut console = Console {
+            row: self.ram[address],
+            column: self.ram[address + 1],
+        };
+        match function {
+            "Console.print_char" => console.print_char(&mut self.screen, arguments[1]),
+            "Console.print_str" => {
+                let text: Vec<i16> = (0..arguments[2] as usize)
+                    .map(|i| self.ram[arguments[1] as usize + i])
+                    .collect();
+                console.print_str(&mut self.screen, &text);
+            }
+            "Console.print_int" => console.print_int(&mut self.screen, arguments[1]),
+            _ => console.newline(&mut self.screen),
+        }
+        self.ram[address] = console.row;
+        self.ram[address + 1] = console.column;
+        self.ram[Segment::Stack.get_base_address()] = (stack_pointer - arg_count as usize) as i16;
+        true
+    }
+
     /// Fetches the next instruction and executes it
     pub fn step(&mut self) {
         if self.instruction_index >= self.instructions.len() {
@@ -180,6 +234,11 @@ impl VmEmulator {
                 }
             }
             VmInstruction::Call(function, arg_count) => {
+                if self.console_builtin(&function, arg_count) {
+                    self.instruction_index += 1;
+                    return;
+                }
+
                 // Save current function's state by storing some important
                 // values onto the stack. Important consideration at this point:
                 // arguments for the function we are calling are already on the stack
@@ -243,11 +302,12 @@ impl VmEmulator {
                 // Restore that pointer: THAT = *(lcl-1)
                 self.ram[Segment::That.get_base_address()] = self.ram[lcl - 1];
 
-                // Overwrite argument section with the return value
-                for i in 0..return_size_in_words {
-                    let offset = return_size_in_words - 1 - i;
-                    let signal = self.pop();
-                    self.ram.data[current_arg_address as usize + offset as usize] = signal;
+                // Overwrite argument section with the return value, lowest
+                // word first, as the two may overlap
+                let sp = self.ram[Segment::Stack.get_base_address()] as usize;
+                let start = sp - return_size_in_words as usize;
+                for i in 0..return_size_in_words as usize {
+                    self.ram.data[current_arg_address as usize + i] = self.ram.data[start + i];
                 }
 
                 // Set stack pointer after popping return value
@@ -2,7 +2,7 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use crate::{code::VmCode, preprocessor::VmPreprocessedCode, segment::Segment};
 
@@ -126,7 +126,12 @@ impl FromStr for VmInstruction {
                 let param_count = words.next().unwrap().parse().unwrap();
                 Ok(VmInstruction::Function(function, param_count))
             }
-            "return" => Ok(VmInstruction::Return(1)),
+            "return" => {
+                // The number of words returned is optional, one by default
+                let word_count = words.next().map_or(Ok(1), str::parse);
+                let word_count = word_count.map_err(|_| format!("Invalid return: `{}`", s))?;
+                Ok(VmInstruction::Return(word_count))
+            }
             "call" => {
                 let function = words.next().unwrap().into();
                 let arg_count = words.next().unwrap().parse().unwrap();
@@ -137,6 +142,34 @@ impl FromStr for VmInstruction {
     }
 }
 
+/// Instructions are displayed as VM code, which can be parsed back
+impl fmt::Display for VmInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmInstruction::Push(segment, index) => write!(f, "push {} {}", segment, index),
+            VmInstruction::Pop(segment, index) => write!(f, "pop {} {}", segment, index),
+            VmInstruction::Add => write!(f, "add"),
+            VmInstruction::Sub => write!(f, "sub"),
+            VmInstruction::Eq => write!(f, "eq"),
+            VmInstruction::Lt => write!(f, "lt"),
+            VmInstruction::Gt => write!(f, "gt"),
+            VmInstruction::Neg => write!(f, "neg"),
+            VmInstruction::And => write!(f, "and"),
+            VmInstruction::Or => write!(f, "or"),
+            VmInstruction::Not => write!(f, "not"),
+            VmInstruction::Label(label) => write!(f, "label {}", label),
+            VmInstruction::Goto(label) => write!(f, "goto {}", label),
+            VmInstruction::IfGoto(label) => write!(f, "if-goto {}", label),
+            VmInstruction::Function(name, local_count) => {
+                write!(f, "function {} {}", name, local_count)
+            }
+            VmInstruction::Call(name, arg_count) => write!(f, "call {} {}", name, arg_count),
+            VmInstruction::Return(1) => write!(f, "return"),
+            VmInstruction::Return(word_count) => write!(f, "return {}", word_count),
+        }
+    }
+}
+
 impl From<VmPreprocessedCode> for Vec<VmInstruction> {
     fn from(preprocessed_code: VmPreprocessedCode) -> Self {
         preprocessed_code
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: MIT
 
 pub mod code;
+pub mod data;
 pub mod emulator;
 pub mod instruction;
 pub mod preprocessor;
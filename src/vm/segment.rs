@@ -2,9 +2,9 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Segment {
     /// Stack pointer address is at `RAM[0]`
     Stack,
@@ -74,3 +74,24 @@ impl FromStr for Segment {
         }
     }
 }
+
+/// Segments are displayed as they are written in VM code
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Segment::Stack => "stack",
+            Segment::Local => "local",
+            Segment::Argument => "argument",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Constant => "constant",
+            Segment::Static => "static",
+            Segment::Temp => "temp",
+            Segment::R13 => "r13",
+            Segment::R14 => "r14",
+            Segment::R15 => "r15",
+            Segment::Pointer => "pointer",
+        };
+        write!(f, "{}", name)
+    }
+}
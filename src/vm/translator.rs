@@ -5,8 +5,9 @@
 use crate::{
     asm::instruction::AsmInstruction,
     asm::instruction::{Comp, Dest, Jump},
+    asm::source_map::{Origin, SourceMap},
     segment::Segment,
-    vm::instruction::VmInstruction,
+    vm::{data::DataSection, instruction::VmInstruction},
     Assembler,
 };
 
@@ -18,17 +19,99 @@ use crate::{
 pub struct VmTranslator {
     /// Labels generated so far
     label_count: u32,
+
+    /// Data initialized at boot time, before the first VM instruction
+    data: DataSection,
+
+    /// Constant pushed by the previous VM instruction, which a comparison
+    /// following it orders against
+    constant: Option<i16>,
+
+    /// Origin of each VM instruction to translate, if known
+    origins: Vec<Origin>,
+    /// Origin of each instruction in ROM, after translating
+    source_map: SourceMap,
 }
 
 use AsmInstruction as I;
 
 impl VmTranslator {
+    /// Runs at least this long are initialized with a loop, which takes
+    /// fewer instructions than storing each word
+    const DATA_LOOP_THRESHOLD: u16 = 8;
+
+    pub fn with_data(mut self, data: DataSection) -> Self {
+        self.data = data;
+        self
+    }
+
+    /// Sets the origin of each VM instruction, which every asm instruction
+    /// translated from it inherits
+    pub fn with_origins(mut self, origins: Vec<Origin>) -> Self {
+        self.origins = origins;
+        self
+    }
+
+    /// Returns the origin of each instruction in ROM, which is empty unless
+    /// origins were set before translating
+    pub fn get_source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
     fn next_label(&mut self) -> String {
         let ret = format!("LABEL{}", self.label_count);
         self.label_count += 1;
         ret
     }
 
+    /// Loads a value into `D`, where values which do not fit into an
+    /// A-instruction are loaded as their complement
+    pub(crate) fn gen_load_d(value: i16) -> Vec<I> {
+        if value < 0 {
+            vec![I::A(!value as u16), I::C(Dest::D, Comp::NotA, Jump::No)]
+        } else {
+            vec![I::A(value as u16), I::C(Dest::D, Comp::A, Jump::No)]
+        }
+    }
+
+    /// Initializes the data section run by run. Short runs store each word,
+    /// while long runs loop from their last address down to their first,
+    /// keeping the address in `R13`.
+    fn gen_data(&mut self) -> Vec<I> {
+        let r13 = Segment::R13.get_base_address() as u16;
+        let mut ret = vec![];
+        for run in self.data.runs() {
+            if run.count < Self::DATA_LOOP_THRESHOLD {
+                ret.extend(Self::gen_load_d(run.value));
+                for address in run.address..run.address + run.count {
+                    ret.extend([I::A(address), I::C(Dest::M, Comp::D, Jump::No)]);
+                }
+                continue;
+            }
+            let loop_label = self.next_label();
+            ret.extend([
+                I::A(run.address + run.count - 1),
+                I::C(Dest::D, Comp::A, Jump::No),
+                I::A(r13),
+                I::C(Dest::M, Comp::D, Jump::No),
+                I::Label(loop_label.clone()),
+            ]);
+            ret.extend(Self::gen_load_d(run.value));
+            ret.extend([
+                I::A(r13),
+                I::C(Dest::A, Comp::M, Jump::No),
+                I::C(Dest::M, Comp::D, Jump::No), // *R13 = value
+                I::A(r13),
+                I::C(Dest::DM, Comp::MMinusOne, Jump::No),
+                I::A(run.address),
+                I::C(Dest::D, Comp::DMinusA, Jump::No),
+                I::Symbol(loop_label),
+                I::C(Dest::D, Comp::D, Jump::Ge), // loop while R13 >= address
+            ]);
+        }
+        ret
+    }
+
     /// Translates a push VM instruction into an equivalent sequence of assembly instructions
     fn gen_push(segment: Segment, value: u16) -> Vec<I> {
         match segment {
@@ -222,21 +305,81 @@ impl VmTranslator {
 
     /// Pops two elements from the stack and pushes whether they _compare_ (encoded in jump)
     fn gen_compare(&mut self, jump: Jump) -> Vec<I> {
-        let next_label = self.next_label();
-        vec![
+        let mut ret = vec![
             // Get stack pointer and decrements it (pop)
             I::A(0),
             // A,M[0]=M[0]-1
             I::C(Dest::AM, Comp::MMinusOne, Jump::No),
-            // Get topmost element of stack
-            // D=M[A]
-            I::C(Dest::D, Comp::M, Jump::No),
-            // Move stack pointer backwards
-            // A=A-1
-            I::C(Dest::A, Comp::AMinusOne, Jump::No),
-            // D = top() - previously_popped()
-            // D=M[A]-D
-            I::C(Dest::D, Comp::MMinusD, Jump::No),
+        ];
+        match (jump, self.constant) {
+            (Jump::Eq, _) => ret.extend([
+                // Get topmost element of stack
+                // D=M[A]
+                I::C(Dest::D, Comp::M, Jump::No),
+                // Move stack pointer backwards
+                // A=A-1
+                I::C(Dest::A, Comp::AMinusOne, Jump::No),
+                // D = top() - previously_popped()
+                // D=M[A]-D
+                I::C(Dest::D, Comp::MMinusD, Jump::No),
+            ]),
+            // Ordering against zero only needs the sign of `x`
+            (_, Some(0)) => ret.extend([
+                I::C(Dest::A, Comp::AMinusOne, Jump::No),
+                I::C(Dest::D, Comp::M, Jump::No),
+            ]),
+            // Only `x - y` for `x` of the other sign than the constant `y`
+            // can overflow, and then the sign of `x` orders them
+            (_, Some(y)) => {
+                let end_label = self.next_label();
+                ret.extend([
+                    I::C(Dest::A, Comp::AMinusOne, Jump::No),
+                    I::C(Dest::D, Comp::M, Jump::No),
+                ]);
+                if y > 0 {
+                    ret.extend([
+                        I::Symbol(end_label.clone()),
+                        I::C(Dest::Null, Comp::D, Jump::Lt),
+                    ]);
+                } else {
+                    let negative_label = self.next_label();
+                    ret.extend([
+                        I::Symbol(negative_label.clone()),
+                        I::C(Dest::Null, Comp::D, Jump::Lt),
+                        I::C(Dest::D, Comp::One, Jump::No),
+                        I::Symbol(end_label.clone()),
+                        I::C(Dest::Null, Comp::Zero, Jump::Jump),
+                        I::Label(negative_label),
+                    ]);
+                }
+                ret.extend([
+                    I::A(0),
+                    I::C(Dest::A, Comp::M, Jump::No),
+                    I::C(Dest::D, Comp::M, Jump::No),
+                    I::C(Dest::A, Comp::AMinusOne, Jump::No),
+                    I::C(Dest::D, Comp::MMinusD, Jump::No),
+                    I::Label(end_label),
+                    I::A(0),
+                    I::C(Dest::A, Comp::MMinusOne, Jump::No),
+                ]);
+            }
+            // Ordering needs `x` in D and `y` in R13
+            (_, None) => {
+                let labels = [self.next_label(), self.next_label(), self.next_label()];
+                ret.extend([
+                    I::C(Dest::D, Comp::M, Jump::No),
+                    I::A(Segment::R13.get_base_address() as u16),
+                    I::C(Dest::M, Comp::D, Jump::No),
+                    I::A(0),
+                    I::C(Dest::A, Comp::MMinusOne, Jump::No),
+                    I::C(Dest::D, Comp::M, Jump::No),
+                ]);
+                ret.extend(Self::gen_ordering(labels));
+                ret.extend([I::A(0), I::C(Dest::A, Comp::MMinusOne, Jump::No)]);
+            }
+        }
+        let next_label = self.next_label();
+        ret.extend([
             // Set element on stack (result) to TRUE
             I::C(Dest::M, Comp::MinusOne, Jump::No),
             // Put label address into A
@@ -249,6 +392,46 @@ impl VmTranslator {
             // Set element on stack (result) to FALSE
             I::C(Dest::M, Comp::Zero, Jump::No),
             I::Label(next_label),
+        ]);
+        ret
+    }
+
+    /// Leaves in D a value with the sign of `x - y`, where `x` is in D and
+    /// `y` in `R13`. Unlike subtracting, it does not overflow when `x` and
+    /// `y` have opposite signs, using `R14` to keep `x`
+    pub(crate) fn gen_ordering(labels: [String; 3]) -> Vec<I> {
+        let [negative, same_sign, end] = labels;
+        let r13 = Segment::R13.get_base_address() as u16;
+        let r14 = Segment::R14.get_base_address() as u16;
+        vec![
+            I::A(r14),
+            I::C(Dest::M, Comp::D, Jump::No),
+            I::Symbol(negative.clone()),
+            I::C(Dest::Null, Comp::D, Jump::Lt),
+            // x >= 0, so x > y unless y >= 0 too
+            I::A(r13),
+            I::C(Dest::D, Comp::M, Jump::No),
+            I::Symbol(same_sign.clone()),
+            I::C(Dest::Null, Comp::D, Jump::Ge),
+            I::C(Dest::D, Comp::One, Jump::No),
+            I::Symbol(end.clone()),
+            I::C(Dest::Null, Comp::Zero, Jump::Jump),
+            // x < 0, so x < y unless y < 0 too
+            I::Label(negative),
+            I::A(r13),
+            I::C(Dest::D, Comp::M, Jump::No),
+            I::Symbol(same_sign.clone()),
+            I::C(Dest::Null, Comp::D, Jump::Lt),
+            I::C(Dest::D, Comp::MinusOne, Jump::No),
+            I::Symbol(end.clone()),
+            I::C(Dest::Null, Comp::Zero, Jump::Jump),
+            // D = x - y
+            I::Label(same_sign),
+            I::A(r13),
+            I::C(Dest::D, Comp::M, Jump::No),
+            I::A(r14),
+            I::C(Dest::D, Comp::MMinusD, Jump::No),
+            I::Label(end),
         ]
     }
 
@@ -340,7 +523,7 @@ impl VmTranslator {
         ]
     }
 
-    fn gen_return(return_size_in_words: u16) -> Vec<I> {
+    pub(crate) fn gen_return(return_size_in_words: u16) -> Vec<I> {
         let mut ret = vec![
             // Put frame on R13, A and D
             I::A(Segment::Local.get_base_address() as u16),
@@ -353,11 +536,9 @@ impl VmTranslator {
             I::C(Dest::D, Comp::M, Jump::No),
             I::A(Segment::R14.get_base_address() as u16),
             I::C(Dest::M, Comp::D, Jump::No),
-            // Put current `*ARG + return_size_in_words` in `R15`
+            // Put current `*ARG` in `R15`
             I::A(Segment::Argument.get_base_address() as u16),
             I::C(Dest::D, Comp::M, Jump::No),
-            I::A(return_size_in_words),
-            I::C(Dest::D, Comp::DPlusA, Jump::No),
             I::A(Segment::R15.get_base_address() as u16),
             I::C(Dest::M, Comp::D, Jump::No),
             // That = *(frame-1)
@@ -386,28 +567,53 @@ impl VmTranslator {
             I::C(Dest::M, Comp::D, Jump::No),
         ];
 
-        // Store return value in current argument segment
-        for _ in 0..return_size_in_words {
+        // Store return value in current argument segment, lowest word first,
+        // as it may overlap the words it is copied from when it is larger
+        // than the frame
+        let r13 = Segment::R13.get_base_address() as u16;
+        let r15 = Segment::R15.get_base_address() as u16;
+        if return_size_in_words > 1 {
             ret.extend([
-                // Pop from stack into RAM[*R15-i]
+                // R13 = *SP - return_size_in_words
+                I::A(return_size_in_words),
+                I::C(Dest::D, Comp::A, Jump::No),
                 I::A(0),
-                // Get SP while decrementing RAM[0] at the same time
-                I::C(Dest::AM, Comp::MMinusOne, Jump::No),
-                // D = *SP
+                I::C(Dest::D, Comp::MMinusD, Jump::No),
+                I::A(r13),
+                I::C(Dest::M, Comp::D, Jump::No),
+            ]);
+            for _ in 1..return_size_in_words {
+                ret.extend([
+                    // D = *R13++
+                    I::A(r13),
+                    I::C(Dest::AM, Comp::MPlusOne, Jump::No),
+                    I::C(Dest::A, Comp::AMinusOne, Jump::No),
+                    I::C(Dest::D, Comp::M, Jump::No),
+                    // *R15++ = D
+                    I::A(r15),
+                    I::C(Dest::AM, Comp::MPlusOne, Jump::No),
+                    I::C(Dest::A, Comp::AMinusOne, Jump::No),
+                    I::C(Dest::M, Comp::D, Jump::No),
+                ]);
+            }
+        }
+        if return_size_in_words > 0 {
+            ret.extend([
+                // The last word is on top of the stack
+                I::A(0),
+                I::C(Dest::A, Comp::MMinusOne, Jump::No),
                 I::C(Dest::D, Comp::M, Jump::No),
-                I::A(Segment::R15.get_base_address() as u16),
-                // AM = *R15 - 1
-                I::C(Dest::AM, Comp::MMinusOne, Jump::No),
+                I::A(r15),
+                I::C(Dest::AM, Comp::MPlusOne, Jump::No),
+                I::C(Dest::A, Comp::AMinusOne, Jump::No),
                 I::C(Dest::M, Comp::D, Jump::No),
             ]);
         }
 
         ret.extend([
             // *SP = old*ARG + return_size_in_words
-            I::A(Segment::R15.get_base_address() as u16),
+            I::A(r15),
             I::C(Dest::D, Comp::M, Jump::No),
-            I::A(return_size_in_words),
-            I::C(Dest::D, Comp::DPlusA, Jump::No),
             I::A(0),
             I::C(Dest::M, Comp::D, Jump::No),
             // goto return address *(frame-5)
@@ -442,22 +648,46 @@ impl VmTranslator {
         }
     }
 
-    /// Translates a VM program into a sequence of assembly instructions
-    pub fn translate(&mut self, vm_instructions: Vec<VmInstruction>) -> Vec<I> {
-        let mut asm_instructions = vec![
+    /// Sets the stack pointer and initializes the data section, before the
+    /// first VM instruction
+    pub(crate) fn gen_boot(&mut self) -> Vec<I> {
+        let mut ret = vec![
             // Set stack pointer to 256
             I::A(256),
             I::C(Dest::D, Comp::A, Jump::No), // D=256
             I::A(0),
             I::C(Dest::M, Comp::D, Jump::No), // M[0]=D
         ];
-        for instruction in vm_instructions {
+        ret.extend(self.gen_data());
+        ret
+    }
+
+    /// Translates a VM program into a sequence of assembly instructions
+    pub fn translate(&mut self, vm_instructions: Vec<VmInstruction>) -> Vec<I> {
+        let mut asm_instructions = self.gen_boot();
+        let mut origins = vec![None; asm_instructions.len()];
+        let tagged = !self.origins.is_empty();
+        for (i, instruction) in vm_instructions.into_iter().enumerate() {
+            let constant = match instruction {
+                VmInstruction::Push(Segment::Constant, value) => Some(value as i16),
+                _ => None,
+            };
             let new_asm_instructions = self.vm_to_asm(instruction);
+            self.constant = constant;
+            if tagged {
+                let origin = self.origins.get(i).cloned();
+                origins.extend(std::iter::repeat_n(origin, new_asm_instructions.len()));
+            }
             asm_instructions.extend(new_asm_instructions)
         }
 
-        let mut assembler = Assembler::default();
-        assembler.resolve(asm_instructions)
+        if !tagged {
+            origins.clear();
+        }
+        let mut assembler = Assembler::default().with_origins(origins);
+        let ret = assembler.resolve(asm_instructions);
+        self.source_map = assembler.get_source_map().clone();
+        ret
     }
 }
 
@@ -0,0 +1,118 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    analysis::{offset_to_position, position_to_offset, Analysis, Severity, SymbolKind},
+    json::Json,
+};
+
+const CODE: &str = r#"struct P { x: i16, y: i16 }
+const N: i16 = 2 * 3;
+impl P {
+    fn len(&self) -> i16 { self.x + self.y }
+}
+fn main() -> i16 {
+    let mut p: P = P { x: 1, y: N };
+    let (a, b) = (1, true);
+    p.x = a;
+    p.len()
+}
+"#;
+
+/// Offset of the `nth` occurrence of a pattern in the code
+fn offset(pattern: &str, nth: usize) -> usize {
+    CODE.match_indices(pattern).nth(nth).unwrap().0
+}
+
+#[test]
+fn diagnostics() {
+    let code = r#"
+    fn first() -> i16 { a }
+    fn second() -> i16 { let unused: i16 = 1; b }
+    "#;
+    let analysis = Analysis::new(code);
+    let diagnostics = analysis.get_diagnostics();
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].message, "Unused variable `unused`");
+    assert_eq!(diagnostics[1].severity, Severity::Error);
+    assert_eq!(diagnostics[1].message, "Undefined variable `a`");
+    assert_eq!(diagnostics[2].severity, Severity::Error);
+    assert_eq!(diagnostics[2].message, "Undefined variable `b`");
+    assert_eq!(
+        &code[diagnostics[2].range.start..diagnostics[2].range.end],
+        "b"
+    );
+
+    let analysis = Analysis::new("fn main() { let }");
+    assert_eq!(analysis.get_diagnostics().len(), 1);
+    assert!(Analysis::new(CODE).get_diagnostics()[0]
+        .message
+        .contains("`b`"));
+}
+
+#[test]
+fn hover() {
+    let analysis = Analysis::new(CODE);
+    let hover = |pattern, nth| analysis.hover(offset(pattern, nth)).unwrap().0;
+    assert_eq!(hover("p.len", 0), "let mut p: P");
+    assert_eq!(hover("len()", 0), "fn P::len(&self) -> i16");
+    assert_eq!(hover("a;", 0), "let a: i16");
+    assert_eq!(hover("self.x", 0), "self: &P");
+    assert_eq!(hover("N }", 0), "const N: i16 = 6");
+    assert_eq!(hover("x: 1", 0), "P\nx: i16");
+    assert_eq!(hover("P {", 1), "struct P { x: i16, y: i16 }");
+    assert_eq!(hover("main", 0), "fn main() -> i16");
+    assert_eq!(analysis.hover(offset("1,", 0)), None);
+}
+
+#[test]
+fn definition() {
+    let analysis = Analysis::new(CODE);
+    let definition = |pattern, nth| analysis.definition(offset(pattern, nth)).unwrap().start;
+    assert_eq!(definition("p.len", 0), offset("p:", 0));
+    assert_eq!(definition("len()", 0), offset("len", 0));
+    assert_eq!(definition("a;", 0), offset("a,", 0));
+    assert_eq!(definition("N }", 0), offset("N", 0));
+    assert_eq!(definition("x = a", 0), offset("x", 0));
+    assert_eq!(definition("y: N", 0), offset("y", 0));
+}
+
+#[test]
+fn symbols() {
+    let symbols = Analysis::new(CODE).symbols();
+    let names: Vec<(&str, SymbolKind)> = symbols
+        .iter()
+        .map(|symbol| (symbol.name.as_str(), symbol.kind))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("P", SymbolKind::Struct),
+            ("N", SymbolKind::Constant),
+            ("P::len", SymbolKind::Method),
+            ("main", SymbolKind::Function)
+        ]
+    );
+    assert_eq!(symbols[0].children.len(), 2);
+    assert_eq!(symbols[0].children[1].detail, "i16");
+}
+
+#[test]
+fn positions() {
+    let code = "fn main() {\n    'é'; 'x'\n}";
+    let offset = code.find("'x'").unwrap();
+    assert_eq!(offset_to_position(code, offset), (1, 9));
+    assert_eq!(position_to_offset(code, 1, 9), offset);
+    assert_eq!(position_to_offset(code, 0, 100), code.find('\n').unwrap());
+}
+
+#[test]
+fn json() {
+    let text = r#"{"a": [1, -2.5, true, null], "b": "q\"\nA"}"#;
+    let json: Json = text.parse().unwrap();
+    assert_eq!(json.get("b").and_then(Json::as_str), Some("q\"\nA"));
+    assert_eq!(json.to_string(), r#"{"a":[1,-2.5,true,null],"b":"q\"\nA"}"#);
+    assert!("[1,".parse::<Json>().is_err());
+}
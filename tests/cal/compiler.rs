@@ -2,7 +2,30 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use acs::{compiler::Compile, error::CalError, Computer};
+use acs::{asm::instruction::AsmInstruction, data::DataSection, error::CalError, Computer};
+
+// Either the VM backend or the Hack one, with or without optimisations,
+// depending on which suite includes this file
+use super::TARGET;
+
+/// Compiles for the target of the suite
+trait Compile {
+    fn compile(&self) -> Result<Vec<AsmInstruction>, CalError>;
+}
+
+impl Compile for str {
+    fn compile(&self) -> Result<Vec<AsmInstruction>, CalError> {
+        TARGET.compile(self)
+    }
+}
+
+/// Compiles code which is expected to fail, returning the error
+fn compile_error(code: &str) -> CalError {
+    let Err(err) = code.compile() else {
+        panic!("Expected error compiling {}", code);
+    };
+    err
+}
 
 #[test]
 fn hello_void() -> Result<(), CalError> {
@@ -56,9 +79,12 @@ fn def_local() -> Result<(), CalError> {
         computer.ticktock();
     }
     assert_eq!(computer.get_memory().ram[0], 256);
-    // 5 elements were pushed on the stack when calling main for saving previous stack frame
-    assert_eq!(computer.get_memory().ram[261], 1);
-    assert_eq!(computer.get_memory().ram[262], 2);
+    // Locals which are never read are left out when optimising
+    if TARGET.opt_level == 0 {
+        // 5 elements were pushed on the stack when calling main for saving previous stack frame
+        assert_eq!(computer.get_memory().ram[261], 1);
+        assert_eq!(computer.get_memory().ram[262], 2);
+    }
     Ok(())
 }
 
@@ -250,7 +276,7 @@ fn cmp() -> Result<(), CalError> {
 
 #[test]
 fn assign_expression() -> Result<(), CalError> {
-    let asm_instructions = r#"fn main() -> i16 { let a: i16 = 0; a = 1; a }"#.compile()?;
+    let asm_instructions = r#"fn main() -> i16 { let mut a: i16 = 0; a = 1; a }"#.compile()?;
     let mut computer = Computer::default();
     computer.set_instructions(asm_instructions);
     for _ in 0..256 {
@@ -348,7 +374,7 @@ fn array() -> Result<(), CalError> {
     assert_eq!(computer.get_memory().ram[256], 2);
 
     let asm_instructions =
-        "fn main() -> i16 { let a: [i16; 2] = [1, 2]; a[1] = 3; a[1] }".compile()?;
+        "fn main() -> i16 { let mut a: [i16; 2] = [1, 2]; a[1] = 3; a[1] }".compile()?;
     let mut computer = Computer::default();
     computer.set_instructions(asm_instructions);
     for _ in 0..512 {
@@ -377,11 +403,11 @@ fn character() -> Result<(), CalError> {
 fn reference() -> Result<(), CalError> {
     let asm_instructions = r#"
     fn main() -> i16 {
-        let a: i16 = 1;
-        pass(&a);
+        let mut a: i16 = 1;
+        pass(&mut a);
         a
     }
-    fn pass(a: &i16) {
+    fn pass(a: &mut i16) {
         a = 2;
     }
     "#
@@ -396,11 +422,11 @@ fn reference() -> Result<(), CalError> {
 
     let asm_instructions = r#"
     fn main() -> i16 {
-        let a: [i16; 2] = [1, 2];
-        pass(&a);
+        let mut a: [i16; 2] = [1, 2];
+        pass(&mut a);
         a[1]
     }
-    fn pass(a: &[i16; 2]) {
+    fn pass(a: &mut [i16; 2]) {
         a[1] = 3;
     }
     "#
@@ -464,13 +490,13 @@ fn array_of_array() -> Result<(), CalError> {
 
 #[test]
 fn array_of_array_reference() -> Result<(), CalError> {
-    let asm_instructions = r#"fn edit(e: &[i16; 2]) {
+    let asm_instructions = r#"fn edit(e: &mut [i16; 2]) {
             e[1] = 5;
         }
 
         fn main() -> [i16; 2] {
-            let a: [[i16; 2]; 2] = [[1, 2], [3, 4]];
-            edit(&a[1]);
+            let mut a: [[i16; 2]; 2] = [[1, 2], [3, 4]];
+            edit(&mut a[1]);
             a[1]
         }"#
     .compile()?;
@@ -484,3 +510,524 @@ fn array_of_array_reference() -> Result<(), CalError> {
     assert_eq!(computer.get_memory().ram[257], 5);
     Ok(())
 }
+
+#[test]
+fn method() -> Result<(), CalError> {
+    let asm_instructions = r#"
+    struct Point { x: i16, y: i16 }
+    impl Point {
+        fn new(x: i16, y: i16) -> Point { Point { x: x, y: y } }
+        fn len(&self) -> i16 { self.x + self.y }
+        fn translate(&mut self, dx: i16) { self.x = self.x + dx; }
+    }
+    fn main() -> i16 {
+        let mut p: Point = Point::new(3, 4);
+        p.translate(2);
+        p.len()
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..4096 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 257);
+    assert_eq!(computer.get_memory().ram[256], 9);
+
+    let asm_instructions = r#"
+    struct Point { x: i16, y: i16 }
+    struct Segment { a: Point, b: Point }
+    impl Point {
+        fn sum(&self) -> i16 { self.x + self.y }
+    }
+    impl Segment {
+        fn len(&self) -> i16 { self.b.sum() - self.a.sum() }
+    }
+    fn main() -> i16 {
+        let s: Segment = Segment { a: Point { x: 1, y: 2 }, b: Point { x: 5, y: 6 } };
+        let r: &Segment = &s;
+        r.len()
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..4096 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 257);
+    assert_eq!(computer.get_memory().ram[256], 8);
+    Ok(())
+}
+
+#[test]
+fn tuple() -> Result<(), CalError> {
+    let asm_instructions = r#"
+    fn divmod(a: i16, b: i16) -> (i16, i16) { (a / b, a % b) }
+    fn main() -> i16 {
+        let (q, r) = divmod(7, 2);
+        r + q * 10
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..8192 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 257);
+    assert_eq!(computer.get_memory().ram[256], 31);
+
+    let asm_instructions = r#"
+    fn swap(t: (i16, i16)) -> (i16, i16) { (t.1, t.0) }
+    fn main() -> (i16, i16) {
+        let mut t: (i16, i16) = (1, 2);
+        let mut i: i16 = 0;
+        while i < 3 {
+            let s: (i16, i16) = swap(t);
+            t = s;
+            t.1 = t.1 + 10;
+            i = i + 1;
+        }
+        t
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..8192 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 258);
+    assert_eq!(computer.get_memory().ram[256], 12);
+    assert_eq!(computer.get_memory().ram[257], 21);
+
+    let asm_instructions = "fn main() -> i16 { (1 + 2) * 3 }".compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..1024 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 257);
+    assert_eq!(computer.get_memory().ram[256], 9);
+    Ok(())
+}
+
+#[test]
+fn wide_tuple() -> Result<(), CalError> {
+    // Returned values larger than the frame overlap the words they are
+    // copied from
+    let asm_instructions = r#"
+    fn six() -> (i16, i16, i16, i16, i16, i16) { (1, 2, 3, 4, 5, 6) }
+    fn shift(a: i16) -> (i16, i16, i16, i16, i16, i16, i16, i16) {
+        let (b, c, d, e, f, g) = six();
+        (a, b, c, d, e, f, g, a)
+    }
+    fn main() -> i16 {
+        let (a, b, c, d, e, g) = six();
+        let (h, i, j, k, l, m, n, o) = shift(7);
+        let shifted: i16 = ((h * 1000) + (i * 100)) + ((n * 10) + o);
+        (shifted + ((a * 10) + g)) + ((b * (c * (d * e))) - (j + (k + (l + m))))
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..32768 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 257);
+    assert_eq!(computer.get_memory().ram[256], 7167 + 16 + 120 - 14);
+    Ok(())
+}
+
+#[test]
+fn nested_index() -> Result<(), CalError> {
+    let asm_instructions = r#"
+    fn set(grid: &mut [[i16; 4]; 3], y: i16, x: i16, value: i16) {
+        grid[y][x] = value;
+    }
+    fn main() -> [i16; 4] {
+        let mut grid: [[i16; 4]; 3] = [[0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
+        set(&mut grid, 2, 3, 7);
+        grid[1][2] = grid[2][3] + 1;
+        grid[1]
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..16384 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 260);
+    assert_eq!(computer.get_memory().ram[256], 0);
+    assert_eq!(computer.get_memory().ram[257], 0);
+    assert_eq!(computer.get_memory().ram[258], 8);
+    assert_eq!(computer.get_memory().ram[259], 0);
+
+    let asm_instructions = r#"
+    struct Point { x: i16, y: i16 }
+    struct Tile { pos: Point, kind: i16 }
+    fn main() -> i16 {
+        let mut tiles: [Tile; 2] = [
+            Tile { pos: Point { x: 1, y: 2 }, kind: 3 },
+            Tile { pos: Point { x: 4, y: 5 }, kind: 6 },
+        ];
+        let t: &mut Tile = &mut tiles[1];
+        t.pos.y = 10;
+        tiles[0].pos.x = tiles[1].kind;
+        tiles[1].pos.y + tiles[0].pos.x
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..16384 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 257);
+    assert_eq!(computer.get_memory().ram[256], 16);
+    Ok(())
+}
+
+#[test]
+fn constant() -> Result<(), CalError> {
+    let asm_instructions = r#"
+    const WIDTH: i16 = 2;
+    const fn factorial(mut n: i16) -> i16 {
+        let mut ret: i16 = 1;
+        while n > 1 {
+            ret = ret * n;
+            n = n - 1;
+        }
+        ret
+    }
+    struct Point { x: i16, y: i16 }
+    fn main() -> [i16; WIDTH * 2] {
+        let mut a: [i16; factorial(3) - WIDTH] = [1, 2, 3, 4];
+        a[WIDTH + 1] = sizeof(Point) + sizeof([Point; WIDTH]);
+        a
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..4096 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 260);
+    assert_eq!(computer.get_memory().ram[256], 1);
+    assert_eq!(computer.get_memory().ram[257], 2);
+    assert_eq!(computer.get_memory().ram[258], 3);
+    assert_eq!(computer.get_memory().ram[259], 6);
+    Ok(())
+}
+
+#[test]
+fn recursive_struct() {
+    let code = "struct A { x: i16, a: A } fn f(v: A) -> i16 { v.x } fn main() { }";
+    let err = compile_error(code);
+    assert_eq!(
+        err.message,
+        "Field `a` of `A` contains `A` by value, which would make it infinitely large"
+    );
+    assert_eq!(&code[err.range.start..err.range.end], "a");
+
+    // Through another struct, or the elements of an array
+    let code = "struct A { b: [B; 2] } struct B { y: i16, a: (i16, A) } fn main() { }";
+    let err = compile_error(code);
+    assert_eq!(
+        err.message,
+        "Field `a` of `B` contains `A` by value, which would make it infinitely large"
+    );
+    assert_eq!(&code[err.range.start..err.range.end], "a");
+
+    // References to the struct itself are fine
+    assert!("struct A { x: i16, next: &A } fn main() { }"
+        .compile()
+        .is_ok());
+}
+
+#[test]
+fn immutable() {
+    let code = "fn main() { let a: i16 = 0; a = 1; }";
+    let err = compile_error(code);
+    assert_eq!(
+        err.message,
+        "Cannot assign to immutable variable `a`, declare it as `mut a`"
+    );
+    assert_eq!(&code[err.range.start..err.range.end], "a = 1");
+
+    let code = "fn main(a: [i16; 2]) { a[0] = 1; }";
+    let err = compile_error(code);
+    assert_eq!(
+        err.message,
+        "Cannot assign to immutable variable `a`, declare it as `mut a`"
+    );
+    assert_eq!(&code[err.range.start..err.range.end], "a[0] = 1");
+
+    // Helpers can not modify arrays passed read-only
+    let code = r#"fn clear(a: &[i16; 2]) { a[1] = 0; }
+        fn main() { let mut a: [i16; 2] = [1, 2]; clear(&a); }"#;
+    let err = compile_error(code);
+    assert_eq!(
+        err.message,
+        "Cannot assign to a place behind a `&` reference, use `&mut` instead"
+    );
+    assert_eq!(&code[err.range.start..err.range.end], "a[1] = 0");
+
+    let code = "fn set(a: &i16) { a = 0; }";
+    let err = compile_error(code);
+    assert_eq!(
+        err.message,
+        "Cannot assign to `a`, which is a `&` reference, declare it as `&mut`"
+    );
+    assert_eq!(&code[err.range.start..err.range.end], "a = 0");
+
+    let code = r#"fn clear(a: &mut [i16; 2]) { a[1] = 0; }
+        fn main() { let mut a: [i16; 2] = [1, 2]; clear(&a); }"#;
+    let err = compile_error(code);
+    assert_eq!(
+        err.message,
+        "Expected `&mut` reference, found `&` reference"
+    );
+    assert_eq!(&code[err.range.start..err.range.end], "&a");
+
+    // Nor returned, or held by tuples and structs
+    let cases = [
+        (
+            "fn f(a: &i16) -> &mut i16 { a } fn main() -> i16 { let x: i16 = 1; let m: &mut i16 = f(&x); m = 7; x }",
+            "a",
+        ),
+        (
+            "fn f(a: &i16) -> &mut i16 { return a; } fn main() { let x: i16 = 1; f(&x); }",
+            "a",
+        ),
+        (
+            "fn f(a: &i16) -> (&mut i16, i16) { (a, 1) } fn main() { let x: i16 = 1; f(&x); }",
+            "(a, 1)",
+        ),
+        (
+            "struct S { r: &mut i16 } fn main() { let x: i16 = 1; let s: S = S { r: &x }; }",
+            "&x",
+        ),
+    ];
+    for (code, expr) in cases {
+        let err = compile_error(code);
+        assert_eq!(
+            err.message,
+            "Expected `&mut` reference, found `&` reference"
+        );
+        assert_eq!(&code[err.range.start..err.range.end], expr);
+    }
+
+    assert_eq!(
+        compile_error("fn main() { let a: i16 = 0; let r: &mut i16 = &mut a; }").message,
+        "Cannot mutably borrow immutable variable `a`, declare it as `mut a`"
+    );
+
+    let err = compile_error(
+        r#"struct P { x: i16 }
+        impl P { fn set(&mut self) { self.x = 1; } fn get(&self) { self.set(); } }
+        fn main() { let p: P = P { x: 0 }; p.get(); }"#,
+    );
+    assert_eq!(
+        err.message,
+        "Cannot call `P.set`, which takes `&mut self`, behind a `&` reference"
+    );
+
+    let err = compile_error(
+        r#"struct P { x: i16 }
+        impl P { fn set(&mut self) { self.x = 1; } }
+        fn main() { let p: P = P { x: 0 }; p.set(); }"#,
+    );
+    assert_eq!(
+        err.message,
+        "Cannot mutably borrow immutable variable `p`, declare it as `mut p`"
+    );
+}
+
+#[test]
+fn type_alias() -> Result<(), CalError> {
+    let asm_instructions = r#"
+    type Row = [i16; WIDTH];
+    type Grid = [Row; 2];
+    const WIDTH: i16 = 3;
+    fn sum(row: &Row) -> i16 { row[0] + row[1] + row[2] }
+    fn main() -> i16 {
+        let mut grid: Grid = [[1, 2, 3], [4, 5, 6]];
+        grid[1][2] = sizeof(Grid);
+        sum(&grid[1])
+    }"#
+    .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..4096 {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 257);
+    assert_eq!(computer.get_memory().ram[256], 15);
+    Ok(())
+}
+
+#[test]
+fn static_data() -> Result<(), CalError> {
+    let asm_instructions = r#"
+    const N: i16 = 4;
+    static SHORT: [i16; 4] = [1, 0xfffe, 0x7fff, N];
+    static LONG: [i16; 12] = [0, 0, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7];
+    fn last(data: &[i16; 12]) -> i16 { data[11] }
+    fn main() -> i16 { SHORT[1] + SHORT[3] + last(&LONG) }"#
+        .compile()?;
+    let mut computer = Computer::default();
+    computer.set_instructions(asm_instructions);
+    for _ in 0..4096 {
+        computer.ticktock();
+    }
+    let ram = &computer.get_memory().ram;
+    let base = DataSection::BASE as usize;
+    let data: Vec<i16> = (base..base + 16).map(|address| ram[address]).collect();
+    assert_eq!(data[..4], [1, -2, 0x7fff, 4]);
+    assert_eq!(data[4..6], [0, 0]);
+    assert_eq!(data[6..], [7; 10]);
+    assert_eq!(ram[0], 257);
+    assert_eq!(ram[256], 9);
+    Ok(())
+}
+
+#[test]
+fn static_errors() {
+    assert_eq!(
+        compile_error("static A: [i16; 2] = [1, 2]; fn main() { A[0] = 3; }").message,
+        "Cannot assign to static `A`, which is read-only"
+    );
+    assert_eq!(
+        compile_error("static A: [i16; 3] = [1, 2]; fn main() {}").message,
+        "Expected 3 elements, found 2"
+    );
+
+    // Statics stay below the Jack heap, even when each of them fits alone
+    let words = DataSection::CAPACITY / 2 + 1;
+    let zeros = vec!["0"; words].join(", ");
+    let code = format!(
+        "static A: [i16; {0}] = [{1}]; static B: [i16; {0}] = [{1}]; fn main() {{}}",
+        words, zeros
+    );
+    assert_eq!(
+        compile_error(&code).message,
+        "Statics exceed the data section of 2048 words at `B`"
+    );
+}
+
+/// Runs `a op b` through a call, so that it is not folded, and returns the
+/// result if it is ready within the budget of cycles
+fn run_operator(op: &str, a: i16, b: i16, cycles: usize) -> Result<i16, CalError> {
+    let code = format!(
+        "fn f(a: i16, b: i16) -> i16 {{ a {} b }} fn main() -> i16 {{ f({:#x}, {:#x}) }}",
+        op, a as u16, b as u16
+    );
+    let mut computer = Computer::default();
+    computer.set_instructions(code.as_str().compile()?);
+    for _ in 0..cycles {
+        computer.ticktock();
+    }
+    assert_eq!(computer.get_memory().ram[0], 257, "{} {} {}", a, op, b);
+    Ok(computer.get_memory().ram[256])
+}
+
+#[test]
+fn cmp_overflow() -> Result<(), CalError> {
+    // Subtracting operands of opposite signs overflows
+    let pairs = [
+        (-32193, 2925),
+        (32000, -5000),
+        (i16::MIN, i16::MAX),
+        (i16::MAX, i16::MIN),
+        (0, i16::MIN),
+        (-1, i16::MAX),
+        (5, 5),
+    ];
+    for (a, b) in pairs {
+        for (op, expected) in [("<", a < b), (">", a > b)] {
+            // Against an argument, and against a constant
+            for rhs in ["b".to_string(), format!("{:#x}", b as u16)] {
+                let code = format!(
+                    "fn f(a: i16, b: i16) -> bool {{ a {} {} }} fn main() -> bool {{ f({:#x}, {:#x}) }}",
+                    op, rhs, a as u16, b as u16
+                );
+                let mut computer = Computer::default();
+                computer.set_instructions(code.as_str().compile()?);
+                for _ in 0..512 {
+                    computer.ticktock();
+                }
+                assert_eq!(computer.get_memory().ram[0], 257, "{} {} {}", a, op, rhs);
+                assert_eq!(
+                    computer.get_memory().ram[256],
+                    -(expected as i16),
+                    "{} {} {}",
+                    a,
+                    op,
+                    rhs
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn mul_cycles() -> Result<(), CalError> {
+    let pairs = [
+        (3, 2),
+        (30000, 30000),
+        (-7, 9),
+        (123, -45),
+        (-1, -1),
+        (i16::MIN, -1),
+        (-3, i16::MIN),
+        (i16::MAX, i16::MAX),
+        (0, i16::MIN),
+    ];
+    for (a, b) in pairs {
+        assert_eq!(
+            run_operator("*", a, b, 4000)?,
+            a.wrapping_mul(b),
+            "{} * {}",
+            a,
+            b
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn div_mod_cycles() -> Result<(), CalError> {
+    let pairs = [
+        (7, 2),
+        (-7, 2),
+        (7, -2),
+        (-7, -2),
+        (i16::MAX, 1),
+        (i16::MIN, -1),
+        (i16::MIN, 3),
+        (i16::MIN, i16::MIN),
+        (100, i16::MIN),
+        (-32767, 3),
+        (1, i16::MAX),
+    ];
+    for (a, b) in pairs {
+        assert_eq!(
+            run_operator("/", a, b, 7000)?,
+            a.wrapping_div(b),
+            "{} / {}",
+            a,
+            b
+        );
+        assert_eq!(
+            run_operator("%", a, b, 7000)?,
+            a.wrapping_rem(b),
+            "{} % {}",
+            a,
+            b
+        );
+    }
+    // Dividing by zero gives -1, with `x` as remainder
+    assert_eq!(run_operator("/", 5, 0, 512)?, -1);
+    assert_eq!(run_operator("/", -5, 0, 512)?, -1);
+    assert_eq!(run_operator("%", -5, 0, 512)?, -5);
+    Ok(())
+}
@@ -0,0 +1,133 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{env, error::Error, fs, path::Path};
+
+use acs::{
+    compiler::{compile, link, CompileOptions},
+    console::{Console, SCREEN_SIZE},
+    generator::Generator,
+    parser::parse,
+    tokenizer::tokenize,
+    Computer, VmEmulator,
+};
+
+/// Runs a program linking the console library until `main` returns 42, and
+/// compares the screen with `tests/cal/images/<name>.png`. Then runs it on
+/// the VM emulator, whose builtins have to draw the same screen
+fn print(name: &str, code: &str) -> Result<(), Box<dyn Error>> {
+    let libraries = vec!["console".to_string()];
+    let options = CompileOptions {
+        link: libraries.clone(),
+        ..Default::default()
+    };
+    let mut computer = Computer::default();
+    computer.set_instructions(
+        compile(code, &options)
+            .map_err(|err| err.message)?
+            .instructions,
+    );
+    let mut cycles = 0;
+    while computer.get_memory().ram[0] != 257 || computer.get_memory().ram[256] != 42 {
+        for _ in 0..1024 {
+            computer.ticktock();
+        }
+        cycles += 1024;
+        assert!(cycles < 3_000_000, "{} did not finish", name);
+    }
+
+    let out_dir = Path::new("target/screen");
+    fs::create_dir_all(out_dir)?;
+    let out_path = out_dir.join(format!("{}.png", name));
+    computer.get_screen().dump(&out_path)?;
+    let reference_path = Path::new("tests/cal/images").join(format!("{}.png", name));
+    if env::var_os("CAL_BLESS").is_some() {
+        fs::copy(&out_path, &reference_path)?;
+    }
+    assert!(
+        fs::read(&out_path)? == fs::read(&reference_path)?,
+        "{} differs from {}",
+        out_path.display(),
+        reference_path.display()
+    );
+
+    let module = parse(tokenize(code).map_err(|err| err.message)?).map_err(|err| err.message)?;
+    let mut generator = Generator::default();
+    let modules = link(module, &libraries).map_err(|err| err.message)?;
+    let instructions = generator.gen(&modules).map_err(|err| err.message)?;
+    let mut emulator = VmEmulator::default();
+    emulator.load_data(generator.get_data());
+    emulator.load(instructions);
+    while emulator.ram[0] != 257 || emulator.ram[256] != 42 {
+        emulator.step();
+    }
+    let screen = computer.get_screen();
+    for y in 0..256 {
+        for x in 0..512 {
+            let word = emulator.screen[y * 32 + x / 16] as u16;
+            let pixel = (word >> (x % 16)) & 1;
+            assert_eq!(screen.get_pixel(x, y), pixel, "({}, {})", x, y);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn text() -> Result<(), Box<dyn Error>> {
+    print(
+        "console",
+        r#"
+        static HELLO: [char; 13] = ['H', 'e', 'l', 'l', 'o', ',', ' ', 'W', 'o', 'r', 'l', 'd', '!'];
+        fn main() -> i16 {
+            let mut console: Console = Console::new();
+            console.print_str(&HELLO, 13);
+            console.newline();
+            console.print_int(0x8000);
+            console.print_char(' ');
+            console.print_int(0);
+            console.print_char(' ');
+            console.print_int(32767);
+            console.print_char(' ');
+            console.print_char(0);
+            console.newline();
+            console.print_char('}');
+            console.print_char('~');
+            console.print_char('g');
+            console.print_char('_');
+            42
+        }"#,
+    )
+}
+
+#[test]
+fn wrap_and_scroll() -> Result<(), Box<dyn Error>> {
+    print(
+        "console_scroll",
+        r#"
+        fn main() -> i16 {
+            let mut console: Console = Console::new();
+            console.print_char('a');
+            console.row = 22;
+            console.column = 62;
+            console.print_char('x');
+            console.print_char('y');
+            console.print_char('z');
+            42
+        }"#,
+    )
+}
+
+#[test]
+fn builtins() {
+    let mut screen = vec![0; SCREEN_SIZE];
+    let mut console = Console::default();
+    console.print_str(&mut screen, &['H' as i16, 'i' as i16]);
+    assert_eq!(console, Console { row: 0, column: 2 });
+    // Second line of the cell holds the first line of the glyphs
+    assert_eq!(screen[32] as u16, 0x0c33);
+    console.print_int(&mut screen, -32768);
+    assert_eq!(console.column, 8);
+    console.print_char(&mut screen, '\n' as i16);
+    assert_eq!(console, Console { row: 1, column: 0 });
+}
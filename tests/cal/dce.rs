@@ -0,0 +1,123 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    compiler::{compile, link, CompileOptions},
+    dce::eliminate_dead_code,
+    generator::Generator,
+    parser::parse,
+    tokenizer::tokenize,
+    vm::instruction::VmInstruction,
+};
+
+/// Generates a program linking some libraries, returning the names of the
+/// functions emitted and of the ones pruned
+fn functions(code: &str, libraries: &[&str]) -> (Vec<String>, Vec<String>) {
+    let module = parse(tokenize(code).unwrap()).unwrap();
+    let libraries: Vec<String> = libraries.iter().map(|name| name.to_string()).collect();
+    let mut generator = Generator::default();
+    let instructions = generator.gen(&link(module, &libraries).unwrap()).unwrap();
+    let emitted = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            VmInstruction::Function(name, _) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    (emitted, generator.get_pruned().to_vec())
+}
+
+#[test]
+fn unreachable_functions() {
+    let (emitted, pruned) = functions(
+        r#"
+        fn leaf(x: i16) -> i16 { x * 3 }
+        fn reached(x: i16) -> i16 { leaf(x) }
+        fn unused_leaf() -> i16 { 6 / 2 }
+        fn unused() -> i16 { unused_leaf() }
+        fn main() -> i16 { reached(1) }"#,
+        &[],
+    );
+    assert_eq!(emitted, ["mul", "leaf", "reached", "main"]);
+    assert_eq!(
+        pruned,
+        ["peek", "poke", "div", "mod", "unused_leaf", "unused"]
+    );
+}
+
+#[test]
+fn recursion_and_methods() {
+    let (emitted, pruned) = functions(
+        r#"
+        struct Counter { count: i16 }
+        impl Counter {
+            fn new() -> Counter { Counter { count: 0 } }
+            fn count_down(&mut self, n: i16) -> i16 {
+                if n == 0 {
+                    return self.count;
+                }
+                self.count = self.count + 1;
+                self.count_down(n - 1)
+            }
+            fn reset(&mut self) { self.count = 0; }
+        }
+        fn main() -> i16 {
+            let mut counter: Counter = Counter::new();
+            counter.count_down(3)
+        }"#,
+        &[],
+    );
+    assert!(emitted.contains(&"Counter.count_down".to_string()));
+    assert!(pruned.contains(&"Counter.reset".to_string()));
+}
+
+#[test]
+fn libraries() {
+    let (emitted, pruned) = functions(
+        r#"
+        fn main() -> i16 {
+            set_pixel(1, 2, true);
+            0
+        }"#,
+        &["graphics"],
+    );
+    assert_eq!(
+        emitted,
+        [
+            "peek",
+            "poke",
+            "main",
+            "screen_row",
+            "screen_column",
+            "screen_write",
+            "set_pixel"
+        ]
+    );
+    assert!(pruned.contains(&"circle".to_string()));
+    assert!(pruned.contains(&"mul".to_string()));
+
+    // Pruning is reported by the compiler as well
+    let options = CompileOptions {
+        link: vec!["graphics".into()],
+        ..Default::default()
+    };
+    let compilation = compile("fn main() -> i16 { clear(); 0 }", &options).unwrap();
+    assert!(compilation.pruned.contains(&"line".to_string()));
+    assert!(!compilation.pruned.contains(&"fill".to_string()));
+}
+
+#[test]
+fn code_before_functions() {
+    let instructions = vec![
+        VmInstruction::Call("main".into(), 0),
+        VmInstruction::Function("unused".into(), 0),
+        VmInstruction::Return(0),
+        VmInstruction::Function("main".into(), 0),
+        VmInstruction::Call("main".into(), 0),
+        VmInstruction::Return(0),
+    ];
+    let (kept, pruned) = eliminate_dead_code(instructions);
+    assert_eq!(pruned, ["unused"]);
+    assert_eq!(kept.len(), 4);
+}
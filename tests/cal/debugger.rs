@@ -0,0 +1,302 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    debugger::{Breakpoint, Debugger, Step, Stop, Target},
+    error::CalError,
+    interpreter::Value,
+    structure::Variable,
+    Computer, VmEmulator,
+};
+
+const SQUARE: &str = "fn square(x: i16) -> i16 {
+    let y: i16 = x * x;
+    y
+}
+fn main() -> i16 {
+    let a: i16 = 3;
+    let b: i16 = square(a);
+    let c: i16 = b + 1;
+    c
+}";
+
+/// Function and line of the statement the debugger stopped at
+fn location<T: Target>(debugger: &Debugger<T>) -> (String, usize) {
+    let origin = debugger.get_origin().expect("Stopped outside the program");
+    (origin.function.clone(), debugger.get_line(origin.range))
+}
+
+/// Formats variables as `name: type = value`
+fn format<T: Target>(debugger: &Debugger<T>, variables: &[(Variable, Value)]) -> Vec<String> {
+    variables
+        .iter()
+        .map(|(variable, value)| {
+            format!(
+                "{}: {} = {}",
+                variable.name,
+                variable.typ,
+                debugger.format_value(value)
+            )
+        })
+        .collect()
+}
+
+/// Variables of the innermost frame, arguments first
+fn variables<T: Target>(debugger: &mut Debugger<T>) -> Result<Vec<String>, CalError> {
+    let frame = debugger.get_backtrace().remove(0);
+    let mut variables = debugger.get_arguments(&frame)?;
+    variables.extend(debugger.get_locals(&frame)?);
+    Ok(format(debugger, &variables))
+}
+
+fn stepping<T: Target>() -> Result<(), CalError> {
+    let mut debugger = Debugger::<T>::new(SQUARE, &[])?;
+    assert_eq!(debugger.run(Step::In), Stop::Step);
+    assert_eq!(location(&debugger), ("main".into(), 6));
+    assert!(variables(&mut debugger)?.is_empty());
+
+    assert_eq!(debugger.run(Step::Over), Stop::Step);
+    assert_eq!(location(&debugger), ("main".into(), 7));
+    assert_eq!(debugger.run(Step::In), Stop::Step);
+    assert_eq!(location(&debugger), ("square".into(), 2));
+    assert_eq!(variables(&mut debugger)?, ["x: i16 = 3"]);
+
+    let backtrace = debugger.get_backtrace();
+    assert_eq!(backtrace.len(), 2);
+    assert_eq!(backtrace[1].origin.function, "main");
+    assert_eq!(debugger.get_line(backtrace[1].origin.range), 7);
+    let locals = debugger.get_locals(&backtrace[1])?;
+    assert_eq!(format(&debugger, &locals), ["a: i16 = 3"]);
+
+    // Multiplying calls a function of the preamble, which is stepped over
+    assert_eq!(debugger.run(Step::In), Stop::Step);
+    assert_eq!(location(&debugger), ("square".into(), 3));
+    assert_eq!(variables(&mut debugger)?, ["x: i16 = 3", "y: i16 = 9"]);
+
+    // Stepping out ends in the call, which is yet to assign its result
+    assert_eq!(debugger.run(Step::Out), Stop::Step);
+    assert_eq!(location(&debugger), ("main".into(), 7));
+    assert_eq!(debugger.get_backtrace().len(), 1);
+    assert_eq!(variables(&mut debugger)?, ["a: i16 = 3"]);
+
+    assert_eq!(debugger.run(Step::Over), Stop::Step);
+    assert_eq!(location(&debugger), ("main".into(), 8));
+    assert_eq!(variables(&mut debugger)?, ["a: i16 = 3", "b: i16 = 9"]);
+    assert_eq!(debugger.run(Step::In), Stop::Step);
+    assert_eq!(location(&debugger), ("main".into(), 9));
+    assert_eq!(debugger.run(Step::Over), Stop::Finished(10.into()));
+    assert_eq!(debugger.run(Step::In), Stop::Finished(10.into()));
+    Ok(())
+}
+
+#[test]
+fn stepping_computer() -> Result<(), CalError> {
+    stepping::<Computer>()
+}
+
+#[test]
+fn stepping_emulator() -> Result<(), CalError> {
+    stepping::<VmEmulator>()
+}
+
+fn stepping_over<T: Target>() -> Result<(), CalError> {
+    let mut debugger = Debugger::<T>::new(SQUARE, &[])?;
+    let mut lines = vec![];
+    while debugger.run(Step::Over) == Stop::Step {
+        lines.push(location(&debugger));
+    }
+    let main = |line| ("main".to_string(), line);
+    assert_eq!(lines, [main(6), main(7), main(8), main(9)]);
+
+    let code = "fn main() -> i16 {
+    let mut i: i16 = 0;
+    while (i < 2) {
+        i = i + 1;
+    }
+    if (i > 0) {
+        i = 5;
+    } else {
+        i = 6;
+    }
+    i
+}";
+    let mut debugger = Debugger::<T>::new(code, &[])?;
+    let mut lines = vec![];
+    let stop = loop {
+        match debugger.run(Step::Over) {
+            Stop::Step => lines.push(location(&debugger).1),
+            stop => break stop,
+        }
+    };
+    assert_eq!(lines, [2, 3, 4, 3, 4, 3, 6, 7, 11]);
+    assert_eq!(stop, Stop::Finished(5.into()));
+    Ok(())
+}
+
+#[test]
+fn stepping_over_computer() -> Result<(), CalError> {
+    stepping_over::<Computer>()
+}
+
+#[test]
+fn stepping_over_emulator() -> Result<(), CalError> {
+    stepping_over::<VmEmulator>()
+}
+
+fn breakpoints<T: Target>() -> Result<(), CalError> {
+    let mut debugger = Debugger::<T>::new(SQUARE, &[])?;
+    assert!(debugger.add_breakpoint(Breakpoint::Line(4)).is_err());
+    assert!(debugger
+        .add_breakpoint(Breakpoint::Function("cube".into()))
+        .is_err());
+    assert_eq!(debugger.add_breakpoint(Breakpoint::Line(3))?, 0);
+    assert_eq!(
+        debugger.add_breakpoint(Breakpoint::Function("square".into()))?,
+        1
+    );
+
+    assert_eq!(debugger.run(Step::Continue), Stop::Breakpoint(1));
+    assert_eq!(debugger.get_origin().unwrap().function, "square");
+    assert_eq!(debugger.get_backtrace().len(), 2);
+    assert_eq!(debugger.run(Step::Continue), Stop::Breakpoint(0));
+    assert_eq!(location(&debugger), ("square".into(), 3));
+
+    debugger.remove_breakpoint(0)?;
+    assert!(debugger.remove_breakpoint(1).is_err());
+    assert_eq!(debugger.run(Step::Continue), Stop::Finished(10.into()));
+    assert!(debugger.is_finished());
+
+    // Finishing from the entry of a function goes back to its caller
+    let mut debugger = Debugger::<T>::new(SQUARE, &[])?;
+    debugger.add_breakpoint(Breakpoint::Function("square".into()))?;
+    assert_eq!(debugger.run(Step::Continue), Stop::Breakpoint(0));
+    assert_eq!(debugger.run(Step::Out), Stop::Step);
+    assert_eq!(location(&debugger), ("main".into(), 7));
+
+    // A function without locals starts right at its first statement
+    let code = "fn sq(x: i16) -> i16 { x * x }
+fn main() -> i16 {
+    let a: i16 = sq(3);
+    a + 1
+}";
+    let mut debugger = Debugger::<T>::new(code, &[])?;
+    debugger.add_breakpoint(Breakpoint::Function("sq".into()))?;
+    assert_eq!(debugger.run(Step::Continue), Stop::Breakpoint(0));
+    assert_eq!(location(&debugger), ("sq".into(), 1));
+    assert_eq!(debugger.run(Step::Out), Stop::Step);
+    assert_eq!(location(&debugger), ("main".into(), 3));
+    assert_eq!(debugger.run(Step::Continue), Stop::Finished(10.into()));
+    Ok(())
+}
+
+#[test]
+fn breakpoints_computer() -> Result<(), CalError> {
+    breakpoints::<Computer>()
+}
+
+#[test]
+fn breakpoints_emulator() -> Result<(), CalError> {
+    breakpoints::<VmEmulator>()
+}
+
+fn call_stack<T: Target>() -> Result<(), CalError> {
+    let code = "fn fact(n: i16) -> i16 {
+    if (n < 2) {
+        return 1;
+    }
+    n * fact(n - 1)
+}
+fn main() -> i16 {
+    fact(4)
+}";
+    let mut debugger = Debugger::<T>::new(code, &[])?;
+    debugger.add_breakpoint(Breakpoint::Line(3))?;
+    assert_eq!(debugger.run(Step::Continue), Stop::Breakpoint(0));
+
+    let backtrace = debugger.get_backtrace();
+    let functions: Vec<&str> = backtrace
+        .iter()
+        .map(|frame| frame.origin.function.as_str())
+        .collect();
+    assert_eq!(functions, ["fact", "fact", "fact", "fact", "main"]);
+    let lines: Vec<usize> = backtrace
+        .iter()
+        .map(|frame| debugger.get_line(frame.origin.range))
+        .collect();
+    assert_eq!(lines, [3, 5, 5, 5, 8]);
+    for (frame, n) in backtrace[..4].iter().zip(1..) {
+        let arguments = debugger.get_arguments(frame)?;
+        assert_eq!(format(&debugger, &arguments), [format!("n: i16 = {}", n)]);
+    }
+
+    // Stepping out of each call goes back through the callers
+    for depth in [4, 3] {
+        assert_eq!(debugger.run(Step::Out), Stop::Step);
+        assert_eq!(location(&debugger), ("fact".into(), 5));
+        assert_eq!(debugger.get_backtrace().len(), depth);
+    }
+    assert_eq!(debugger.run(Step::Continue), Stop::Finished(24.into()));
+    Ok(())
+}
+
+#[test]
+fn call_stack_computer() -> Result<(), CalError> {
+    call_stack::<Computer>()
+}
+
+#[test]
+fn call_stack_emulator() -> Result<(), CalError> {
+    call_stack::<VmEmulator>()
+}
+
+fn values<T: Target>() -> Result<(), CalError> {
+    let code = "struct Point { x: i16, y: i16 }
+fn double(v: i16) -> i16 { v * 2 }
+fn main() -> i16 {
+    let p: Point = Point { x: 1, y: 2 };
+    let a: [i16; 3] = [4, 5, 6];
+    let r: &Point = &p;
+    let ok: bool = true;
+    let c: char = 'z';
+    0
+}";
+    let mut debugger = Debugger::<T>::new(code, &[])?;
+    debugger.add_breakpoint(Breakpoint::Line(9))?;
+    assert_eq!(debugger.add_watch("a[1] + r.y")?, 0);
+    assert_eq!(debugger.add_watch("double(a[2])")?, 1);
+    assert_eq!(debugger.add_watch("missing")?, 2);
+    assert!(debugger.add_watch("a[").is_err());
+
+    // Before `main` runs there is nothing to evaluate
+    assert!(debugger.get_watch_values().is_empty());
+
+    assert_eq!(debugger.run(Step::Continue), Stop::Breakpoint(0));
+    let variables = variables(&mut debugger)?;
+    assert_eq!(variables[0], "p: Point = Point { x: 1, y: 2 }");
+    assert_eq!(variables[1], "a: [i16; 3] = [4, 5, 6]");
+    assert!(variables[2].starts_with("r: &Point = &"));
+    assert_eq!(variables[3..], ["ok: bool = true", "c: char = 'z'"]);
+
+    let values = debugger.get_watch_values();
+    assert_eq!(values[0].clone()?, 7.into());
+    assert_eq!(values[1].clone()?, 12.into());
+    assert!(values[2].is_err());
+
+    let frame = debugger.get_backtrace().remove(0);
+    let value = debugger.evaluate("p", &frame)?;
+    assert_eq!(debugger.format_value(&value), "Point { x: 1, y: 2 }");
+    assert_eq!(debugger.remove_watch(2)?, "missing");
+    assert_eq!(debugger.get_watches().len(), 2);
+    Ok(())
+}
+
+#[test]
+fn values_computer() -> Result<(), CalError> {
+    values::<Computer>()
+}
+
+#[test]
+fn values_emulator() -> Result<(), CalError> {
+    values::<VmEmulator>()
+}
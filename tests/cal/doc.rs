@@ -0,0 +1,91 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    doc::{document, index, DocFormat},
+    structure::Module,
+};
+
+const CODE: &str = r#"/// A point
+///
+/// Coordinates are in pixels
+struct P { x: i16, y: i16 }
+
+// Not a doc comment
+impl P {
+    /// Sum of the coordinates
+    fn len(&self) -> i16 { self.x + self.y }
+}
+
+/// Returns `a < b`
+const fn less(a: i16, b: i16) -> bool { a < b }
+
+// A comment
+fn main() -> i16 { 0 }
+"#;
+
+#[test]
+fn doc_comments() {
+    let module: Module = CODE.parse().unwrap();
+    assert_eq!(
+        module.structs[0].doc,
+        "A point\n\nCoordinates are in pixels"
+    );
+    assert_eq!(module.functions[0].doc, "Sum of the coordinates");
+    assert_eq!(module.functions[1].doc, "Returns `a < b`");
+    assert_eq!(module.functions[2].doc, "");
+}
+
+#[test]
+fn impl_doc() {
+    let module: Module = r#"
+    /// Methods of a point
+    impl P { fn zero() -> P { P { x: 0 } } }
+    /// A point
+    struct P { x: i16 }
+    /// Comparing points
+    impl P { fn eq(&self, other: &P) -> bool { self.x == other.x } }
+    fn main() {}"#
+        .parse()
+        .unwrap();
+    assert_eq!(
+        module.structs[0].doc,
+        "A point\n\nMethods of a point\n\nComparing points"
+    );
+    let page = document("lib", &module, DocFormat::Markdown);
+    assert!(page.contains("### `P`\n\nA point\n\nMethods of a point\n\nComparing points\n\n"));
+}
+
+#[test]
+fn markdown() {
+    let module: Module = CODE.parse().unwrap();
+    let page = document("lib", &module, DocFormat::Markdown);
+    assert!(page.starts_with("# `lib`\n\n## Structs\n\n### `P`\n\nA point\n\n"));
+    assert!(page.contains("| `x` | `i16` |\n| `y` | `i16` |\n"));
+    assert!(page.contains(
+        "### `less`\n\n```cal\nconst fn less(a: i16, b: i16) -> bool\n```\n\nReturns `a < b`\n\n\
+         | Parameter | Type |\n| --- | --- |\n| `a` | `i16` |\n| `b` | `i16` |\n"
+    ));
+    assert!(page.contains("### `P::len`\n\n```cal\nfn P::len(&self) -> i16\n```\n\n"));
+    assert!(page.ends_with("### `main`\n\n```cal\nfn main() -> i16\n```\n\n"));
+}
+
+#[test]
+fn html() {
+    let module: Module = CODE.parse().unwrap();
+    let page = document("lib", &module, DocFormat::Html);
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("<p>A point</p>\n<p>Coordinates are in pixels</p>\n"));
+    assert!(page.contains("<pre><code>fn P::len(&amp;self) -&gt; i16</code></pre>"));
+    assert!(page.contains("<p>Returns `a &lt; b`</p>"));
+    assert!(page.ends_with("</body>\n</html>\n"));
+
+    let names = vec!["lib".to_string(), "math".to_string()];
+    assert!(index(&names, DocFormat::Html).contains("<a href=\"math.html\"><code>math</code></a>"));
+    assert_eq!(
+        index(&names, DocFormat::Markdown),
+        "## Modules\n\n- [`lib`](lib.md)\n- [`math`](math.md)\n"
+    );
+    assert!("pdf".parse::<DocFormat>().is_err());
+}
@@ -0,0 +1,147 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    dump::{Sexp, ToJson, ToSexp},
+    generator::Generate,
+    json::Json,
+    structure::Module,
+    tokenizer::tokenize,
+    vm::instruction::VmInstruction,
+};
+
+const CODE: &str = r#"struct P { x: i16 }
+fn main(p: &P) -> i16 {
+    let mut a: [i16; 2] = [1, 2];
+    while a[0] < 3 { a[0] = a[0] + 1; }
+    p.x
+}
+"#;
+
+#[test]
+fn tokens() {
+    let tokens: Vec<_> = tokenize("fn f(a: char) -> i16 { 'c' }")
+        .unwrap()
+        .by_ref()
+        .collect();
+    let json = tokens.to_json();
+    let first = &json.as_array().unwrap()[0];
+    assert_eq!(
+        first.to_string(),
+        r#"{"kind":"keyword","value":"fn","range":{"start":0,"end":2}}"#
+    );
+
+    let sexps: Vec<String> = tokens
+        .iter()
+        .map(|token| token.to_sexp().to_string())
+        .collect();
+    assert_eq!(sexps[1], "(identifier f 3..4)");
+    assert_eq!(sexps[2], r#"(symbol "(" 4..5)"#);
+    assert_eq!(sexps[10], "(char 'c' 23..26)");
+}
+
+#[test]
+fn ast_json() {
+    let module: Module = CODE.parse().unwrap();
+    let json = module.to_json();
+    // The JSON written is valid and can be read back
+    assert_eq!(json.to_string().parse::<Json>().unwrap(), json);
+
+    let function = &json.get("functions").unwrap().as_array().unwrap()[0];
+    assert_eq!(function.get("name").unwrap().as_str(), Some("main"));
+    assert_eq!(
+        function
+            .get("range")
+            .unwrap()
+            .get("start")
+            .unwrap()
+            .as_usize(),
+        Some(23)
+    );
+    let parameter = &function.get("parameters").unwrap().as_array().unwrap()[0];
+    assert_eq!(parameter.get("type").unwrap().as_str(), Some("&P"));
+
+    let body = function.get("body").unwrap().as_array().unwrap();
+    assert_eq!(body.len(), 3);
+    assert_eq!(body[1].get("kind").unwrap().as_str(), Some("while"));
+    let predicate = body[1].get("predicate").unwrap();
+    assert_eq!(predicate.get("op").unwrap().as_str(), Some("<"));
+    let range = predicate.get("range").unwrap();
+    assert_eq!(
+        &CODE[range.get("start").unwrap().as_usize().unwrap()
+            ..range.get("end").unwrap().as_usize().unwrap()],
+        "a[0] < 3"
+    );
+}
+
+#[test]
+fn ast_sexp() {
+    let module: Module = CODE.parse().unwrap();
+    let sexp = module.to_sexp();
+    assert_eq!(
+        sexp.to_string(),
+        "(module main (struct P (x i16)) (fn main ((p (ref P))) i16 \
+         (let (mut a (array i16 2)) (array 1 2)) \
+         (while (< (index a 0) 3) (= (index a 0) (+ (index a 0) 1))) \
+         (field p x)))"
+    );
+    assert_eq!(
+        sexp.pretty(40),
+        r#"(module main
+  (struct P (x i16))
+  (fn main
+    ((p (ref P)))
+    i16
+    (let
+      (mut a (array i16 2))
+      (array 1 2))
+    (while
+      (< (index a 0) 3)
+      (= (index a 0) (+ (index a 0) 1)))
+    (field p x)))"#
+    );
+}
+
+#[test]
+fn pretty() {
+    let sexp = Sexp::list("a", [Sexp::atom("b"), Sexp::list("c", [Sexp::atom("d")])]);
+    assert_eq!(sexp.pretty(80), "(a b (c d))");
+    assert_eq!(sexp.pretty(4), "(a b\n  (c d))");
+}
+
+#[test]
+fn vm() {
+    let instructions = "fn main() -> i16 { 1 }".generate().unwrap();
+    let sexps: Vec<String> = instructions
+        .iter()
+        .map(|i| i.to_sexp().to_string())
+        .collect();
+    assert_eq!(
+        sexps,
+        ["(function main 0)", "(push constant 1)", "(return)"]
+    );
+    assert_eq!(
+        instructions.to_json().to_string(),
+        r#"[{"op":"function","name":"main","local_count":0},{"op":"push","segment":"constant","index":1},{"op":"return","word_count":1}]"#
+    );
+
+    // VM code displayed can be parsed back
+    for instruction in [
+        VmInstruction::Return(0),
+        VmInstruction::IfGoto("L".into()),
+        VmInstruction::Pop(acs::vm::segment::Segment::Pointer, 1),
+    ] {
+        let text = instruction.to_string();
+        assert_eq!(text.parse::<VmInstruction>().unwrap(), instruction);
+    }
+}
+
+#[test]
+fn static_item() {
+    let module: Module = "static A: [i16; 2] = [1, 2];".parse().unwrap();
+    assert_eq!(
+        module.to_sexp().to_string(),
+        "(module main (static A (array i16 2) (array 1 2)))"
+    );
+}
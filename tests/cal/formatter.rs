@@ -0,0 +1,161 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use acs::{error::CalError, formatter::format, structure::Module};
+
+/// Formats code checking that formatting is idempotent and that the code
+/// means the same before and after
+fn check_format(code: &str) -> Result<String, CalError> {
+    let formatted = format(code)?;
+    assert_eq!(format(&formatted)?, formatted);
+    assert_eq!(formatted.parse::<Module>()?, code.parse::<Module>()?);
+    Ok(formatted)
+}
+
+#[test]
+fn canonical() -> Result<(), CalError> {
+    let code = "fn main()->i16{let a:[i16;2]=[1,2,];let mut x:i16=a[0]*2;x=x+1; x}";
+    let expected = r#"fn main() -> i16 {
+    let a: [i16; 2] = [1, 2];
+    let mut x: i16 = a[0] * 2;
+    x = x + 1;
+    x
+}
+"#;
+    assert_eq!(check_format(code)?, expected);
+
+    let code = r#"
+struct P{x:i16,y:i16}
+impl P { fn new()->Self{Self{x:0,y:0,}} # [ inline ] fn sum(&mut self)->i16{ self.x+self.y } }
+fn main(){ let mut p:P=P::new(); let r:&mut P=&mut p; if r.sum()==0&true{ p.x=1; }else{} while false {} }
+"#;
+    let expected = r#"struct P {
+    x: i16,
+    y: i16,
+}
+impl P {
+    fn new() -> Self {
+        Self { x: 0, y: 0 }
+    }
+    #[inline]
+    fn sum(&mut self) -> i16 {
+        self.x + self.y
+    }
+}
+fn main() {
+    let mut p: P = P::new();
+    let r: &mut P = &mut p;
+    if r.sum() == 0 & true {
+        p.x = 1;
+    } else {}
+    while false {}
+}
+"#;
+    assert_eq!(check_format(code)?, expected);
+    Ok(())
+}
+
+#[test]
+fn literals() -> Result<(), CalError> {
+    let code =
+        "const MASK:i16=0b1010;fn main()->(char,i16){let t:(i16,)=(sizeof([i16;2]),);('a',t.0)}";
+    let expected = r#"const MASK: i16 = 0b1010;
+fn main() -> (char, i16) {
+    let t: (i16,) = (sizeof([i16; 2]),);
+    ('a', t.0)
+}
+"#;
+    assert_eq!(check_format(code)?, expected);
+
+    // Long arrays are laid out one element per line, with a trailing comma
+    let values: Vec<String> = (0..30).map(|i| (i * 1000).to_string()).collect();
+    let code = format!("fn main() {{ let a: [i16; 30] = [{}]; }}", values.join(","));
+    let formatted = check_format(&code)?;
+    assert!(formatted.contains("= [\n        0,\n        1000,\n"));
+    assert!(formatted.contains("        29000,\n    ];\n"));
+    Ok(())
+}
+
+#[test]
+fn stdlib() {
+    let std_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/cal/std");
+    let mut paths: Vec<PathBuf> = fs::read_dir(std_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    let output = Command::new(env!("CARGO_BIN_EXE_calfmt"))
+        .arg("--check")
+        .args(&paths)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn comments() -> Result<(), CalError> {
+    let code = r#"// Entry point
+fn main() -> i16 { // returns one
+
+
+    // first
+    let a: i16 = 1; // trailing
+    a
+    // last
+}
+"#;
+    let expected = r#"// Entry point
+fn main() -> i16 { // returns one
+
+    // first
+    let a: i16 = 1; // trailing
+    a
+    // last
+}
+"#;
+    assert_eq!(check_format(code)?, expected);
+    Ok(())
+}
+
+#[test]
+fn continuation_comments() -> Result<(), CalError> {
+    let code = r#"fn main() -> i16 {
+    let mut a: i16 = 1;
+    a = a + // c
+    1;
+    a = a * // d
+    // e
+    2;
+    a
+}
+"#;
+    let expected = r#"fn main() -> i16 {
+    let mut a: i16 = 1;
+    a = a + // c
+        1;
+    a = a * // d
+        // e
+        2;
+    a
+}
+"#;
+    assert_eq!(check_format(code)?, expected);
+    Ok(())
+}
+
+#[test]
+fn invalid() {
+    assert!(format("fn main() { let }").is_err());
+}
@@ -151,14 +151,14 @@ fn multi_parameters() -> Result<(), CalError> {
 
 #[test]
 fn add() -> Result<(), CalError> {
-    let vm_instructions = "fn main() { 1 + 2; }".generate()?;
+    let vm_instructions = "fn main(a: i16) { a + 2; }".generate()?;
     let VmInstruction::Function(name, 0) = &vm_instructions[0] else {
         panic!();
     };
     assert_eq!(name, "main");
     assert_eq!(
         vm_instructions[1],
-        VmInstruction::Push(Segment::Constant, 1)
+        VmInstruction::Push(Segment::Argument, 0)
     );
     assert_eq!(
         vm_instructions[2],
@@ -268,11 +268,11 @@ fn while_statement() -> Result<(), CalError> {
 #[test]
 fn cmp() -> Result<(), CalError> {
     let vm_instructions = r#"
-        fn main() -> bool {
-            1 == 1;
-            1 != 2;
-            1 < 2;
-            2 > 1
+        fn main(a: i16) -> bool {
+            a == 1;
+            a != 2;
+            a < 2;
+            2 > a
         }"#
     .generate()?;
     let mut index = 0;
@@ -284,7 +284,7 @@ fn cmp() -> Result<(), CalError> {
     index += 1;
     assert_eq!(
         vm_instructions[index],
-        VmInstruction::Push(Segment::Constant, 1)
+        VmInstruction::Push(Segment::Argument, 0)
     );
     index += 1;
     assert_eq!(
@@ -297,7 +297,7 @@ fn cmp() -> Result<(), CalError> {
     index += 1;
     assert_eq!(
         vm_instructions[index],
-        VmInstruction::Push(Segment::Constant, 1)
+        VmInstruction::Push(Segment::Argument, 0)
     );
     index += 1;
     assert_eq!(
@@ -312,7 +312,7 @@ fn cmp() -> Result<(), CalError> {
     index += 1;
     assert_eq!(
         vm_instructions[index],
-        VmInstruction::Push(Segment::Constant, 1)
+        VmInstruction::Push(Segment::Argument, 0)
     );
     index += 1;
     assert_eq!(
@@ -330,7 +330,7 @@ fn cmp() -> Result<(), CalError> {
     index += 1;
     assert_eq!(
         vm_instructions[index],
-        VmInstruction::Push(Segment::Constant, 1),
+        VmInstruction::Push(Segment::Argument, 0),
     );
     index += 1;
     assert_eq!(vm_instructions[index], VmInstruction::Gt);
@@ -344,7 +344,7 @@ fn cmp() -> Result<(), CalError> {
 fn assign_expression() -> Result<(), CalError> {
     let vm_instructions = r#"
     fn main() {
-        let a: i16 = 0;
+        let mut a: i16 = 0;
         a = 1;
     }"#
     .generate()?;
@@ -383,14 +383,14 @@ fn assign_expression() -> Result<(), CalError> {
 
 #[test]
 fn mul() -> Result<(), CalError> {
-    let vm_instructions = "fn main() { 1 * 2; }".generate()?;
+    let vm_instructions = "fn main(a: i16) { a * 2; }".generate()?;
     let VmInstruction::Function(name, 0) = &vm_instructions[0] else {
         panic!();
     };
     assert_eq!(name, "main");
     assert_eq!(
         vm_instructions[1],
-        VmInstruction::Push(Segment::Constant, 1)
+        VmInstruction::Push(Segment::Argument, 0)
     );
     assert_eq!(
         vm_instructions[2],
@@ -405,14 +405,14 @@ fn mul() -> Result<(), CalError> {
 
 #[test]
 fn and() -> Result<(), CalError> {
-    let vm_instructions = "fn main() { 1 & 2; }".generate()?;
+    let vm_instructions = "fn main(a: i16) { a & 2; }".generate()?;
     let VmInstruction::Function(name, 0) = &vm_instructions[0] else {
         panic!();
     };
     assert_eq!(name, "main");
     assert_eq!(
         vm_instructions[1],
-        VmInstruction::Push(Segment::Constant, 1)
+        VmInstruction::Push(Segment::Argument, 0)
     );
     assert_eq!(
         vm_instructions[2],
@@ -424,14 +424,14 @@ fn and() -> Result<(), CalError> {
 
 #[test]
 fn or() -> Result<(), CalError> {
-    let vm_instructions = "fn main() { 1 | 2; }".generate()?;
+    let vm_instructions = "fn main(a: i16) { a | 2; }".generate()?;
     let VmInstruction::Function(name, 0) = &vm_instructions[0] else {
         panic!();
     };
     assert_eq!(name, "main");
     assert_eq!(
         vm_instructions[1],
-        VmInstruction::Push(Segment::Constant, 1)
+        VmInstruction::Push(Segment::Argument, 0)
     );
     assert_eq!(
         vm_instructions[2],
@@ -443,14 +443,14 @@ fn or() -> Result<(), CalError> {
 
 #[test]
 fn modulo() -> Result<(), CalError> {
-    let vm_instructions = "fn main() { 2 % 1; }".generate()?;
+    let vm_instructions = "fn main(a: i16) { a % 1; }".generate()?;
     let VmInstruction::Function(name, 0) = &vm_instructions[0] else {
         panic!();
     };
     assert_eq!(name, "main");
     assert_eq!(
         vm_instructions[1],
-        VmInstruction::Push(Segment::Constant, 2)
+        VmInstruction::Push(Segment::Argument, 0)
     );
     assert_eq!(
         vm_instructions[2],
@@ -507,11 +507,11 @@ fn character() -> Result<(), CalError> {
 fn reference() -> Result<(), CalError> {
     let vm_instructions = r#"
     fn main() -> i16 {
-        let a: i16 = 1;
-        pass(&a);
+        let mut a: i16 = 1;
+        pass(&mut a);
         a
     }
-    fn pass(a: &i16) {
+    fn pass(a: &mut i16) {
         a = 2;
     }
     "#
@@ -561,11 +561,11 @@ fn reference() -> Result<(), CalError> {
 
     let vm_instructions = r#"
     fn main() -> i16 {
-        let a: [i16; 2] = [1, 2];
-        pass(&a);
+        let mut a: [i16; 2] = [1, 2];
+        pass(&mut a);
         a[1]
     }
-    fn pass(a: &[i16; 2]) {
+    fn pass(a: &mut [i16; 2]) {
         a[1] = 3;
     }
     "#
@@ -710,11 +710,11 @@ fn array_of_array() -> Result<(), CalError> {
 fn array_of_array_reference() -> Result<(), CalError> {
     let vm_instructions = r#"
     fn main() -> [i16; 2] {
-        let a: [[i16; 2]; 2] = [[1, 2], [3, 4]];
-        pass(&a[1]);
+        let mut a: [[i16; 2]; 2] = [[1, 2], [3, 4]];
+        pass(&mut a[1]);
         a[1]
     }
-    fn pass(a: &[i16; 2]) {
+    fn pass(a: &mut [i16; 2]) {
         a[1] = 5;
     }"#
     .generate()?;
@@ -804,3 +804,95 @@ fn array_of_array_reference() -> Result<(), CalError> {
     assert_eq!(vm_instructions[31], VmInstruction::Return(2));
     Ok(())
 }
+
+#[test]
+fn nested_index() -> Result<(), CalError> {
+    let vm_instructions = r#"
+    fn get(grid: &[[i16; 32]; 16]) -> i16 {
+        grid[3][5]
+    }"#
+    .generate()?;
+    // Column index
+    assert_eq!(
+        vm_instructions[1],
+        VmInstruction::Push(Segment::Constant, 5)
+    );
+    // Row index
+    assert_eq!(
+        vm_instructions[2],
+        VmInstruction::Push(Segment::Constant, 3)
+    );
+    // Size of a row
+    assert_eq!(
+        vm_instructions[3],
+        VmInstruction::Push(Segment::Constant, 32)
+    );
+    assert_eq!(vm_instructions[4], VmInstruction::Call("mul".into(), 2));
+    // Reference is already a pointer to the beginning of the grid
+    assert_eq!(
+        vm_instructions[5],
+        VmInstruction::Push(Segment::Argument, 0)
+    );
+    assert_eq!(vm_instructions[6], VmInstruction::Add);
+    // Address of the row + column index
+    assert_eq!(vm_instructions[7], VmInstruction::Add);
+    assert_eq!(vm_instructions[8], VmInstruction::Pop(Segment::Pointer, 0));
+    assert_eq!(vm_instructions[9], VmInstruction::Push(Segment::This, 0));
+    assert_eq!(vm_instructions[10], VmInstruction::Return(1));
+    Ok(())
+}
+
+#[test]
+fn constant_folding() -> Result<(), CalError> {
+    let vm_instructions = "fn main() -> i16 { 1 + 2 }".generate()?;
+    assert_eq!(
+        vm_instructions[1],
+        VmInstruction::Push(Segment::Constant, 3)
+    );
+    assert_eq!(vm_instructions[2], VmInstruction::Return(1));
+
+    // Negative values are pushed as their complement
+    let vm_instructions = "fn main() -> i16 { 1 - 2 }".generate()?;
+    assert_eq!(
+        vm_instructions[1],
+        VmInstruction::Push(Segment::Constant, 0)
+    );
+    assert_eq!(vm_instructions[2], VmInstruction::Not);
+
+    // Locals shadow constants and are not folded
+    let vm_instructions = r#"
+    const A: i16 = 3;
+    const fn twice(x: i16) -> i16 { x + x }
+    fn main() -> i16 { let A: i16 = 1; twice(A) + A }"#
+        .generate()?;
+    // Functions are generated in order, `main` comes after `twice`
+    assert_eq!(vm_instructions[8], VmInstruction::Push(Segment::Local, 0));
+    assert_eq!(vm_instructions[9], VmInstruction::Call("twice".into(), 1));
+
+    let vm_instructions = r#"
+    const A: i16 = 3;
+    const fn twice(x: i16) -> i16 { x + x }
+    fn main() -> i16 { twice(A) + A }"#
+        .generate()?;
+    assert_eq!(
+        vm_instructions[6],
+        VmInstruction::Push(Segment::Constant, 9)
+    );
+    Ok(())
+}
+
+#[test]
+fn constant_error() {
+    assert!("const A: i16 = 1 / 0; fn main() {}".generate().is_err());
+    assert!("fn main() { let a: [i16; 2 - 2] = []; }"
+        .generate()
+        .is_err());
+    assert!("fn f() -> i16 { 1 } fn main() { let a: [i16; f()] = [1]; }"
+        .generate()
+        .is_err());
+    assert!(
+        "const fn f(x: i16) -> i16 { f(x) } fn main() { let a: [i16; f(1)] = [1]; }"
+            .generate()
+            .is_err()
+    );
+}
@@ -0,0 +1,173 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{env, error::Error, fs, path::Path};
+
+use acs::{
+    compiler::{compile, CompileOptions},
+    Computer,
+};
+
+/// Runs a program linking the graphics library until `main` returns 42, then
+/// compares the screen with `tests/cal/images/<name>.png`. Setting
+/// `CAL_BLESS` writes the reference image instead
+fn draw(name: &str, code: &str) -> Result<Computer, Box<dyn Error>> {
+    let options = CompileOptions {
+        link: vec!["graphics".into()],
+        ..Default::default()
+    };
+    let mut computer = Computer::default();
+    computer.set_instructions(
+        compile(code, &options)
+            .map_err(|err| err.message)?
+            .instructions,
+    );
+    let mut cycles = 0;
+    while computer.get_memory().ram[0] != 257 || computer.get_memory().ram[256] != 42 {
+        for _ in 0..1024 {
+            computer.ticktock();
+        }
+        cycles += 1024;
+        assert!(cycles < 2_000_000, "{} did not finish", name);
+    }
+
+    let out_dir = Path::new("target/screen");
+    fs::create_dir_all(out_dir)?;
+    let out_path = out_dir.join(format!("{}.png", name));
+    computer.get_screen().dump(&out_path)?;
+    let reference_path = Path::new("tests/cal/images").join(format!("{}.png", name));
+    if env::var_os("CAL_BLESS").is_some() {
+        fs::copy(&out_path, &reference_path)?;
+    }
+    assert!(
+        fs::read(&out_path)? == fs::read(&reference_path)?,
+        "{} differs from {}",
+        out_path.display(),
+        reference_path.display()
+    );
+    Ok(computer)
+}
+
+#[test]
+fn pixels() -> Result<(), Box<dyn Error>> {
+    let computer = draw(
+        "pixels",
+        r#"
+        fn main() -> i16 {
+            set_pixel(0, 0, true);
+            set_pixel(511, 0, true);
+            set_pixel(0, 255, true);
+            set_pixel(511, 255, true);
+            set_pixel(15, 100, true);
+            set_pixel(16, 100, true);
+            set_pixel(17, 100, true);
+            set_pixel(16, 100, false);
+            set_pixel(512, 10, true);
+            set_pixel(10, 0 - 1, true);
+            42
+        }"#,
+    )?;
+    let screen = computer.get_screen();
+    for (x, y) in [(0, 0), (511, 0), (0, 255), (511, 255), (15, 100), (17, 100)] {
+        assert_eq!(screen.get_pixel(x, y), 1, "({}, {})", x, y);
+    }
+    assert_eq!(screen.get_pixel(16, 100), 0);
+    Ok(())
+}
+
+#[test]
+fn lines() -> Result<(), Box<dyn Error>> {
+    draw(
+        "lines",
+        r#"
+        fn main() -> i16 {
+            hline(5, 40, 10, true);
+            hline(100, 103, 12, true);
+            hline(600, 0 - 20, 14, true);
+            hline(20, 30, 14, false);
+            vline(3, 20, 60, true);
+            vline(300, 300, 200, true);
+            line(256, 128, 356, 168, true);
+            line(256, 128, 296, 28, true);
+            line(256, 128, 156, 88, true);
+            line(256, 128, 216, 228, true);
+            42
+        }"#,
+    )?;
+    Ok(())
+}
+
+#[test]
+fn shapes() -> Result<(), Box<dyn Error>> {
+    draw(
+        "shapes",
+        r#"
+        fn main() -> i16 {
+            rect(10, 10, 100, 50, true);
+            fill_rect(20, 20, 30, 20, true);
+            fill_rect(25, 25, 10, 5, false);
+            circle(200, 100, 40, true);
+            fill_circle(350, 150, 30, true);
+            circle(500, 240, 30, true);
+            42
+        }"#,
+    )?;
+    Ok(())
+}
+
+#[test]
+fn sprite() -> Result<(), Box<dyn Error>> {
+    draw(
+        "sprite",
+        r#"
+        static FACE: [i16; 16] = [
+            0x07e0, 0x1818, 0x2004, 0x4002, 0x4c32, 0x8c31, 0x8001, 0x8001,
+            0x8001, 0x9009, 0x8811, 0x47e2, 0x4002, 0x2004, 0x1818, 0x07e0,
+        ];
+        fn main() -> i16 {
+            blit(0, 0, &FACE);
+            blit(10, 100, &FACE);
+            blit(31, 248, &FACE);
+            blit(32, 0, &FACE);
+            42
+        }"#,
+    )?;
+    Ok(())
+}
+
+#[test]
+fn fill() -> Result<(), Box<dyn Error>> {
+    draw(
+        "fill",
+        r#"
+        fn main() -> i16 {
+            fill(true);
+            fill_rect(200, 100, 100, 40, false);
+            rect(210, 110, 80, 20, true);
+            42
+        }"#,
+    )?;
+    Ok(())
+}
+
+#[test]
+fn link_errors() {
+    let options = CompileOptions {
+        link: vec!["graphics".into()],
+        ..Default::default()
+    };
+    let code = "fn clear() {} fn main() { clear(); }";
+    assert_eq!(
+        compile(code, &options).err().unwrap().message,
+        "`clear` is declared by both `main` and library `graphics`"
+    );
+    let options = CompileOptions {
+        link: vec!["sound".into()],
+        ..Default::default()
+    };
+    assert_eq!(
+        compile("fn main() {}", &options).err().unwrap().message,
+        "Unknown library `sound`, expected one of graphics, console, keyboard"
+    );
+}
@@ -0,0 +1,99 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{fs, process::Command};
+
+use acs::{compiler::Backend, error::CalError};
+
+use super::Target;
+
+const TARGET: Target = Target::new(Backend::Hack, 0);
+
+// Loaded again on purpose, to run the compiler suite on `TARGET`
+#[allow(clippy::duplicate_mod)]
+#[path = "compiler.rs"]
+mod compiler;
+
+#[test]
+fn faster() -> Result<(), CalError> {
+    let programs = [
+        r#"
+        fn run() -> i16 {
+            let mut sum: i16 = 0;
+            let mut i: i16 = 0;
+            while i < 100 {
+                if (i & 1) == 0 {
+                    sum = sum + i;
+                }
+                i = i + 1;
+            }
+            sum
+        }"#,
+        r#"
+        fn fib(n: i16) -> i16 {
+            if n < 2 {
+                return n;
+            }
+            fib(n - 1) + fib(n - 2)
+        }
+        fn run() -> i16 { fib(10) }"#,
+        r#"
+        fn run() -> i16 {
+            let mut a: [i16; 8] = [5, 3, 7, 1, 8, 2, 6, 4];
+            let mut i: i16 = 0;
+            while i < 8 {
+                let mut j: i16 = i + 1;
+                while j < 8 {
+                    if a[j] < a[i] {
+                        let t: i16 = a[i];
+                        a[i] = a[j];
+                        a[j] = t;
+                    }
+                    j = j + 1;
+                }
+                i = i + 1;
+            }
+            (a[0] * 1000) + ((a[3] * 100) + (a[7] * 7 / 2))
+        }"#,
+    ];
+    for code in programs {
+        let (vm_result, vm_cycles, _) = Target::new(Backend::Vm, 0).run(code)?;
+        let (hack_result, hack_cycles, _) = Target::new(Backend::Hack, 0).run(code)?;
+        assert_eq!(hack_result, vm_result);
+        // At least one and a half times faster, even when mostly calling
+        assert!(
+            hack_cycles * 3 < vm_cycles * 2,
+            "{} cycles against {}",
+            hack_cycles,
+            vm_cycles
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn unsupported_options() {
+    let dir = std::env::temp_dir().join("hack_unsupported_options");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.cal"), "fn main() -> i16 { 1 }").unwrap();
+    let cases: [(&[&str], &str); 2] = [
+        (&["-g"], "`-g` is only supported with `--backend vm`"),
+        (
+            &["--emit", "ir"],
+            "`--emit ir` stops before lowering to asm, so `--backend hack` has no effect",
+        ),
+    ];
+    for (args, message) in cases {
+        let output = Command::new(env!("CARGO_BIN_EXE_calc"))
+            .current_dir(&dir)
+            .arg("main.cal")
+            .args(args)
+            .args(["--backend", "hack"])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains(message));
+        assert!(!dir.join("out.asm.map").exists());
+    }
+}
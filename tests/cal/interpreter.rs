@@ -0,0 +1,231 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    compiler::{compile, CompileOptions},
+    error::CalError,
+    interpreter::{Interpret, Interpreter, Value},
+    structure::{Module, Type},
+    Computer,
+};
+
+#[test]
+fn return_value() -> Result<(), CalError> {
+    assert_eq!("fn main() {}".interpret()?, Value::void());
+    assert_eq!("fn main() -> i16 { 1 }".interpret()?, Value::from(1));
+    assert_eq!(
+        "fn main() -> bool { return 1 < 2; }".interpret()?,
+        Value::new(Type::Bool, vec![-1])
+    );
+    assert_eq!(
+        "fn main() -> (i16, char) { (1, 'a') }".interpret()?,
+        Value::new(Type::Tuple(vec![Type::I16, Type::Char]), vec![1, 97])
+    );
+    Ok(())
+}
+
+#[test]
+fn wrap() -> Result<(), CalError> {
+    let code = "fn main() -> i16 { let a: i16 = 32767; a + 1 }";
+    assert_eq!(code.interpret()?, Value::from(i16::MIN));
+    let code = "fn main() -> i16 { let a: i16 = 300; a * 300 }";
+    assert_eq!(code.interpret()?, Value::from(300i16.wrapping_mul(300)));
+    let code = "fn main() -> i16 { let a: i16 = 7; a / 0 }";
+    assert_eq!(code.interpret()?, Value::from(-1));
+    let code = "fn main() -> i16 { let a: i16 = 7; a % 0 }";
+    assert_eq!(code.interpret()?, Value::from(7));
+    Ok(())
+}
+
+#[test]
+fn memory() -> Result<(), CalError> {
+    let code = r#"
+    fn main() -> i16 {
+        poke(16384, 42);
+        let a: i16 = 1;
+        peek(16384) + peek(261)
+    }"#;
+    let module: Module = code.parse()?;
+    let mut interpreter = Interpreter::default();
+    interpreter.load(&module)?;
+    assert_eq!(interpreter.run()?, Value::from(43));
+    assert_eq!(interpreter.get_memory()[16384], 42);
+
+    interpreter.get_memory_mut()[24576] = 'k' as i16;
+    let module: Module = "fn key() -> char { let k: char = peek(24576); k }".parse()?;
+    interpreter.load(&module)?;
+    assert_eq!(
+        interpreter.call("key", vec![])?,
+        Value::new(Type::Char, vec!['k' as i16])
+    );
+    Ok(())
+}
+
+#[test]
+fn mutability() {
+    let code = "fn main() { let a: i16 = 1; a = 2; }";
+    assert_eq!(
+        code.interpret().unwrap_err().message,
+        "Cannot assign to immutable variable `a`, declare it as `mut a`"
+    );
+    let code = r#"
+    fn set(a: &[i16; 2]) { a[0] = 1; }
+    fn main() { let mut a: [i16; 2] = [0, 0]; set(&a); }
+    "#;
+    assert_eq!(
+        code.interpret().unwrap_err().message,
+        "Cannot assign to a place behind a `&` reference, use `&mut` instead"
+    );
+    let code = r#"
+    fn set(a: &mut i16) { a = 1; }
+    fn main() { let mut a: i16 = 0; set(&a); }
+    "#;
+    assert_eq!(
+        code.interpret().unwrap_err().message,
+        "Expected `&mut` reference, found `&` reference"
+    );
+    // Nor returned, or held by tuples and structs
+    for code in [
+        "fn f(a: &i16) -> &mut i16 { a } fn main() { let x: i16 = 1; let m: &mut i16 = f(&x); m = 7; }",
+        "fn f(a: &i16) -> &mut i16 { return a; } fn main() { let x: i16 = 1; f(&x); }",
+        "fn f(a: &i16) -> (&mut i16, i16) { (a, 1) } fn main() { let x: i16 = 1; f(&x); }",
+        "struct S { r: &mut i16 } fn main() { let x: i16 = 1; let s: S = S { r: &x }; }",
+    ] {
+        assert_eq!(
+            code.interpret().unwrap_err().message,
+            "Expected `&mut` reference, found `&` reference"
+        );
+    }
+    let code = "static A: [i16; 2] = [1, 2]; fn main() { A[1] = 0; }";
+    assert_eq!(
+        code.interpret().unwrap_err().message,
+        "Cannot assign to static `A`, which is read-only"
+    );
+    let zeros = vec!["0"; 2049].join(", ");
+    let code = format!("static A: [i16; 2049] = [{}]; fn main() {{}}", zeros);
+    assert_eq!(
+        code.as_str().interpret().unwrap_err().message,
+        "Statics exceed the data section of 2048 words at `A`"
+    );
+}
+
+#[test]
+fn statements() -> Result<(), CalError> {
+    let module: Module = r#"
+    struct P { x: i16, y: i16 }
+    fn first() { let mut p: P = P { x: 1, y: 2 }; p.x = 3; }
+    fn second() { p }
+    "#
+    .parse()?;
+    let mut interpreter = Interpreter::default();
+    interpreter.load(&module)?;
+
+    let first = &module.functions[0].body_statements;
+    assert_eq!(interpreter.exec(first)?, None);
+
+    // Variables are kept across executions
+    let second = &module.functions[1].body_statements;
+    let value = interpreter.exec(second)?.unwrap();
+    assert_eq!(interpreter.format_value(&value), "P { x: 3, y: 2 }");
+    Ok(())
+}
+
+/// Runs `main` on the computer, returning the word it leaves on the stack
+fn run_compiled(code: &str, opt_level: u8) -> Result<i16, CalError> {
+    let options = CompileOptions {
+        opt_level,
+        ..Default::default()
+    };
+    let mut computer = Computer::default();
+    computer.set_instructions(compile(code, &options)?.instructions);
+    for _ in 0..65536 {
+        computer.ticktock();
+    }
+    Ok(computer.get_memory().ram[256])
+}
+
+#[test]
+fn oracle() -> Result<(), CalError> {
+    let programs = [
+        r#"
+        fn fib(n: i16) -> i16 {
+            if n < 2 { return n; }
+            fib(n - 1) + fib(n - 2)
+        }
+        fn main() -> i16 { fib(10) }
+        "#,
+        r#"
+        const N: i16 = 4;
+        fn main() -> i16 {
+            let mut a: [i16; N] = [3, 1, 4, 1];
+            let mut i: i16 = 0;
+            let mut sum: i16 = 0;
+            while i < N {
+                a[i] = a[i] * 3 % 5;
+                sum = sum + a[i];
+                i = i + 1;
+            }
+            sum
+        }
+        "#,
+        r#"
+        struct Point { x: i16, y: i16 }
+        impl Point {
+            fn translate(&mut self, dx: i16) { self.x = self.x + dx; }
+            fn len(&self) -> i16 { self.x + self.y }
+        }
+        fn reset(a: &mut i16) { a = 5; }
+        fn main() -> i16 {
+            let mut p: Point = Point { x: 1, y: 20 };
+            p.translate(2);
+            reset(&mut p.y);
+            p.x - p.len() / 3
+        }
+        "#,
+        r#"
+        fn divmod(a: i16, b: i16) -> (i16, i16) { (a / b, a % b) }
+        fn main() -> i16 {
+            let (q, r) = divmod(47, 5);
+            let m: [[i16; 2]; 2] = [[q, r], [r, q]];
+            m[1][0] * 10 + m[1][1]
+        }
+        "#,
+        r#"
+        fn main() -> i16 {
+            let mut x: i16 = 32000;
+            x = x + 1000;
+            (x > 0) | (x == 0 - 32536)
+        }
+        "#,
+        r#"
+        struct P { x: i16, y: i16 }
+        static POINTS: [P; 2] = [P { x: 1, y: 2 }, P { x: 3, y: 4 }];
+        static ONES: [i16; 9] = [1, 1, 1, 1, 1, 1, 1, 1, 1];
+        fn main() -> i16 { POINTS[1].y * 10 + ONES[8] + POINTS[0].x }
+        "#,
+        r#"
+        fn divmod(a: i16, b: i16) -> (i16, i16) { (a / b, a % b) }
+        fn main() -> i16 {
+            let (q, r) = divmod(7, 0);
+            let (s, t) = divmod(0 - 9, 0);
+            let z: i16 = 0;
+            q * 1000 + r * 100 + s * 10 + t + 5 / z
+        }
+        "#,
+    ];
+
+    for program in programs {
+        for opt_level in [0, 2] {
+            let expected = run_compiled(program, opt_level)?;
+            assert_eq!(
+                program.interpret()?.words,
+                vec![expected],
+                "-O{}: {}",
+                opt_level,
+                program
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,421 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    compiler::Backend,
+    error::CalError,
+    generator::Generator,
+    ir::{pass, Code, Function, Program},
+    parser::parse,
+    tokenizer::tokenize,
+    vm::instruction::VmInstruction,
+};
+
+use super::Target;
+
+const TARGET: Target = Target::new(Backend::Vm, 2);
+
+// Loaded again on purpose, to run the compiler suite on `TARGET`
+#[allow(clippy::duplicate_mod)]
+#[path = "compiler.rs"]
+mod compiler;
+
+/// Generates the VM instructions of a program, without optimising them
+fn generate(code: &str) -> Result<Vec<VmInstruction>, CalError> {
+    Generator::default().gen(&[parse(tokenize(code)?)?])
+}
+
+/// Lifts a function out of a program and runs some passes on it, returning
+/// its dump
+fn optimize(code: &str, name: &str, passes: &[fn(&mut Function)]) -> Result<String, CalError> {
+    let program = Program::new(&generate(code)?);
+    let mut function = program
+        .functions
+        .into_iter()
+        .find_map(|code| match code {
+            Code::Ir(function) if function.name == name => Some(function),
+            _ => None,
+        })
+        .expect("Function not lifted");
+    for pass in passes {
+        pass(&mut function);
+    }
+    pass::simplify_cfg(&mut function);
+    Ok(function.to_string())
+}
+
+#[test]
+fn constant_propagation() -> Result<(), CalError> {
+    let code = "fn main() -> i16 { let x: i16 = 2; let y: i16 = x + 3; y - 1 }";
+    let passes = [pass::propagate_constants, pass::eliminate_dead_code];
+    assert_eq!(
+        optimize(code, "main", &passes)?,
+        "function main {\nb0:\n    %12: word = const 4\n    return %12\n}\n"
+    );
+
+    // Branches on constants become jumps
+    let code = "fn main() -> i16 { let mut x: i16 = 1; if 2 > 1 { x = 5; } x }";
+    let dump = optimize(code, "main", &passes)?;
+    assert!(!dump.contains("branch"), "{}", dump);
+    assert!(dump.contains("const 5"), "{}", dump);
+    Ok(())
+}
+
+#[test]
+fn copy_propagation() -> Result<(), CalError> {
+    let code = "fn copies(a: i16) -> i16 { let b: i16 = a; let c: i16 = b * 1; c }
+        fn main() -> i16 { copies(1) }";
+    let passes = [
+        pass::reduce_strength,
+        pass::propagate_copies,
+        pass::eliminate_dead_code,
+    ];
+    assert_eq!(
+        optimize(code, "copies", &passes)?,
+        "function copies {\nb0:\n    %2: word = arg 0\n    return %2\n}\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn common_subexpressions() -> Result<(), CalError> {
+    let code = "fn cse(a: i16, b: i16) -> i16 { (a + b) & ((a + b) | 1) }
+        fn main() -> i16 { cse(1, 2) }";
+    let passes = [
+        pass::eliminate_common_subexpressions,
+        pass::eliminate_dead_code,
+    ];
+    let dump = optimize(code, "cse", &passes)?;
+    assert_eq!(dump.matches("add").count(), 1, "{}", dump);
+
+    // Loads are not reused across a store which may change them
+    let code = "struct P { x: i16 }
+        fn loads(p: &mut P, q: &mut P) -> i16 { let a: i16 = p.x; q.x = 1; a + p.x }
+        fn main() -> i16 { let mut p: P = P { x: 2 }; loads(&mut p, &mut p) }";
+    let dump = optimize(code, "loads", &passes)?;
+    assert_eq!(dump.matches("load [").count(), 2, "{}", dump);
+    Ok(())
+}
+
+#[test]
+fn dead_stores() -> Result<(), CalError> {
+    let code = "struct P { x: i16, y: i16 }
+        fn stores(p: &mut P) { p.x = 1; p.y = 3; p.x = 2; }
+        fn main() { let mut p: P = P { x: 0, y: 0 }; stores(&mut p); }";
+    let passes = [
+        pass::propagate_constants,
+        pass::eliminate_dead_stores,
+        pass::eliminate_dead_code,
+    ];
+    let dump = optimize(code, "stores", &passes)?;
+    assert_eq!(dump.matches("store [").count(), 2, "{}", dump);
+    assert!(!dump.contains("const 1"), "{}", dump);
+
+    // Locals are not read after returning, but they may be read through a
+    // pointer, here to `a`, until then
+    let code = "fn main() { let mut a: [i16; 2] = [1, 2]; let mut x: i16 = a[1]; x = x + 1; }";
+    let passes = [
+        pass::propagate_constants,
+        pass::eliminate_dead_stores,
+        pass::eliminate_dead_code,
+        pass::eliminate_dead_stores,
+    ];
+    let dump = optimize(code, "main", &passes)?;
+    assert!(dump.contains("store local 0"), "{}", dump);
+    assert!(!dump.contains("store local 2"), "{}", dump);
+    Ok(())
+}
+
+#[test]
+fn strength_reduction() -> Result<(), CalError> {
+    let code = "fn strength(a: i16) -> i16 { (a * 8) + (a / 1) }
+        fn main() -> i16 { strength(3) }";
+    let passes = [
+        pass::reduce_strength,
+        pass::propagate_copies,
+        pass::eliminate_dead_code,
+    ];
+    assert_eq!(
+        optimize(code, "strength", &passes)?,
+        "function strength {
+b0:
+    %0: word = arg 0
+    %11: word = add %0, %0
+    %12: word = add %11, %11
+    %7: word = add %12, %12
+    %10: word = add %7, %0
+    return %10
+}
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn dump() -> Result<(), CalError> {
+    let code = "fn sum(n: i16) -> i16 {
+            let mut s: i16 = 0;
+            while n > s { s = s + 1; }
+            s
+        }
+        fn main() -> i16 { sum(3) }";
+    let mut program = Program::new(&generate(code)?);
+    program.optimize(2);
+    let dump = program.to_string();
+    assert!(dump.starts_with("call main 0\nlabel END\ngoto END\n"));
+    assert!(dump.contains(
+        "function sum {
+b0:
+    %1: word = arg 0
+    %8: word = const 0
+    jump b1(%8)
+b1(%9: word):
+    %13: bool = gt %1, %9
+    %14: bool = not %13
+    branch %14, b3, b2
+b2:
+    %23: word = const 1
+    %24: word = add %9, %23
+    jump b1(%24)
+b3:
+    return %9
+}
+"
+    ));
+    Ok(())
+}
+
+#[test]
+fn faster() -> Result<(), CalError> {
+    let programs = [
+        r#"
+        fn run() -> i16 {
+            let mut sum: i16 = 0;
+            let mut i: i16 = 0;
+            while i < 100 {
+                if (i & 1) == 0 {
+                    sum = sum + (i * 2);
+                }
+                i = i + 1;
+            }
+            sum
+        }"#,
+        r#"
+        fn fib(n: i16) -> i16 {
+            if n < 2 {
+                return n;
+            }
+            fib(n - 1) + fib(n - 2)
+        }
+        fn run() -> i16 { fib(10) }"#,
+        r#"
+        fn run() -> i16 {
+            let mut a: [i16; 8] = [5, 3, 7, 1, 8, 2, 6, 4];
+            let mut i: i16 = 0;
+            while i < 8 {
+                let mut j: i16 = i + 1;
+                while j < 8 {
+                    if a[j] < a[i] {
+                        let t: i16 = a[i];
+                        a[i] = a[j];
+                        a[j] = t;
+                    }
+                    j = j + 1;
+                }
+                i = i + 1;
+            }
+            (a[0] * 1000) + ((a[3] * 100) + (a[7] * 7 / 2))
+        }"#,
+    ];
+    let mut speedups = vec![];
+    for code in programs {
+        let (result, cycles, _) = Target::new(Backend::Vm, 0).run(code)?;
+        for opt_level in 1..=2 {
+            let (opt_result, opt_cycles, _) = Target::new(Backend::Vm, opt_level).run(code)?;
+            assert_eq!(opt_result, result);
+            assert!(
+                opt_cycles <= cycles,
+                "{} cycles against {}",
+                opt_cycles,
+                cycles
+            );
+            if opt_level == 2 {
+                speedups.push(cycles * 100 / opt_cycles);
+            }
+        }
+    }
+    // Multiplying by two no longer calls `mul`
+    assert!(speedups[0] > 300, "{:?}", speedups);
+    // Arrays are addressed without reading the frame again
+    assert!(speedups[2] > 110, "{:?}", speedups);
+    Ok(())
+}
+
+#[test]
+fn comparisons() -> Result<(), CalError> {
+    // The differences of these operands overflow
+    let code = "
+        fn bits(x: i16, y: i16) -> i16 {
+            let mut r: i16 = 0;
+            if x > y { r = r + 1; }
+            if x < y { r = r + 2; }
+            if y > x { r = r + 4; }
+            if y < x { r = r + 8; }
+            r
+        }
+        fn against(x: i16) -> i16 {
+            let mut r: i16 = 0;
+            if x < 2925 { r = r + 1; }
+            if x > (0 - 5000) { r = r + 2; }
+            r
+        }
+        fn run() -> i16 {
+            let a: i16 = 0 - 32193;
+            let b: i16 = 2925;
+            let mut r: i16 = 0;
+            if a > b { r = r + 1; }
+            if a < b { r = r + 2; }
+            r = r + (bits(a, b) * 4);
+            r = r + (bits(32000, 0 - 5000) * 64);
+            r = r + (against(a) * 1024);
+            r + (against(32000) * 4096)
+        }";
+    for opt_level in 0..=2 {
+        let (result, _, _) = Target::new(Backend::Vm, opt_level).run(code)?;
+        assert_eq!(
+            result,
+            2 + 6 * 4 + 9 * 64 + 1024 + 2 * 4096,
+            "-O{}",
+            opt_level
+        );
+    }
+    Ok(())
+}
+
+/// Names of the functions called in the VM code of a program
+fn calls(code: &str, opt_level: u8) -> Result<Vec<String>, CalError> {
+    let module = parse(tokenize(code)?)?;
+    let instructions = Generator::default()
+        .with_opt_level(opt_level)
+        .gen(&[module])?;
+    Ok(instructions
+        .into_iter()
+        .filter_map(|instruction| match instruction {
+            VmInstruction::Call(name, _) => Some(name),
+            _ => None,
+        })
+        .collect())
+}
+
+#[test]
+fn inlining() -> Result<(), CalError> {
+    let code = r#"
+        struct P { x: i16 }
+        impl P { fn x(&self) -> i16 { self.x } }
+        fn min(a: i16, b: i16) -> i16 {
+            if a < b {
+                return a;
+            }
+            b
+        }
+        fn run() -> i16 {
+            let p: P = P { x: 3 };
+            let mut sum: i16 = 0;
+            let mut i: i16 = 0;
+            while i < 50 {
+                sum = sum + min(i, p.x());
+                i = i + 1;
+            }
+            sum
+        }"#;
+    let (result, cycles, _) = Target::new(Backend::Vm, 0).run(code)?;
+    let (opt_result, opt_cycles, _) = Target::new(Backend::Vm, 2).run(code)?;
+    assert_eq!(result, 1 + 2 + (47 * 3));
+    assert_eq!(opt_result, result);
+    // Two calls per iteration are gone
+    assert!(
+        opt_cycles * 10 < cycles * 7,
+        "{} against {}",
+        opt_cycles,
+        cycles
+    );
+    let main = format!("{}\nfn main() -> i16 {{ run() }}", code);
+    let called = calls(&main, 2)?;
+    assert!(!called.contains(&"min".to_string()), "{:?}", called);
+    assert!(!called.contains(&"P.x".to_string()), "{:?}", called);
+
+    // Bigger functions are only inlined when asked to, from level 1
+    let big = "fn big(a: i16) -> i16 {
+            let mut x: i16 = a;
+            while x < 100 { x = (x + (x & 3)) + ((x | 1) - (x & 1)); }
+            (x & 7) + ((x | 5) - a)
+        }
+        fn main() -> i16 { big(1) + big(2) }";
+    assert!(calls(big, 2)?.contains(&"big".to_string()));
+    let hinted = format!("#[inline] {}", big);
+    assert!(!calls(&hinted, 1)?.contains(&"big".to_string()));
+    assert!(calls(&hinted, 0)?.contains(&"big".to_string()));
+
+    // Recursive functions are left alone
+    let code = "#[inline] fn fib(n: i16) -> i16 { if n < 2 { return n; } fib(n - 1) + fib(n - 2) }
+        fn run() -> i16 { fib(12) }";
+    assert_eq!(Target::new(Backend::Vm, 2).run(code)?.0, 144);
+    Ok(())
+}
+
+#[test]
+fn tail_calls() -> Result<(), CalError> {
+    let count = |n: i16| {
+        format!(
+            "fn count(n: i16, acc: i16) -> i16 {{
+                if n == 0 {{
+                    return acc;
+                }}
+                count(n - 1, acc + 2)
+            }}
+            fn run() -> i16 {{ count({}, 0) }}",
+            n
+        )
+    };
+    let (result, cycles, depth) = Target::new(Backend::Vm, 0).run(&count(100))?;
+    assert_eq!(result, 200);
+    let (opt_result, opt_cycles, opt_depth) = Target::new(Backend::Vm, 1).run(&count(100))?;
+    assert_eq!(opt_result, result);
+    assert!(
+        opt_cycles * 10 < cycles * 7,
+        "{} against {}",
+        opt_cycles,
+        cycles
+    );
+    // Each call pushed a frame, while the loop reuses the first one
+    assert!(depth > 256 + (100 * 7), "{}", depth);
+    assert!(opt_depth < 256 + 30, "{}", opt_depth);
+
+    // Far deeper than the stack could hold
+    let (result, _, depth) = Target::new(Backend::Vm, 1).run(&count(5000))?;
+    assert_eq!(result, 10000);
+    assert!(depth < 256 + 30, "{}", depth);
+
+    // Arguments held in memory are overwritten in place
+    let code = "fn sum(n: i16, acc: i16) -> i16 {
+            let a: [i16; 1] = [n];
+            let p: &[i16; 1] = &a;
+            if n == 0 {
+                return acc;
+            }
+            sum(p[0] - 1, acc + n)
+        }
+        fn run() -> i16 { sum(200, 0) }";
+    let (result, _, depth) = Target::new(Backend::Vm, 0).run(code)?;
+    let (opt_result, _, opt_depth) = Target::new(Backend::Vm, 2).run(code)?;
+    assert_eq!(result, 20100);
+    assert_eq!(opt_result, result);
+    assert!(
+        opt_depth * 10 < depth * 2,
+        "{} against {}",
+        opt_depth,
+        depth
+    );
+    Ok(())
+}
@@ -0,0 +1,146 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::error::Error;
+
+use acs::{
+    compiler::{compile, CompileOptions},
+    console::{Console, SCREEN_SIZE},
+    Computer,
+};
+
+const NEWLINE: i16 = 128;
+const BACKSPACE: i16 = 129;
+
+/// Cycles a key stays pressed, and then released, when typing
+const HOLD: usize = 16 * 1024;
+
+/// Runs a program linking the keyboard library, typing `keys` one after the
+/// other, until `main` returns
+fn run(code: &str, keys: &[i16]) -> Result<Computer, Box<dyn Error>> {
+    let options = CompileOptions {
+        link: vec!["keyboard".into()],
+        ..Default::default()
+    };
+    let mut computer = Computer::default();
+    computer.set_instructions(
+        compile(code, &options)
+            .map_err(|err| err.message)?
+            .instructions,
+    );
+    for key in keys {
+        computer.get_keyboard_mut().set((*key).into());
+        for _ in 0..HOLD {
+            computer.ticktock();
+        }
+        computer.get_keyboard_mut().set(0.into());
+        for _ in 0..HOLD {
+            computer.ticktock();
+        }
+    }
+    let mut cycles = 0;
+    while computer.get_memory().ram[0] != 257 {
+        for _ in 0..1024 {
+            computer.ticktock();
+        }
+        cycles += 1024;
+        assert!(cycles < 1_000_000, "program did not finish");
+    }
+    Ok(computer)
+}
+
+fn keys(text: &str) -> Vec<i16> {
+    text.chars().map(|c| c as i16).collect()
+}
+
+/// Asserts that the first rows of the screen show `text` printed on a console
+fn assert_echo(computer: &Computer, text: &str) {
+    let mut screen = vec![0; SCREEN_SIZE];
+    Console::default().print_str(&mut screen, &keys(text));
+    for y in 0..22 {
+        for x in 0..512 {
+            let pixel = (screen[y * 32 + x / 16] as u16 >> (x % 16)) & 1;
+            assert_eq!(
+                computer.get_screen().get_pixel(x, y),
+                pixel,
+                "({}, {})",
+                x,
+                y
+            );
+        }
+    }
+}
+
+#[test]
+fn read_char() -> Result<(), Box<dyn Error>> {
+    let computer = run(
+        r#"
+        fn main() -> i16 {
+            let a: char = read_char();
+            let b: char = read_char();
+            if key_pressed() != 0 {
+                return 0;
+            }
+            (a - 'a') + ((b - 'a') * 10)
+        }"#,
+        &keys("bd"),
+    )?;
+    assert_eq!(computer.get_memory().ram[256], 31);
+    Ok(())
+}
+
+#[test]
+fn key_pressed() -> Result<(), Box<dyn Error>> {
+    let computer = run(
+        r#"
+        fn main() -> i16 {
+            while key_pressed() == 0 {}
+            key_pressed()
+        }"#,
+        &[NEWLINE],
+    )?;
+    assert_eq!(computer.get_memory().ram[256], NEWLINE);
+    Ok(())
+}
+
+#[test]
+fn read_line() -> Result<(), Box<dyn Error>> {
+    let code = r#"
+        fn main() -> i16 {
+            let mut console: Console = Console::new();
+            let mut buf: [char; 5] = [' ', ' ', ' ', ' ', ' '];
+            let len: i16 = read_line(&mut console, &mut buf, 5);
+            console.print_str(&buf, len);
+            len
+        }"#;
+    let mut typed = keys("hex");
+    typed.extend([BACKSPACE, 'l' as i16, BACKSPACE]);
+    typed.extend(keys("llo!?"));
+    typed.push(NEWLINE);
+    let computer = run(code, &typed)?;
+    assert_eq!(computer.get_memory().ram[256], 5);
+    assert_echo(&computer, "hello\nhello");
+
+    let computer = run(code, &[BACKSPACE, 'a' as i16, NEWLINE])?;
+    assert_eq!(computer.get_memory().ram[256], 1);
+    assert_echo(&computer, "a\na");
+    Ok(())
+}
+
+#[test]
+fn read_int() -> Result<(), Box<dyn Error>> {
+    let code = r#"
+        fn main() -> i16 {
+            let mut console: Console = Console::new();
+            read_int(&mut console)
+        }"#;
+    for (text, value) in [("42x", 42), ("-123", -123), ("-32768", i16::MIN), ("", 0)] {
+        let mut typed = keys(text);
+        typed.push(NEWLINE);
+        let computer = run(code, &typed)?;
+        assert_eq!(computer.get_memory().ram[256], value, "{}", text);
+        assert_echo(&computer, &format!("{}\n", text));
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use acs::json::Json;
+
+fn frame(message: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", message.len(), message)
+}
+
+/// Pipes the messages into the language server, returning its replies
+fn run_server(messages: &[String]) -> (bool, Vec<Json>) {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_cal-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input: String = messages.iter().map(|message| frame(message)).collect();
+    server
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = server.wait_with_output().unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let replies = stdout
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|reply| reply.split_once("\r\n\r\n").unwrap().1.parse().unwrap())
+        .collect();
+    (output.status.success(), replies)
+}
+
+#[test]
+fn session() {
+    let open = Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/didOpen".into()),
+        (
+            "params",
+            Json::object([(
+                "textDocument",
+                Json::object([
+                    ("uri", "file:///main.cal".into()),
+                    (
+                        "text",
+                        "fn main() -> i16 {\n    let a: i16 = 1;\n    a\n}".into(),
+                    ),
+                ]),
+            )]),
+        ),
+    ]);
+    let messages = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#.into(),
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#.into(),
+        open.to_string(),
+        r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///main.cal"},"position":{"line":2,"character":4}}}"#.into(),
+        r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///main.cal"},"position":{"line":2,"character":4}}}"#.into(),
+        r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///main.cal"},"contentChanges":[{"text":"fn main() -> i16 { b }"}]}}"#.into(),
+        r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///main.cal"}}}"#.into(),
+        r#"{"jsonrpc":"2.0","id":5,"method":"unknown"}"#.into(),
+        r#"{"jsonrpc":"2.0","id":6,"method":"shutdown"}"#.into(),
+        r#"{"jsonrpc":"2.0","method":"exit"}"#.into(),
+    ];
+    let (success, replies) = run_server(&messages);
+    assert!(success);
+    let replies: Vec<String> = replies.iter().map(Json::to_string).collect();
+    assert_eq!(replies.len(), 8);
+    assert!(replies[0].contains(r#""hoverProvider":true"#));
+    assert_eq!(
+        replies[1],
+        r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///main.cal","diagnostics":[]}}"#
+    );
+    assert!(replies[2].contains(r#""value":"```cal\nlet a: i16\n```""#));
+    assert_eq!(
+        replies[3],
+        r#"{"jsonrpc":"2.0","id":3,"result":{"uri":"file:///main.cal","range":{"start":{"line":1,"character":8},"end":{"line":1,"character":9}}}}"#
+    );
+    assert!(
+        replies[4].contains(r#""severity":1,"source":"cal","message":"Undefined variable `b`""#)
+    );
+    assert!(replies[5].contains(r#""name":"main","detail":"fn main() -> i16","kind":12"#));
+    assert!(replies[6].contains(r#""error":{"code":-32601"#));
+    assert_eq!(replies[7], r#"{"jsonrpc":"2.0","id":6,"result":null}"#);
+}
+
+#[test]
+fn exit_without_shutdown() {
+    let (success, replies) = run_server(&[r#"{"jsonrpc":"2.0","method":"exit"}"#.into()]);
+    assert!(!success);
+    assert!(replies.is_empty());
+}
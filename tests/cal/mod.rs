@@ -8,4 +8,82 @@ mod parser;
 
 mod generator;
 
+use acs::{
+    asm::instruction::AsmInstruction,
+    compiler::{compile, Backend, CompileOptions},
+    error::CalError,
+    Computer,
+};
+
+/// Address in the static segment, which Cal does not use
+const DONE: usize = 16;
+
+/// Backend and optimisation level a program is compiled for
+#[derive(Copy, Clone, Debug)]
+pub struct Target {
+    pub backend: Backend,
+    pub opt_level: u8,
+}
+
+impl Target {
+    pub const fn new(backend: Backend, opt_level: u8) -> Self {
+        Self { backend, opt_level }
+    }
+
+    pub fn compile(&self, code: &str) -> Result<Vec<AsmInstruction>, CalError> {
+        let options = CompileOptions {
+            backend: self.backend,
+            opt_level: self.opt_level,
+            ..Default::default()
+        };
+        Ok(compile(code, &options)?.instructions)
+    }
+
+    /// Runs a program until `main` returns `result` after writing `1` at
+    /// `DONE`, returning `result` along with the cycles it took and the
+    /// highest address the stack reached
+    pub fn run(&self, code: &str) -> Result<(i16, usize, i16), CalError> {
+        let code = format!(
+            "{}\nfn main() -> i16 {{ let result: i16 = run(); poke({}, 1); result }}",
+            code, DONE
+        );
+        let mut computer = Computer::default();
+        computer.set_instructions(self.compile(&code)?);
+        let mut cycles = 0;
+        let mut depth = 0;
+        while computer.get_memory().ram[DONE] != 1 || computer.get_memory().ram[0] != 257 {
+            computer.ticktock();
+            cycles += 1;
+            depth = depth.max(computer.get_memory().ram[0]);
+            assert!(cycles < 1_000_000, "{:?} did not finish", self);
+        }
+        Ok((computer.get_memory().ram[256], cycles, depth))
+    }
+}
+
+// The compiler suite runs on the VM backend here, on the Hack one in `hack`
+// and with all optimisations in `ir`
+const TARGET: Target = Target::new(Backend::Vm, 0);
 mod compiler;
+
+mod warning;
+
+mod interpreter;
+
+mod formatter;
+
+mod analysis;
+
+mod lsp;
+
+mod dump;
+
+mod console;
+mod dce;
+mod debugger;
+mod doc;
+mod graphics;
+mod hack;
+mod ir;
+mod keyboard;
+mod source_map;
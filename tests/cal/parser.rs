@@ -353,10 +353,10 @@ fn array() -> Result<(), CalError> {
     let Statement::Expression(expr) = statement else {
         panic!();
     };
-    let Term::Index(var_name, index_expr) = expr.term.as_ref() else {
+    let Term::Index(var, index_expr) = expr.term.as_ref() else {
         panic!();
     };
-    assert_eq!(var_name, "a");
+    assert_eq!(var.as_ref(), &Term::Variable("a".into()));
     assert_eq!(index_expr.term.as_ref(), &Term::Literal(Literal::I16(1)));
     assert!(index_expr.op_and_expr.is_none());
 
@@ -513,7 +513,7 @@ fn array_of_array_reference() -> Result<(), CalError> {
     };
     assert!(args[0].op_and_expr.is_none());
     let a_index_1 = Term::Index(
-        "a".into(),
+        Box::new(Term::Variable("a".into())),
         Expression::new(Box::new(Term::Literal(Literal::I16(1))), None),
     );
     assert_eq!(rhs.as_ref(), &a_index_1);
@@ -545,3 +545,273 @@ fn array_of_array_reference() -> Result<(), CalError> {
 
     Ok(())
 }
+
+#[test]
+fn method() -> Result<(), CalError> {
+    let module: Module = r#"
+    struct Point { x: i16, y: i16 }
+    impl Point {
+        fn new(x: i16, y: i16) -> Self { Point { x: x, y: y } }
+        fn len(&self) -> i16 { self.x + self.y }
+    }
+    fn main() -> i16 {
+        let p: Point = Point::new(1, 2);
+        p.len()
+    }"#
+    .parse()?;
+    assert_eq!(module.structs.len(), 1);
+    assert_eq!(module.structs[0].name, "Point");
+    assert_eq!(module.structs[0].fields.len(), 2);
+    assert_eq!(module.functions.len(), 3);
+
+    let new = &module.functions[0];
+    assert_eq!(new.name, "Point.new");
+    assert_eq!(new.return_type, Type::Struct("Point".into()));
+    assert_eq!(new.parameters.len(), 2);
+
+    let len = &module.functions[1];
+    assert_eq!(len.name, "Point.len");
+    assert_eq!(len.parameters.len(), 1);
+    assert_eq!(len.parameters[0].name, "self");
+    assert_eq!(
+        len.parameters[0].typ,
+        Type::Ref(Box::new(Type::Struct("Point".into())))
+    );
+
+    let main = &module.functions[2];
+    let Statement::Let(_, expression) = &main.body_statements[0] else {
+        panic!()
+    };
+    assert!(matches!(expression.term.as_ref(), Term::Call(name, _) if name == "Point.new"));
+    let Statement::Expression(expression) = &main.body_statements[1] else {
+        panic!()
+    };
+    assert!(matches!(
+        expression.term.as_ref(),
+        Term::MethodCall(receiver, name, _)
+            if **receiver == Term::Variable("p".into()) && name == "len"
+    ));
+    Ok(())
+}
+
+#[test]
+fn tuple() -> Result<(), CalError> {
+    let module: Module = r#"
+    fn divmod(a: i16, b: i16) -> (i16, i16) { (a / b, a % b) }
+    fn main() -> i16 {
+        let (q, r) = divmod(7, 2);
+        let t: (i16, bool) = (q, true);
+        t.0
+    }"#
+    .parse()?;
+    let divmod = &module.functions[0];
+    assert_eq!(divmod.return_type, Type::Tuple(vec![Type::I16, Type::I16]));
+    let Statement::Expression(expression) = &divmod.body_statements[0] else {
+        panic!()
+    };
+    assert!(matches!(expression.term.as_ref(), Term::Tuple(elements) if elements.len() == 2));
+
+    let main = &module.functions[1];
+    let Statement::LetTuple(variables, _) = &main.body_statements[0] else {
+        panic!()
+    };
+    assert_eq!(variables.len(), 2);
+    assert_eq!(variables[0].name, "q");
+    assert_eq!(variables[1].name, "r");
+    let Statement::Let(variable, _) = &main.body_statements[1] else {
+        panic!()
+    };
+    assert_eq!(variable.typ, Type::Tuple(vec![Type::I16, Type::Bool]));
+    let Statement::Expression(expression) = &main.body_statements[2] else {
+        panic!()
+    };
+    assert_eq!(
+        *expression.term.as_ref(),
+        Term::Field(Box::new(Term::Variable("t".into())), "0".into())
+    );
+    Ok(())
+}
+
+#[test]
+fn nested_index() -> Result<(), CalError> {
+    let module: Module = "fn main() -> i16 { grid[1][2] = a[0].x; grid[1][2] }".parse()?;
+    let function = &module.functions[0];
+    let Statement::Expression(expr) = &function.body_statements[0] else {
+        panic!();
+    };
+    let int_expr = |int| Expression::new(Box::new(Term::Literal(Literal::I16(int))), None);
+    let grid_1_2 = Term::Index(
+        Box::new(Term::Index(
+            Box::new(Term::Variable("grid".into())),
+            int_expr(1),
+        )),
+        int_expr(2),
+    );
+    assert_eq!(expr.term.as_ref(), &grid_1_2);
+    let Some((Operator::Assign, rhs)) = expr.op_and_expr.as_ref() else {
+        panic!();
+    };
+    assert_eq!(
+        rhs.term.as_ref(),
+        &Term::Field(
+            Box::new(Term::Index(
+                Box::new(Term::Variable("a".into())),
+                int_expr(0)
+            )),
+            "x".into()
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn constant() -> Result<(), CalError> {
+    let module: Module = r#"
+    const WIDTH: i16 = 4;
+    const fn double(x: i16) -> i16 { x * 2 }
+    fn main() -> [i16; WIDTH * 2] { let a: [i16; 2] = [0, 0]; sizeof(i16) }"#
+        .parse()?;
+    assert_eq!(module.constants.len(), 1);
+    let constant = &module.constants[0];
+    assert_eq!(constant.name, "WIDTH");
+    assert_eq!(constant.typ, Type::I16);
+    let int_expr = |int| Expression::new(Box::new(Term::Literal(Literal::I16(int))), None);
+    assert_eq!(constant.value, int_expr(4));
+
+    assert_eq!(module.functions.len(), 2);
+    assert!(module.functions[0].is_const);
+    let function = &module.functions[1];
+    assert!(!function.is_const);
+    let width_2 = Expression::new(
+        Box::new(Term::Variable("WIDTH".into())),
+        Some((Operator::Mul, Box::new(int_expr(2)))),
+    );
+    assert_eq!(
+        function.return_type,
+        Type::ArrayExpr(Box::new(Type::I16), Box::new(width_2))
+    );
+    // A single integer literal is still a plain array length
+    let Statement::Let(variable, _) = &function.body_statements[0] else {
+        panic!();
+    };
+    assert_eq!(variable.typ, Type::Array(Box::new(Type::I16), 2));
+    let Statement::Expression(expr) = &function.body_statements[1] else {
+        panic!();
+    };
+    assert_eq!(*expr.term, Term::SizeOf(Type::I16));
+    Ok(())
+}
+
+#[test]
+fn mutable() -> Result<(), CalError> {
+    let module: Module = r#"
+    struct Point { x: i16 }
+    impl Point {
+        fn set(&mut self, x: i16) { self.x = x; }
+    }
+    fn edit(mut a: i16, b: &mut i16, c: &i16) {
+        let mut d: i16 = 0;
+        let (mut e, f) = (1, 2);
+        pass(&mut d);
+    }"#
+    .parse()?;
+    let set = &module.functions[0];
+    assert_eq!(
+        set.parameters[0].typ,
+        Type::MutRef(Box::new(Type::Struct("Point".into())))
+    );
+
+    let edit = &module.functions[1];
+    assert!(edit.parameters[0].mutable);
+    assert_eq!(edit.parameters[0].typ, Type::I16);
+    assert!(!edit.parameters[1].mutable);
+    assert_eq!(edit.parameters[1].typ, Type::MutRef(Box::new(Type::I16)));
+    assert_eq!(edit.parameters[2].typ, Type::Ref(Box::new(Type::I16)));
+
+    let Statement::Let(d, _) = &edit.body_statements[0] else {
+        panic!();
+    };
+    assert!(d.mutable);
+    let Statement::LetTuple(variables, _) = &edit.body_statements[1] else {
+        panic!();
+    };
+    assert!(variables[0].mutable);
+    assert!(!variables[1].mutable);
+    let Statement::Expression(expr) = &edit.body_statements[2] else {
+        panic!();
+    };
+    let Term::Call(_, args) = expr.term.as_ref() else {
+        panic!();
+    };
+    assert_eq!(
+        *args[0].term,
+        Term::UnaryOp(UnaryOperator::MutRef, Box::new(Term::Variable("d".into())))
+    );
+    Ok(())
+}
+
+#[test]
+fn type_alias() -> Result<(), CalError> {
+    let module: Module = r#"
+    fn draw(sprite: &Sprite, rows: Rows) {}
+    type Rows = [Row; 2];
+    type Row = [i16; 32];
+    type Sprite = [i16; 16];
+    "#
+    .parse()?;
+    assert_eq!(module.type_aliases.len(), 3);
+    let row = Type::Array(Box::new(Type::I16), 32);
+    assert_eq!(module.type_aliases[0].name, "Rows");
+    assert_eq!(
+        module.type_aliases[0].typ,
+        Type::Array(Box::new(row.clone()), 2)
+    );
+    assert_eq!(module.type_aliases[1].typ, row);
+
+    let draw = &module.functions[0];
+    assert_eq!(
+        draw.parameters[0].typ,
+        Type::Ref(Box::new(Type::Array(Box::new(Type::I16), 16)))
+    );
+    assert_eq!(draw.parameters[1].typ, Type::Array(Box::new(row), 2));
+    Ok(())
+}
+
+#[test]
+fn type_alias_error() {
+    let code = "type A = [B; 2];\ntype B = (i16, &C);\ntype C = A;";
+    let Err(err) = code.parse::<Module>() else {
+        panic!("Expected recursive type alias error");
+    };
+    assert_eq!(err.message, "Type alias `A` is recursive: A -> B -> C -> A");
+    assert_eq!(&code[err.range.start..err.range.end], "A");
+
+    let code = "struct Row { a: i16 }\ntype Row = [i16; 2];";
+    let Err(err) = code.parse::<Module>() else {
+        panic!("Expected duplicate type error");
+    };
+    assert_eq!(err.message, "Type `Row` is defined multiple times");
+    assert_eq!(&code[err.range.start..err.range.end], "Row");
+}
+
+#[test]
+fn attributes() -> Result<(), CalError> {
+    let module: Module = r#"
+    /// Smaller of two
+    #[inline]
+    fn min(a: i16, b: i16) -> i16 { if a < b { return a; } b }
+    struct P { x: i16 }
+    impl P { #[inline] fn x(&self) -> i16 { self.x } }
+    fn main() -> i16 { min(1, 2) }"#
+        .parse()?;
+    assert!(module.functions[0].inline);
+    assert_eq!(module.functions[0].doc, "Smaller of two");
+    assert!(module.functions[1].inline);
+    assert!(!module.functions[2].inline);
+
+    let code = "#[fast] fn main() {}";
+    let err = code.parse::<Module>().err().unwrap();
+    assert_eq!(err.message, "Unknown attribute `fast`");
+    assert_eq!(&code[err.range.start..err.range.end], "fast");
+    Ok(())
+}
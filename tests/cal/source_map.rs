@@ -0,0 +1,105 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    compiler::{compile, Backend, CompileOptions},
+    dump::ToJson,
+    error::CalError,
+    source_map::SourceMap,
+    Computer,
+};
+
+fn options(opt_level: u8) -> CompileOptions {
+    CompileOptions {
+        opt_level,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn every_address() -> Result<(), CalError> {
+    let code = "fn main() -> i16 { let x: i16 = 3; x + 1 }";
+    let compilation = compile(code, &options(0))?;
+    let source_map = &compilation.source_map;
+    assert_eq!(source_map.len(), compilation.instructions.len());
+
+    // Boot code comes first
+    assert!(source_map.get(0).is_none());
+    let main: Vec<usize> = source_map.addresses("main").collect();
+    assert!(!main.is_empty());
+    assert!(main.iter().all(|address| *address > 0));
+    Ok(())
+}
+
+#[test]
+fn statements() -> Result<(), CalError> {
+    let code = "fn f() -> i16 {\n    let x: i16 = 3;\n    x\n}\nfn main() -> i16 { f() }";
+    let compilation = compile(code, &options(0))?;
+    let source_map = &compilation.source_map;
+    let text = |address: usize| {
+        let origin = source_map.get(address).unwrap();
+        &code[origin.range.start..origin.range.end]
+    };
+    let assignment: Vec<usize> = source_map
+        .addresses("f")
+        .filter(|address| text(*address) == "x: i16 = 3")
+        .collect();
+    assert!(!assignment.is_empty());
+
+    // The program reaches the statement while running
+    let mut computer = Computer::default();
+    computer.set_instructions(compilation.instructions);
+    let mut cycles = 0;
+    while !assignment.contains(&computer.get_pc()) {
+        computer.ticktock();
+        cycles += 1;
+        assert!(cycles < 100_000, "assignment not reached");
+    }
+    assert_eq!(source_map.get(computer.get_pc()).unwrap().function, "f");
+    Ok(())
+}
+
+#[test]
+fn round_trip() -> Result<(), CalError> {
+    let code = "fn add(a: i16, b: i16) -> i16 { a + b }\nfn main() -> i16 { add(1, 2) }";
+    let source_map = compile(code, &options(0))?.source_map;
+    let parsed: SourceMap = source_map.to_json().to_string().parse()?;
+    assert_eq!(parsed, source_map);
+
+    assert!("[{\"address\": 1, \"count\": 2}]"
+        .parse::<SourceMap>()
+        .is_err());
+    assert!("{}".parse::<SourceMap>().is_err());
+    Ok(())
+}
+
+#[test]
+fn optimized() -> Result<(), CalError> {
+    let code = "fn f(a: i16) -> i16 { let b: i16 = a + 1; b + b }\nfn main() -> i16 { f(2) }";
+    let compilation = compile(code, &options(2))?;
+    let source_map = &compilation.source_map;
+    assert_eq!(source_map.len(), compilation.instructions.len());
+
+    // Rewritten functions only map to their name, and are reported
+    assert_eq!(compilation.rewritten, ["main"]);
+    assert!(compile(code, &options(0))?.rewritten.is_empty());
+    let main: Vec<usize> = source_map.addresses("main").collect();
+    assert!(!main.is_empty());
+    for address in main {
+        let range = source_map.get(address).unwrap().range;
+        assert_eq!(&code[range.start..range.end], "main");
+    }
+    Ok(())
+}
+
+#[test]
+fn hack_backend() -> Result<(), CalError> {
+    let options = CompileOptions {
+        backend: Backend::Hack,
+        ..Default::default()
+    };
+    let compilation = compile("fn main() -> i16 { 1 }", &options)?;
+    assert!(compilation.source_map.is_empty());
+    Ok(())
+}
@@ -4,7 +4,7 @@
 
 use acs::{
     error::CalError,
-    tokenizer::{Keyword, Symbol, Tokenize},
+    tokenizer::{Keyword, Range, Symbol, TokenKind, Tokenize},
 };
 
 #[test]
@@ -415,3 +415,105 @@ fn reference() -> Result<(), CalError> {
     tokens.eat_symbol(Symbol::RightBrace)?;
     Ok(())
 }
+
+#[test]
+fn comment() -> Result<(), CalError> {
+    let code = "// main\nfn main() { // body\n}";
+    let mut tokens = code.tokenize()?;
+    let comments = tokens.comments().to_vec();
+    assert_eq!(comments.len(), 2);
+    assert_eq!(comments[0].value, TokenKind::Comment("// main".into()));
+    assert_eq!(comments[0].range, Range::new(0, 7));
+    assert_eq!(comments[1].value, TokenKind::Comment("// body".into()));
+    assert_eq!(
+        &code[comments[1].range.start..comments[1].range.end],
+        "// body"
+    );
+
+    // Comments are not seen as tokens
+    tokens.eat_keyword(Keyword::Function)?;
+    tokens.eat_identifier("main")?;
+    tokens.eat_symbol(Symbol::LeftParen)?;
+    tokens.eat_symbol(Symbol::RightParen)?;
+    tokens.eat_symbol(Symbol::LeftBrace)?;
+    tokens.eat_symbol(Symbol::RightBrace)?;
+    Ok(())
+}
+
+#[test]
+fn keyword_boundaries() -> Result<(), CalError> {
+    let mut tokens = "iffy whileCount i16x truest fn(let)".tokenize()?;
+    tokens.eat_identifier("iffy")?;
+    tokens.eat_identifier("whileCount")?;
+    tokens.eat_identifier("i16x")?;
+    tokens.eat_identifier("truest")?;
+    // Keywords do not need a trailing space
+    tokens.eat_keyword(Keyword::Function)?;
+    tokens.eat_symbol(Symbol::LeftParen)?;
+    tokens.eat_keyword(Keyword::Let)?;
+    tokens.eat_symbol(Symbol::RightParen)?;
+    Ok(())
+}
+
+#[test]
+fn integers() -> Result<(), CalError> {
+    let mut tokens = "0x7fff 0xFFFF 0b1000_0000_0000_0000 1_000 32767".tokenize()?;
+    tokens.eat_integer(0x7fff)?;
+    tokens.eat_integer(-1)?;
+    tokens.eat_integer(i16::MIN)?;
+    tokens.eat_integer(1000)?;
+    tokens.eat_integer(i16::MAX)?;
+
+    let err = "let a: i16 = 40000;".tokenize().err().unwrap();
+    assert_eq!(err.message, "Integer `40000` is out of range");
+    assert_eq!(err.range, Range::new(13, 18));
+
+    let err = "0x1_0000".tokenize().err().unwrap();
+    assert_eq!(err.message, "Integer `0x1_0000` is out of range");
+    assert_eq!(err.range, Range::new(0, 8));
+
+    let err = "a + 12ab".tokenize().err().unwrap();
+    assert_eq!(err.message, "Invalid integer `12ab`");
+    assert_eq!(err.range, Range::new(4, 8));
+    Ok(())
+}
+
+#[test]
+fn block_comment() -> Result<(), CalError> {
+    let code = "fn /* a\n b */ main /**/() {}";
+    let mut tokens = code.tokenize()?;
+    let comments = tokens.comments().to_vec();
+    assert_eq!(comments.len(), 2);
+    assert_eq!(comments[0].value, TokenKind::Comment("/* a\n b */".into()));
+    assert_eq!(comments[0].range, Range::new(3, 13));
+    assert_eq!(comments[1].range, Range::new(19, 23));
+    tokens.eat_keyword(Keyword::Function)?;
+    tokens.eat_identifier("main")?;
+
+    let err = "fn main() /* {}".tokenize().err().unwrap();
+    assert_eq!(err.message, "Unterminated comment");
+    assert_eq!(err.range, Range::new(10, 15));
+    Ok(())
+}
+
+#[test]
+fn exact_ranges() -> Result<(), CalError> {
+    let code = "fn  main()->i16 { let mut x: char = 'x'; x != 1 }";
+    let tokens: Vec<_> = code.tokenize()?.by_ref().collect();
+    let texts: Vec<&str> = tokens
+        .iter()
+        .map(|token| &code[token.range.start..token.range.end])
+        .collect();
+    assert_eq!(
+        texts,
+        [
+            "fn", "main", "(", ")", "->", "i16", "{", "let", "mut", "x", ":", "char", "=", "'x'",
+            ";", "x", "!=", "1", "}"
+        ]
+    );
+
+    let err = "let a = $;".tokenize().err().unwrap();
+    assert_eq!(err.message, "Unexpected character `$`");
+    assert_eq!(err.range, Range::new(8, 9));
+    Ok(())
+}
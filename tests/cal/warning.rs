@@ -0,0 +1,223 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    compiler::{compile, CompileOptions},
+    error::CalError,
+    structure::Module,
+    warning::{check, CalWarning},
+};
+
+/// Returns the source code each warning refers to
+fn get_warned_code<'a>(code: &'a str, warnings: &[CalWarning]) -> Vec<&'a str> {
+    warnings
+        .iter()
+        .map(|warning| &code[warning.range.start..warning.range.end])
+        .collect()
+}
+
+#[test]
+fn no_warnings() -> Result<(), CalError> {
+    let code = r#"
+    fn add(a: i16, b: i16) -> i16 { a + b }
+    fn main() -> i16 {
+        let mut c: i16 = 0;
+        c = add(1, 2);
+        c
+    }"#;
+    let module: Module = code.parse()?;
+    assert!(check(&module).is_empty());
+    Ok(())
+}
+
+#[test]
+fn unused() -> Result<(), CalError> {
+    let code = r#"
+    fn main(argc: i16, _ignored: i16) {
+        let a: i16 = 1;
+        let mut b: i16 = 2;
+        b = 3;
+        let (c, d) = (a, 4);
+        c;
+    }"#;
+    let module: Module = code.parse()?;
+    let warnings = check(&module);
+    assert_eq!(warnings[0].message, "Unused parameter `argc`");
+    assert_eq!(warnings[1].message, "Unused variable `b`");
+    assert_eq!(warnings[2].message, "Unused variable `d`");
+    assert_eq!(warnings.len(), 3);
+    assert_eq!(get_warned_code(code, &warnings), ["argc", "b", "d"]);
+    Ok(())
+}
+
+#[test]
+fn written_through() -> Result<(), CalError> {
+    // Writing through a reference uses it, even when destructured
+    let code = r#"
+    fn pair(a: &mut i16) -> (&mut i16, i16) { (a, 1) }
+    fn main() {
+        let mut x: i16 = 0;
+        let r: &mut i16 = &mut x;
+        r = 5;
+        let (s, t) = pair(&mut x);
+        s = t;
+        let (u, mut v) = (&mut x, 2);
+        u = 6;
+        v = 7;
+    }"#;
+    let module: Module = code.parse()?;
+    let warnings = check(&module);
+    assert_eq!(get_warned_code(code, &warnings), ["v"]);
+    assert_eq!(warnings[0].message, "Unused variable `v`");
+    Ok(())
+}
+
+#[test]
+fn never_called() -> Result<(), CalError> {
+    let code = r#"
+    struct Point { x: i16 }
+    impl Point {
+        fn get(&self) -> i16 { self.x }
+        fn unused(&self) -> i16 { self.x }
+    }
+    const fn size() -> i16 { 2 }
+    const SIZE: i16 = size();
+    fn helper(p: &Point) -> i16 { p.get() }
+    fn dead() -> i16 { helper(&Point { x: 1 }) }
+    fn main() -> i16 {
+        let a: [i16; SIZE] = [1, 2];
+        let p: Point = Point { x: a[0] };
+        helper(&p)
+    }"#;
+    let module: Module = code.parse()?;
+    let warnings = check(&module);
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(
+        warnings[0].message,
+        "Function `Point.unused` is never called from `main`"
+    );
+    assert_eq!(
+        warnings[1].message,
+        "Function `dead` is never called from `main`"
+    );
+    assert_eq!(get_warned_code(code, &warnings), ["unused", "dead"]);
+    Ok(())
+}
+
+#[test]
+fn unreachable() -> Result<(), CalError> {
+    let code = r#"
+    fn main(mut a: i16) -> i16 {
+        if a > 0 {
+            return 1;
+            a = 2;
+        }
+        return a;
+        a + 1;
+        a + 2
+    }"#;
+    let module: Module = code.parse()?;
+    let warnings = check(&module);
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings
+        .iter()
+        .all(|warning| warning.message == "Unreachable statement"));
+    assert_eq!(get_warned_code(code, &warnings), ["a = 2", "a + 1"]);
+    Ok(())
+}
+
+#[test]
+fn constant_condition() -> Result<(), CalError> {
+    let code = r#"
+    const DEBUG: bool = false;
+    fn main(mut a: i16) -> i16 {
+        if DEBUG { a = 1; }
+        while 1 < 2 { a = a + 1; }
+        while true { return a; }
+        let DEBUG: bool = a > 0;
+        if DEBUG { a = 2; }
+        a
+    }"#;
+    let module: Module = code.parse()?;
+    let warnings = check(&module);
+    assert_eq!(warnings.len(), 3);
+    assert_eq!(warnings[0].message, "Condition is always false");
+    assert_eq!(warnings[1].message, "Condition is always true");
+    assert_eq!(warnings[2].message, "Variable `DEBUG` shadows a constant");
+    assert_eq!(
+        get_warned_code(code, &warnings),
+        ["DEBUG", "1 < 2", "DEBUG"]
+    );
+    Ok(())
+}
+
+#[test]
+fn shadowing() -> Result<(), CalError> {
+    let code = r#"
+    fn main(a: i16) -> i16 {
+        let a: i16 = a + 1;
+        let (b, a) = (a, 2);
+        a + b
+    }"#;
+    let module: Module = code.parse()?;
+    let warnings = check(&module);
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings
+        .iter()
+        .all(|warning| warning.message == "Variable `a` shadows a previous variable"));
+    assert_eq!(get_warned_code(code, &warnings), ["a", "a"]);
+
+    // Variables of sibling blocks do not shadow each other, unlike those of
+    // enclosing blocks
+    let code = r#"
+    fn main(c: bool) -> i16 {
+        let mut r: i16 = 0;
+        if c {
+            let x: i16 = 1;
+            r = x;
+        } else {
+            let x: i16 = 2;
+            while r < x {
+                let r: i16 = 3;
+                let y: i16 = r;
+            }
+        }
+        while r > 0 {
+            let x: i16 = r;
+            r = x - 1;
+        }
+        r
+    }"#;
+    let module: Module = code.parse()?;
+    let warnings: Vec<_> = check(&module)
+        .into_iter()
+        .filter(|warning| warning.message.contains("shadows"))
+        .collect();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(
+        warnings[0].message,
+        "Variable `r` shadows a previous variable"
+    );
+    assert_eq!(get_warned_code(code, &warnings), ["r"]);
+    Ok(())
+}
+
+#[test]
+fn deny_warnings() -> Result<(), CalError> {
+    let code = "fn main(a: i16) {}";
+    let compilation = compile(code, &CompileOptions::default())?;
+    assert_eq!(compilation.warnings.len(), 1);
+    assert!(!compilation.instructions.is_empty());
+
+    let options = CompileOptions {
+        deny_warnings: true,
+        ..Default::default()
+    };
+    let Err(err) = compile(code, &options) else {
+        panic!("Expected warning to be denied");
+    };
+    assert_eq!(err.message, "Unused parameter `a`");
+    assert_eq!(&code[err.range.start..err.range.end], "a");
+    Ok(())
+}
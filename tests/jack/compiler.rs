@@ -0,0 +1,124 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    data::DataSection,
+    jack::{compiler::Compile, error::JackError},
+    Computer,
+};
+
+/// Runs `Main.main` on the computer, returning the word it leaves on the stack
+fn run(sources: &[&str]) -> Result<i16, JackError> {
+    let mut computer = Computer::default();
+    computer.set_instructions(sources.compile()?);
+    for _ in 0..65536 {
+        computer.ticktock();
+    }
+    Ok(computer.get_memory().ram[256])
+}
+
+#[test]
+fn function() -> Result<(), JackError> {
+    let main = r#"
+    class Main {
+        function int main() {
+            var int i, sum;
+            let i = 0;
+            while (i < 5) {
+                if (~(i = 2)) {
+                    let sum = sum + (i * 3);
+                }
+                let i = i + 1;
+            }
+            // Operators have no precedence
+            return sum - (Math.abs(-20) / 4);
+        }
+    }"#;
+    assert_eq!(run(&[main])?, 24 - 5);
+    Ok(())
+}
+
+#[test]
+fn objects() -> Result<(), JackError> {
+    let point = r#"
+    class Point {
+        static int count;
+        field int x, y;
+
+        constructor Point new(int ax, int ay) {
+            let x = ax;
+            let y = ay;
+            let count = count + 1;
+            return this;
+        }
+
+        method int getX() { return x; }
+
+        method Point add(Point other) {
+            return Point.new(x + other.getX(), y + other.getY());
+        }
+
+        method int getY() { return y; }
+
+        function int getCount() { return count; }
+    }"#;
+    let main = r#"
+    class Main {
+        function int main() {
+            var Point p;
+            var Array a;
+            var String s;
+            let p = Point.new(1, 2);
+            let p = p.add(Point.new(10, 20));
+            let a = Array.new(3);
+            let a[0] = 100;
+            let a[a[0] - 99] = a[0] + 1;
+            let s = "abc";
+            return p.getX() + p.getY() + Point.getCount() + a[1] + s.charAt(2) + s.length();
+        }
+    }"#;
+    assert_eq!(run(&[point, main])?, 11 + 22 + 3 + 101 + 'c' as i16 + 3);
+    Ok(())
+}
+
+#[test]
+fn heap_after_data() -> Result<(), JackError> {
+    // The heap starts past the data section of Cal statics
+    let main = r#"
+    class Main {
+        function int main() {
+            var Array a;
+            let a = Array.new(2);
+            return a;
+        }
+    }"#;
+    assert_eq!(run(&[main])?, DataSection::END as i16);
+    Ok(())
+}
+
+#[test]
+fn errors() {
+    let err = [r#"class Main { function int main() { return x; } }"#]
+        .compile()
+        .unwrap_err();
+    assert_eq!(err.message, "Undefined variable `x`");
+
+    let err = [r#"class Main { function void main() { do Main.run(); } }"#]
+        .compile()
+        .unwrap_err();
+    assert_eq!(err.message, "Undefined subroutine `Main.run`");
+
+    let err = [r#"class Main { field int x; function int main() { return x; } }"#]
+        .compile()
+        .unwrap_err();
+    assert_eq!(
+        err.message,
+        "Cannot access field `x` from function `Main.main`"
+    );
+
+    let err = [r#"class Main { function void main() { var int a, a; return; } }"#]
+        .compile()
+        .unwrap_err();
+    assert_eq!(err.message, "Variable `a` is already declared");
+}
@@ -0,0 +1,9 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+mod tokenizer;
+
+mod parser;
+
+mod compiler;
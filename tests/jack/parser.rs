@@ -0,0 +1,81 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::jack::{
+    error::JackError,
+    parser::parse,
+    structure::{Call, Operator, Statement, SubroutineKind, Term, Type, UnaryOperator},
+};
+
+#[test]
+fn class() -> Result<(), JackError> {
+    let code = r#"
+    class Point {
+        static int count;
+        field int x, y;
+
+        constructor Point new(int ax, int ay) {
+            let x = ax;
+            let y = ay;
+            let count = count + 1;
+            return this;
+        }
+
+        method int sum() {
+            var int i;
+            var Array a;
+            if (x < y) { let a[i] = -x; } else { do Sys.halt(); }
+            while (~(i = 2)) { let i = i + 1; }
+            return (x + y) * 2;
+        }
+    }"#;
+    let classes = parse(code)?;
+    assert_eq!(classes.len(), 1);
+    let point = &classes[0];
+    assert_eq!(point.name, "Point");
+    assert_eq!(point.statics.len(), 1);
+    let fields: Vec<&str> = point.fields.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(fields, vec!["x", "y"]);
+
+    let new = &point.subroutines[0];
+    assert_eq!(new.kind, SubroutineKind::Constructor);
+    assert_eq!(new.return_type, Some(Type::Class("Point".into())));
+    assert_eq!(new.parameters.len(), 2);
+    assert_eq!(new.statements.len(), 4);
+
+    let sum = &point.subroutines[1];
+    assert_eq!(sum.kind, SubroutineKind::Method);
+    assert_eq!(sum.locals[1].typ, Type::Class("Array".into()));
+    let Statement::If(_, if_branch, else_branch) = &sum.statements[0] else {
+        panic!("Expected if statement");
+    };
+    let Statement::Let(name, Some(_), value, _) = &if_branch[0] else {
+        panic!("Expected let statement");
+    };
+    assert_eq!(name, "a");
+    assert_eq!(
+        value.term,
+        Term::UnaryOp(UnaryOperator::Neg, Box::new(Term::Variable("x".into())))
+    );
+    let Statement::Do(Call { receiver, name, .. }) = &else_branch[0] else {
+        panic!("Expected do statement");
+    };
+    assert_eq!((receiver.as_deref(), name.as_str()), (Some("Sys"), "halt"));
+    let Statement::Return(Some(value)) = &sum.statements[2] else {
+        panic!("Expected return statement");
+    };
+    assert_eq!(value.ops.len(), 1);
+    assert_eq!(value.ops[0].0, Operator::Mul);
+    Ok(())
+}
+
+#[test]
+fn errors() {
+    let err = parse("class Main { function void main() { let x = 1 } }").unwrap_err();
+    assert_eq!(err.message, "Expected `;`, found `}`");
+    let err = parse("class Main { function void main() { return; }").unwrap_err();
+    assert_eq!(err.message, "Expected subroutine, found end of file");
+    let err = parse("class Main { field x; }").unwrap_err();
+    assert_eq!(err.message, "Expected identifier, found `;`");
+}
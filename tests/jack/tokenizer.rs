@@ -0,0 +1,77 @@
+// Copyright © 2022
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use acs::{
+    jack::{
+        error::JackError,
+        tokenizer::{tokenize, Keyword, TokenKind},
+    },
+    tokenizer::Range,
+};
+
+#[test]
+fn tokens() -> Result<(), JackError> {
+    let code = r#"
+    /** Doc comment */
+    class Main { // line comment
+        /* block
+           comment */
+        field int x_1;
+        let s = "hi there"; do x.y(32767, ~a);
+    }"#;
+    let kinds: Vec<TokenKind> = tokenize(code)?
+        .into_iter()
+        .map(|token| token.value)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::Keyword(Keyword::Class),
+            TokenKind::Identifier("Main".into()),
+            TokenKind::Symbol('{'),
+            TokenKind::Keyword(Keyword::Field),
+            TokenKind::Keyword(Keyword::Int),
+            TokenKind::Identifier("x_1".into()),
+            TokenKind::Symbol(';'),
+            TokenKind::Keyword(Keyword::Let),
+            TokenKind::Identifier("s".into()),
+            TokenKind::Symbol('='),
+            TokenKind::String("hi there".into()),
+            TokenKind::Symbol(';'),
+            TokenKind::Keyword(Keyword::Do),
+            TokenKind::Identifier("x".into()),
+            TokenKind::Symbol('.'),
+            TokenKind::Identifier("y".into()),
+            TokenKind::Symbol('('),
+            TokenKind::Integer(32767),
+            TokenKind::Symbol(','),
+            TokenKind::Symbol('~'),
+            TokenKind::Identifier("a".into()),
+            TokenKind::Symbol(')'),
+            TokenKind::Symbol(';'),
+            TokenKind::Symbol('}'),
+        ]
+    );
+
+    let tokens = tokenize("let x = 1;")?;
+    assert_eq!(tokens[1].range, Range::new(4, 5));
+    Ok(())
+}
+
+#[test]
+fn errors() {
+    let err = tokenize("let x = 32768;").unwrap_err();
+    assert_eq!(err.message, "Integer `32768` is out of range");
+    assert_eq!(err.range, Range::new(8, 13));
+    let err = tokenize("let s = \"abc\n\";").unwrap_err();
+    assert_eq!(err.message, "Unterminated string");
+    assert_eq!(
+        tokenize("/* abc").unwrap_err().message,
+        "Unterminated comment"
+    );
+    assert_eq!(
+        tokenize("x % y").unwrap_err().message,
+        "Unexpected character `%`"
+    );
+}
@@ -560,6 +560,46 @@ fn simple_function() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn wide_return() -> Result<(), Box<dyn Error>> {
+    // The return value is larger than the frame, so it overlaps the words
+    // it is copied from
+    let instructions = VmInstruction::parse(
+        r#"
+            function Wide.six 0
+            push constant 1
+            push constant 2
+            push constant 3
+            push constant 4
+            push constant 5
+            push constant 6
+            return 6
+        "#,
+    );
+
+    let mut emulator = VmEmulator::default();
+    emulator.load(instructions);
+
+    emulator.ram[0] = 261;
+    emulator.ram[1] = 261;
+    emulator.ram[2] = 256;
+    emulator.ram[256] = 400;
+    emulator.ram[257] = 300;
+    emulator.ram[258] = 256;
+    emulator.ram[259] = 3000;
+    emulator.ram[260] = 4000;
+
+    for _ in 0..8 {
+        emulator.step();
+    }
+    assert_eq!(emulator.ram[0], 262);
+    assert_eq!(emulator.ram[1], 300);
+    for i in 0..6 {
+        assert_eq!(emulator.ram[256 + i], i as i16 + 1);
+    }
+    Ok(())
+}
+
 #[test]
 fn fibonacci_element() -> Result<(), Box<dyn Error>> {
     let sys_code = VmCode::new(